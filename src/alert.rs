@@ -0,0 +1,225 @@
+//! Whether a threshold fires again: cooldown and hysteresis
+//!
+//! A threshold that fired clears once RSS falls to its clear level, the
+//! limit less the hysteresis, and fires again at the next crossing unless
+//! that comes within the cooldown of the last alert. RSS hovering around a
+//! limit then alerts once instead of on every other sample. A floor, such
+//! as `--system-floor`, works the other way round: it fires when the value
+//! falls below it and clears at the limit plus the hysteresis.
+
+use std::time::Duration;
+
+use crate::size;
+
+/// How far RSS must fall below a limit to clear it, or a value rise over
+/// a floor
+#[derive(Clone, Copy, Debug)]
+pub enum Hysteresis {
+    Bytes(u64),
+    /// Percent of the limit
+    Percent(f64),
+}
+
+/// `100M` or `10%`
+pub fn parse_hysteresis(s: &str) -> Result<Hysteresis, String> {
+    match s.trim().strip_suffix('%') {
+        Some(percent) => percent
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|percent| (0.0..=100.0).contains(percent))
+            .map(Hysteresis::Percent)
+            .ok_or_else(|| format!("invalid percentage `{}`, expected 0% to 100%", s)),
+        None => size::parse_size(s).map(Hysteresis::Bytes),
+    }
+}
+
+/// What a threshold does after firing
+#[derive(Clone, Copy, Debug)]
+pub enum Rearm {
+    /// Nothing: it fires once
+    Never,
+    /// Clear below the limit less `hysteresis`, and fire at most once per
+    /// `cooldown`
+    After {
+        hysteresis: Hysteresis,
+        cooldown: Duration,
+    },
+}
+
+impl Rearm {
+    /// `--alert-cooldown` and `--alert-hysteresis`; `default` without
+    /// either
+    pub fn from_args(cooldown: Option<Duration>, hysteresis: Option<Hysteresis>, default: Self) -> Self {
+        if cooldown.is_none() && hysteresis.is_none() {
+            return default;
+        }
+        Self::After {
+            hysteresis: hysteresis.unwrap_or(Hysteresis::Bytes(0)),
+            cooldown: cooldown.unwrap_or_default(),
+        }
+    }
+}
+
+/// One threshold and whether it fired
+pub struct Alert {
+    limit: u64,
+    rearm: Rearm,
+    /// A floor, crossed by falling below it
+    floor: bool,
+    /// Fired and not cleared since
+    fired: bool,
+    /// Time of the last alert, for the cooldown
+    last: Option<Duration>,
+}
+
+impl Alert {
+    pub fn new(limit: u64, rearm: Rearm) -> Self {
+        Self {
+            limit,
+            rearm,
+            floor: false,
+            fired: false,
+            last: None,
+        }
+    }
+
+    /// An alert that fires when the value falls below `limit`
+    pub fn floor(limit: u64, rearm: Rearm) -> Self {
+        Self {
+            floor: true,
+            ..Self::new(limit, rearm)
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn rearm(&self) -> Rearm {
+        self.rearm
+    }
+
+    /// Fired and not cleared since
+    pub fn fired(&self) -> bool {
+        self.fired
+    }
+
+    /// RSS at or below which a fired alert clears, or the value at or over
+    /// which a fired floor does
+    pub fn clear_level(&self) -> u64 {
+        let sign = if self.floor { 1.0 } else { -1.0 };
+        match self.rearm {
+            Rearm::Never if self.floor => u64::MAX,
+            Rearm::Never => 0,
+            Rearm::After {
+                hysteresis: Hysteresis::Bytes(bytes),
+                ..
+            } if self.floor => self.limit.saturating_add(bytes),
+            Rearm::After {
+                hysteresis: Hysteresis::Bytes(bytes),
+                ..
+            } => self.limit.saturating_sub(bytes),
+            Rearm::After {
+                hysteresis: Hysteresis::Percent(percent),
+                ..
+            } => (self.limit as f64 * (1.0 + sign * percent / 100.0)) as u64,
+        }
+    }
+
+    /// Clear a fired alert for a new process, keeping the cooldown
+    pub fn reset(&mut self) {
+        self.fired = false;
+    }
+
+    /// Feed a value sampled `at` into the run, and whether the alert
+    /// fires on it; a crossing in the cooldown fires once it is over, if
+    /// the value is still past the limit
+    pub fn update(&mut self, at: Duration, value: u64) -> bool {
+        let (crossed, cleared) = match self.floor {
+            false => (value > self.limit, value <= self.clear_level()),
+            true => (value < self.limit, value >= self.clear_level()),
+        };
+        if self.fired {
+            if matches!(self.rearm, Rearm::After { .. }) && cleared {
+                self.fired = false;
+            }
+            return false;
+        }
+        if !crossed {
+            return false;
+        }
+        if let (Rearm::After { cooldown, .. }, Some(last)) = (self.rearm, self.last) {
+            if at < last + cooldown {
+                return false;
+            }
+        }
+        self.fired = true;
+        self.last = Some(at);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    fn rearm(hysteresis: Hysteresis, cooldown: Duration) -> Rearm {
+        Rearm::After { hysteresis, cooldown }
+    }
+
+    #[test]
+    fn fires_once_without_rearm() {
+        let mut alert = Alert::new(100, Rearm::Never);
+        assert!(!alert.update(SEC, 100));
+        assert!(alert.update(2 * SEC, 101));
+        assert!(!alert.update(3 * SEC, 0));
+        assert!(!alert.update(4 * SEC, 200));
+    }
+
+    #[test]
+    fn rearms_below_the_hysteresis() {
+        let mut alert = Alert::new(100, rearm(Hysteresis::Percent(10.0), Duration::ZERO));
+        assert_eq!(alert.clear_level(), 90);
+        assert!(alert.update(SEC, 150));
+        assert!(!alert.update(2 * SEC, 95));
+        assert!(!alert.update(3 * SEC, 150));
+        assert!(!alert.update(4 * SEC, 90));
+        assert!(alert.update(5 * SEC, 150));
+    }
+
+    #[test]
+    fn waits_out_the_cooldown() {
+        let mut alert = Alert::new(100, rearm(Hysteresis::Bytes(0), 10 * SEC));
+        assert!(alert.update(SEC, 150));
+        assert!(!alert.update(2 * SEC, 50));
+        assert!(!alert.update(3 * SEC, 150));
+        assert!(alert.update(11 * SEC, 150));
+    }
+
+    #[test]
+    fn floors_fire_when_falling_below() {
+        let mut floor = Alert::floor(1000, rearm(Hysteresis::Percent(10.0), Duration::ZERO));
+        assert_eq!(floor.clear_level(), 1100);
+        assert!(!floor.update(SEC, 1000));
+        assert!(floor.update(2 * SEC, 999));
+        assert!(floor.fired());
+        assert!(!floor.update(3 * SEC, 1050));
+        assert!(floor.fired());
+        assert!(!floor.update(4 * SEC, 1100));
+        assert!(!floor.fired());
+        assert!(floor.update(5 * SEC, 500));
+
+        let bytes = Alert::floor(1000, rearm(Hysteresis::Bytes(200), Duration::ZERO));
+        assert_eq!(bytes.clear_level(), 1200);
+    }
+
+    #[test]
+    fn parses_hysteresis() {
+        assert!(matches!(parse_hysteresis("10%"), Ok(Hysteresis::Percent(p)) if p == 10.0));
+        assert!(matches!(parse_hysteresis("1M"), Ok(Hysteresis::Bytes(1048576))));
+        assert!(parse_hysteresis("101%").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! `--detect-anomalies`: samples far outside the recent band of RSS
+//!
+//! The recent level is an exponentially weighted moving average of RSS,
+//! with a variance weighted alike. A sample more than `sigma` standard
+//! deviations away from it is an anomaly. The band follows a lasting
+//! change within a few dozen samples, so a step is reported once, at its
+//! onset, rather than for as long as RSS stays at the new level.
+
+use std::time::Duration;
+
+use crate::{
+    observer::Anomaly,
+    size::format_bytes,
+};
+
+/// Samples the average mostly looks back over
+const SPAN: f64 = 30.0;
+
+/// Samples taken before anything counts as unusual
+const WARMUP: u32 = 10;
+
+/// Smallest deviation assumed, relative to the level, so that a flat line
+/// does not make each page faulted in an anomaly
+const MIN_DEVIATION: f64 = 0.01;
+
+pub struct Detector {
+    sigma: f64,
+    mean: f64,
+    variance: f64,
+    samples: u32,
+    /// The last sample was an anomaly
+    active: bool,
+}
+
+impl Detector {
+    pub fn new(sigma: f64) -> Self {
+        Self {
+            sigma,
+            mean: 0.0,
+            variance: 0.0,
+            samples: 0,
+            active: false,
+        }
+    }
+
+    /// Fold in a sample; returns an anomaly if one starts with it
+    pub fn update(&mut self, elapsed: Duration, rss: u64) -> Option<Anomaly> {
+        let value = rss as f64;
+        self.samples = self.samples.saturating_add(1);
+        if self.samples == 1 {
+            self.mean = value;
+            return None;
+        }
+
+        let deviation = self
+            .variance
+            .sqrt()
+            .max(self.mean * MIN_DEVIATION)
+            .max(1.0);
+        let score = (value - self.mean) / deviation;
+        let expected = self.mean;
+
+        let alpha = 2.0 / (SPAN + 1.0);
+        let diff = value - self.mean;
+        self.mean += alpha * diff;
+        self.variance = (1.0 - alpha) * (self.variance + alpha * diff * diff);
+
+        let anomalous = self.samples > WARMUP && score.abs() > self.sigma;
+        let onset = anomalous && !self.active;
+        self.active = anomalous;
+        onset.then_some(Anomaly {
+            rss,
+            expected: expected as u64,
+            score,
+            elapsed,
+        })
+    }
+}
+
+pub fn parse_sigma(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(sigma) if sigma > 0.0 && sigma.is_finite() => Ok(sigma),
+        _ => Err(format!("expected a positive number of standard deviations, got '{}'", s)),
+    }
+}
+
+/// Notice for an anomaly, e.g. `Anomaly: RSS 1.10 GB is 5.2σ above the
+/// recent 790.12 MB`
+pub fn format_anomaly(anomaly: &Anomaly) -> String {
+    format!(
+        "Anomaly: RSS {} is {:.1}σ {} the recent {}",
+        format_bytes(anomaly.rss),
+        anomaly.score.abs(),
+        if anomaly.score < 0.0 { "below" } else { "above" },
+        format_bytes(anomaly.expected)
+    )
+}
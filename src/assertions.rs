@@ -0,0 +1,191 @@
+//! Memory budgets checked when the command finishes
+
+use std::time::Duration;
+
+use crate::{
+    clock::{
+        self,
+        format_duration,
+    },
+    expr::Expr,
+    size::{
+        self,
+        format_bytes,
+    },
+    summary::Summary,
+};
+
+/// Measured value an assertion is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    PeakRss,
+    /// Mean RSS over the samples
+    AvgRss,
+    PeakVsz,
+    /// Run time, in milliseconds
+    Duration,
+    /// CPU time of the tree over the run time, in percent of one core
+    AvgCpu,
+    PeakProcesses,
+}
+
+impl Metric {
+    const ALL: [Self; 6] = [
+        Self::PeakRss,
+        Self::AvgRss,
+        Self::PeakVsz,
+        Self::Duration,
+        Self::AvgCpu,
+        Self::PeakProcesses,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PeakRss => "peak_rss",
+            Self::AvgRss => "avg_rss",
+            Self::PeakVsz => "peak_vsz",
+            Self::Duration => "duration",
+            Self::AvgCpu => "avg_cpu",
+            Self::PeakProcesses => "peak_processes",
+        }
+    }
+
+    /// Metric called `name` in an expression or budget file
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|metric| metric.name() == name)
+    }
+
+    /// `peak_rss, avg_rss, ...` for error messages
+    pub fn names() -> String {
+        Self::ALL.map(Self::name).join(", ")
+    }
+
+    /// Unit of measured values and limits, for machine-readable output
+    pub fn unit(self) -> &'static str {
+        match self {
+            Self::Duration => "ms",
+            Self::AvgCpu => "percent",
+            Self::PeakProcesses => "processes",
+            _ => "bytes",
+        }
+    }
+
+    pub fn format(self, value: f64) -> String {
+        match self {
+            Self::Duration => format_duration(Duration::from_secs_f64(value.max(0.0) / 1000.0)),
+            Self::AvgCpu => format!("{:.1}%", value),
+            Self::PeakProcesses => format!("{}", value),
+            _ => format_bytes(value as u64),
+        }
+    }
+
+    /// Limit written for this metric: a size such as `2G`, a duration such
+    /// as `10m`, a percentage such as `150%` or a count
+    pub fn parse(self, text: &str) -> Result<f64, String> {
+        match self {
+            Self::Duration => clock::parse_duration(text).map(|d| d.as_secs_f64() * 1000.0),
+            Self::AvgCpu => text
+                .trim()
+                .trim_end_matches('%')
+                .parse()
+                .map_err(|_| format!("invalid percentage `{}`, expected e.g. 150%", text)),
+            Self::PeakProcesses => text
+                .trim()
+                .parse::<u64>()
+                .map(|count| count as f64)
+                .map_err(|_| format!("invalid process count `{}`", text)),
+            _ => size::parse_size(text).map(|bytes| bytes as f64),
+        }
+    }
+
+    pub fn measure(self, summary: &Summary, elapsed: Duration) -> f64 {
+        match self {
+            Self::PeakRss => summary.peak_rss as f64,
+            Self::AvgRss => summary.avg_rss() as f64,
+            Self::PeakVsz => summary.peak_vsz as f64,
+            Self::Duration => elapsed.as_secs_f64() * 1000.0,
+            Self::AvgCpu => {
+                summary.cpu_time().as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON) * 100.0
+            }
+            Self::PeakProcesses => summary.peak_processes as f64,
+        }
+    }
+}
+
+/// `metric` must not exceed `limit`
+pub struct Assertion {
+    pub metric: Metric,
+    pub limit: f64,
+    /// Budget of the budget file the limit comes from, or `None` for the
+    /// `--assert-*` flags
+    pub budget: Option<String>,
+}
+
+/// Result of checking one assertion or `--assert` expression
+pub struct Outcome {
+    /// What was checked, e.g. `peak_rss <= 2.00 GB`
+    pub name: String,
+    /// One-line description, e.g. `peak_rss 2.10 GB exceeds 2.00 GB`
+    pub description: String,
+    /// Exact figures for machine-readable output, e.g.
+    /// `measured 2254857830 bytes, limit 2147483648 bytes`
+    pub detail: String,
+    pub passed: bool,
+}
+
+pub fn check(
+    assertions: &[Assertion],
+    expressions: &[Expr],
+    summary: &Summary,
+    elapsed: Duration,
+) -> Vec<Outcome> {
+    let mut outcomes: Vec<Outcome> = assertions
+        .iter()
+        .map(|assertion| {
+            let metric = assertion.metric;
+            let measured = metric.measure(summary, elapsed);
+            let name = match &assertion.budget {
+                Some(budget) => format!("{}.{}", budget, metric.name()),
+                None => metric.name().to_string(),
+            };
+            let passed = measured <= assertion.limit;
+            Outcome {
+                description: format!(
+                    "{} {} {} {}",
+                    name,
+                    metric.format(measured),
+                    if passed { "within" } else { "exceeds" },
+                    metric.format(assertion.limit)
+                ),
+                name: format!("{} <= {}", name, metric.format(assertion.limit)),
+                detail: format!(
+                    "measured {} {unit}, limit {} {unit}",
+                    measured.round(),
+                    assertion.limit.round(),
+                    unit = metric.unit()
+                ),
+                passed,
+            }
+        })
+        .collect();
+
+    for expr in expressions {
+        let measure = |metric: Metric| metric.measure(summary, elapsed);
+        let metrics = expr.metrics();
+        let values: Vec<String> = metrics
+            .iter()
+            .map(|&metric| format!("{} {}", metric.name(), metric.format(measure(metric))))
+            .collect();
+        outcomes.push(Outcome {
+            name: expr.to_string(),
+            description: format!("{} ({})", expr, values.join(", ")),
+            detail: metrics
+                .iter()
+                .map(|&metric| format!("{} = {} {}", metric.name(), measure(metric), metric.unit()))
+                .collect::<Vec<_>>()
+                .join(", "),
+            passed: expr.eval(&measure),
+        });
+    }
+    outcomes
+}
@@ -0,0 +1,35 @@
+//! `--ballast`: memory pressure made by memwatch itself
+//!
+//! The ballast is allocated and written to before the command starts, so
+//! every page of it is resident and the command runs with that much less
+//! memory to go around. It is held until memwatch exits. Nothing keeps the
+//! kernel from swapping it out; with swap, pressure eases as it does.
+
+use std::io;
+
+use crate::size::format_bytes;
+
+/// Written to every byte, so that no page is left to zero-page sharing
+const FILL: u8 = 0xa5;
+
+pub struct Ballast {
+    _memory: Vec<u8>,
+}
+
+impl Ballast {
+    /// Allocate and touch `size` bytes
+    pub fn new(size: u64) -> io::Result<Self> {
+        let size = usize::try_from(size).map_err(|_| too_large(size))?;
+        let mut memory = Vec::new();
+        memory.try_reserve_exact(size).map_err(|_| too_large(size as u64))?;
+        memory.resize(size, FILL);
+        Ok(Self { _memory: memory })
+    }
+}
+
+fn too_large(size: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::OutOfMemory,
+        format!("cannot allocate a ballast of {}", format_bytes(size)),
+    )
+}
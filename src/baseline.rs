@@ -0,0 +1,62 @@
+//! `--overlay-baseline`: RSS of an earlier run at the same elapsed time,
+//! next to the current value
+
+use std::{
+    io,
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    recording::Recording,
+    size::format_bytes_short,
+};
+
+pub struct Baseline {
+    /// Elapsed time and RSS of the recorded samples, in order
+    points: Vec<(Duration, u64)>,
+}
+
+impl Baseline {
+    /// Samples of a `--record` recording; one without any is an error,
+    /// as is a `--summary-json` file
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut points: Vec<(Duration, u64)> = Recording::read(path)?
+            .samples
+            .iter()
+            .map(|sample| (sample.elapsed, sample.rss))
+            .collect();
+        if points.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has no samples to compare with; make it with --record", path.display()),
+            ));
+        }
+        points.sort_by_key(|&(at, _)| at);
+        Ok(Self { points })
+    }
+
+    /// RSS of the latest baseline sample at or before `at`, or `None` once
+    /// the baseline run was over
+    fn rss_at(&self, at: Duration) -> Option<u64> {
+        let &(end, _) = self.points.last()?;
+        if at > end {
+            return None;
+        }
+        let next = self.points.partition_point(|&(sample, _)| sample <= at);
+        Some(self.points[next.saturating_sub(1)].1)
+    }
+
+    /// Status line column, e.g. `vs baseline 0.90G, +33%`
+    pub fn status(&self, at: Duration, rss: u64) -> String {
+        match self.rss_at(at) {
+            Some(0) => String::from("vs baseline 0B"),
+            Some(baseline) => format!(
+                "vs baseline {}, {:+.0}%",
+                format_bytes_short(baseline),
+                (rss as f64 - baseline as f64) / baseline as f64 * 100.0
+            ),
+            None => String::from("baseline ended"),
+        }
+    }
+}
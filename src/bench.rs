@@ -0,0 +1,701 @@
+//! Measuring whole runs of commands for comparisons and benchmarks
+
+use std::{
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        Child,
+        Command,
+        Stdio,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            Ordering,
+        },
+        mpsc,
+        Arc,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    clock::format_elapsed,
+    exit::ExitInfo,
+    json::Value,
+    multi,
+    output::{
+        self,
+        Stream,
+    },
+    render::Renderer,
+    size::format_bytes,
+    stats::Stats,
+    summary::{
+        self,
+        Summary,
+    },
+    thermal::{
+        self,
+        Cooldown,
+        RunThermal,
+        Thermal,
+    },
+    ticks::Ticks,
+    tree::{
+        self,
+        ProcessTracker,
+        ReadError,
+        TreeSample,
+    },
+};
+
+/// `memwatch bench`: the benchmark options with what they apply to
+#[derive(clap::Args, Debug)]
+pub struct BenchCommand {
+    #[command(flatten)]
+    bench: BenchArgs,
+
+    /// Update interval in milliseconds
+    #[arg(short, long, default_value_t = 1000)]
+    interval: u64,
+
+    /// Write the JSON report of all runs to this file
+    #[arg(long, value_name = "FILE")]
+    summary_json: Option<PathBuf>,
+
+    /// Command to measure (everything after `--`)
+    #[arg(trailing_var_arg = true, required = true)]
+    command: Vec<String>,
+}
+
+/// Options of benchmark mode, entered when any of them is given
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Run once per value, substituting `{NAME}` in the command
+    /// (e.g. --param N=1,2,4,8); repeat for a full matrix
+    #[arg(long, value_name = "NAME=V1,V2,..", value_parser = parse_param)]
+    pub param: Vec<Param>,
+
+    /// Measure the command this many times (per parameter combination)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub runs: Option<u32>,
+
+    /// Run the command this many times first without measuring it, to warm
+    /// up caches
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub warmup_runs: u32,
+
+    /// Pause this long between runs, or with `auto` until the CPU is about
+    /// as cool as before the first run
+    #[arg(long, value_name = "TIME|auto", value_parser = thermal::parse_cooldown)]
+    pub cooldown: Option<Cooldown>,
+
+    /// Write results for continuous-benchmarking tools to this file
+    #[arg(long, value_name = "FILE")]
+    pub export_bench: Option<PathBuf>,
+
+    /// Format of --export-bench
+    #[arg(long, value_enum, default_value_t = BenchFormat::Bmf, requires = "export_bench")]
+    pub bench_format: BenchFormat,
+}
+
+impl BenchArgs {
+    pub fn enabled(&self) -> bool {
+        !self.param.is_empty()
+            || self.runs.is_some()
+            || self.warmup_runs > 0
+            || self.cooldown.is_some()
+            || self.export_bench.is_some()
+    }
+}
+
+/// Result formats of continuous-benchmarking tools
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum BenchFormat {
+    /// Bencher Metric Format
+    Bmf,
+    /// github-action-benchmark `customSmallerIsBetter`
+    Github,
+}
+
+/// A substituted parameter and the values it takes
+#[derive(Clone, Debug)]
+pub struct Param {
+    name: String,
+    values: Vec<String>,
+}
+
+fn parse_param(s: &str) -> Result<Param, String> {
+    let (name, values) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=V1,V2,.., got '{}'", s))?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid parameter name '{}'", name));
+    }
+    let values: Vec<String> = values.split(',').map(str::to_string).collect();
+    if values.iter().any(String::is_empty) {
+        return Err(format!("empty value in '{}'", s));
+    }
+    Ok(Param {
+        name: name.to_string(),
+        values,
+    })
+}
+
+/// Measured runs of one parameter combination
+struct Case<'a> {
+    values: &'a [String],
+    runs: Vec<Measurement>,
+    /// Alongside `runs`
+    thermal: Vec<RunThermal>,
+}
+
+impl Case<'_> {
+    fn mean(&self, f: impl Fn(&Measurement) -> f64) -> f64 {
+        self.runs.iter().map(f).sum::<f64>() / self.runs.len() as f64
+    }
+
+    fn peak_stats(&self) -> Option<Stats> {
+        let peaks: Vec<f64> = self.runs.iter().map(|m| m.summary.peak_rss as f64).collect();
+        Stats::new(&peaks)
+    }
+
+    /// Outcome of the first failed run, or of the last one
+    fn exit(&self) -> String {
+        self.runs
+            .iter()
+            .find(|m| m.exit.code != Some(0))
+            .or(self.runs.last())
+            .map(|m| m.exit.describe())
+            .unwrap_or_default()
+    }
+}
+
+/// Run `command` for every combination of parameter values and print a
+/// table of memory use per combination
+///
+/// Warmup runs go first for each combination and are left out of the
+/// statistics; with several runs the table shows means.
+pub fn run_command(args: BenchCommand, terminated: &AtomicBool) -> io::Result<()> {
+    let interval = Duration::from_millis(args.interval);
+    run(&args.bench, &args.command, interval, args.summary_json.as_deref(), terminated)
+}
+
+pub fn run(
+    args: &BenchArgs,
+    command: &[String],
+    interval: Duration,
+    summary_json: Option<&Path>,
+    terminated: &AtomicBool,
+) -> io::Result<()> {
+    let combinations = combinations(&args.param);
+    let runs = args.runs.unwrap_or(1);
+    let mut cases = Vec::new();
+    let thermal = match args.cooldown {
+        Some(Cooldown::Auto) => Some(Thermal::open().map_err(|e| {
+            io::Error::new(e.kind(), format!("--cooldown auto needs the CPU's temperature: {}", e))
+        })?),
+        _ => Thermal::open().ok(),
+    };
+    let baseline = thermal.as_ref().and_then(Thermal::temperature);
+    let mut first = true;
+
+    for values in &combinations {
+        let substituted: Vec<String> = command
+            .iter()
+            .map(|arg| {
+                args.param.iter().zip(values).fold(arg.clone(), |arg, (param, value)| {
+                    arg.replace(&format!("{{{}}}", param.name), value)
+                })
+            })
+            .collect();
+        let mut case = Case {
+            values,
+            runs: Vec::new(),
+            thermal: Vec::new(),
+        };
+
+        for i in 0..args.warmup_runs + runs {
+            let warmup = i < args.warmup_runs;
+            let mut heading = describe_values(&args.param, values);
+            if warmup {
+                heading.push_str(&format!(" warmup {}/{}", i + 1, args.warmup_runs));
+            } else if runs > 1 {
+                heading.push_str(&format!(" run {}/{}", i - args.warmup_runs + 1, runs));
+            }
+            // Nothing to cool down from before the first run
+            if !std::mem::take(&mut first)
+                && !cool_down(args.cooldown, thermal.as_ref(), baseline, terminated)
+            {
+                eprintln!("Interrupted (Ctrl+C)");
+                return Ok(());
+            }
+            eprintln!(
+                "{}: {}",
+                heading.trim_start(),
+                Summary::new(&substituted).command_line()
+            );
+
+            let temperature = thermal.as_ref().and_then(Thermal::temperature);
+            let throttled = thermal::throttle_count();
+            match measure(&[(String::new(), substituted.clone())], interval, terminated)? {
+                Some(_) if warmup => {}
+                Some(mut measured) => {
+                    let throttled = throttled
+                        .zip(thermal::throttle_count())
+                        .map(|(before, after)| after.saturating_sub(before));
+                    if let Some(events) = throttled.filter(|&events| events > 0) {
+                        eprintln!("memwatch: the CPU was throttled {} times during the run", events);
+                    }
+                    case.runs.push(measured.remove(0));
+                    case.thermal.push(RunThermal {
+                        temperature,
+                        throttled,
+                    });
+                }
+                None => {
+                    eprintln!("Interrupted (Ctrl+C)");
+                    return Ok(());
+                }
+            }
+        }
+        cases.push(case);
+    }
+
+    eprintln!();
+    let names: String = args.param.iter().map(|p| format!("{:<10}", p.name)).collect();
+    eprintln!(
+        "{}{:>12} {:>12} {:>10} {:>8} {:>5}  Exit",
+        names, "Peak RSS", "Avg RSS", "Duration", "vs first", "Runs"
+    );
+    let peak = |m: &Measurement| m.summary.peak_rss as f64;
+    let first_peak = cases.first().map(|case| case.mean(peak)).unwrap_or(0.0);
+    for case in &cases {
+        let cells: String = case.values.iter().map(|v| format!("{:<10}", v)).collect();
+        let ratio = if first_peak == 0.0 {
+            0.0
+        } else {
+            case.mean(peak) / first_peak
+        };
+        eprintln!(
+            "{}{:>12} {:>12} {:>9.1}s {:>7.2}x {:>5}  {}",
+            cells,
+            format_bytes(case.mean(peak) as u64),
+            format_bytes(case.mean(|m| m.avg_rss() as f64) as u64),
+            case.mean(|m| m.elapsed.as_secs_f64()),
+            ratio,
+            case.runs.len(),
+            case.exit()
+        );
+    }
+
+    if runs > 1 {
+        eprintln!();
+        for case in &cases {
+            let Some(stats) = case.peak_stats() else {
+                continue;
+            };
+            let heading = describe_values(&args.param, case.values);
+            eprintln!(
+                "{}Peak RSS: mean {} | median {} | MAD {} | 95% CI {} – {}",
+                if heading.is_empty() { heading } else { heading + ": " },
+                format_bytes(stats.mean as u64),
+                format_bytes(stats.median as u64),
+                format_bytes(stats.mad as u64),
+                format_bytes(stats.ci.0.max(0.0) as u64),
+                format_bytes(stats.ci.1 as u64)
+            );
+            for &i in &stats.outliers {
+                let peak = case.runs[i].summary.peak_rss as f64;
+                // A share of a median of 0 B would print as inf% or NaN%
+                let from = if stats.median > 0.0 {
+                    format!("{:+.1}% from median", (peak - stats.median) / stats.median * 100.0)
+                } else {
+                    String::from("median 0 B")
+                };
+                eprintln!(
+                    "  outlier: run {} peak RSS {} ({})",
+                    i + 1,
+                    format_bytes(peak as u64),
+                    from
+                );
+            }
+        }
+    }
+
+    if let Some(path) = &args.export_bench {
+        let names: Vec<String> = cases
+            .iter()
+            .map(|case| bench_name(command, &args.param, case.values))
+            .collect();
+        let export = match args.bench_format {
+            BenchFormat::Bmf => export_bmf(&names, &cases),
+            BenchFormat::Github => export_github(&names, &cases),
+        };
+        if let Err(e) = summary::write_file(path, &export) {
+            eprintln!("memwatch: failed to write benchmark results to {}: {}", path.display(), e);
+        }
+    }
+
+    if let Some(path) = summary_json {
+        let mut reports = Vec::new();
+        for case in &cases {
+            let outliers = case.peak_stats().map(|stats| stats.outliers).unwrap_or_default();
+            for (i, m) in case.runs.iter().enumerate() {
+                let params = args
+                    .param
+                    .iter()
+                    .zip(case.values)
+                    .map(|(param, value)| (param.name.clone(), value.as_str().into()))
+                    .collect();
+                let mut report = m.summary.report(m.elapsed, &m.exit, None);
+                // After `schema_version`, which stays first
+                if let Value::Object(fields) = &mut report {
+                    fields.insert(1, ("params".into(), Value::Object(params)));
+                    fields.insert(2, ("run".into(), (i as u64 + 1).into()));
+                    fields.insert(3, ("outlier".into(), outliers.contains(&i).into()));
+                    let thermal = case.thermal.get(i).copied().unwrap_or_default();
+                    fields.insert(4, ("temperature_start".into(), thermal.temperature.into()));
+                    fields.insert(5, ("throttle_events".into(), thermal.throttled.into()));
+                }
+                reports.push(report);
+            }
+        }
+        if let Err(e) = summary::write_file(path, &Value::Array(reports)) {
+            eprintln!("memwatch: failed to write summary to {}: {}", path.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Wait before the next run as `--cooldown` says; false if interrupted
+fn cool_down(
+    cooldown: Option<Cooldown>,
+    thermal: Option<&Thermal>,
+    baseline: Option<f64>,
+    terminated: &AtomicBool,
+) -> bool {
+    match (cooldown, thermal, baseline) {
+        (Some(Cooldown::Pause(pause)), _, _) => thermal::pause(pause, terminated),
+        (Some(Cooldown::Auto), Some(thermal), Some(baseline)) => {
+            let start = Instant::now();
+            let before = thermal.temperature();
+            let reached = thermal.settle(baseline, terminated);
+            if let (Some(before), Some(reached)) = (before, reached) {
+                eprintln!(
+                    "Cooling down: {:.1} °C → {:.1} °C in {} (started at {:.1} °C)",
+                    before,
+                    reached,
+                    format_elapsed(start.elapsed()),
+                    baseline
+                );
+            }
+            !terminated.load(Ordering::SeqCst)
+        }
+        _ => true,
+    }
+}
+
+/// Benchmark name: the command template with the parameter values
+fn bench_name(command: &[String], params: &[Param], values: &[String]) -> String {
+    let name = Summary::new(command).command_line();
+    match describe_values(params, values) {
+        values if values.is_empty() => name,
+        values => format!("{} [{}]", name, values),
+    }
+}
+
+/// Bencher Metric Format: `{name: {measure: {value, lower_value, upper_value}}}`
+fn export_bmf(names: &[String], cases: &[Case]) -> Value {
+    let metric = |case: &Case, f: &dyn Fn(&Measurement) -> f64| {
+        let (lower, upper) = case.runs.iter().map(f).fold((f64::MAX, f64::MIN), |(lo, hi), v| {
+            (lo.min(v), hi.max(v))
+        });
+        Value::Object(vec![
+            ("value".into(), case.mean(f).into()),
+            ("lower_value".into(), lower.into()),
+            ("upper_value".into(), upper.into()),
+        ])
+    };
+    Value::Object(
+        names
+            .iter()
+            .zip(cases)
+            .map(|(name, case)| {
+                let measures = vec![
+                    ("peak-rss".into(), metric(case, &|m| m.summary.peak_rss as f64)),
+                    ("avg-rss".into(), metric(case, &|m| m.avg_rss() as f64)),
+                    // Bencher's built-in latency measure is in nanoseconds
+                    ("latency".into(), metric(case, &|m| m.elapsed.as_nanos() as f64)),
+                ];
+                (name.clone(), Value::Object(measures))
+            })
+            .collect(),
+    )
+}
+
+/// github-action-benchmark `customSmallerIsBetter`: `[{name, unit, value, range}]`
+fn export_github(names: &[String], cases: &[Case]) -> Value {
+    let mut entries = Vec::new();
+    for (name, case) in names.iter().zip(cases) {
+        let peak = case.mean(|m| m.summary.peak_rss as f64);
+        // Largest deviation of a single run from the mean
+        let spread = case
+            .runs
+            .iter()
+            .map(|m| (m.summary.peak_rss as f64 - peak).abs())
+            .fold(0.0, f64::max);
+        entries.push(Value::Object(vec![
+            ("name".into(), format!("{} - peak RSS", name).into()),
+            ("unit".into(), "bytes".into()),
+            ("value".into(), (peak as u64).into()),
+            ("range".into(), format!("± {}", spread as u64).into()),
+            ("extra".into(), format!("{} runs", case.runs.len()).into()),
+        ]));
+        entries.push(Value::Object(vec![
+            ("name".into(), format!("{} - avg RSS", name).into()),
+            ("unit".into(), "bytes".into()),
+            ("value".into(), (case.mean(|m| m.avg_rss() as f64) as u64).into()),
+        ]));
+    }
+    Value::Array(entries)
+}
+
+/// Every combination of parameter values, the first parameter varying slowest
+fn combinations(params: &[Param]) -> Vec<Vec<String>> {
+    params.iter().fold(vec![Vec::new()], |acc, param| {
+        acc.iter()
+            .flat_map(|prefix| {
+                param.values.iter().map(move |value| {
+                    let mut combination = prefix.clone();
+                    combination.push(value.clone());
+                    combination
+                })
+            })
+            .collect()
+    })
+}
+
+fn describe_values(params: &[Param], values: &[String]) -> String {
+    params
+        .iter()
+        .zip(values)
+        .map(|(param, value)| format!("{}={}", param.name, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Memory profile of one finished run
+pub struct Measurement {
+    pub summary: Summary,
+    pub elapsed: Duration,
+    pub exit: ExitInfo,
+    /// Total RSS of the tree at each sample
+    pub timeline: Vec<(Duration, u64)>,
+}
+
+impl Measurement {
+    /// Mean RSS over the samples, which are evenly spaced
+    pub fn avg_rss(&self) -> u64 {
+        match self.timeline.len() as u64 {
+            0 => 0,
+            n => self.timeline.iter().map(|&(_, rss)| rss).sum::<u64>() / n,
+        }
+    }
+
+    /// RSS at `offset` into the run: the last sample taken by then, or the
+    /// first one for offsets before it
+    pub fn rss_at(&self, offset: Duration) -> Option<u64> {
+        if offset > self.elapsed {
+            return None;
+        }
+        self.timeline
+            .iter()
+            .take_while(|&&(at, _)| at <= offset)
+            .last()
+            .or(self.timeline.first())
+            .map(|&(_, rss)| rss)
+    }
+}
+
+/// A command being measured
+struct Run {
+    label: String,
+    child: Child,
+    pid: i32,
+    summary: Summary,
+    tracker: ProcessTracker,
+    timeline: Vec<(Duration, u64)>,
+    last: TreeSample,
+    exit: Option<(ExitInfo, Duration)>,
+}
+
+/// Run `commands` at the same time and measure each until all have exited
+///
+/// Output is forwarded, prefixed with the label when there are several
+/// commands. Returns `None` if interrupted with Ctrl+C.
+pub fn measure(
+    commands: &[(String, Vec<String>)],
+    interval: Duration,
+    terminated: &AtomicBool,
+) -> io::Result<Option<Vec<Measurement>>> {
+    let start = Instant::now();
+    let mut ticks = Ticks::new(start, interval);
+    let (tx, rx) = mpsc::sync_channel(output::BACKLOG);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let mut runs = Vec::new();
+
+    for (index, (label, command)) in commands.iter().enumerate() {
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .stdin(if commands.len() == 1 {
+                Stdio::inherit()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                io::Error::new(e.kind(), format!("failed to spawn `{}`: {}", command[0], e))
+            })?;
+
+        let streams = [
+            child.stdout.take().map(|s| (Box::new(s) as Box<dyn io::Read + Send>, Stream::Stdout)),
+            child.stderr.take().map(|s| (Box::new(s) as Box<dyn io::Read + Send>, Stream::Stderr)),
+        ];
+        for (reader, stream) in streams.into_iter().flatten() {
+            let (run_tx, run_rx) = mpsc::sync_channel(output::BACKLOG);
+            let dropped = dropped.clone();
+            thread::spawn(move || output::forward(reader, stream, run_tx, dropped));
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for msg in run_rx {
+                    if tx.send((index, msg)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        runs.push(Run {
+            label: label.clone(),
+            pid: child.id() as i32,
+            child,
+            summary: Summary::new(command),
+            tracker: ProcessTracker::default(),
+            timeline: Vec::new(),
+            last: TreeSample::default(),
+            exit: None,
+        });
+    }
+    drop(tx);
+
+    let mut renderer = Renderer::plain();
+    let tagged = runs.len() > 1;
+
+    loop {
+        let interrupted = terminated.load(Ordering::SeqCst);
+        for run in runs.iter_mut().filter(|run| run.exit.is_none()) {
+            if interrupted {
+                tree::kill_tree(Some(run.pid), &run.tracker.live());
+                let _ = run.child.kill();
+            }
+            if let Some(status) = run.child.try_wait()? {
+                run.exit = Some((ExitInfo::from_status(status), start.elapsed()));
+                run.last = TreeSample::default();
+            }
+        }
+        if runs.iter().all(|run| run.exit.is_some()) {
+            break;
+        }
+
+        for run in runs.iter_mut().filter(|run| run.exit.is_none()) {
+            run.last = tree::sample(run.pid)
+                .unwrap_or_else(|e| run.last.stale(ReadError::of(&e)));
+            run.summary.record(&run.last);
+            run.tracker.update(&run.last);
+            run.summary.processes_seen = run.tracker.seen;
+            run.timeline.push((ticks.elapsed(), run.last.rss));
+        }
+
+        let status = format_status(start.elapsed(), &runs);
+        renderer.status(&status);
+
+        let deadline = ticks.advance();
+        loop {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((index, msg)) => {
+                    renderer.output(if tagged { multi::tag(&runs[index].label, msg) } else { msg });
+                    renderer.status(&status);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    // The output of every run closed; wait out the tick
+                    // unless a run exits, so its duration is not rounded
+                    // up to the interval
+                    while Instant::now() < deadline
+                        && runs
+                            .iter_mut()
+                            .filter(|run| run.exit.is_none())
+                            .all(|run| matches!(run.child.try_wait(), Ok(None)))
+                    {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    while let Ok((index, msg)) = rx.recv_timeout(Duration::from_millis(50)) {
+        renderer.output(if tagged { multi::tag(&runs[index].label, msg) } else { msg });
+    }
+    drop(renderer);
+
+    if terminated.load(Ordering::SeqCst) {
+        return Ok(None);
+    }
+    let measurements = runs
+        .into_iter()
+        .map(|mut run| {
+            run.summary.top_processes = run.tracker.top();
+            let (exit, elapsed) = run.exit.expect("all runs have exited");
+            Measurement {
+                summary: run.summary,
+                elapsed,
+                exit,
+                timeline: run.timeline,
+            }
+        })
+        .collect();
+    Ok(Some(measurements))
+}
+
+fn format_status(elapsed: Duration, runs: &[Run]) -> String {
+    let clock = format_elapsed(elapsed);
+    runs.iter()
+        .map(|run| {
+            let state = match &run.exit {
+                Some((exit, _)) => exit.describe(),
+                None => format!(
+                    "RSS: {} | Procs: {}",
+                    format_bytes(run.last.rss),
+                    run.last.processes.len()
+                ),
+            };
+            format!("[{}] {}: {}", clock, run.label, state)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
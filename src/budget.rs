@@ -0,0 +1,115 @@
+//! Memory budgets committed to a project in `memwatch.toml`
+//!
+//! ```toml
+//! [budget.build]
+//! command = "cargo build*"
+//! peak_rss = "2G"
+//! duration = "10m"
+//! ```
+//!
+//! Each `[budget.NAME]` table holds limits for the commands its `command`
+//! pattern matches, or for every command without one.
+
+use crate::{
+    assertions::{
+        Assertion,
+        Metric,
+    },
+    config::Scalar,
+};
+
+pub struct Budget {
+    pub name: String,
+    /// Glob pattern of command lines, `*` and `?`; `None` matches all
+    pattern: Option<String>,
+    limits: Vec<(Metric, f64)>,
+}
+
+impl Budget {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            pattern: None,
+            limits: Vec::new(),
+        }
+    }
+
+    /// Key of the budget's table
+    pub fn set(&mut self, key: &str, value: Scalar) -> Result<(), String> {
+        if key == "command" {
+            self.pattern = Some(value.into_string(key)?);
+            return Ok(());
+        }
+        let metric = Metric::from_name(key).ok_or_else(|| {
+            format!(
+                "unknown key `{}`, expected command or one of {}",
+                key,
+                Metric::names()
+            )
+        })?;
+        let limit = match value {
+            // Seconds, bytes, percent or processes
+            Scalar::Integer(value) if metric == Metric::Duration => value as f64 * 1000.0,
+            Scalar::Integer(value) => value as f64,
+            Scalar::String(text) => metric.parse(&text)?,
+        };
+        self.limits.retain(|&(m, _)| m != metric);
+        self.limits.push((metric, limit));
+        Ok(())
+    }
+
+    fn matches(&self, command: &str) -> bool {
+        self.pattern.as_deref().is_none_or(|pattern| {
+            glob(
+                &pattern.chars().collect::<Vec<_>>(),
+                &command.chars().collect::<Vec<_>>(),
+            )
+        })
+    }
+}
+
+/// Limits of the budgets matching `command`, the command line as shown in
+/// reports
+pub fn assertions(budgets: &[Budget], command: &str) -> Vec<Assertion> {
+    budgets
+        .iter()
+        .filter(|budget| budget.matches(command))
+        .flat_map(|budget| {
+            budget.limits.iter().map(|&(metric, limit)| Assertion {
+                metric,
+                limit,
+                budget: Some(budget.name.clone()),
+            })
+        })
+        .collect()
+}
+
+/// Whether `text` matches `pattern`, where `*` is any run of characters
+/// and `?` any one
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Last `*` seen and where in `text` it matched up to, to widen it
+    // when the rest of the pattern fails
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
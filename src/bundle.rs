@@ -0,0 +1,125 @@
+//! Diagnostic bundles: raw `/proc` files of the tree, kept for post-mortems
+//!
+//! Each bundle is a directory `DIR/<run id>/<n>-<reason>` with a directory
+//! per process holding copies of its `/proc` files, and `bundle.json`
+//! listing the processes with their open file descriptor counts. Unlike
+//! the processes, the copies are still there after the run.
+
+use std::{
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use crate::{
+    json::Value,
+    record::{
+        self,
+        SCHEMA_VERSION,
+    },
+    redact,
+    summary::{
+        self,
+        Summary,
+    },
+    tree,
+};
+
+/// Files copied for every process
+const FILES: [&str; 6] = ["status", "smaps_rollup", "limits", "cgroup", "cmdline", "stat"];
+
+pub struct Bundles {
+    dir: PathBuf,
+    taken: usize,
+}
+
+impl Bundles {
+    /// Bundles under `dir`, created up front so an unwritable one stops the
+    /// run before it starts
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot create {}: {}", dir.display(), e)))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            taken: 0,
+        })
+    }
+
+    /// Copy the files of the tree of `root_pid` and `known`, the processes
+    /// of the last sample, into a new bundle named after `reason`; returns
+    /// its directory and the number of processes in it
+    pub fn take(
+        &mut self,
+        summary: &Summary,
+        reason: &str,
+        elapsed: Duration,
+        root_pid: Option<i32>,
+        known: &[(i32, u64)],
+    ) -> io::Result<(PathBuf, usize)> {
+        self.taken += 1;
+        let dir = self
+            .dir
+            .join(&summary.run_id)
+            .join(format!("{}-{}", self.taken, reason));
+        fs::create_dir_all(&dir)?;
+
+        let mut pids = tree::tree_members(root_pid, known);
+        pids.sort_unstable();
+        let mut processes = Vec::new();
+        for pid in pids {
+            let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+            let out = dir.join(pid.to_string());
+            fs::create_dir_all(&out)?;
+            // Files of a process that exits meanwhile are listed as missing
+            let mut missing = Vec::new();
+            for file in FILES {
+                match fs::read(proc_dir.join(file)) {
+                    Ok(data) if file == "cmdline" => fs::write(out.join(file), redact_cmdline(&data))?,
+                    Ok(data) => fs::write(out.join(file), data)?,
+                    Err(_) => missing.push(Value::from(file)),
+                }
+            }
+            let fds = fs::read_dir(proc_dir.join("fd")).ok().map(|fds| fds.count() as u64);
+            processes.push(Value::Object(vec![
+                ("pid".into(), pid.into()),
+                ("fds".into(), fds.into()),
+                ("missing".into(), Value::Array(missing)),
+            ]));
+        }
+
+        let count = processes.len();
+        let index = Value::Object(vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("run_id".into(), summary.run_id.as_str().into()),
+            ("command".into(), summary.command_line().into()),
+            ("reason".into(), reason.into()),
+            ("time".into(), record::format_time(SystemTime::now()).into()),
+            ("elapsed_secs".into(), elapsed.as_secs_f64().into()),
+            ("processes".into(), Value::Array(processes)),
+        ]);
+        summary::write_file(&dir.join("bundle.json"), &index)?;
+        Ok((dir, count))
+    }
+}
+
+/// `cmdline`, NUL-separated arguments, with its secrets replaced
+fn redact_cmdline(data: &[u8]) -> Vec<u8> {
+    let args: Vec<String> = data
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    let mut redacted = Vec::new();
+    for arg in redact::args(&args) {
+        redacted.extend(arg.into_bytes());
+        redacted.push(0);
+    }
+    redacted
+}
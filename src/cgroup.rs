@@ -0,0 +1,326 @@
+//! Dedicated cgroup for the monitored command, or an existing one for
+//! `--slice`
+//!
+//! The kernel accounts every process in a cgroup, including ones that start
+//! and exit between two samples, so its high-water mark catches spikes that
+//! sampling `/proc` misses.
+
+use std::{
+    ffi::{
+        CStr,
+        CString,
+    },
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Version {
+    /// Unified hierarchy
+    V2,
+    /// Legacy `memory` controller hierarchy
+    V1,
+}
+
+pub struct Cgroup {
+    path: PathBuf,
+    version: Version,
+    /// `cgroup.procs` path prepared for use between fork and exec
+    procs: CString,
+    /// Created by memwatch, so removed when done with
+    owned: bool,
+}
+
+/// Memory of a cgroup and its descendants by kind, from `memory.stat`
+pub struct MemoryStat {
+    pub anon: u64,
+    pub file: u64,
+}
+
+impl Cgroup {
+    /// Create a new cgroup next to the one memwatch itself runs in
+    pub fn create() -> io::Result<Self> {
+        let own = fs::read_to_string("/proc/self/cgroup")?;
+        let root = Path::new(CGROUP_ROOT);
+
+        let (version, parent) = if root.join("cgroup.controllers").exists() {
+            // cgroup v2 forbids processes in inner nodes, so the new group
+            // becomes a sibling of ours rather than a child
+            let own_path = own
+                .lines()
+                .find_map(|line| line.strip_prefix("0::"))
+                .ok_or_else(|| not_supported("no cgroup v2 membership"))?;
+            let parent = match own_path.trim_start_matches('/') {
+                "" => root.to_path_buf(),
+                own_path => root.join(own_path).parent().unwrap_or(root).to_path_buf(),
+            };
+            (Version::V2, parent)
+        } else if root.join("memory").is_dir() {
+            let own_path = own
+                .lines()
+                .find_map(|line| {
+                    let mut parts = line.splitn(3, ':');
+                    let _id = parts.next()?;
+                    let controllers = parts.next()?;
+                    let path = parts.next()?;
+                    controllers.split(',').any(|c| c == "memory").then_some(path)
+                })
+                .ok_or_else(|| not_supported("no memory cgroup membership"))?;
+            let parent = root.join("memory").join(own_path.trim_start_matches('/'));
+            (Version::V1, parent)
+        } else {
+            return Err(not_supported("no cgroup filesystem at /sys/fs/cgroup"));
+        };
+
+        let path = parent.join(format!("memwatch-{}", std::process::id()));
+        fs::create_dir(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to create cgroup {}: {}", path.display(), e),
+            )
+        })?;
+
+        let mut cgroup = Self::open(path, version)?;
+        cgroup.owned = true;
+        if cgroup.memory_current().is_none() {
+            return Err(not_supported("memory controller is not enabled for the cgroup"));
+        }
+        Ok(cgroup)
+    }
+
+    fn open(path: PathBuf, version: Version) -> io::Result<Self> {
+        let procs = CString::new(path.join("cgroup.procs").into_os_string().into_encoded_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(Self {
+            path,
+            version,
+            procs,
+            owned: false,
+        })
+    }
+
+    /// An existing cgroup, by its path under `/sys/fs/cgroup` (under
+    /// `/sys/fs/cgroup/memory` with cgroup v1) or in full, such as
+    /// `user.slice/user-1000.slice`; it is left in place when done with
+    pub fn existing(path: &str) -> io::Result<Self> {
+        let root = Path::new(CGROUP_ROOT);
+        let (version, base) = if root.join("cgroup.controllers").exists() {
+            (Version::V2, root.to_path_buf())
+        } else if root.join("memory").is_dir() {
+            (Version::V1, root.join("memory"))
+        } else {
+            return Err(not_supported("no cgroup filesystem at /sys/fs/cgroup"));
+        };
+        let relative = Path::new(path).strip_prefix(&base).unwrap_or(Path::new(path));
+        let full = base.join(relative.strip_prefix("/").unwrap_or(relative));
+        if !full.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no cgroup {} in {}", path, base.display()),
+            ));
+        }
+        let cgroup = Self::open(full, version)?;
+        if cgroup.memory_current().is_none() {
+            return Err(not_supported("memory controller is not enabled for the cgroup"));
+        }
+        Ok(cgroup)
+    }
+
+    /// Last part of the cgroup's path, such as `docker-4f1c.scope`
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "/".into())
+    }
+
+    /// Cgroups directly below this one
+    pub fn children(&self) -> Vec<Self> {
+        let Ok(entries) = fs::read_dir(&self.path) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+            .filter_map(|entry| Self::open(entry.path(), self.version).ok())
+            .collect()
+    }
+
+    /// Processes of this cgroup and of every cgroup below it
+    pub fn processes(&self) -> Vec<i32> {
+        let mut pids = Vec::new();
+        let mut dirs = vec![self.path.clone()];
+        while let Some(dir) = dirs.pop() {
+            let procs = fs::read_to_string(dir.join("cgroup.procs")).unwrap_or_default();
+            pids.extend(procs.lines().filter_map(|line| line.trim().parse::<i32>().ok()));
+            if let Ok(entries) = fs::read_dir(&dir) {
+                dirs.extend(
+                    entries
+                        .flatten()
+                        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+                        .map(|entry| entry.path()),
+                );
+            }
+        }
+        pids
+    }
+
+    /// Anonymous memory and page cache of the cgroup and everything below
+    pub fn memory_stat(&self) -> Option<MemoryStat> {
+        let stat = fs::read_to_string(self.path.join("memory.stat")).ok()?;
+        let field = |name: &str| {
+            stat.lines().find_map(|line| {
+                let (key, value) = line.split_once(' ')?;
+                (key == name).then(|| value.trim().parse::<u64>().ok()).flatten()
+            })
+        };
+        // v2 counts the subtree; v1 does in the `total_` fields
+        let (anon, file) = match self.version {
+            Version::V2 => ("anon", "file"),
+            Version::V1 => ("total_rss", "total_cache"),
+        };
+        Some(MemoryStat {
+            anon: field(anon)?,
+            file: field(file)?,
+        })
+    }
+
+    /// Hook for `CommandExt::pre_exec` that moves the child into the cgroup
+    pub fn join_hook(&self) -> impl FnMut() -> io::Result<()> + Send + Sync + 'static {
+        let procs = self.procs.clone();
+        move || join_self(&procs)
+    }
+
+    /// SIGKILL every process in the cgroup
+    pub fn kill_all(&self) {
+        // cgroup.kill (Linux 5.14) kills atomically, racing no forks
+        if self.version == Version::V2 && fs::write(self.path.join("cgroup.kill"), "1").is_ok() {
+            return;
+        }
+        for _ in 0..20 {
+            let procs = fs::read_to_string(self.path.join("cgroup.procs")).unwrap_or_default();
+            let pids: Vec<i32> = procs.lines().filter_map(|l| l.trim().parse().ok()).collect();
+            if pids.is_empty() {
+                return;
+            }
+            for pid in pids {
+                // SAFETY: sending a signal has no memory-safety implications
+                unsafe {
+                    libc::kill(pid, libc::SIGKILL);
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+    }
+
+    /// Whether the cgroup has the control file `file`, e.g. `memory.reclaim`
+    pub fn has(&self, file: &str) -> bool {
+        self.path.join(file).exists()
+    }
+
+    /// Freeze or thaw every process in the cgroup (cgroup v2 only)
+    pub fn set_frozen(&self, frozen: bool) -> io::Result<()> {
+        if self.version != Version::V2 {
+            return Err(not_supported("freezing needs cgroup v2"));
+        }
+        fs::write(self.path.join("cgroup.freeze"), if frozen { "1" } else { "0" })
+    }
+
+    /// Throttle and reclaim hard once the cgroup is charged above `limit`
+    /// (cgroup v2 only)
+    pub fn set_memory_high(&self, limit: u64) -> io::Result<()> {
+        if self.version != Version::V2 {
+            return Err(not_supported("--memory-high needs cgroup v2"));
+        }
+        fs::write(self.path.join("memory.high"), limit.to_string()).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to set memory.high of {}: {}", self.path.display(), e),
+            )
+        })
+    }
+
+    /// Have the kernel reclaim what it can of the cgroup's memory, as
+    /// under pressure (cgroup v2, Linux 5.19)
+    pub fn reclaim(&self) -> io::Result<()> {
+        if self.version != Version::V2 {
+            return Err(not_supported("--reclaim-every needs cgroup v2"));
+        }
+        let Some(current) = self.memory_current() else {
+            return Ok(());
+        };
+        match fs::write(self.path.join("memory.reclaim"), current.to_string()) {
+            // Less than all of it could be reclaimed, as expected
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(not_supported("memory.reclaim needs Linux 5.19"))
+            }
+            result => result,
+        }
+    }
+
+    /// Memory currently charged to the cgroup (anon, page cache, kernel)
+    pub fn memory_current(&self) -> Option<u64> {
+        match self.version {
+            Version::V2 => self.read_u64("memory.current"),
+            Version::V1 => self.read_u64("memory.usage_in_bytes"),
+        }
+    }
+
+    /// High-water mark of charged memory, if the kernel tracks it
+    pub fn memory_peak(&self) -> Option<u64> {
+        match self.version {
+            // memory.peak needs Linux 5.19
+            Version::V2 => self.read_u64("memory.peak"),
+            Version::V1 => self.read_u64("memory.max_usage_in_bytes"),
+        }
+    }
+
+    fn read_u64(&self, file: &str) -> Option<u64> {
+        fs::read_to_string(self.path.join(file))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Fails while processes remain, which leaves the group for inspection
+        if self.owned {
+            let _ = fs::remove_dir(&self.path);
+        }
+    }
+}
+
+/// Move the calling process into the cgroup owning `procs`
+///
+/// Runs between fork and exec, so it only uses async-signal-safe calls.
+fn join_self(procs: &CStr) -> io::Result<()> {
+    // SAFETY: `procs` is a valid NUL-terminated path; the buffer is static
+    unsafe {
+        let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Writing 0 means "the writing process"
+        let rc = libc::write(fd, b"0".as_ptr().cast(), 1);
+        let err = io::Error::last_os_error();
+        libc::close(fd);
+        if rc != 1 {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+fn not_supported(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, msg.to_string())
+}
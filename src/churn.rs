@@ -0,0 +1,89 @@
+//! `--churn`: how fast the tree starts processes
+//!
+//! A build or a shell loop that forks thousands of short-lived processes
+//! spends its time in `fork` and `exec` rather than in the work itself.
+//! Starts are the processes new to a sample, and those that came and went
+//! in between when exit accounting is available; execs are processes whose
+//! name changed between two samples, so a fork that execs before it is
+//! first seen counts as a start only. Both are counted from the first
+//! sample on.
+
+use std::time::Duration;
+
+use crate::json::Value;
+
+/// Reported in the summary
+#[derive(Default)]
+pub struct ChurnStats {
+    pub started: u64,
+    pub execs: u64,
+    /// Highest rate over one interval, starts and execs per second
+    pub peak_rate: f64,
+    pub average_rate: f64,
+}
+
+impl ChurnStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("started".into(), self.started.into()),
+            ("execs".into(), self.execs.into()),
+            ("peak_rate".into(), self.peak_rate.into()),
+            ("average_rate".into(), self.average_rate.into()),
+        ])
+    }
+
+    /// `Churn: 412 processes started, 380 execs | peak 52.0 procs/s,
+    /// 1.3 procs/s on average`
+    pub fn format(&self) -> String {
+        format!(
+            "Churn: {} process{} started, {} exec{} | peak {:.1} procs/s, {:.1} procs/s on average",
+            self.started,
+            if self.started == 1 { "" } else { "es" },
+            self.execs,
+            if self.execs == 1 { "" } else { "s" },
+            self.peak_rate,
+            self.average_rate
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Churn {
+    /// Time and counts of the tracker at the first sample
+    first: Option<(Duration, u64, u64)>,
+    /// Time and starts plus execs at the previous sample
+    last: Option<(Duration, u64)>,
+    rate: f64,
+    stats: ChurnStats,
+}
+
+impl Churn {
+    /// Take the tracker's counts of processes seen and execs at a sample
+    pub fn update(&mut self, at: Duration, seen: u64, execs: u64) {
+        let (first_at, first_seen, first_execs) = *self.first.get_or_insert((at, seen, execs));
+        let total = seen + execs;
+        if let Some((last_at, last_total)) = self.last {
+            let secs = at.saturating_sub(last_at).as_secs_f64();
+            if secs > 0.0 {
+                self.rate = total.saturating_sub(last_total) as f64 / secs;
+                self.stats.peak_rate = self.stats.peak_rate.max(self.rate);
+            }
+        }
+        self.last = Some((at, total));
+        self.stats.started = seen - first_seen;
+        self.stats.execs = execs - first_execs;
+        let secs = at.saturating_sub(first_at).as_secs_f64();
+        if secs > 0.0 {
+            self.stats.average_rate = (self.stats.started + self.stats.execs) as f64 / secs;
+        }
+    }
+
+    /// Rate over the last interval, for the status line
+    pub fn status(&self) -> Vec<String> {
+        vec![format!("{:.1} procs/s", self.rate)]
+    }
+
+    pub fn finish(self) -> ChurnStats {
+        self.stats
+    }
+}
@@ -0,0 +1,177 @@
+//! Times as people read them: the clock of status lines and durations in
+//! summaries
+
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+/// What the clock at the start of status lines shows
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum Clock {
+    /// Time since the command started
+    #[default]
+    Elapsed,
+    /// Local time of day
+    Wall,
+    /// Both, elapsed first
+    Both,
+}
+
+impl Clock {
+    /// Bracketed clock for a moment `elapsed` into the run, at wall-clock
+    /// time `time` if known, e.g. `[01:02:03]` or `[02:03 14:05:09]`
+    pub fn stamp(self, elapsed: Duration, time: Option<SystemTime>) -> String {
+        match (self, time) {
+            (Self::Wall, Some(time)) => format!("[{}]", time_of_day(time)),
+            (Self::Both, Some(time)) => {
+                format!("[{} {}]", format_elapsed(elapsed), time_of_day(time))
+            }
+            _ => format!("[{}]", format_elapsed(elapsed)),
+        }
+    }
+}
+
+/// `MM:SS`, `HH:MM:SS` past an hour and `Nd HH:MM:SS` past a day
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let (days, hours, minutes, seconds) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {:02}:{:02}:{:02}", days, hours, minutes, seconds)
+    } else if hours > 0 {
+        format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
+/// Duration for summaries: `12.3s`, `5m 03s`, `1h 02m 03s` or `2d 03h 04m`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, minutes, seconds) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {:02}h {:02}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+/// Duration such as `90s`, `5m`, `1.5h` or `2d`; a bare number is seconds
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let scale = match unit {
+        "" | "s" => 1.0,
+        "ms" => 0.001,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(format!("unknown unit '{}', expected ms, s, m, h or d", unit)),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|value| Duration::try_from_secs_f64(value * scale).ok())
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| format!("expected a positive duration such as 30s or 5m, got '{}'", s))
+}
+
+/// Compact duration for labels: `90s`, `5m`, `2h`, `1d`
+pub fn format_span(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs == 0 {
+        format!("{}ms", duration.as_millis())
+    } else if secs.is_multiple_of(86400) {
+        format!("{}d", secs / 86400)
+    } else if secs.is_multiple_of(3600) {
+        format!("{}h", secs / 3600)
+    } else if secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// `HH:MM:SS` in the local time zone
+fn time_of_day(time: SystemTime) -> String {
+    let of_day = seconds_of_day(time);
+    format!("{:02}:{:02}:{:02}", of_day / 3600, of_day / 60 % 60, of_day % 60)
+}
+
+/// Seconds since midnight in the local time zone, or in UTC if it is unknown
+pub fn seconds_of_day(time: SystemTime) -> u32 {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as libc::time_t;
+    // SAFETY: tm is plain data, filled in by localtime_r on success
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return secs.rem_euclid(86400) as u32;
+    }
+    (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elapsed_grows_hours_and_days() {
+        assert_eq!(format_elapsed(Duration::ZERO), "00:00");
+        assert_eq!(format_elapsed(Duration::from_secs(59 * 60 + 59)), "59:59");
+        assert_eq!(format_elapsed(Duration::from_secs(3600)), "01:00:00");
+        assert_eq!(format_elapsed(Duration::from_secs(3600 + 2 * 60 + 3)), "01:02:03");
+        assert_eq!(format_elapsed(Duration::from_secs(86400 * 2 + 3 * 3600 + 4)), "2d 03:00:04");
+    }
+
+    #[test]
+    fn stamps() {
+        let elapsed = Duration::from_secs(3723);
+        assert_eq!(Clock::Elapsed.stamp(elapsed, Some(SystemTime::now())), "[01:02:03]");
+        // Without a time of day, the wall clock falls back to the elapsed time
+        assert_eq!(Clock::Wall.stamp(elapsed, None), "[01:02:03]");
+        let both = Clock::Both.stamp(Duration::from_secs(59), Some(SystemTime::now()));
+        assert!(both.starts_with("[00:59 ") && both.len() == "[00:59 12:00:00]".len(), "{}", both);
+    }
+
+    #[test]
+    fn durations_for_summaries() {
+        assert_eq!(format_duration(Duration::from_millis(12_340)), "12.3s");
+        assert_eq!(format_duration(Duration::from_secs(5 * 60 + 3)), "5m 03s");
+        assert_eq!(format_duration(Duration::from_secs(3600 + 2 * 60 + 3)), "1h 02m 03s");
+        assert_eq!(format_duration(Duration::from_secs(2 * 86400 + 3 * 3600 + 4 * 60)), "2d 03h 04m");
+    }
+
+    #[test]
+    fn spans_take_the_largest_whole_unit() {
+        assert_eq!(format_span(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_span(Duration::from_secs(90)), "90s");
+        assert_eq!(format_span(Duration::from_secs(300)), "5m");
+        assert_eq!(format_span(Duration::from_secs(7200)), "2h");
+        assert_eq!(format_span(Duration::from_secs(86400)), "1d");
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 30s "), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(2 * 86400)));
+    }
+
+    #[test]
+    fn refuses_bad_durations() {
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("-5s").is_err());
+        assert!(parse_duration("5w").unwrap_err().contains("unknown unit 'w'"));
+        assert!(parse_duration("fast").is_err());
+    }
+}
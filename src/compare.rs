@@ -0,0 +1,155 @@
+//! `memwatch compare`: run commands and compare their memory use
+
+use std::{
+    io,
+    sync::atomic::AtomicBool,
+    time::Duration,
+};
+
+use crate::{
+    bench::{
+        self,
+        Measurement,
+    },
+    size::format_bytes,
+};
+
+/// Rows of the aligned timeline at most
+const TIMELINE_ROWS: u32 = 10;
+
+#[derive(clap::Args, Debug)]
+pub struct CompareArgs {
+    /// Run the commands at the same time instead of one after another
+    #[arg(long)]
+    parallel: bool,
+
+    /// Update interval in milliseconds
+    #[arg(short, long, default_value_t = 1000)]
+    interval: u64,
+
+    /// Commands separated by `--`: `-- cmd-a args -- cmd-b args`
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+    commands: Vec<String>,
+}
+
+pub fn run(args: CompareArgs, terminated: &AtomicBool) -> io::Result<()> {
+    let commands: Vec<(String, Vec<String>)> = args
+        .commands
+        .split(|arg| arg == "--")
+        .filter(|command| !command.is_empty())
+        .zip('A'..='Z')
+        .map(|(command, label)| (label.to_string(), command.to_vec()))
+        .collect();
+    if commands.len() < 2 {
+        return Err(io::Error::other(
+            "compare needs at least two commands separated by `--`",
+        ));
+    }
+
+    let interval = Duration::from_millis(args.interval);
+    let measured = if args.parallel {
+        bench::measure(&commands, interval, terminated)?
+    } else {
+        let mut all = Vec::new();
+        for command in &commands {
+            match bench::measure(std::slice::from_ref(command), interval, terminated)? {
+                Some(measured) => all.extend(measured),
+                None => {
+                    all.clear();
+                    break;
+                }
+            }
+        }
+        (!all.is_empty()).then_some(all)
+    };
+    let Some(measured) = measured else {
+        eprintln!("Interrupted (Ctrl+C)");
+        return Ok(());
+    };
+
+    eprintln!();
+    eprintln!(
+        "{:<4} {:>12} {:>12} {:>10}  Command",
+        "", "Peak RSS", "Avg RSS", "Duration"
+    );
+    for ((label, _), m) in commands.iter().zip(&measured) {
+        eprintln!(
+            "{:<4} {:>12} {:>12} {:>9.1}s  {}{}",
+            label,
+            format_bytes(m.summary.peak_rss),
+            format_bytes(m.avg_rss()),
+            m.elapsed.as_secs_f64(),
+            m.summary.command_line(),
+            if m.exit.code == Some(0) {
+                String::new()
+            } else {
+                format!(" ({})", m.exit.describe())
+            }
+        );
+    }
+
+    eprintln!();
+    let header: String = commands
+        .iter()
+        .map(|(label, _)| format!("{:>12}", label))
+        .collect();
+    eprintln!("{:<10}{}", "Time", header);
+    for offset in timeline_offsets(&measured, interval) {
+        let cells: String = measured
+            .iter()
+            .map(|m| {
+                let cell = m.rss_at(offset).map(format_bytes);
+                format!("{:>12}", cell.as_deref().unwrap_or("-"))
+            })
+            .collect();
+        eprintln!("{:<10}{}", format!("{:.1}s", offset.as_secs_f64()), cells);
+    }
+
+    eprintln!();
+    let (base_label, _) = &commands[0];
+    let base = &measured[0];
+    for ((label, _), m) in commands.iter().zip(&measured).skip(1) {
+        eprintln!(
+            "{} used {} peak memory than {} ({} vs {})",
+            label,
+            relative(m.summary.peak_rss, base.summary.peak_rss),
+            base_label,
+            format_bytes(m.summary.peak_rss),
+            format_bytes(base.summary.peak_rss)
+        );
+        eprintln!(
+            "{} used {} average memory than {} ({} vs {})",
+            label,
+            relative(m.avg_rss(), base.avg_rss()),
+            base_label,
+            format_bytes(m.avg_rss()),
+            format_bytes(base.avg_rss())
+        );
+    }
+
+    Ok(())
+}
+
+/// Evenly spaced offsets covering the longest run, on sample boundaries
+fn timeline_offsets(measured: &[Measurement], interval: Duration) -> Vec<Duration> {
+    let longest = measured.iter().map(|m| m.elapsed).max().unwrap_or_default();
+    let samples = (longest.as_secs_f64() / interval.as_secs_f64()).ceil() as u32;
+    let step = interval * samples.div_ceil(TIMELINE_ROWS).max(1);
+    (0..)
+        .map(|i| step * i)
+        .take_while(|&offset| offset <= longest)
+        .collect()
+}
+
+/// `25.0% less` / `12.5% more` / `the same`
+fn relative(value: u64, base: u64) -> String {
+    if base == 0 || value == base {
+        return String::from("the same");
+    }
+    let change = (value as f64 - base as f64) / base as f64 * 100.0;
+    if change < 0.0 {
+        format!("{:.1}% less", -change)
+    } else {
+        format!("{:.1}% more", change)
+    }
+}
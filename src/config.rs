@@ -0,0 +1,287 @@
+//! `memwatch.toml`: settings committed to a project
+//!
+//! ```toml
+//! [budget.build]
+//! command = "cargo build*"
+//! peak_rss = "2G"
+//!
+//! [smtp]
+//! url = "smtp://mail.example.com:587"
+//! from = "memwatch@example.com"
+//! ```
+//!
+//! `[budget.NAME]` tables hold [budgets](crate::budget), `[smtp]` how to
+//! send [email](crate::email). Only the part of TOML these files need is
+//! read: tables, comments, and string or integer values.
+
+use std::{
+    env,
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use crate::{
+    budget::Budget,
+    email::Smtp,
+};
+
+/// Name of the file looked for from the current directory up
+pub const FILE_NAME: &str = "memwatch.toml";
+
+#[derive(Default)]
+pub struct Config {
+    pub budgets: Vec<Budget>,
+    pub smtp: Option<Smtp>,
+}
+
+/// Value of a key
+pub enum Scalar {
+    String(String),
+    Integer(u64),
+}
+
+impl Scalar {
+    /// The string, or an error naming `key` for an integer
+    pub fn into_string(self, key: &str) -> Result<String, String> {
+        match self {
+            Self::String(value) => Ok(value),
+            Self::Integer(_) => Err(format!("`{}` must be a string", key)),
+        }
+    }
+}
+
+/// `memwatch.toml` in the current directory or the nearest parent
+pub fn find() -> Option<PathBuf> {
+    let dir = env::current_dir().ok()?;
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+pub fn load(path: &Path) -> io::Result<Config> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))?;
+    parse(&text).map_err(|(line, e)| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}:{}: {}", path.display(), line, e),
+        )
+    })
+}
+
+/// Table the keys that follow belong to
+enum Table {
+    None,
+    Budget,
+    Smtp,
+}
+
+fn parse(text: &str) -> Result<Config, (usize, String)> {
+    let mut config = Config::default();
+    let mut table = Table::None;
+    // Line of the [smtp] header, for keys it lacks
+    let mut smtp_line = 0;
+    for (index, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |e: String| (index + 1, e);
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| error(String::from("expected `]` after the table name")))?
+                .trim();
+            if header == "smtp" {
+                if config.smtp.is_some() {
+                    return Err(error(String::from("[smtp] is defined twice")));
+                }
+                config.smtp = Some(Smtp::default());
+                table = Table::Smtp;
+                smtp_line = index + 1;
+                continue;
+            }
+            let name = header.strip_prefix("budget.").ok_or_else(|| {
+                error(format!(
+                    "unknown table `{}`, expected [budget.NAME] or [smtp]",
+                    header
+                ))
+            })?;
+            let name = key(name).map_err(error)?;
+            if config.budgets.iter().any(|budget| budget.name == name) {
+                return Err(error(format!("budget `{}` is defined twice", name)));
+            }
+            config.budgets.push(Budget::new(name));
+            table = Table::Budget;
+            continue;
+        }
+
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(String::from("expected `key = value`")))?;
+        let name = key(name).map_err(error)?;
+        let value = scalar(value.trim()).map_err(error)?;
+        match (&table, config.budgets.last_mut(), config.smtp.as_mut()) {
+            (Table::Budget, Some(budget), _) => budget.set(&name, value),
+            (Table::Smtp, _, Some(smtp)) => smtp.set(&name, value),
+            _ => Err(format!("`{}` must be inside a [budget.NAME] or [smtp] table", name)),
+        }
+        .map_err(error)?;
+    }
+
+    if let Some(smtp) = &config.smtp {
+        smtp.check().map_err(|e| (smtp_line, e))?;
+    }
+    Ok(config)
+}
+
+/// `line` up to a `#` outside of a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (at, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..at],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Bare key, or one in double quotes
+fn key(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    if text.starts_with('"') {
+        return match scalar(text)? {
+            Scalar::String(key) => Ok(key),
+            Scalar::Integer(_) => unreachable!(),
+        };
+    }
+    let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if text.is_empty() || !text.chars().all(bare) {
+        return Err(format!("invalid key `{}`", text));
+    }
+    Ok(text.to_string())
+}
+
+/// Double-quoted string or non-negative integer
+fn scalar(text: &str) -> Result<Scalar, String> {
+    let Some(quoted) = text.strip_prefix('"') else {
+        let digits = text.replace('_', "");
+        return digits
+            .parse()
+            .map(Scalar::Integer)
+            .map_err(|_| format!("invalid value `{}`, expected a string or an integer", text));
+    };
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if chars.as_str().trim().is_empty() => return Ok(Scalar::String(value)),
+            '"' => return Err(format!("unexpected `{}` after the string", chars.as_str().trim())),
+            '\\' => match chars.next() {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('t') => value.push('\t'),
+                Some('n') => value.push('\n'),
+                other => {
+                    return Err(format!(
+                        "unsupported escape `\\{}`",
+                        other.map(String::from).unwrap_or_default()
+                    ))
+                }
+            },
+            c => value.push(c),
+        }
+    }
+    Err(String::from("unterminated string"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assertions::Metric,
+        budget,
+    };
+
+    const MIB: f64 = 1024.0 * 1024.0;
+
+    #[test]
+    fn reads_budgets_and_smtp() {
+        let config = parse(
+            r#"
+            # Limits of the CI jobs
+            [budget.build]
+            command = "cargo build*"  # any profile
+            peak_rss = "2G"
+            duration = 600
+
+            [budget."all runs"]
+            peak_processes = 1_000
+
+            [smtp]
+            url = "smtp://mail.example.com:587"
+            from = "memwatch@example.com"
+            password = "a#b \"c\""
+            "#,
+        )
+        .unwrap();
+        assert!(config.smtp.is_some());
+
+        let limits: Vec<(Metric, f64, Option<String>)> =
+            budget::assertions(&config.budgets, "cargo build --release")
+                .into_iter()
+                .map(|assertion| (assertion.metric, assertion.limit, assertion.budget))
+                .collect();
+        assert_eq!(
+            limits,
+            vec![
+                (Metric::PeakRss, 2048.0 * MIB, Some("build".into())),
+                (Metric::Duration, 600_000.0, Some("build".into())),
+                (Metric::PeakProcesses, 1000.0, Some("all runs".into())),
+            ]
+        );
+        assert_eq!(budget::assertions(&config.budgets, "make").len(), 1);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let error = |text: &str| parse(text).err().unwrap();
+        assert_eq!(error("peak_rss = \"1G\"").0, 1);
+        assert!(error("peak_rss = \"1G\"").1.contains("must be inside"));
+        assert_eq!(error("\n[budget.a]\nrss = \"1G\"").0, 3);
+        assert!(error("[budget.a]\nrss = \"1G\"").1.contains("unknown key `rss`"));
+        assert!(error("[budget.a]\n[budget.a]").1.contains("defined twice"));
+        assert!(error("[other]").1.contains("unknown table `other`"));
+        assert!(error("[budget.a").1.contains("expected `]`"));
+        assert!(error("[budget.a]\ncommand = 1").1.contains("must be a string"));
+        assert!(error("[budget.a]\ncommand = \"x").1.contains("unterminated string"));
+        assert!(error("[budget.a]\ncommand = \"x\" y").1.contains("unexpected `y`"));
+        assert!(error("[budget.a]\npeak_rss").1.contains("expected `key = value`"));
+    }
+
+    #[test]
+    fn checks_smtp_when_the_table_ends() {
+        let error = parse("[smtp]\nfrom = \"a@example.com\"\n").err().unwrap();
+        assert_eq!(error, (1, "[smtp] needs a `url` such as smtp://mail.example.com:587".into()));
+        let both = "[smtp]\nurl = \"smtps://x\"\nfrom = \"a@x\"\npassword = \"p\"\npassword_env = \"P\"";
+        assert!(parse(both).err().unwrap().1.contains("exclude each other"));
+        assert!(parse("[smtp]\n[smtp]").is_err());
+    }
+
+    #[test]
+    fn strips_comments_outside_strings() {
+        assert_eq!(strip_comment(r#"a = "x # y" # z"#), r#"a = "x # y" "#);
+        assert_eq!(strip_comment(r#"a = "x \" # y""#), r#"a = "x \" # y""#);
+        assert_eq!(strip_comment("# all of it"), "");
+    }
+}
@@ -0,0 +1,363 @@
+//! Querying and steering a running memwatch
+//!
+//! With `--control-socket`, memwatch listens on a Unix socket for one
+//! request per connection, a line such as `GET current`, and answers with
+//! one JSON line before closing it; failures are `{"error": "..."}`. A
+//! request must arrive within half a second and fit in 1 KiB.
+//! `memwatch ctl <socket> <request>` sends a request and prints the answer.
+//!
+//! - `SAMPLE`: take a sample now, off the regular schedule, and answer it
+//! - `GET current`: the latest sample
+//! - `GET summary`: the summary so far, as `--summary-json` would have it
+//! - `SET interval <time>`: sample every `<time>` from now on, e.g. `100ms`,
+//!   from 10ms to 10m
+//! - `MARK <label>`: label this moment in the output and the recording
+//!
+//! With `--tui`, `-` and `+` halve and double the interval in the same way.
+//! `kill -USR1 <memwatch pid>` takes a sample too, without the socket.
+//! Such samples are printed as notices and go to recordings and exporters
+//! like any other.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        Read,
+        Write,
+    },
+    os::unix::{
+        fs::FileTypeExt,
+        net::{
+            UnixListener,
+            UnixStream,
+        },
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc::{
+            self,
+            Receiver,
+            Sender,
+        },
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use termion::event::Key;
+
+use crate::{
+    clock,
+    json::{
+        self,
+        Value,
+    },
+};
+
+/// Set by SIGUSR1
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_usr1(_: libc::c_int) {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// How long a client has to send its request once connected, and how
+/// long the request may be
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_REQUEST: usize = 1024;
+
+/// Bounds of the interval set with keys or `SET interval`
+const MIN_INTERVAL: Duration = Duration::from_millis(10);
+const MAX_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(clap::Args, Debug)]
+pub struct CtlArgs {
+    /// Socket of a memwatch started with --control-socket
+    #[arg(value_name = "SOCKET")]
+    socket: PathBuf,
+
+    /// SAMPLE, GET current, GET summary, SET interval TIME or MARK LABEL
+    #[arg(value_name = "REQUEST", required = true, num_args = 1.., trailing_var_arg = true)]
+    request: Vec<String>,
+}
+
+/// What a client asked for
+pub enum Request {
+    Sample,
+    Current,
+    Summary,
+    Interval(Duration),
+    Mark(String),
+}
+
+impl Request {
+    fn parse(line: &str) -> Result<Self, String> {
+        let (verb, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        match (verb.to_ascii_uppercase().as_str(), rest) {
+            ("SAMPLE", "") => Ok(Self::Sample),
+            ("GET", "current") => Ok(Self::Current),
+            ("GET", "summary") => Ok(Self::Summary),
+            ("GET", _) => Err(format!("cannot GET `{}`, expected current or summary", rest)),
+            ("SET", _) => match rest.split_once(' ') {
+                Some(("interval", time)) => match clock::parse_duration(time)? {
+                    interval if (MIN_INTERVAL..=MAX_INTERVAL).contains(&interval) => {
+                        Ok(Self::Interval(interval))
+                    }
+                    _ => Err(format!("interval `{}` is out of range, expected 10ms to 10m", time)),
+                },
+                _ => Err(format!("cannot SET `{}`, expected interval TIME", rest)),
+            },
+            ("MARK", "") => Err(String::from("MARK needs a label")),
+            ("MARK", label) => Ok(Self::Mark(label.to_string())),
+            _ => Err(format!(
+                "unknown request `{}`, expected SAMPLE, GET, SET or MARK",
+                line
+            )),
+        }
+    }
+}
+
+/// The request of a key pressed in `--tui`, if it is one: `-` halves the
+/// interval and `+` doubles it
+pub fn key_request(key: &Key, interval: Duration) -> Option<Request> {
+    let interval = match key {
+        Key::Char('-') => interval / 2,
+        Key::Char('+') => interval * 2,
+        _ => return None,
+    };
+    Some(Request::Interval(interval.clamp(MIN_INTERVAL, MAX_INTERVAL)))
+}
+
+/// The client to answer a request, if it came through the socket
+pub struct Reply(Option<UnixStream>);
+
+impl Reply {
+    pub fn send(self, value: &Value) {
+        if let Some(client) = self.0 {
+            send(client, value);
+        }
+    }
+
+    pub fn ok(self) {
+        self.send(&Value::Object(vec![("ok".into(), true.into())]));
+    }
+}
+
+/// Requests from SIGUSR1, keys and the control socket
+///
+/// Clients of the socket are accepted and read on a thread of their own,
+/// which passes on the requests they made, so a client that is slow to
+/// send its request never holds up sampling.
+pub struct Control {
+    socket: Option<(PathBuf, Receiver<(Request, Reply)>)>,
+    /// Requests made other than through the socket, e.g. with keys
+    pending: VecDeque<Request>,
+}
+
+impl Control {
+    /// Catch SIGUSR1 and, with `socket`, listen on it
+    pub fn new(socket: Option<&Path>) -> io::Result<Self> {
+        // SAFETY: the handler only stores to an atomic
+        unsafe {
+            libc::signal(libc::SIGUSR1, on_usr1 as *const () as libc::sighandler_t);
+        }
+        let socket = match socket {
+            Some(path) => {
+                // A socket left behind by a memwatch that was killed
+                if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket())
+                    && UnixStream::connect(path).is_err()
+                {
+                    fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path).map_err(|e| {
+                    io::Error::new(e.kind(), format!("cannot listen on {}: {}", path.display(), e))
+                })?;
+                let (sender, requests) = mpsc::channel();
+                thread::spawn(move || serve(listener, sender));
+                Some((path.to_path_buf(), requests))
+            }
+            None => None,
+        };
+        Ok(Self {
+            socket,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Queue a request that has no one to answer
+    pub fn push(&mut self, request: Request) {
+        self.pending.push_back(request);
+    }
+
+    /// The next request waiting, if any
+    pub fn next(&mut self) -> Option<(Request, Reply)> {
+        if REQUESTED.swap(false, Ordering::Relaxed) {
+            return Some((Request::Sample, Reply(None)));
+        }
+        if let Some(request) = self.pending.pop_front() {
+            return Some((request, Reply(None)));
+        }
+        let (_, requests) = self.socket.as_ref()?;
+        requests.try_recv().ok()
+    }
+}
+
+/// Body of the socket thread: read the request of each client and pass
+/// it on, answering malformed ones here, until the run is over
+fn serve(listener: UnixListener, requests: Sender<(Request, Reply)>) {
+    for client in listener.incoming() {
+        let client = match client {
+            Ok(client) => client,
+            Err(e) => {
+                crate::log::info!("control socket: {}", e);
+                continue;
+            }
+        };
+        let line = match read_request(&client) {
+            Ok(line) => line,
+            Err(e) => {
+                crate::log::info!("control socket: {}", e);
+                continue;
+            }
+        };
+        match Request::parse(&line) {
+            Ok(request) => {
+                if requests.send((request, Reply(Some(client)))).is_err() {
+                    return;
+                }
+            }
+            Err(e) => send(client, &error(&e)),
+        }
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        if let Some((path, _)) = &self.socket {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// First line of a client, e.g. `GET current`, sent within
+/// `REQUEST_TIMEOUT` and no longer than `MAX_REQUEST`
+fn read_request(mut client: &UnixStream) -> io::Result<String> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut request = Vec::new();
+    let mut chunk = [0; 256];
+    while !request.contains(&b'\n') {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no request in time"));
+        }
+        client.set_read_timeout(Some(left))?;
+        let read = client.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&chunk[..read]);
+        if request.len() > MAX_REQUEST {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("request longer than {} bytes", MAX_REQUEST),
+            ));
+        }
+    }
+    let line = request.split(|&b| b == b'\n').next().unwrap_or_default();
+    Ok(String::from_utf8_lossy(line).trim().to_string())
+}
+
+fn send(mut client: UnixStream, value: &Value) {
+    if let Err(e) = writeln!(client, "{}", value) {
+        crate::log::info!("control socket: {}", e);
+    }
+}
+
+fn error(message: &str) -> Value {
+    Value::Object(vec![("error".into(), message.into())])
+}
+
+/// `memwatch ctl`
+pub fn run(args: CtlArgs) -> io::Result<()> {
+    let mut stream = UnixStream::connect(&args.socket).map_err(|e| {
+        io::Error::new(e.kind(), format!("cannot connect to {}: {}", args.socket.display(), e))
+    })?;
+    writeln!(stream, "{}", args.request.join(" "))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} closed without an answer; the run may have ended", args.socket.display()),
+        ));
+    }
+    if let Some(e) = json::parse(line.trim())
+        .ok()
+        .and_then(|reply| reply.get("error").and_then(Value::as_str).map(String::from))
+    {
+        return Err(io::Error::other(e));
+    }
+    print!("{}", line);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_first_line_of_a_request() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(b"GET current\nignored").unwrap();
+        assert_eq!(read_request(&server).unwrap(), "GET current");
+    }
+
+    #[test]
+    fn gives_up_on_slow_and_long_requests() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(b"GET").unwrap();
+        let start = Instant::now();
+        assert!(read_request(&server).is_err());
+        assert!(start.elapsed() < REQUEST_TIMEOUT * 2);
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(&[b'x'; MAX_REQUEST + 1]).unwrap();
+        let e = read_request(&server).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn socket_requests_reach_the_sampling_loop() {
+        let path = std::env::temp_dir().join(format!("memwatch-ctl-{}.sock", std::process::id()));
+        let mut control = Control::new(Some(&path)).unwrap();
+        // A client that never sends holds up only itself
+        let _silent = UnixStream::connect(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        writeln!(client, "MARK build").unwrap();
+        let deadline = Instant::now() + REQUEST_TIMEOUT * 4;
+        let request = loop {
+            if let Some((request, _)) = control.next() {
+                break request;
+            }
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(matches!(request, Request::Mark(label) if label == "build"));
+        drop(control);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,183 @@
+//! `memwatch diff`: compare two finished runs
+//!
+//! Either side may instead be samples read the way `report --from` reads
+//! them, such as system-wide memory from `sar -r`. Those have no summary:
+//! only their peaks, duration and process counts are compared.
+
+use std::{
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+
+use crate::{
+    clock::format_duration,
+    import,
+    record::RunSummary,
+    recording::Recording,
+    size::format_bytes,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// Summary of the baseline run: a --summary-json file or a recording,
+    /// or samples as read by report --from
+    #[arg(value_name = "BEFORE")]
+    before: PathBuf,
+
+    /// Summary of the run to compare against it
+    #[arg(value_name = "AFTER")]
+    after: PathBuf,
+}
+
+/// A number compared between the runs
+struct Field {
+    name: &'static str,
+    before: f64,
+    after: f64,
+    format: fn(f64) -> String,
+}
+
+/// A side of the comparison
+struct Side {
+    summary: RunSummary,
+    /// Whether it was made from imported samples rather than a run
+    imported: bool,
+}
+
+impl Side {
+    fn read(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))?;
+        if text.trim_start().starts_with('{') {
+            return Ok(Self {
+                summary: Recording::parse(path, &text)?.into_summary(path)?,
+                imported: false,
+            });
+        }
+        let imported = import::parse(path, &text)?;
+        let samples = &imported.samples;
+        let summary = RunSummary {
+            command: String::from("imported samples"),
+            elapsed: samples.iter().map(|s| s.elapsed).max().unwrap_or_default()
+                - samples.iter().map(|s| s.elapsed).min().unwrap_or_default(),
+            peak_rss: samples.iter().map(|s| s.rss).max().unwrap_or(0),
+            peak_vsz: samples.iter().map(|s| s.vsz).max().unwrap_or(0),
+            peak_processes: imported.peak_processes.unwrap_or(0),
+            samples: samples.len() as u64,
+            ..RunSummary::default()
+        };
+        Ok(Self {
+            summary,
+            imported: true,
+        })
+    }
+}
+
+pub fn run(args: DiffArgs) -> io::Result<()> {
+    let (before, after) = (Side::read(&args.before)?, Side::read(&args.after)?);
+    let imported = before.imported || after.imported;
+    let (before, after) = (before.summary, after.summary);
+
+    println!("Before: {} ({})", before.command, args.before.display());
+    println!("After:  {} ({})", after.command, args.after.display());
+    println!();
+    println!(
+        "{:<28} {:>12} {:>12} {:>12} {:>9}",
+        "", "Before", "After", "Change", "%"
+    );
+    for field in fields(&before, &after, imported) {
+        let change = field.after - field.before;
+        let percent = if field.before == 0.0 {
+            String::from("-")
+        } else {
+            format!("{:+.1}%", change / field.before * 100.0)
+        };
+        let sign = if change < 0.0 { "-" } else { "+" };
+        println!(
+            "{:<28} {:>12} {:>12} {:>12} {:>9}",
+            field.name,
+            (field.format)(field.before),
+            (field.format)(field.after),
+            format!("{}{}", sign, (field.format)(change.abs())),
+            percent
+        );
+    }
+    if !imported && before.describe_exit() != after.describe_exit() {
+        println!();
+        println!("Before {}, after {}", before.describe_exit(), after.describe_exit());
+    }
+    if let (Some(before), Some(after)) = (&before.environment, &after.environment) {
+        let differences = before.differences(after);
+        if !differences.is_empty() {
+            println!();
+            println!("Environment differs: {}", differences.join(", "));
+        }
+    }
+    Ok(())
+}
+
+/// Numbers of both summaries; with `imported`, only those samples have
+fn fields(before: &RunSummary, after: &RunSummary, imported: bool) -> Vec<Field> {
+    let bytes = |value: f64| format_bytes(value as u64);
+    let count = |value: f64| format!("{}", value as u64);
+    let mut fields = vec![
+        Field {
+            name: "Peak RSS",
+            before: before.peak_rss as f64,
+            after: after.peak_rss as f64,
+            format: bytes,
+        },
+        Field {
+            name: "Peak RSS (kernel-reported)",
+            before: before.kernel_peak_rss as f64,
+            after: after.kernel_peak_rss as f64,
+            format: bytes,
+        },
+        Field {
+            name: "Peak VSZ",
+            before: before.peak_vsz as f64,
+            after: after.peak_vsz as f64,
+            format: bytes,
+        },
+        Field {
+            name: "Duration",
+            before: before.elapsed.as_secs_f64(),
+            after: after.elapsed.as_secs_f64(),
+            format: |secs| format_duration(Duration::from_secs_f64(secs)),
+        },
+        Field {
+            name: "Peak processes",
+            before: before.peak_processes as f64,
+            after: after.peak_processes as f64,
+            format: count,
+        },
+        Field {
+            name: "Processes seen",
+            before: before.processes_seen as f64,
+            after: after.processes_seen as f64,
+            format: count,
+        },
+    ];
+    if imported {
+        fields.retain(|field| match field.name {
+            "Peak RSS (kernel-reported)" | "Processes seen" => false,
+            // Samples such as those of sar do not count processes
+            "Peak processes" => field.before > 0.0 && field.after > 0.0,
+            _ => true,
+        });
+    }
+    if let (Some(before), Some(after)) = (before.cgroup_peak, after.cgroup_peak) {
+        fields.push(Field {
+            name: "Cgroup peak",
+            before: before as f64,
+            after: after as f64,
+            format: bytes,
+        });
+    }
+    fields
+}
@@ -0,0 +1,320 @@
+//! `memwatch doctor`: which figures memwatch can get here, and how to get
+//! the rest
+//!
+//! Most of what memwatch reads depends on who runs it and where: another
+//! user's `/proc` files need root or the right capabilities, exact
+//! per-process peaks need `CAP_NET_ADMIN`, a dedicated cgroup needs a
+//! delegated cgroup v2 subtree, and containers hide much of `/sys`. Every
+//! check tries the source the way the feature does, so what it reports
+//! working is what works, and a failure comes with the error it gave.
+
+use std::{
+    fs,
+    io,
+    path::Path,
+};
+
+use memwatch::{
+    record::Environment,
+    taskstats,
+};
+
+use crate::{
+    cgroup::Cgroup,
+    power::PowerMeter,
+    system_delta::SystemDelta,
+    thermal::Thermal,
+    working_set::WorkingSet,
+};
+
+/// Capabilities some feature needs, by bit in `CapEff`
+const CAPABILITIES: [(u32, &str); 4] = [
+    (2, "CAP_DAC_READ_SEARCH"),
+    (12, "CAP_NET_ADMIN"),
+    (19, "CAP_SYS_PTRACE"),
+    (21, "CAP_SYS_ADMIN"),
+];
+
+/// Bits of `PR_GET_SECUREBITS` that change what root may do
+const SECUREBITS: [(u32, &str); 4] = [
+    (0, "noroot"),
+    (2, "no_setuid_fixup"),
+    (4, "keep_caps"),
+    (6, "no_cap_ambient_raise"),
+];
+
+enum Outcome {
+    Works,
+    /// Works with less than all of it, for this reason
+    Limited(String),
+    /// Does not work, for this reason
+    Missing(String),
+}
+
+struct Check {
+    what: &'static str,
+    /// Flags and modes that rely on it
+    used_by: &'static str,
+    outcome: Outcome,
+    /// What to do about a limit or failure
+    remedy: &'static str,
+}
+
+pub fn run() -> io::Result<()> {
+    let environment = Environment::collect();
+    // SAFETY: geteuid has no preconditions
+    let uid = unsafe { libc::geteuid() };
+    let capabilities = effective_capabilities();
+
+    println!("User:         uid {}{}", uid, if uid == 0 { " (root)" } else { "" });
+    println!(
+        "Capabilities: {}",
+        match &capabilities {
+            Some(caps) if caps.is_empty() => "none of those memwatch uses".to_string(),
+            Some(caps) => caps.join(", "),
+            None => "unknown".to_string(),
+        }
+    );
+    println!("Securebits:   {}", securebits().unwrap_or_else(|| "unknown".into()));
+    println!("Container:    {}", container().unwrap_or("none detected"));
+    println!(
+        "Cgroups:      {}",
+        match &environment.cgroup {
+            Some(version) => format!("{} ({})", version, environment.cgroup_controllers.join(", ")),
+            None => "not mounted".to_string(),
+        }
+    );
+    println!("Kernel:       {}", environment.kernel.as_deref().unwrap_or("unknown"));
+    println!();
+
+    let checks = checks(uid == 0);
+    let width = checks.iter().map(|check| check.what.len()).max().unwrap_or(0);
+    for check in &checks {
+        let (mark, reason) = match &check.outcome {
+            Outcome::Works => ("ok", None),
+            Outcome::Limited(reason) => ("limited", Some(reason)),
+            Outcome::Missing(reason) => ("missing", Some(reason)),
+        };
+        println!("{:<7}  {:<width$}  {}", mark, check.what, check.used_by, width = width);
+        if let Some(reason) = reason {
+            println!("{:<7}  {:<width$}  {}", "", "", reason, width = width);
+            println!("{:<7}  {:<width$}  → {}", "", "", check.remedy, width = width);
+        }
+    }
+    let missing = checks
+        .iter()
+        .filter(|check| !matches!(check.outcome, Outcome::Works))
+        .count();
+    println!();
+    match missing {
+        0 => println!("Everything memwatch reads is available."),
+        n => println!("{} of {} sources are limited or missing; the rest works.", n, checks.len()),
+    }
+    Ok(())
+}
+
+/// Every check, with remedies for running as root when `root`
+fn checks(root: bool) -> Vec<Check> {
+    let cgroup = Cgroup::create();
+    let cgroup_file = |file: &str, reason: &str| match &cgroup {
+        Ok(cgroup) if cgroup.has(file) => Outcome::Works,
+        Ok(_) => Outcome::Missing(reason.to_string()),
+        Err(e) => Outcome::Missing(format!("no cgroup of its own: {}", e)),
+    };
+    vec![
+        Check {
+            what: "Other users' processes",
+            used_by: "attach, guard, --user-all, --top, --slice",
+            outcome: other_users(),
+            remedy: match root {
+                true => "under hidepid, add memwatch's group to the gid= of the /proc mount; \
+                         in a container, add CAP_SYS_PTRACE (docker run --cap-add SYS_PTRACE)",
+                false => "run as root or as the owner of the processes; under hidepid, \
+                          add memwatch's group to the gid= of the /proc mount; in a container, \
+                          add CAP_SYS_PTRACE (docker run --cap-add SYS_PTRACE)",
+            },
+        },
+        Check {
+            what: "Exact per-process peaks",
+            used_by: "peaks of short-lived children",
+            outcome: match taskstats::listen() {
+                Ok(_) => Outcome::Works,
+                Err(e) => Outcome::Limited(format!(
+                    "taskstats: {}; peaks fall back to sampled VmHWM",
+                    e
+                )),
+            },
+            remedy: "grant CAP_NET_ADMIN: sudo setcap cap_net_admin+ep $(command -v memwatch)",
+        },
+        Check {
+            what: "Cgroup of the command",
+            used_by: "--cgroup, cgroup peak",
+            outcome: match &cgroup {
+                Ok(_) => Outcome::Works,
+                Err(e) => Outcome::Missing(e.to_string()),
+            },
+            remedy: "run under a delegated cgroup v2 subtree, e.g. \
+                     systemd-run --user --scope -p Delegate=yes memwatch ...",
+        },
+        Check {
+            what: "Cgroup freezer",
+            used_by: "--freeze-on-limit, --pause-below-floor",
+            outcome: match cgroup_file("cgroup.freeze", "") {
+                Outcome::Works => Outcome::Works,
+                _ => Outcome::Limited(
+                    "no cgroup v2 freezer; the tree is stopped with SIGSTOP, which it can see".into(),
+                ),
+            },
+            remedy: "use cgroup v2 and --cgroup",
+        },
+        Check {
+            what: "Memory throttling",
+            used_by: "--memory-high",
+            outcome: cgroup_file("memory.high", "memory.high needs cgroup v2"),
+            remedy: "use cgroup v2 and --cgroup",
+        },
+        Check {
+            what: "Proactive reclaim",
+            used_by: "--reclaim-every",
+            outcome: cgroup_file("memory.reclaim", "memory.reclaim needs cgroup v2 and Linux 5.19"),
+            remedy: "use cgroup v2 on Linux 5.19 or later, and --cgroup",
+        },
+        Check {
+            what: "PSS",
+            used_by: "--top, diagnostic bundles",
+            outcome: match fs::read_to_string("/proc/self/smaps_rollup") {
+                Ok(_) => Outcome::Works,
+                Err(e) => Outcome::Missing(format!("/proc/self/smaps_rollup: {}", e)),
+            },
+            remedy: "smaps_rollup needs Linux 4.14",
+        },
+        Check {
+            what: "Available memory",
+            used_by: "--system-delta, --system-floor",
+            outcome: match SystemDelta::start() {
+                Ok(_) => Outcome::Works,
+                Err(e) => Outcome::Missing(e.to_string()),
+            },
+            remedy: "MemAvailable needs Linux 3.14",
+        },
+        Check {
+            what: "Idle page tracking",
+            used_by: "--working-set",
+            outcome: match WorkingSet::open() {
+                Ok(_) => Outcome::Works,
+                Err(e) => Outcome::Missing(e.to_string()),
+            },
+            remedy: "run as root on a kernel built with CONFIG_IDLE_PAGE_TRACKING",
+        },
+        Check {
+            what: "Energy counters",
+            used_by: "--power",
+            outcome: match PowerMeter::start() {
+                Ok(_) => Outcome::Works,
+                Err(e) => Outcome::Missing(e.to_string()),
+            },
+            remedy: "run as root on bare metal with an Intel or AMD processor",
+        },
+        Check {
+            what: "Temperatures",
+            used_by: "bench --cooldown auto",
+            outcome: match Thermal::open() {
+                Ok(_) => Outcome::Works,
+                Err(e) => Outcome::Missing(e.to_string()),
+            },
+            remedy: "use --cooldown with a fixed time; virtual machines and containers \
+                     rarely expose thermal zones",
+        },
+        Check {
+            what: "Thread stacks",
+            used_by: "--threads-of, t in --tui",
+            outcome: match fs::read_to_string("/proc/1/task/1/syscall") {
+                Ok(_) => Outcome::Works,
+                Err(e) => Outcome::Limited(format!(
+                    "the syscall file of other users' processes is unreadable ({}); \
+                     stacks are left blank for them",
+                    e
+                )),
+            },
+            remedy: match root {
+                true => "allow it in /proc/sys/kernel/yama/ptrace_scope; in a container, \
+                         docker run --cap-add SYS_PTRACE",
+                false => "run as root or with CAP_SYS_PTRACE, and allow it in \
+                          /proc/sys/kernel/yama/ptrace_scope; in a container, \
+                          docker run --cap-add SYS_PTRACE",
+            },
+        },
+    ]
+}
+
+/// Whether another user's `/proc` files can be read, tried on those of
+/// init, which are root's
+fn other_users() -> Outcome {
+    let hidepid = fs::read_to_string("/proc/self/mountinfo")
+        .ok()
+        .and_then(|mounts| {
+            mounts
+                .lines()
+                .find(|line| line.split_whitespace().nth(4) == Some("/proc"))
+                .map(|line| line.contains("hidepid=") && !line.contains("hidepid=0"))
+        })
+        .unwrap_or(false);
+    let status = fs::read_to_string("/proc/1/status");
+    let rollup = fs::read_to_string("/proc/1/smaps_rollup");
+    match (status, rollup) {
+        (Ok(_), Ok(_)) => Outcome::Works,
+        (Ok(_), Err(e)) => Outcome::Limited(format!(
+            "memory maps of other users' processes are unreadable ({}); RSS is still known",
+            e
+        )),
+        (Err(e), _) if hidepid => Outcome::Missing(format!(
+            "/proc is mounted with hidepid, other users' processes are hidden ({})",
+            e
+        )),
+        (Err(e), _) => Outcome::Missing(format!("/proc/1/status: {}", e)),
+    }
+}
+
+/// Names of the capabilities of `CAPABILITIES` that memwatch has
+fn effective_capabilities() -> Option<Vec<&'static str>> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let mask = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+    let mask = u64::from_str_radix(mask.trim(), 16).ok()?;
+    Some(
+        CAPABILITIES
+            .iter()
+            .filter(|(bit, _)| mask & (1 << bit) != 0)
+            .map(|&(_, name)| name)
+            .collect(),
+    )
+}
+
+/// Set securebits by name, `none` without any
+fn securebits() -> Option<String> {
+    // SAFETY: PR_GET_SECUREBITS takes no arguments and only returns a value
+    let bits = unsafe { libc::prctl(libc::PR_GET_SECUREBITS) };
+    if bits < 0 {
+        return None;
+    }
+    let names: Vec<&str> = SECUREBITS
+        .iter()
+        .filter(|(bit, _)| bits & (1 << bit) != 0)
+        .map(|&(_, name)| name)
+        .collect();
+    Some(if names.is_empty() { "none".into() } else { names.join(", ") })
+}
+
+/// The kind of container memwatch runs in, if it can tell
+fn container() -> Option<&'static str> {
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker");
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman");
+    }
+    let cgroup = fs::read_to_string("/proc/1/cgroup").unwrap_or_default();
+    [("kubepods", "kubernetes"), ("docker", "docker"), ("lxc", "lxc")]
+        .into_iter()
+        .find(|(marker, _)| cgroup.contains(marker))
+        .map(|(_, kind)| kind)
+}
@@ -0,0 +1,299 @@
+//! Email about `--max-rss` breaches and finished runs
+//!
+//! Messages go through the SMTP server of the `[smtp]` table in
+//! `memwatch.toml`, delivered by `curl` like webhooks:
+//!
+//! ```toml
+//! [smtp]
+//! url = "smtp://mail.example.com:587"
+//! from = "memwatch@example.com"
+//! user = "memwatch"
+//! password_env = "SMTP_PASSWORD"
+//! ```
+
+use std::{
+    env,
+    io::Write,
+    process::{
+        Command,
+        Stdio,
+    },
+    sync::Arc,
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use crate::{
+    clock::format_duration,
+    config::Scalar,
+    http,
+    record::{
+        self,
+        RunSummary,
+    },
+    report,
+    size::format_bytes,
+    summary::Summary,
+};
+
+/// How to reach the SMTP server
+#[derive(Default)]
+pub struct Smtp {
+    /// `smtp://host:587`, upgraded with STARTTLS where the server offers
+    /// it, or `smtps://host:465`
+    url: String,
+    from: String,
+    user: Option<String>,
+    password: Option<String>,
+    /// Environment variable holding the password, which keeps it out of
+    /// the file
+    password_env: Option<String>,
+}
+
+impl Smtp {
+    /// Key of the `[smtp]` table
+    pub fn set(&mut self, key: &str, value: Scalar) -> Result<(), String> {
+        let value = value.into_string(key)?;
+        match key {
+            "url" => self.url = value,
+            "from" => self.from = value,
+            "user" => self.user = Some(value),
+            "password" => self.password = Some(value),
+            "password_env" => self.password_env = Some(value),
+            _ => {
+                return Err(format!(
+                    "unknown key `{}`, expected url, from, user, password or password_env",
+                    key
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the table has all it needs, once read
+    pub fn check(&self) -> Result<(), String> {
+        if !self.url.starts_with("smtp://") && !self.url.starts_with("smtps://") {
+            return Err(String::from(
+                "[smtp] needs a `url` such as smtp://mail.example.com:587",
+            ));
+        }
+        if self.from.is_empty() {
+            return Err(String::from("[smtp] needs a `from` address"));
+        }
+        if self.password.is_some() && self.password_env.is_some() {
+            return Err(String::from("`password` and `password_env` exclude each other"));
+        }
+        Ok(())
+    }
+
+    fn password(&self) -> String {
+        match (&self.password, &self.password_env) {
+            (Some(password), _) => password.clone(),
+            (None, Some(name)) => env::var(name).unwrap_or_default(),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// Sends messages to the `--email` addresses
+///
+/// Like [`Webhook`](crate::notify::Webhook), each message is sent on a
+/// background thread; call [`Mailer::finish`] before exit to deliver
+/// pending ones.
+pub struct Mailer {
+    smtp: Arc<Smtp>,
+    to: Vec<String>,
+    pending: Vec<JoinHandle<()>>,
+}
+
+impl Mailer {
+    pub fn new(smtp: Smtp, to: Vec<String>) -> Self {
+        Self {
+            smtp: Arc::new(smtp),
+            to,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Tell that RSS went over `--max-rss`
+    pub fn breach(&mut self, summary: &Summary, elapsed: Duration, limit: u64, rss: u64) {
+        let subject = format!(
+            "memwatch: `{}` on {} exceeded {} RSS",
+            summary.command_line(),
+            summary.host,
+            format_bytes(limit)
+        );
+        let body = format!(
+            "RSS {} exceeded the limit of {} after {}.\n\n\
+             Command: {}\nHost: {}\nRun: {}\n",
+            format_bytes(rss),
+            format_bytes(limit),
+            format_duration(elapsed),
+            summary.command_line(),
+            summary.host,
+            summary.run_id
+        );
+        self.send(subject, body);
+    }
+
+    /// Tell that the command finished, with the figures of the report
+    pub fn exit(&mut self, summary: &RunSummary) {
+        let subject = format!(
+            "memwatch: `{}` on {} {} after {}",
+            summary.command,
+            summary.host,
+            summary.describe_exit(),
+            format_duration(summary.elapsed)
+        );
+        let rows = report::summary_rows(summary);
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        let mut body = format!(
+            "Command: {}\nHost: {}\nRun: {}\n\n",
+            summary.command, summary.host, summary.run_id
+        );
+        for (name, value) in rows {
+            body.push_str(&format!("{:width$}  {}\n", name, value, width = width));
+        }
+        self.send(subject, body);
+    }
+
+    /// Wait for all messages in flight to be sent
+    pub fn finish(self) {
+        for handle in self.pending {
+            let _ = handle.join();
+        }
+    }
+
+    fn send(&mut self, subject: String, body: String) {
+        let smtp = Arc::clone(&self.smtp);
+        let to = self.to.clone();
+        self.pending.push(thread::spawn(move || {
+            let message = message(&smtp.from, &to, &subject, &body);
+            if let Err(e) = send(&smtp, &to, &message) {
+                eprintln!("\rmemwatch: email to {} failed: {}", to.join(", "), e);
+            }
+        }));
+    }
+}
+
+/// Hand `message` to curl; its error message on failure
+fn send(smtp: &Smtp, to: &[String], message: &str) -> Result<(), String> {
+    let mut command = Command::new("curl");
+    command
+        .args(["-sS", "-m", "30", "--url", &smtp.url])
+        .args(["--mail-from", &smtp.from]);
+    for address in to {
+        command.args(["--mail-rcpt", address]);
+    }
+    // Held until curl has started with its own copy
+    let credentials = match &smtp.user {
+        // Credentials never go out unencrypted
+        Some(user) => {
+            // A config keeps the password off curl's command line
+            let (user, password) = (http::quote(user), http::quote(&smtp.password()));
+            let config = http::config(&format!("user = \"{}:{}\"\n", user, password))
+                .map_err(|e| format!("failed to pass the SMTP credentials: {}", e))?;
+            command.arg("--ssl-reqd");
+            http::pass_config(&mut command, &config);
+            Some(config)
+        }
+        None => {
+            command.arg("--ssl");
+            None
+        }
+    };
+    let spawned = command
+        .args(["--upload-file", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+    drop(credentials);
+    let mut child = spawned.map_err(|e| format!("failed to run curl: {}", e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(message.as_bytes())
+            .map_err(|e| format!("failed to write message: {}", e))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("failed to wait for curl: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Plain-text message with its headers and CRLF line ends
+fn message(from: &str, to: &[String], subject: &str, body: &str) -> String {
+    let mut message = String::new();
+    for (name, value) in [
+        ("From", from.to_string()),
+        ("To", to.join(", ")),
+        ("Subject", encode_header(subject)),
+        ("Date", mail_date(SystemTime::now())),
+        ("MIME-Version", String::from("1.0")),
+        ("Content-Type", String::from("text/plain; charset=utf-8")),
+        ("Content-Transfer-Encoding", String::from("8bit")),
+    ] {
+        // A line break in a value would start a header of its own
+        let value: String = value.chars().filter(|c| !c.is_control()).collect();
+        message.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    message.push_str("\r\n");
+    for line in body.lines() {
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message
+}
+
+/// `value`, as an RFC 2047 encoded word when it is not plain ASCII
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    format!("=?UTF-8?B?{}?=", base64(value.as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().fold(0u32, |n, &b| n << 8 | b as u32) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            out.push(match i <= chunk.len() {
+                true => ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char,
+                false => '=',
+            });
+        }
+    }
+    out
+}
+
+/// RFC 5322 date in UTC, e.g. `1 Mar 2025 09:17:52 +0000`
+fn mail_date(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    // 2025-03-01T09:17:52.730Z
+    let stamp = record::format_time(time);
+    let month: usize = stamp[5..7].parse().unwrap_or(1);
+    let day: u32 = stamp[8..10].parse().unwrap_or(1);
+    format!(
+        "{} {} {} {} +0000",
+        day,
+        MONTHS[month - 1],
+        &stamp[..4],
+        &stamp[11..19]
+    )
+}
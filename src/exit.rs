@@ -0,0 +1,100 @@
+//! Decoding of the command's exit status
+
+use std::{
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+};
+
+use crate::json::Value;
+
+/// How the monitored command ended
+#[derive(Clone, Copy)]
+pub struct ExitInfo {
+    /// Exit code when the command exited normally
+    pub code: Option<i32>,
+    /// Signal number when the command was killed by a signal
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+}
+
+impl ExitInfo {
+    pub fn from_status(status: ExitStatus) -> Self {
+        Self {
+            code: status.code(),
+            signal: status.signal(),
+            core_dumped: status.core_dumped(),
+        }
+    }
+
+    /// Human-readable outcome, e.g. `exited with status 1` or
+    /// `killed by SIGSEGV (core dumped)`
+    pub fn describe(&self) -> String {
+        match (self.code, self.signal) {
+            (Some(code), _) => format!("exited with status {}", code),
+            (None, Some(signal)) => {
+                let mut text = format!("killed by {}", signal_name(signal));
+                if self.core_dumped {
+                    text.push_str(" (core dumped)");
+                }
+                text
+            }
+            (None, None) => String::from("terminated"),
+        }
+    }
+
+    /// Fields describing the exit in machine-readable reports
+    pub fn json_fields(&self) -> Vec<(String, Value)> {
+        vec![
+            ("exit_code".into(), self.code.into()),
+            ("signal".into(), self.signal.map(signal_name).into()),
+            ("core_dumped".into(), self.core_dumped.into()),
+        ]
+    }
+}
+
+/// Symbolic name of a signal number, e.g. `SIGKILL`
+pub fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGSTKFLT => "SIGSTKFLT",
+        libc::SIGCHLD => "SIGCHLD",
+        libc::SIGCONT => "SIGCONT",
+        libc::SIGSTOP => "SIGSTOP",
+        libc::SIGTSTP => "SIGTSTP",
+        libc::SIGTTIN => "SIGTTIN",
+        libc::SIGTTOU => "SIGTTOU",
+        libc::SIGURG => "SIGURG",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGVTALRM => "SIGVTALRM",
+        libc::SIGPROF => "SIGPROF",
+        libc::SIGWINCH => "SIGWINCH",
+        libc::SIGIO => "SIGIO",
+        libc::SIGPWR => "SIGPWR",
+        libc::SIGSYS => "SIGSYS",
+        _ => {
+            let rtmin = libc::SIGRTMIN();
+            let rtmax = libc::SIGRTMAX();
+            return if (rtmin..=rtmax).contains(&signal) {
+                format!("SIGRTMIN+{}", signal - rtmin)
+            } else {
+                format!("signal {}", signal)
+            };
+        }
+    };
+    name.to_string()
+}
@@ -0,0 +1,197 @@
+//! User-supplied exporters, with `--exporter-cmd`
+//!
+//! The command is run with `sh -c` and gets one JSON object per line on
+//! its stdin: every sample, threshold crossings and the final summary,
+//! each tagged with `"type"`. A slow exporter loses samples rather than
+//! holding up sampling, and one that dies only stops the export.
+
+use std::{
+    io::{
+        BufWriter,
+        Write,
+    },
+    os::unix::process::CommandExt,
+    process::{
+        Child,
+        ChildStdin,
+        Command,
+        Stdio,
+    },
+    sync::mpsc::{
+        self,
+        SyncSender,
+        TrySendError,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    json::Value,
+    observer::{
+        Anomaly,
+        Crossing,
+        IntervalChange,
+        Mark,
+        Observer,
+    },
+    record::{
+        RunSummary,
+        Sample,
+    },
+    recording::tagged,
+};
+
+/// Lines buffered for an exporter that is not keeping up
+const BACKLOG: usize = 256;
+
+/// How long an exporter may take to finish after the summary was sent
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct CommandExporter {
+    command: String,
+    child: Option<Child>,
+    lines: Option<SyncSender<String>>,
+    writer: Option<JoinHandle<()>>,
+    dropped: u64,
+}
+
+impl CommandExporter {
+    /// Start the exporter; failing to spawn it is reported once and the
+    /// run goes on without it
+    pub fn spawn(command: &str) -> Self {
+        let mut exporter = Self {
+            command: command.to_string(),
+            child: None,
+            lines: None,
+            writer: None,
+            dropped: 0,
+        };
+        let spawned = Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            // Ctrl+C is for the monitored command; the exporter still has
+            // the summary to receive
+            .process_group(0)
+            .spawn();
+        match spawned {
+            Ok(mut child) => {
+                if let Some(stdin) = child.stdin.take() {
+                    let (tx, rx) = mpsc::sync_channel(BACKLOG);
+                    let command = exporter.command.clone();
+                    exporter.writer = Some(thread::spawn(move || write_lines(stdin, rx, &command)));
+                    exporter.lines = Some(tx);
+                }
+                exporter.child = Some(child);
+            }
+            Err(e) => eprintln!("\rmemwatch: failed to start exporter `{}`: {}", command, e),
+        }
+        exporter
+    }
+
+    fn send(&mut self, kind: &str, record: Value) {
+        let Some(lines) = &self.lines else {
+            return;
+        };
+        match lines.try_send(tagged(kind, record)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                crate::log::debug!(
+                    "exporter `{}` is behind, dropped a {} record",
+                    self.command,
+                    kind
+                );
+            }
+            // The writer gave up on a dead exporter
+            Err(TrySendError::Disconnected(_)) => self.lines = None,
+        }
+    }
+}
+
+impl Observer for CommandExporter {
+    fn on_sample(&mut self, sample: &Sample) {
+        self.send("sample", sample.to_json());
+    }
+
+    fn on_threshold(&mut self, crossing: &Crossing) {
+        self.send("threshold", crossing.to_json());
+    }
+
+    fn on_anomaly(&mut self, anomaly: &Anomaly) {
+        self.send("anomaly", anomaly.to_json());
+    }
+
+    fn on_mark(&mut self, mark: &Mark) {
+        self.send("mark", mark.to_json());
+    }
+
+    fn on_interval(&mut self, change: &IntervalChange) {
+        self.send("interval", change.to_json());
+    }
+
+    /// Send the summary, close the exporter's stdin and give it a moment
+    /// to finish
+    fn on_exit(&mut self, summary: &RunSummary) {
+        if let Some(lines) = self.lines.take() {
+            let _ = lines.send(tagged("summary", summary.to_json()));
+        }
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        if self.dropped > 0 {
+            eprintln!(
+                "memwatch: exporter `{}` fell behind; {} records were not sent",
+                self.command, self.dropped
+            );
+        }
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) if !status.success() => {
+                    eprintln!("memwatch: exporter `{}` {}", self.command, status);
+                    return;
+                }
+                Ok(Some(_)) | Err(_) => return,
+                Ok(None) if Instant::now() >= deadline => {
+                    eprintln!(
+                        "memwatch: exporter `{}` did not finish within {}s, killing it",
+                        self.command,
+                        EXIT_TIMEOUT.as_secs()
+                    );
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return;
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+    }
+}
+
+/// Feed queued lines to the exporter until it hangs up or the run ends
+fn write_lines(stdin: ChildStdin, lines: mpsc::Receiver<String>, command: &str) {
+    let mut stdin = BufWriter::new(stdin);
+    for line in lines {
+        let written = writeln!(stdin, "{}", line).and_then(|()| stdin.flush());
+        if let Err(e) = written {
+            // SIGPIPE is ignored, so a dead exporter shows up as EPIPE here
+            eprintln!(
+                "\rmemwatch: exporter `{}` stopped reading ({}); no longer exporting",
+                command, e
+            );
+            return;
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! `--assert` expressions over the figures of a finished run
+//!
+//! Comparisons of a metric with a limit, `peak_rss < 2G`, combined with
+//! `&&`, `||`, `!` and parentheses. Limits are written in the metric's
+//! own terms, see [`Metric::parse`].
+
+use std::fmt;
+
+use crate::assertions::Metric;
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Op {
+    /// Longer operators first, so `<=` is not read as `<`
+    const ALL: [(&'static str, Self); 6] = [
+        ("<=", Self::LessEqual),
+        (">=", Self::GreaterEqual),
+        ("==", Self::Equal),
+        ("!=", Self::NotEqual),
+        ("<", Self::Less),
+        (">", Self::Greater),
+    ];
+
+    fn apply(self, left: f64, right: f64) -> bool {
+        match self {
+            Self::Less => left < right,
+            Self::LessEqual => left <= right,
+            Self::Greater => left > right,
+            Self::GreaterEqual => left >= right,
+            Self::Equal => left == right,
+            Self::NotEqual => left != right,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Compare {
+        metric: Metric,
+        op: Op,
+        limit: f64,
+    },
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, measure: &dyn Fn(Metric) -> f64) -> bool {
+        match self {
+            Self::Compare { metric, op, limit } => op.apply(measure(*metric), *limit),
+            Self::Not(node) => !node.eval(measure),
+            Self::And(left, right) => left.eval(measure) && right.eval(measure),
+            Self::Or(left, right) => left.eval(measure) || right.eval(measure),
+        }
+    }
+
+    fn metrics(&self, out: &mut Vec<Metric>) {
+        match self {
+            Self::Compare { metric, .. } => {
+                if !out.contains(metric) {
+                    out.push(*metric);
+                }
+            }
+            Self::Not(node) => node.metrics(out),
+            Self::And(left, right) | Self::Or(left, right) => {
+                left.metrics(out);
+                right.metrics(out);
+            }
+        }
+    }
+}
+
+/// A parsed `--assert` expression
+#[derive(Clone, Debug)]
+pub struct Expr {
+    /// As written, for reports
+    source: String,
+    node: Node,
+}
+
+impl Expr {
+    /// Whether the expression holds, given how to measure each metric
+    pub fn eval(&self, measure: &dyn Fn(Metric) -> f64) -> bool {
+        self.node.eval(measure)
+    }
+
+    /// Metrics the expression compares, in the order they appear
+    pub fn metrics(&self) -> Vec<Metric> {
+        let mut metrics = Vec::new();
+        self.node.metrics(&mut metrics);
+        metrics
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser { rest: text, depth: 0 };
+    let node = parser.or()?;
+    parser.skip_space();
+    if !parser.rest.is_empty() {
+        return Err(format!("unexpected `{}` in `{}`", parser.rest, text));
+    }
+    Ok(Expr {
+        source: text.trim().to_string(),
+        node,
+    })
+}
+
+/// Parentheses and `!` nested deeper than this are refused rather than
+/// parsed by recursing until the stack overflows
+const MAX_DEPTH: usize = 64;
+
+/// Recursive descent over what is left of the text
+struct Parser<'a> {
+    rest: &'a str,
+    /// Parentheses and `!` the parser is inside of
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    /// Consume `token` if the text continues with it
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.eat("||") {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        while self.eat("&&") {
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("nested deeper than {} parentheses and `!`", MAX_DEPTH));
+        }
+        if self.eat("(") {
+            self.depth += 1;
+            let node = self.or()?;
+            self.depth -= 1;
+            if !self.eat(")") {
+                return Err(String::from("missing `)`"));
+            }
+            return Ok(node);
+        }
+        // `!` of a comparison, not the start of `!=`
+        if !self.rest.trim_start().starts_with("!=") && self.eat("!") {
+            self.depth += 1;
+            let node = self.unary()?;
+            self.depth -= 1;
+            return Ok(Node::Not(Box::new(node)));
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> Result<Node, String> {
+        let name = self.word(|c| c.is_ascii_alphanumeric() || c == '_');
+        if name.is_empty() {
+            return Err(match self.rest.is_empty() {
+                true => String::from("expected a comparison such as `peak_rss < 2G`"),
+                false => format!("expected a metric at `{}`", self.rest),
+            });
+        }
+        let metric = Metric::from_name(name)
+            .ok_or_else(|| format!("unknown metric `{}`, expected one of {}", name, Metric::names()))?;
+
+        self.skip_space();
+        let (token, op) = Op::ALL
+            .into_iter()
+            .find(|(token, _)| self.rest.starts_with(token))
+            .ok_or_else(|| format!("expected <, <=, >, >=, == or != after `{}`", name))?;
+        self.rest = &self.rest[token.len()..];
+
+        let limit = self.word(|c| !c.is_whitespace() && !"()&|!<>=".contains(c));
+        if limit.is_empty() {
+            return Err(format!("expected a value after `{} {}`", name, token));
+        }
+        Ok(Node::Compare {
+            metric,
+            op,
+            limit: metric.parse(limit)?,
+        })
+    }
+
+    /// Longest run of characters that satisfy `accept`, after any spaces
+    fn word(&mut self, accept: impl Fn(char) -> bool) -> &'a str {
+        self.skip_space();
+        let end = self.rest.find(|c| !accept(c)).unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        word
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: f64 = 1024.0 * 1024.0;
+
+    /// A run that peaked at 800M of RSS over 90 seconds
+    fn measure(metric: Metric) -> f64 {
+        match metric {
+            Metric::PeakRss => 800.0 * MIB,
+            Metric::Duration => 90_000.0,
+            Metric::PeakProcesses => 3.0,
+            _ => 0.0,
+        }
+    }
+
+    fn holds(text: &str) -> bool {
+        parse(text).unwrap().eval(&measure)
+    }
+
+    #[test]
+    fn compares_in_the_terms_of_the_metric() {
+        assert!(holds("peak_rss < 1G"));
+        assert!(!holds("peak_rss > 1G"));
+        assert!(holds("peak_rss <= 800M"));
+        assert!(holds("peak_rss>=800M"));
+        assert!(holds("duration == 90s"));
+        assert!(holds("peak_processes != 4"));
+    }
+
+    #[test]
+    fn combines_with_precedence() {
+        // && binds tighter than ||
+        assert!(holds("peak_rss > 1G && duration > 1m || peak_processes == 3"));
+        assert!(!holds("peak_rss > 1G && (duration > 1m || peak_processes == 3)"));
+        assert!(holds("!(peak_rss > 1G)"));
+        assert!(holds("!!(peak_rss < 1G)"));
+        assert!(holds("! peak_rss > 1G"));
+    }
+
+    #[test]
+    fn keeps_the_source_and_metrics() {
+        let expr = parse("  duration < 2m && peak_rss < 1G || duration > 1s ").unwrap();
+        assert_eq!(expr.to_string(), "duration < 2m && peak_rss < 1G || duration > 1s");
+        assert_eq!(expr.metrics(), vec![Metric::Duration, Metric::PeakRss]);
+    }
+
+    #[test]
+    fn refuses_invalid_expressions() {
+        assert!(parse("").unwrap_err().contains("expected a comparison"));
+        assert!(parse("rss < 1G").unwrap_err().contains("unknown metric `rss`"));
+        assert!(parse("peak_rss 1G").unwrap_err().contains("expected <, <="));
+        assert!(parse("peak_rss <").unwrap_err().contains("expected a value"));
+        assert!(parse("(peak_rss < 1G").unwrap_err().contains("missing `)`"));
+        assert!(parse("peak_rss < 1G)").unwrap_err().contains("unexpected `)`"));
+        assert!(parse("peak_rss < lots").is_err());
+    }
+
+    #[test]
+    fn refuses_deep_nesting() {
+        let nested = |depth: usize| "(".repeat(depth) + "peak_rss < 1G" + &")".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH - 1)).is_ok());
+        assert!(parse(&nested(MAX_DEPTH + 1)).unwrap_err().contains("nested deeper"));
+        assert!(parse(&("!".repeat(100_000) + "peak_rss < 1G")).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Numeric metrics parsed out of the child's own log lines with `--extract`
+//!
+//! Patterns are in the dialect of [`crate::pattern`]. The value is the
+//! first capture group that took part in the match, or the whole match if
+//! none did.
+
+use std::{
+    fs::File,
+    io::{
+        self,
+        BufWriter,
+        Write,
+    },
+    path::Path,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use crate::{
+    json::Value,
+    pattern::Pattern,
+    record::{
+        self,
+        Timestamps,
+        SCHEMA_VERSION,
+    },
+    render::OutputMsg,
+};
+
+/// A parsed `--extract NAME:PATTERN`
+#[derive(Clone, Debug)]
+pub struct ExtractSpec {
+    pub name: String,
+    pattern: Pattern,
+}
+
+/// Parse `--extract`: a metric name, a colon and a pattern
+pub fn parse_extract(s: &str) -> Result<ExtractSpec, String> {
+    let (name, pattern) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected NAME:PATTERN, got '{}'", s))?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!(
+            "invalid metric name '{}': use letters, digits and '_'",
+            name
+        ));
+    }
+    Ok(ExtractSpec {
+        name: name.to_string(),
+        pattern: Pattern::parse(pattern)?,
+    })
+}
+
+/// One value found in the output
+pub struct ExtractPoint {
+    pub at: Duration,
+    pub value: f64,
+    /// RSS of the tree at the latest sample
+    pub rss: u64,
+}
+
+/// Values of one metric over the run
+pub struct ExtractSeries {
+    pub name: String,
+    pub points: Vec<ExtractPoint>,
+}
+
+impl ExtractSeries {
+    pub fn json(&self) -> Value {
+        let values = || self.points.iter().map(|p| p.value);
+        let stat = |v: Option<f64>| v.map(Value::from).unwrap_or(Value::Null);
+        Value::Object(vec![
+            ("name".into(), self.name.as_str().into()),
+            ("count".into(), (self.points.len() as u64).into()),
+            ("last".into(), stat(self.points.last().map(|p| p.value))),
+            ("min".into(), stat(values().reduce(f64::min))),
+            ("max".into(), stat(values().reduce(f64::max))),
+            (
+                "points".into(),
+                Value::Array(
+                    self.points
+                        .iter()
+                        .map(|p| {
+                            Value::Object(vec![
+                                ("elapsed_secs".into(), p.at.as_secs_f64().into()),
+                                ("value".into(), p.value.into()),
+                                ("rss".into(), p.rss.into()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+}
+
+/// Scans output lines for every `--extract` pattern
+pub struct Extractor {
+    specs: Vec<ExtractSpec>,
+    series: Vec<ExtractSeries>,
+    rss: u64,
+}
+
+impl Extractor {
+    pub fn new(specs: Vec<ExtractSpec>) -> Self {
+        let series = specs
+            .iter()
+            .map(|spec| ExtractSeries {
+                name: spec.name.clone(),
+                points: Vec::new(),
+            })
+            .collect();
+        Self {
+            specs,
+            series,
+            rss: 0,
+        }
+    }
+
+    /// RSS of the latest sample, recorded along with each value
+    pub fn set_rss(&mut self, rss: u64) {
+        self.rss = rss;
+    }
+
+    /// Record the values `msg` holds, if it is a line of output
+    pub fn scan(&mut self, msg: &OutputMsg, at: Duration) {
+        let (OutputMsg::Stdout(line) | OutputMsg::Stderr(line)) = msg else {
+            return;
+        };
+        let rss = self.rss;
+        for (spec, series) in self.specs.iter().zip(&mut self.series) {
+            let value = spec
+                .pattern
+                .capture(line)
+                .and_then(|text| text.trim().parse::<f64>().ok());
+            if let Some(value) = value {
+                series.points.push(ExtractPoint { at, value, rss });
+            }
+        }
+    }
+
+    /// Latest values for the status line, e.g. `heap: 1234`
+    pub fn status(&self) -> Vec<String> {
+        self.series
+            .iter()
+            .filter_map(|s| s.points.last().map(|p| format!("{}: {}", s.name, p.value)))
+            .collect()
+    }
+
+    pub fn finish(self) -> Vec<ExtractSeries> {
+        self.series
+    }
+}
+
+/// Write every extracted value as `schema_version,elapsed_secs,metric,value,rss` rows
+pub fn write_csv(
+    path: &Path,
+    series: &[ExtractSeries],
+    started_at: Option<SystemTime>,
+    timestamps: Timestamps,
+) -> io::Result<()> {
+    let mut points: Vec<(&str, &ExtractPoint)> = series
+        .iter()
+        .flat_map(|s| s.points.iter().map(move |p| (s.name.as_str(), p)))
+        .collect();
+    points.sort_by_key(|(_, p)| p.at);
+
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut header = String::from("schema_version");
+    if timestamps.elapsed() {
+        header.push_str(",elapsed_secs");
+    }
+    if timestamps.wall() {
+        header.push_str(",time");
+    }
+    writeln!(file, "{},metric,value,rss", header)?;
+    for (name, point) in points {
+        let mut row = SCHEMA_VERSION.to_string();
+        if timestamps.elapsed() {
+            row.push_str(&format!(",{:.3}", point.at.as_secs_f64()));
+        }
+        if timestamps.wall() {
+            let time = started_at.map(|started| record::format_time(started + point.at));
+            row.push_str(&format!(",{}", time.unwrap_or_default()));
+        }
+        writeln!(file, "{},{},{},{}", row, name, point.value, point.rss)?;
+    }
+    file.flush()
+}
@@ -0,0 +1,434 @@
+//! `--user-all`, `--match`, `--top` and `--slice`: a fleet of processes
+//! rather than one tree
+//!
+//! On a shared build server each user runs many unrelated trees, started
+//! from shells, cron jobs and IDEs; a service may run as a set of workers
+//! no single parent holds. The fleet is found anew at every sample, so
+//! processes that start or finish count from then on. With `--user-all` a
+//! process belongs to the user its `/proc/<pid>` is owned by, which is its
+//! effective user; with `--match` it is one whose command line matches,
+//! and `--top` takes every process. Matches and the largest processes of
+//! `--top` have a row of their own under the total, with their PSS and a
+//! sparkline of their recent RSS. `--slice` takes the processes of an
+//! existing cgroup and every cgroup below it, and adds the kernel's figures
+//! for the whole subtree, which count page cache and kernel memory besides
+//! RSS, with a row for each cgroup directly below. memwatch is never part
+//! of the fleet. Watching goes on until Ctrl+C, or until `--for` is over.
+
+use std::{
+    cmp::Reverse,
+    collections::{
+        HashMap,
+        HashSet,
+        VecDeque,
+    },
+    ffi::CString,
+    fs,
+    io,
+    path::Path,
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+use procfs::process::Process;
+
+use crate::{
+    cgroup::{
+        Cgroup,
+        MemoryStat,
+    },
+    clock::{
+        format_duration,
+        format_elapsed,
+        Clock,
+    },
+    exit::ExitInfo,
+    pattern::Pattern,
+    render::Renderer,
+    report::BLOCKS,
+    size::{
+        self,
+        format_bytes,
+    },
+    status_line::{
+        Priority,
+        StatusLine,
+    },
+    summary::{
+        self,
+        Summary,
+    },
+    ticks::Ticks,
+    tree::{
+        self,
+        ProcessSample,
+        ProcessTracker,
+        ReadError,
+        TreeSample,
+    },
+};
+
+/// Most rows of matches or child cgroups shown under the total, the
+/// largest first
+const MATCH_ROWS: usize = 10;
+
+/// Readings in the history column of a row
+const HISTORY: usize = 20;
+
+/// Which processes make up the fleet
+pub enum Fleet {
+    /// `--user-all`: every process of a user
+    User { name: String, uid: u32 },
+    /// `--match`: every process whose command line matches
+    Match(Pattern),
+    /// `--top`: every process, with that many rows
+    Top(usize),
+    /// `--slice`: every process of a cgroup and the cgroups below it
+    Slice { path: String, cgroup: Cgroup },
+}
+
+impl Fleet {
+    /// The processes of user `name`, a user name or a number
+    pub fn user(name: &str) -> io::Result<Self> {
+        let uid = match name.parse() {
+            Ok(uid) => uid,
+            Err(_) => resolve(name)?,
+        };
+        Ok(Self::User {
+            name: name.to_string(),
+            uid,
+        })
+    }
+
+    fn sample(&self) -> procfs::ProcResult<TreeSample> {
+        match self {
+            Self::User { uid, .. } => tree::sample_user(*uid),
+            Self::Match(pattern) => tree::sample_selected(&|proc| {
+                command_line(proc).is_some_and(|line| pattern.is_match(&line))
+            }),
+            Self::Top(_) => tree::sample_selected(&|_| true),
+            Self::Slice { cgroup, .. } => {
+                let pids: HashSet<i32> = cgroup.processes().into_iter().collect();
+                tree::sample_selected(&|proc| pids.contains(&proc.pid()))
+            }
+        }
+    }
+
+    /// Stands in for the command in summaries: `user alice`, `match
+    /// 'worker.*'`, `top`, `slice user.slice`
+    fn command(&self) -> Vec<String> {
+        match self {
+            Self::User { name, .. } => vec!["user".into(), name.clone()],
+            Self::Match(pattern) => vec!["match".into(), pattern.as_str().to_string()],
+            Self::Top(_) => vec!["top".into()],
+            Self::Slice { path, .. } => vec!["slice".into(), path.clone()],
+        }
+    }
+
+    /// Processes with a row of their own, none for a user or a cgroup
+    fn rows(&self) -> Option<Rows> {
+        match self {
+            Self::User { .. } | Self::Slice { .. } => None,
+            Self::Match(_) => Some(Rows::new(MATCH_ROWS)),
+            Self::Top(rows) => Some(Rows::new(*rows)),
+        }
+    }
+
+    fn label(&self) -> &str {
+        match self {
+            Self::User { name, .. } | Self::Slice { path: name, .. } => name,
+            Self::Match(_) | Self::Top(_) => "Total",
+        }
+    }
+}
+
+fn resolve(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "a user name cannot hold NUL"))?;
+    // SAFETY: `c_name` is a valid C string; the entry is read before any
+    // other call can reuse it
+    let entry = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if entry.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("--user-all: no user named {}", name),
+        ));
+    }
+    // SAFETY: checked for null above
+    Ok(unsafe { (*entry).pw_uid })
+}
+
+/// How much of a command line `--match` looks at; a JVM's classpath alone
+/// can run to hundreds of kilobytes
+const MATCHED_LEN: usize = 4096;
+
+/// Arguments joined by spaces, up to `MATCHED_LEN` bytes; none for kernel
+/// threads
+fn command_line(proc: &Process) -> Option<String> {
+    let cmdline = proc.cmdline().ok()?;
+    let mut line = cmdline.join(" ");
+    if line.len() > MATCHED_LEN {
+        let end = (0..=MATCHED_LEN).rev().find(|&end| line.is_char_boundary(end)).unwrap_or(0);
+        line.truncate(end);
+    }
+    (!cmdline.is_empty()).then_some(line)
+}
+
+/// Watch `fleet` until interrupted, then report on it
+pub fn run(
+    fleet: Fleet,
+    interval: Duration,
+    clock: Clock,
+    watch_for: Option<Duration>,
+    summary_json: Option<&Path>,
+    terminated: &AtomicBool,
+) -> io::Result<()> {
+    let start = Instant::now();
+    let mut ticks = Ticks::new(start, interval);
+    let mut summary = Summary::new(&fleet.command());
+    let mut tracker = ProcessTracker::default();
+    let mut last = TreeSample::default();
+    // When the fleet's RSS peaked
+    let mut peak_at = Duration::ZERO;
+    let mut rows = fleet.rows();
+    let mut renderer = Renderer::plain();
+    let mut time_up = false;
+
+    while !terminated.load(Ordering::SeqCst) {
+        let now = ticks.elapsed();
+        if watch_for.is_some_and(|limit| now >= limit) {
+            time_up = true;
+            break;
+        }
+        last = fleet.sample().unwrap_or_else(|e| last.stale(ReadError::of(&e)));
+        if last.rss > summary.peak_rss {
+            peak_at = now;
+        }
+        summary.record(&last);
+        tracker.update(&last);
+        summary.processes_seen = tracker.seen;
+
+        let stamp = clock.stamp(now, Some(SystemTime::now()));
+        let mut status = format_total(&stamp, fleet.label(), &last, summary.peak_rss);
+        let cgroup = match &fleet {
+            Fleet::Slice { cgroup, .. } => Some(cgroup),
+            _ => None,
+        };
+        if let Some(cgroup) = cgroup {
+            let current = cgroup.memory_current();
+            summary.record_cgroup(current);
+            status.push(Priority::High, format_cgroup(current, cgroup.memory_stat()));
+        }
+        let mut line = status.fit(renderer.width());
+        if let Some(cgroup) = cgroup {
+            line.push_str(&format_children(cgroup));
+        }
+        if let Some(rows) = rows.as_mut() {
+            rows.update(&last);
+            line.push_str(&rows.format(&last));
+        }
+        renderer.status(&line);
+
+        thread::sleep(ticks.advance().saturating_duration_since(Instant::now()));
+    }
+    drop(renderer);
+
+    let elapsed = start.elapsed();
+    summary.top_processes = tracker.top();
+    eprintln!(
+        "{}: peak RSS {} at {} | Peak processes: {} | Processes seen: {}",
+        match &fleet {
+            Fleet::User { name, .. } => format!("User {}", name),
+            Fleet::Match(pattern) => format!("Matching '{}'", pattern.as_str()),
+            Fleet::Top(_) => "All processes".to_string(),
+            Fleet::Slice { path, .. } => format!("Cgroup {}", path),
+        },
+        format_bytes(summary.peak_rss),
+        format_elapsed(peak_at),
+        summary.peak_processes,
+        summary.processes_seen
+    );
+    for top in summary.top_processes.iter().take(5) {
+        eprintln!("  {} ({}) peak RSS {}", top.name, top.pid, format_bytes(top.peak_rss));
+    }
+    if let (true, Some(limit)) = (time_up, watch_for) {
+        eprintln!("Stopped after {} (--for)", format_duration(limit));
+    }
+
+    if let Some(path) = summary_json {
+        let exit = ExitInfo {
+            code: None,
+            signal: None,
+            core_dumped: false,
+        };
+        let termination = if time_up { "time_limit" } else { "interrupted" };
+        let report = summary.report(elapsed, &exit, Some(termination));
+        if let Err(e) = summary::write_file(path, &report) {
+            eprintln!("memwatch: failed to write summary to {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// `[12:40] alice:    7.84 GB | Procs:  57 | Peak: 9.12 GB | top: cc1plus
+/// 1.2G`
+fn format_total(stamp: &str, label: &str, sample: &TreeSample, peak_rss: u64) -> StatusLine {
+    let mark = if sample.is_incomplete() { "?" } else { "" };
+    let mut line = StatusLine::new(stamp);
+    line.push_short(
+        Priority::Essential,
+        format!("{}: {}{}", label, size::format_bytes_fixed(sample.rss), mark),
+        format!("{} {}{}", label, size::format_bytes_short(sample.rss), mark),
+    );
+    line.push(Priority::High, format!("Procs: {:>3}", sample.processes.len()));
+    line.push(Priority::High, format!("Peak: {}", format_bytes(peak_rss)));
+    if let Some(largest) = sample.processes.iter().max_by_key(|p| p.rss) {
+        line.push(
+            Priority::Low,
+            format!("top: {} {}", largest.name, size::format_bytes_short(largest.rss)),
+        );
+    }
+    line
+}
+
+/// `Cgroup: 9.80 GB (anon 6.10 GB, file 3.52 GB)`
+fn format_cgroup(current: Option<u64>, stat: Option<MemoryStat>) -> String {
+    let mut column = format!("Cgroup: {}", current.map_or_else(|| "-".into(), format_bytes));
+    if let Some(stat) = stat {
+        column.push_str(&format!(
+            " (anon {}, file {})",
+            format_bytes(stat.anon),
+            format_bytes(stat.file)
+        ));
+    }
+    column
+}
+
+/// A row for each of the largest cgroups directly below `cgroup`:
+/// `   3.20 GB  docker-4f1c.scope`
+fn format_children(cgroup: &Cgroup) -> String {
+    let mut children: Vec<(u64, String)> = cgroup
+        .children()
+        .iter()
+        .filter_map(|child| Some((child.memory_current()?, child.name())))
+        .collect();
+    children.sort_by_key(|&(current, _)| Reverse(current));
+    let mut rows = String::new();
+    for (current, name) in children.iter().take(MATCH_ROWS) {
+        rows.push_str(&format!("\n  {} {}", size::format_bytes_fixed(*current), name));
+    }
+    if children.len() > MATCH_ROWS {
+        rows.push_str(&format!("\n  … and {} more", children.len() - MATCH_ROWS));
+    }
+    rows
+}
+
+/// Processes with a row of their own
+struct Rows {
+    limit: usize,
+    /// By PID and start time
+    processes: HashMap<(i32, u64), Row>,
+}
+
+struct Row {
+    command: String,
+    peak_rss: u64,
+    /// Latest RSS readings, oldest first
+    history: VecDeque<u64>,
+}
+
+impl Rows {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            processes: HashMap::new(),
+        }
+    }
+
+    fn update(&mut self, sample: &TreeSample) {
+        let live: HashSet<(i32, u64)> = sample.processes.iter().map(key).collect();
+        self.processes.retain(|key, _| live.contains(key));
+        for process in &sample.processes {
+            let row = self.processes.entry(key(process)).or_insert_with(|| Row {
+                command: Process::new(process.pid)
+                    .ok()
+                    .and_then(|proc| command_line(&proc))
+                    .unwrap_or_else(|| format!("[{}]", process.name)),
+                peak_rss: 0,
+                history: VecDeque::with_capacity(HISTORY),
+            });
+            row.peak_rss = row.peak_rss.max(process.rss);
+            if row.history.len() == HISTORY {
+                row.history.pop_front();
+            }
+            row.history.push_back(process.rss);
+        }
+    }
+
+    /// A header, then a row for each of the largest processes:
+    /// `   4121    1.08 GB  1.02 GB   1.20 GB  ▃▃▄▅▅▆▇█▇▇ celery -A app …`
+    fn format(&self, sample: &TreeSample) -> String {
+        let mut processes: Vec<&ProcessSample> = sample.processes.iter().collect();
+        processes.sort_by_key(|p| Reverse(p.rss));
+        let fixed = size::format_bytes_fixed;
+        let mut rows = format!(
+            "\n{:>7} {:>10} {:>10} {:>10}  {:<width$} COMMAND",
+            "PID",
+            "RSS",
+            "PSS",
+            "PEAK",
+            "HISTORY",
+            width = HISTORY
+        );
+        for process in processes.iter().take(self.limit) {
+            let row = &self.processes[&key(process)];
+            rows.push_str(&format!(
+                "\n{:>7} {} {} {}  {} {}",
+                process.pid,
+                fixed(process.rss),
+                read_pss(process.pid).map_or_else(|| format!("{:>10}", "-"), fixed),
+                fixed(row.peak_rss),
+                sparkline(&row.history),
+                row.command
+            ));
+        }
+        if processes.len() > self.limit {
+            rows.push_str(&format!("\n  … and {} more", processes.len() - self.limit));
+        }
+        rows
+    }
+}
+
+fn key(process: &ProcessSample) -> (i32, u64) {
+    (process.pid, process.starttime)
+}
+
+/// `Pss` of `/proc/<pid>/smaps_rollup`, in bytes
+fn read_pss(pid: i32) -> Option<u64> {
+    let rollup = fs::read_to_string(format!("/proc/{}/smaps_rollup", pid)).ok()?;
+    let line = rollup.lines().find_map(|line| line.strip_prefix("Pss:"))?;
+    let kib: u64 = line.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// `history` in eighth blocks scaled to its own highest reading, padded so
+/// the column keeps its width
+fn sparkline(history: &VecDeque<u64>) -> String {
+    let peak = history.iter().copied().max().unwrap_or(0);
+    let line: String = history
+        .iter()
+        .map(|&rss| match peak {
+            0 => BLOCKS[1],
+            _ => BLOCKS[1 + (rss * 7 / peak) as usize],
+        })
+        .collect();
+    format!("{:<width$}", line, width = HISTORY)
+}
@@ -0,0 +1,163 @@
+//! `--system-floor`: keep the machine usable while the command runs
+//!
+//! The floor is on `MemAvailable` of `/proc/meminfo`, what the whole
+//! machine could still hand out without swapping, rather than on the
+//! tree's RSS: a job under test on a workstation is a problem once the
+//! desktop starts to stall, however much of that is the job's own doing.
+//! The floor is an [`Alert`] that fires on falling below it: a drop is
+//! reported once, and again only after `MemAvailable` was back over the
+//! floor plus the hysteresis, 10% of the floor unless `--alert-hysteresis`
+//! says otherwise, and the `--alert-cooldown` is over. With
+//! `--pause-below-floor` the tree is stopped from the drop until it is
+//! back over that level.
+
+use std::{
+    fs,
+    time::Duration,
+};
+
+use crate::{
+    alert::{
+        Alert,
+        Hysteresis,
+        Rearm,
+    },
+    clock::format_elapsed,
+    json::Value,
+    size::{
+        format_bytes,
+        format_bytes_short,
+    },
+};
+
+/// How far above the floor `MemAvailable` must be back before the floor
+/// is armed again or a paused tree resumes, without `--alert-hysteresis`
+const MARGIN: Hysteresis = Hysteresis::Percent(10.0);
+
+/// `MemAvailable` in bytes
+fn read_available() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    meminfo.lines().find_map(|line| {
+        let value = line.strip_prefix("MemAvailable:")?;
+        Some(value.trim().trim_end_matches("kB").trim().parse::<u64>().ok()? * 1024)
+    })
+}
+
+/// A change of side of the floor
+pub enum FloorEvent {
+    /// `MemAvailable` fell below the floor to this
+    Below(u64),
+    /// ... and is back over the floor and hysteresis at this
+    Recovered(u64),
+}
+
+/// Reported in the summary
+pub struct FloorStats {
+    pub floor: u64,
+    /// Drops below the floor
+    pub breaches: u64,
+    pub lowest_available: Option<u64>,
+    /// Time the tree spent stopped, with `--pause-below-floor`
+    pub paused: Duration,
+}
+
+impl FloorStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("floor".into(), self.floor.into()),
+            ("breaches".into(), self.breaches.into()),
+            ("lowest_available".into(), self.lowest_available.into()),
+            ("paused_secs".into(), self.paused.as_secs_f64().into()),
+        ])
+    }
+
+    /// `System floor: 2 drops below 1.00 GB, lowest 612.40 MB available |
+    /// paused for 01:12`
+    pub fn format(&self) -> String {
+        let mut line = format!(
+            "System floor: {} drop{} below {}",
+            self.breaches,
+            if self.breaches == 1 { "" } else { "s" },
+            format_bytes(self.floor)
+        );
+        if let Some(lowest) = self.lowest_available {
+            line.push_str(&format!(", lowest {} available", format_bytes(lowest)));
+        }
+        if !self.paused.is_zero() {
+            line.push_str(&format!(" | paused for {}", format_elapsed(self.paused)));
+        }
+        line
+    }
+}
+
+pub struct SystemFloor {
+    /// Fired while below the floor and not back over the hysteresis since
+    alert: Alert,
+    available: Option<u64>,
+    /// When the current stay below the floor began
+    below_since: Duration,
+    stats: FloorStats,
+}
+
+impl SystemFloor {
+    /// A floor re-armed with `--alert-cooldown` and `--alert-hysteresis`
+    pub fn new(floor: u64, cooldown: Option<Duration>, hysteresis: Option<Hysteresis>) -> Self {
+        let rearm = Rearm::After {
+            hysteresis: hysteresis.unwrap_or(MARGIN),
+            cooldown: cooldown.unwrap_or_default(),
+        };
+        Self {
+            alert: Alert::floor(floor, rearm),
+            available: None,
+            below_since: Duration::ZERO,
+            stats: FloorStats {
+                floor,
+                breaches: 0,
+                lowest_available: None,
+                paused: Duration::ZERO,
+            },
+        }
+    }
+
+    /// `MemAvailable` at or over which the floor is armed again
+    pub fn clear_level(&self) -> u64 {
+        self.alert.clear_level()
+    }
+
+    /// Read `MemAvailable` at `at` into the run, and whether it crossed a
+    /// side of the floor
+    pub fn update(&mut self, at: Duration) -> Option<FloorEvent> {
+        let available = read_available()?;
+        self.available = Some(available);
+        let lowest = self.stats.lowest_available.get_or_insert(available);
+        *lowest = (*lowest).min(available);
+        let was_below = self.alert.fired();
+        if self.alert.update(at, available) {
+            self.below_since = at;
+            self.stats.breaches += 1;
+            return Some(FloorEvent::Below(available));
+        }
+        match was_below && !self.alert.fired() {
+            true => Some(FloorEvent::Recovered(available)),
+            false => None,
+        }
+    }
+
+    /// Count the stay below the floor that ends at `at` as time paused
+    pub fn resumed(&mut self, at: Duration) {
+        self.stats.paused += at.saturating_sub(self.below_since);
+    }
+
+    /// `MemAvailable`, marked while below the floor, for the status line
+    pub fn status(&self) -> Vec<String> {
+        let Some(available) = self.available else {
+            return Vec::new();
+        };
+        let mark = if self.alert.fired() { " (below floor)" } else { "" };
+        vec![format!("Avail: {}{}", format_bytes_short(available), mark)]
+    }
+
+    pub fn finish(self) -> FloorStats {
+        self.stats
+    }
+}
@@ -0,0 +1,94 @@
+//! Freezing the process tree on limit breach so it can be inspected
+
+use std::{
+    fs::File,
+    io::{
+        BufRead,
+        BufReader,
+    },
+    os::fd::AsRawFd,
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    cgroup::Cgroup,
+    tree,
+};
+
+/// What to do with a frozen command
+pub enum Decision {
+    Resume,
+    Kill,
+}
+
+/// Stop the whole tree via the cgroup freezer, or SIGSTOP when unavailable
+pub fn freeze(root_pid: i32, known: &[(i32, u64)], cgroup: Option<&Cgroup>) {
+    if cgroup.is_some_and(|cgroup| cgroup.set_frozen(true).is_ok()) {
+        return;
+    }
+    // Repeat to catch processes forked while we were stopping their parents
+    for _ in 0..3 {
+        tree::signal_tree(Some(root_pid), known, libc::SIGSTOP);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Undo [`freeze`]
+pub fn thaw(root_pid: i32, known: &[(i32, u64)], cgroup: Option<&Cgroup>) {
+    if cgroup.is_some_and(|cgroup| cgroup.set_frozen(false).is_ok()) {
+        return;
+    }
+    tree::signal_tree(Some(root_pid), known, libc::SIGCONT);
+}
+
+/// Ask on the controlling terminal whether to resume or kill
+///
+/// Without a terminal the tree stays frozen until Ctrl+C, which kills it.
+pub fn prompt(root_pid: i32, terminated: &AtomicBool) -> Decision {
+    let tty = File::open("/dev/tty").ok();
+    if tty.is_some() {
+        eprintln!(
+            "Command frozen (pid {}). Inspect it now, e.g. `gdb -p {}`.\nType 'r' and Enter to resume, or Enter to kill:",
+            root_pid, root_pid
+        );
+    } else {
+        eprintln!(
+            "Command frozen (pid {}). Press Ctrl+C to kill it.",
+            root_pid
+        );
+    }
+
+    let mut reader = tty.map(BufReader::new);
+    loop {
+        if terminated.load(Ordering::SeqCst) {
+            return Decision::Kill;
+        }
+
+        let Some(reader) = reader.as_mut() else {
+            thread::sleep(Duration::from_millis(200));
+            continue;
+        };
+
+        // Poll so Ctrl+C is noticed while waiting for input
+        let mut pfd = libc::pollfd {
+            fd: reader.get_ref().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `pfd` is a valid pollfd for the duration of the call
+        if unsafe { libc::poll(&mut pfd, 1, 200) } <= 0 {
+            continue;
+        }
+
+        let mut line = String::new();
+        return match reader.read_line(&mut line) {
+            Ok(_) if line.trim().eq_ignore_ascii_case("r") => Decision::Resume,
+            _ => Decision::Kill,
+        };
+    }
+}
@@ -0,0 +1,63 @@
+//! GitHub Actions workflow commands and job summary
+
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{
+        self,
+        Write,
+    },
+};
+
+use crate::{
+    record::RunSummary,
+    report,
+};
+
+/// Severity of a workflow annotation
+#[derive(Clone, Copy)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+/// `::warning title=..::message` line for the workflow log
+pub fn annotation(level: Level, title: &str, message: &str) -> String {
+    let command = match level {
+        Level::Warning => "warning",
+        Level::Error => "error",
+    };
+    format!(
+        "::{} title={}::{}",
+        command,
+        escape_property(title),
+        escape_data(message)
+    )
+}
+
+/// Append a Markdown table of the run to `$GITHUB_STEP_SUMMARY`
+///
+/// Does nothing outside of GitHub Actions.
+pub fn write_step_summary(summary: &RunSummary) -> io::Result<()> {
+    let Some(path) = env::var_os("GITHUB_STEP_SUMMARY") else {
+        return Ok(());
+    };
+
+    let mut markdown = format!(
+        "### memwatch: `{}`\n\n",
+        summary.command.replace('`', "'")
+    );
+    markdown.push_str(&report::markdown_table(&report::summary_rows(summary)));
+    markdown.push('\n');
+
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    file.write_all(markdown.as_bytes())
+}
+
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
@@ -0,0 +1,199 @@
+//! Graphite/Carbon plaintext protocol export
+//!
+//! Points are sent from a thread of their own, so resolving and connecting
+//! to Carbon never holds up sampling: while it is down or slow, points
+//! beyond a small backlog are dropped, and reconnecting is tried at most
+//! once per `RETRY`.
+
+use std::{
+    io::Write,
+    net::{
+        TcpStream,
+        ToSocketAddrs,
+    },
+    sync::mpsc::{
+        self,
+        Receiver,
+        SyncSender,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use crate::{
+    observer::Observer,
+    record::{
+        RunSummary,
+        Sample,
+    },
+};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time between attempts to reach Carbon after a failed one
+const RETRY: Duration = Duration::from_secs(30);
+
+/// Samples buffered while Carbon is slow to accept them
+const BACKLOG: usize = 64;
+
+/// Sends `<prefix>.rss <value> <timestamp>` lines to a Carbon receiver
+pub struct GraphiteExporter {
+    prefix: String,
+    lines: Option<SyncSender<String>>,
+    sender: Option<JoinHandle<()>>,
+}
+
+impl GraphiteExporter {
+    /// Export to `addr` under `prefix`, a dotted metric path whose parts
+    /// are sanitized like the default one
+    pub fn new(addr: String, prefix: String) -> Self {
+        let (lines, receiver) = mpsc::sync_channel(BACKLOG);
+        Self {
+            prefix: prefix.split('.').map(sanitize).collect::<Vec<_>>().join("."),
+            lines: Some(lines),
+            sender: Some(thread::spawn(move || send_lines(&addr, receiver))),
+        }
+    }
+
+    /// Default metric path: `memwatch.<program name>`
+    pub fn default_prefix(command: &[String]) -> String {
+        let program = command
+            .first()
+            .map(|p| p.rsplit('/').next().unwrap_or(p))
+            .unwrap_or("command");
+        format!("memwatch.{}", sanitize(program))
+    }
+}
+
+impl Observer for GraphiteExporter {
+    fn on_sample(&mut self, sample: &Sample) {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let lines = format!(
+            "{prefix}.rss {} {ts}\n{prefix}.vsz {} {ts}\n",
+            sample.rss,
+            sample.vsz,
+            prefix = self.prefix,
+            ts = ts
+        );
+        if let Some(sender) = &self.lines {
+            // A full backlog drops the point rather than waiting
+            let _ = sender.try_send(lines);
+        }
+    }
+
+    fn on_exit(&mut self, _summary: &RunSummary) {
+        // Let the last points out; with Carbon down, this waits for one
+        // connection attempt at most
+        self.lines = None;
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+/// Body of the sending thread: one connection, reopened after a failure
+/// once `RETRY` is over
+fn send_lines(addr: &str, lines: Receiver<String>) {
+    let mut stream: Option<TcpStream> = None;
+    let mut failed_at: Option<Instant> = None;
+    let mut warned = false;
+    for data in lines {
+        if stream.is_none() {
+            if failed_at.is_some_and(|at| at.elapsed() < RETRY) {
+                continue;
+            }
+            match connect(addr) {
+                Ok(connected) => stream = Some(connected),
+                Err(e) => {
+                    failed_at = Some(Instant::now());
+                    warn(&mut warned, addr, &e);
+                    continue;
+                }
+            }
+        }
+        if let Some(Err(e)) = stream.as_mut().map(|stream| stream.write_all(data.as_bytes())) {
+            stream = None;
+            failed_at = Some(Instant::now());
+            warn(&mut warned, addr, &e);
+        }
+    }
+}
+
+fn warn(warned: &mut bool, addr: &str, e: &std::io::Error) {
+    if !*warned {
+        *warned = true;
+        eprintln!("\rmemwatch: graphite export to {} failed: {}", addr, e);
+    } else {
+        crate::log::info!("graphite export to {} failed: {}", addr, e);
+    }
+}
+
+fn connect(addr: &str) -> std::io::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "no address resolved")
+    }))
+}
+
+/// Replace characters that have meaning in Graphite metric paths
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[test]
+    fn sends_lines_under_a_sanitized_prefix() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut exporter = GraphiteExporter::new(addr, "memwatch.my app\n.x".into());
+        exporter.on_sample(&Sample {
+            rss: 1024,
+            vsz: 2048,
+            ..Sample::default()
+        });
+        exporter.on_exit(&RunSummary::default());
+
+        let mut received = String::new();
+        listener.accept().unwrap().0.read_to_string(&mut received).unwrap();
+        let fields: Vec<Vec<&str>> = received.lines().map(|line| line.split(' ').collect()).collect();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0][..2], ["memwatch.my_app_.x.rss", "1024"]);
+        assert_eq!(fields[1][..2], ["memwatch.my_app_.x.vsz", "2048"]);
+    }
+}
@@ -0,0 +1,234 @@
+//! `memwatch guard`: a memory watchdog for a service memwatch did not start
+//!
+//! The service is found through its pid file, which is read again whenever
+//! the process it named is gone or the file names another one, so guarding
+//! carries on across restarts. A breach runs `--on-breach` once; the guard
+//! is armed again when RSS falls back under the limit, less any
+//! `--alert-hysteresis`, or the service restarts, and `--alert-cooldown`
+//! holds across restarts. With `--between`, the service is only guarded
+//! within those windows of the day; with `--for`, guarding ends after that
+//! long, leaving the service running.
+
+use std::{
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::Command,
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+use crate::{
+    alert::{
+        self,
+        Alert,
+        Rearm,
+    },
+    clock::{
+        self,
+        Clock,
+    },
+    exit::ExitInfo,
+    log,
+    size::{
+        self,
+        format_bytes,
+    },
+    target::Target,
+    tree,
+    window::{
+        self,
+        Schedule,
+        Window,
+    },
+};
+
+#[derive(clap::Args, Debug)]
+pub struct GuardArgs {
+    /// File holding the PID of the service, such as /run/foo.pid
+    #[arg(long, value_name = "FILE")]
+    pid_file: PathBuf,
+
+    /// Breach when the service and its descendants use more RSS than this
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    max_rss: u64,
+
+    /// Shell command to run on a breach, with MEMWATCH_PID, MEMWATCH_RSS
+    /// and MEMWATCH_LIMIT set; without it a breach is only reported
+    #[arg(long, value_name = "COMMAND")]
+    on_breach: Option<String>,
+
+    /// Run --on-breach at most once per TIME (e.g. 5m)
+    #[arg(long, value_name = "TIME", value_parser = clock::parse_duration)]
+    alert_cooldown: Option<Duration>,
+
+    /// Arm the guard again only once RSS falls this far below the limit
+    /// (e.g. 100M or 10%)
+    #[arg(long, value_name = "SIZE", value_parser = alert::parse_hysteresis)]
+    alert_hysteresis: Option<alert::Hysteresis>,
+
+    /// Only guard in this window of local time, e.g. 22:00-06:00;
+    /// repeatable
+    #[arg(long, value_name = "HH:MM-HH:MM", value_parser = window::parse_window)]
+    between: Vec<Window>,
+
+    /// Stop guarding after this long, leaving the service running (e.g. 1h)
+    #[arg(long = "for", value_name = "TIME", value_parser = clock::parse_duration)]
+    guard_for: Option<Duration>,
+
+    /// Update interval in milliseconds
+    #[arg(short, long, default_value_t = 1000)]
+    interval: u64,
+}
+
+pub fn run(args: GuardArgs, terminated: &AtomicBool) -> io::Result<()> {
+    let interval = Duration::from_millis(args.interval);
+    let rearm = Rearm::from_args(
+        args.alert_cooldown,
+        args.alert_hysteresis,
+        Rearm::After {
+            hysteresis: alert::Hysteresis::Bytes(0),
+            cooldown: Duration::ZERO,
+        },
+    );
+    let started = Instant::now();
+    // Shared by restarts, so the cooldown holds for a service that keeps
+    // running out of memory
+    let mut alert = Alert::new(args.max_rss, rearm);
+    // The service process currently guarded
+    let mut guarded: Option<Target> = None;
+    // Last reason the pid file gave no process, reported once per reason
+    let mut waiting: Option<String> = None;
+    let mut schedule = (!args.between.is_empty()).then(|| Schedule::new(args.between.clone()));
+    let mut breaches = 0;
+
+    while !terminated.load(Ordering::SeqCst) {
+        if let Some(limit) = args.guard_for.filter(|&limit| started.elapsed() >= limit) {
+            notice(&format!(
+                "Stopped guarding after {}: {} breach{}",
+                clock::format_duration(limit),
+                breaches,
+                if breaches == 1 { "" } else { "es" }
+            ));
+            break;
+        }
+        let named = read_pid(&args.pid_file);
+
+        if let Some(current) = &mut guarded {
+            let gone = current.try_wait()?.is_some();
+            let replaced = matches!(named, Ok(pid) if pid != current.pid());
+            if gone || replaced {
+                notice(&format!(
+                    "Process {} {}",
+                    current.pid(),
+                    if gone { "exited" } else { "was replaced in the pid file" }
+                ));
+                guarded = None;
+            }
+        }
+
+        if guarded.is_none() {
+            match named.and_then(|pid| Target::attach(pid).map_err(|e| e.to_string())) {
+                Ok(target) => {
+                    notice(&format!(
+                        "Guarding process {}: {}",
+                        target.pid(),
+                        target.command_line().join(" ")
+                    ));
+                    guarded = Some(target);
+                    alert.reset();
+                    waiting = None;
+                }
+                Err(reason) => {
+                    if waiting.as_ref() != Some(&reason) {
+                        notice(&format!("Waiting for the service: {}", reason));
+                        waiting = Some(reason);
+                    }
+                }
+            }
+        }
+
+        let inside = match schedule.as_mut().map(|schedule| schedule.update(SystemTime::now())) {
+            Some((inside, event)) => {
+                if let Some(event) = event {
+                    notice(&window::format_event(&event));
+                }
+                inside
+            }
+            None => true,
+        };
+
+        if let Some(current) = guarded.as_mut().filter(|_| inside) {
+            let pid = current.pid();
+            // A process that is gone by now is noticed on the next tick
+            if let Ok(sample) = tree::sample(pid) {
+                log::debug!("process {}: RSS {} in {} processes", pid, sample.rss, sample.processes.len());
+                if alert.update(started.elapsed(), sample.rss) {
+                    breaches += 1;
+                    notice(&format!(
+                        "Process {} uses {} RSS, over the limit of {}",
+                        pid,
+                        format_bytes(sample.rss),
+                        format_bytes(args.max_rss)
+                    ));
+                    if let Some(command) = &args.on_breach {
+                        on_breach(command, pid, sample.rss, args.max_rss);
+                    }
+                }
+            }
+        }
+
+        thread::sleep(interval);
+    }
+    Ok(())
+}
+
+/// PID in the pid file, or why there is none
+fn read_pid(path: &Path) -> Result<i32, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    text.trim()
+        .parse()
+        .ok()
+        .filter(|&pid: &i32| pid > 0)
+        .ok_or_else(|| format!("{} does not hold a PID", path.display()))
+}
+
+/// Run the `--on-breach` command and wait for it, so a restart it makes is
+/// over before the service is looked at again
+fn on_breach(command: &str, pid: i32, rss: u64, limit: u64) {
+    notice(&format!("Running: {}", command));
+    let status = Command::new("sh")
+        .args(["-c", command])
+        .env("MEMWATCH_PID", pid.to_string())
+        .env("MEMWATCH_RSS", rss.to_string())
+        .env("MEMWATCH_LIMIT", limit.to_string())
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => notice(&format!(
+            "--on-breach command {}",
+            ExitInfo::from_status(status).describe()
+        )),
+        Err(e) => notice(&format!("cannot run --on-breach command: {}", e)),
+    }
+}
+
+/// A line for the service's log, stamped with the time of day
+fn notice(message: &str) {
+    eprintln!(
+        "{} {}",
+        Clock::Wall.stamp(Duration::ZERO, Some(SystemTime::now())),
+        message
+    );
+}
@@ -0,0 +1,98 @@
+//! Distribution of sampled RSS, shown with the final summary
+//!
+//! Counts go into logarithmic buckets, so the histogram takes the same
+//! little memory however long the run is.
+
+use std::collections::BTreeMap;
+
+use crate::size::format_bytes;
+
+/// Buckets per doubling of RSS, before merging into rows
+const STEPS_PER_DOUBLING: f64 = 16.0;
+
+/// Rows shown at most; adjacent buckets are merged to fit
+const MAX_ROWS: u32 = 10;
+
+/// Length of the longest bar
+const BAR_WIDTH: u64 = 40;
+
+/// Samples below which a histogram says too little to be shown
+pub const MIN_SAMPLES: u64 = 30;
+
+#[derive(Default)]
+pub struct Histogram {
+    /// Sample count by bucket index, see [`bucket`]
+    counts: BTreeMap<u32, u64>,
+    samples: u64,
+}
+
+impl Histogram {
+    /// Count a sample; those with nothing resident, such as after the
+    /// command exited, are left out
+    pub fn record(&mut self, rss: u64) {
+        if rss == 0 {
+            return;
+        }
+        *self.counts.entry(bucket(rss)).or_default() += 1;
+        self.samples += 1;
+    }
+
+    /// `(from, to, samples)` rows from the smallest RSS seen to the largest
+    pub fn rows(&self) -> Vec<(u64, u64, u64)> {
+        let (Some((&first, _)), Some((&last, _))) =
+            (self.counts.first_key_value(), self.counts.last_key_value())
+        else {
+            return Vec::new();
+        };
+        let per_row = (last - first + 1).div_ceil(MAX_ROWS);
+        let mut rows = Vec::new();
+        let mut start = first;
+        while start <= last {
+            let end = start + per_row;
+            let samples = self.counts.range(start..end).map(|(_, count)| count).sum();
+            rows.push((lower_bound(start), lower_bound(end), samples));
+            start = end;
+        }
+        rows
+    }
+
+    /// Text for the final summary, or `None` when there is too little to
+    /// show: few samples, or RSS that hardly moved
+    pub fn format(&self) -> Option<String> {
+        let rows = self.rows();
+        if self.samples < MIN_SAMPLES || rows.len() < 2 {
+            return None;
+        }
+        let labels: Vec<String> = rows
+            .iter()
+            .map(|(from, to, _)| format!("{} – {}", format_bytes(*from), format_bytes(*to)))
+            .collect();
+        let label_width = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+        let most = rows.iter().map(|(_, _, samples)| *samples).max().unwrap_or(1);
+
+        let mut out = format!("RSS distribution over {} samples:", self.samples);
+        for ((_, _, samples), label) in rows.iter().zip(&labels) {
+            // A bucket that was hit at all gets at least one block
+            let width = (samples * BAR_WIDTH).div_ceil(most) as usize;
+            out.push_str(&format!(
+                "\n  {:<label_width$}  {:<bar_width$} {}",
+                label,
+                "█".repeat(width),
+                samples,
+                bar_width = BAR_WIDTH as usize,
+            ));
+        }
+        Some(out)
+    }
+}
+
+/// Bucket of a non-zero `rss`, counting `1 / STEPS_PER_DOUBLING` steps of
+/// a doubling up from one byte
+fn bucket(rss: u64) -> u32 {
+    ((rss as f64).log2() * STEPS_PER_DOUBLING) as u32
+}
+
+/// Smallest RSS that falls into `bucket`
+fn lower_bound(bucket: u32) -> u64 {
+    (f64::from(bucket) / STEPS_PER_DOUBLING).exp2().ceil() as u64
+}
@@ -0,0 +1,127 @@
+//! Outgoing HTTP requests, delegated to `curl` to keep TLS out of the binary
+
+use std::{
+    fs::File,
+    io::{
+        self,
+        Write,
+    },
+    os::{
+        fd::{
+            AsRawFd,
+            FromRawFd,
+            OwnedFd,
+        },
+        unix::process::CommandExt,
+    },
+    process::{
+        Command,
+        Stdio,
+    },
+};
+
+/// POST a JSON body to `url`, returning curl's error message on failure
+///
+/// Webhook URLs of chat services carry their secret in the path, so the
+/// URL goes to curl in a config rather than on its command line.
+pub fn post_json(url: &str, body: &str) -> Result<(), String> {
+    let mut command = Command::new("curl");
+    command
+        .args(["-fsS", "-m", "10", "-X", "POST"])
+        .args(["-H", "Content-Type: application/json"])
+        .args(["--data-binary", "@-"]);
+    let config = config(&format!("url = \"{}\"\n", quote(url)))
+        .map_err(|e| format!("failed to pass the URL to curl: {}", e))?;
+    pass_config(&mut command, &config);
+    let spawned = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+    drop(config);
+    let mut child = spawned.map_err(|e| format!("failed to run curl: {}", e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(body.as_bytes())
+            .map_err(|e| format!("failed to write request: {}", e))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("failed to wait for curl: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// `url` cut down to its scheme and host, for messages: the path, query
+/// and user info of a URL may hold its secret
+pub fn origin(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    match scheme {
+        "" => host.to_string(),
+        scheme => format!("{}://{}", scheme, host),
+    }
+}
+
+/// `s` quoted for a value in a curl config
+pub fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Read end of a pipe holding the curl config `text`, which keeps what it
+/// holds off curl's command line, readable by any user in `/proc`
+pub fn config(text: &str) -> io::Result<OwnedFd> {
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for both ends of the pipe
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both descriptors were just opened and are owned by nothing else
+    let (read, mut write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    // Far less than a pipe holds, so this does not wait for a reader
+    write.write_all(text.as_bytes())?;
+    Ok(read)
+}
+
+/// Have curl read `config` with `--config`; only curl, which clears
+/// close-on-exec on it, inherits it. `config` must stay open until the
+/// command has been spawned.
+pub fn pass_config(command: &mut Command, config: &OwnedFd) {
+    let fd = config.as_raw_fd();
+    command.args(["--config", &format!("/dev/fd/{}", fd)]);
+    // SAFETY: fcntl is async-signal-safe, and fd stays open in the parent
+    // until after the spawn
+    unsafe {
+        command.pre_exec(move || match libc::fcntl(fd, libc::F_SETFD, 0) {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_leaves_out_path_and_user_info() {
+        assert_eq!(
+            origin("https://hooks.slack.com/services/T0/B0/secret"),
+            "https://hooks.slack.com"
+        );
+        assert_eq!(origin("http://user:pw@collector:4318/v1/metrics"), "http://collector:4318");
+        assert_eq!(origin("https://example.com?token=x"), "https://example.com");
+        assert_eq!(origin("example.com/a"), "example.com");
+    }
+
+    #[test]
+    fn quotes_config_values() {
+        assert_eq!(quote(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+}
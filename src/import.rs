@@ -0,0 +1,558 @@
+//! `memwatch report --from`: samples exported earlier or by other tools
+//!
+//! Two shapes are read. JSON lines hold one sample per line as written by
+//! `--record` or `--exporter-cmd`; lines of other types are skipped. CSV
+//! has a header naming its columns, in any order:
+//!
+//! - `elapsed_secs`, seconds since the start, or `time`, an RFC 3339
+//!   timestamp; one of them is required
+//! - `rss`, required, and `vsz`: bytes, or sizes such as `512M`
+//! - `processes`: number of processes in the tree
+//!
+//! Other columns, such as `schema_version`, are ignored.
+//!
+//! System-wide memory from sysstat and atop is read too: the text of
+//! `sar -r`, `sadf -d -- -r`, and `atop -P MEM,SWP`. Memory in use counts as
+//! RSS, committed virtual memory as VSZ.
+
+use std::{
+    fs,
+    io,
+    path::Path,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use memwatch::{
+    json::{
+        self,
+        Value,
+    },
+    record::{
+        self,
+        Sample,
+    },
+    size::parse_size,
+};
+
+/// Samples of an import, with what the file had besides RSS
+#[derive(Debug)]
+pub struct Imported {
+    pub samples: Vec<Sample>,
+    /// Whether the file had a `vsz` column or field
+    pub has_vsz: bool,
+    /// Largest `processes`, when the file had them
+    pub peak_processes: Option<u64>,
+}
+
+pub fn read(path: &Path) -> io::Result<Imported> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))?;
+    parse(path, &text)
+}
+
+/// [`read`] of `text` already read from `path`
+pub fn parse(path: &Path, text: &str) -> io::Result<Imported> {
+    let invalid = |line: usize, e: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}:{}: {}", path.display(), line, e),
+        )
+    };
+    let first = text.lines().find(|line| !line.trim().is_empty());
+    let imported = match first.map(str::trim) {
+        Some(line) if line.starts_with('{') => json_lines(text),
+        Some(line) if line.starts_with("Linux ") => sar(text),
+        Some(line) if line.starts_with("# hostname;") => sadf(text),
+        Some(line) if line == "RESET" || line.starts_with("MEM ") || line.starts_with("SWP ") => atop(text),
+        Some(_) => csv(text),
+        None => Err((1, String::from("no samples"))),
+    }
+    .map_err(|(line, e)| invalid(line, e))?;
+    if imported.samples.is_empty() {
+        return Err(invalid(1, String::from("no samples")));
+    }
+    Ok(imported)
+}
+
+fn json_lines(text: &str) -> Result<Imported, (usize, String)> {
+    let mut samples = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = json::parse(line).map_err(|e| (index + 1, e))?;
+        if !matches!(value.get("type").and_then(Value::as_str), None | Some("sample")) {
+            continue;
+        }
+        samples.push(Sample::from_json(&value).map_err(|e| (index + 1, e))?);
+    }
+    let peak_processes = samples.iter().map(|s| s.processes.len() as u64).max();
+    Ok(Imported {
+        samples,
+        has_vsz: true,
+        peak_processes,
+    })
+}
+
+fn csv(text: &str) -> Result<Imported, (usize, String)> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (header_index, header) = lines.next().unwrap_or((0, ""));
+    let columns: Vec<String> = fields(header).map(str::to_ascii_lowercase).collect();
+    let column = |name: &str| columns.iter().position(|c| c == name);
+    let (elapsed, time) = (column("elapsed_secs"), column("time"));
+    if elapsed.is_none() && time.is_none() {
+        return Err((header_index + 1, String::from("no `elapsed_secs` or `time` column")));
+    }
+    let rss = column("rss").ok_or((header_index + 1, String::from("no `rss` column")))?;
+    let (vsz, processes) = (column("vsz"), column("processes"));
+
+    let mut imported = Imported {
+        samples: Vec::new(),
+        has_vsz: vsz.is_some(),
+        peak_processes: None,
+    };
+    let mut started = None;
+    for (index, line) in lines {
+        let row: Vec<&str> = fields(line).collect();
+        let cell = |at: usize| {
+            row.get(at)
+                .copied()
+                .filter(|cell| !cell.is_empty())
+                .ok_or_else(|| format!("missing `{}`", columns[at]))
+        };
+        let mut parse = || -> Result<Sample, String> {
+            let wall = match time {
+                Some(at) => Some(
+                    record::parse_time(cell(at)?)
+                        .ok_or_else(|| format!("invalid time `{}`", cell(at).unwrap_or("")))?,
+                ),
+                None => None,
+            };
+            let elapsed = match (elapsed, wall) {
+                (Some(at), _) => {
+                    let secs: f64 = cell(at)?
+                        .parse()
+                        .map_err(|_| format!("invalid elapsed_secs `{}`", row[at]))?;
+                    Duration::try_from_secs_f64(secs)
+                        .map_err(|_| format!("invalid elapsed_secs `{}`", row[at]))?
+                }
+                // Times count from the first row
+                (None, Some(wall)) => {
+                    let started = *started.get_or_insert(wall);
+                    wall.duration_since(started).unwrap_or_default()
+                }
+                (None, None) => unreachable!(),
+            };
+            let size = |at: usize| cell(at).and_then(parse_size);
+            Ok(Sample {
+                elapsed,
+                rss: size(rss)?,
+                vsz: vsz.map(size).transpose()?.unwrap_or(0),
+                time: wall,
+                ..Sample::default()
+            })
+        };
+        let sample = parse().map_err(|e| (index + 1, e))?;
+        if let Some(at) = processes {
+            let count: u64 = cell(at)
+                .and_then(|cell| cell.parse().map_err(|_| format!("invalid processes `{}`", cell)))
+                .map_err(|e| (index + 1, e))?;
+            imported.peak_processes = Some(imported.peak_processes.unwrap_or(0).max(count));
+        }
+        imported.samples.push(sample);
+    }
+    Ok(imported)
+}
+
+/// Cells of a CSV line; quotes around a cell are dropped, but commas
+/// inside them are not supported
+fn fields(line: &str) -> impl Iterator<Item = &str> {
+    line.split(',').map(|cell| cell.trim().trim_matches('"'))
+}
+
+/// `sar -r` as printed: a `Linux` banner, then rows of a time of day and
+/// the columns named by the header, such as `kbmemused`
+fn sar(text: &str) -> Result<Imported, (usize, String)> {
+    let mut columns: Vec<&str> = Vec::new();
+    let mut imported = Imported {
+        samples: Vec::new(),
+        has_vsz: false,
+        peak_processes: None,
+    };
+    let mut started = None;
+    let mut previous = None;
+    let mut days = 0;
+    for (index, line) in text.lines().enumerate().skip(1) {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() || tokens[0].ends_with(':') || line.contains("RESTART") {
+            // Blank lines, `Average:` and restarts of the system
+            continue;
+        }
+        if tokens.contains(&"kbmemused") {
+            columns = tokens.into_iter().skip_while(|token| !token.starts_with("kb")).collect();
+            imported.has_vsz = columns.contains(&"kbcommit");
+            continue;
+        }
+        if columns.is_empty() {
+            return Err((index + 1, String::from("no `kbmemused` column; expected the output of sar -r")));
+        }
+        let Some(values) = tokens.len().checked_sub(columns.len()).map(|at| &tokens[at..]) else {
+            return Err((index + 1, String::from("fewer columns than the header")));
+        };
+        let at = time_of_day(&tokens[..tokens.len() - columns.len()])
+            .ok_or_else(|| (index + 1, format!("invalid time `{}`", tokens[0])))?;
+        // Times only go back at midnight
+        if previous.is_some_and(|previous| at < previous) {
+            days += 1;
+        }
+        previous = Some(at);
+        let at = at + days * 86_400;
+        let kb = |name: &str| -> Result<u64, (usize, String)> {
+            let Some(position) = columns.iter().position(|c| *c == name) else {
+                return Ok(0);
+            };
+            values[position]
+                .parse::<u64>()
+                .map(|kb| kb * 1024)
+                .map_err(|_| (index + 1, format!("invalid {} `{}`", name, values[position])))
+        };
+        imported.samples.push(Sample {
+            elapsed: Duration::from_secs(at - *started.get_or_insert(at)),
+            rss: kb("kbmemused")?,
+            vsz: kb("kbcommit")?,
+            ..Sample::default()
+        });
+    }
+    Ok(imported)
+}
+
+/// Seconds since midnight of `HH:MM:SS`, optionally followed by `AM` or
+/// `PM`
+fn time_of_day(tokens: &[&str]) -> Option<u64> {
+    let mut parts = tokens.first()?.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (parts.next()??, parts.next()??, parts.next()??);
+    let hour = match tokens.get(1).copied() {
+        None => hour,
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some("AM") => hour % 12,
+        Some("PM") => hour % 12 + 12,
+        Some(_) => return None,
+    };
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some(hour * 3600 + minute * 60 + second)
+}
+
+/// `sadf -d -- -r`: `;`-separated, with a `timestamp` such as
+/// `2024-05-01 00:10:01 UTC` and the columns of `sar -r`
+fn sadf(text: &str) -> Result<Imported, (usize, String)> {
+    let mut columns: Vec<&str> = Vec::new();
+    let mut imported = Imported {
+        samples: Vec::new(),
+        has_vsz: false,
+        peak_processes: None,
+    };
+    let mut started = None;
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        // `host;-1;timestamp;LINUX-RESTART`, and the header again
+        if line.contains("LINUX-RESTART") {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix("# ") {
+            columns = header.split(';').collect();
+            imported.has_vsz = columns.contains(&"kbcommit");
+            continue;
+        }
+        let row: Vec<&str> = line.split(';').collect();
+        let cell = |name: &str| {
+            columns
+                .iter()
+                .position(|c| *c == name)
+                .and_then(|at| row.get(at).copied())
+                .ok_or_else(|| (index + 1, format!("missing `{}`", name)))
+        };
+        let timestamp = cell("timestamp")?;
+        let wall = timestamp
+            .get(..19)
+            .and_then(|time| record::parse_time(&format!("{}Z", time)))
+            .ok_or_else(|| (index + 1, format!("invalid timestamp `{}`", timestamp)))?;
+        let started = *started.get_or_insert(wall);
+        let kb = |name: &str| -> Result<u64, (usize, String)> {
+            let value = cell(name)?;
+            value
+                .parse::<u64>()
+                .map(|kb| kb * 1024)
+                .map_err(|_| (index + 1, format!("invalid {} `{}`", name, value)))
+        };
+        imported.samples.push(Sample {
+            elapsed: wall.duration_since(started).unwrap_or_default(),
+            rss: kb("kbmemused")?,
+            vsz: if imported.has_vsz { kb("kbcommit")? } else { 0 },
+            time: Some(wall),
+            ..Sample::default()
+        });
+    }
+    Ok(imported)
+}
+
+/// `atop -P MEM,SWP`: a `MEM` line per interval, in pages, and optionally
+/// a `SWP` line with the committed virtual memory
+fn atop(text: &str) -> Result<Imported, (usize, String)> {
+    let mut imported = Imported {
+        samples: Vec::new(),
+        has_vsz: false,
+        peak_processes: None,
+    };
+    let mut started = None;
+    // Committed memory of the `SWP` line of each interval, by epoch
+    let mut committed = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let label = tokens.first().copied();
+        if !matches!(label, Some("MEM" | "SWP")) {
+            // `RESET`, `SEP` and the labels of other counters
+            continue;
+        }
+        // `LABEL host epoch date time interval` come first
+        let number = |at: usize| {
+            tokens
+                .get(at)
+                .and_then(|token| token.parse::<u64>().ok())
+                .ok_or_else(|| (index + 1, format!("invalid {} line, expected the output of atop -P", label.unwrap_or(""))))
+        };
+        let epoch = number(2)?;
+        let page = number(6)?;
+        if label == Some("SWP") {
+            // page size, swap size, free swap, unused, committed, limit
+            committed.push((epoch, number(10)? * page));
+            continue;
+        }
+        // page size, physical, free, page cache, buffers, slab, dirty,
+        // reclaimable slab
+        let used = number(7)?
+            .saturating_sub(number(8)?)
+            .saturating_sub(number(9)?)
+            .saturating_sub(number(10)?)
+            .saturating_sub(number(13)?);
+        let wall = SystemTime::UNIX_EPOCH + Duration::from_secs(epoch);
+        imported.samples.push(Sample {
+            elapsed: Duration::from_secs(epoch.saturating_sub(*started.get_or_insert(epoch))),
+            rss: used * page,
+            time: Some(wall),
+            ..Sample::default()
+        });
+    }
+    if !committed.is_empty() {
+        imported.has_vsz = true;
+        for sample in &mut imported.samples {
+            let epoch = sample.time.and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok());
+            sample.vsz = committed
+                .iter()
+                .find(|(at, _)| Some(Duration::from_secs(*at)) == epoch)
+                .map_or(0, |(_, bytes)| *bytes);
+        }
+    }
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(text: &str) -> Result<Imported, String> {
+        parse(Path::new("run.csv"), text).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn reads_csv_columns_in_any_order() {
+        let imported = import("processes,rss,elapsed_secs,vsz,schema_version\n3,1048576,0,2M,1\n5,\"2M\",1.5,4M,1\n").unwrap();
+        let samples: Vec<_> = imported.samples.iter().map(|s| (s.elapsed, s.rss, s.vsz)).collect();
+        assert_eq!(
+            samples,
+            [
+                (Duration::ZERO, 1 << 20, 2 << 20),
+                (Duration::from_millis(1500), 2 << 20, 4 << 20)
+            ]
+        );
+        assert!(imported.has_vsz);
+        assert_eq!(imported.peak_processes, Some(5));
+    }
+
+    #[test]
+    fn counts_csv_times_from_the_first_row() {
+        let text = "time,rss\n2025-03-01T12:00:00Z,1\n2025-03-01T12:00:02.5Z,2\n";
+        let imported = import(text).unwrap();
+        assert_eq!(imported.samples[1].elapsed, Duration::from_millis(2500));
+        assert_eq!(imported.samples[0].time, record::parse_time("2025-03-01T12:00:00Z"));
+        assert!(!imported.has_vsz);
+        assert_eq!(imported.peak_processes, None);
+    }
+
+    #[test]
+    fn refuses_csv_without_samples_or_columns() {
+        assert_eq!(import("elapsed_secs,rss\n").unwrap_err(), "run.csv:1: no samples");
+        assert_eq!(import("").unwrap_err(), "run.csv:1: no samples");
+        assert_eq!(
+            import("\nrss,vsz\n1,2\n").unwrap_err(),
+            "run.csv:2: no `elapsed_secs` or `time` column"
+        );
+        assert_eq!(import("elapsed_secs,vsz\n0,1\n").unwrap_err(), "run.csv:1: no `rss` column");
+    }
+
+    #[test]
+    fn reports_the_line_of_bad_csv_rows() {
+        assert_eq!(import("elapsed_secs,rss\n0,1\n1,\n").unwrap_err(), "run.csv:3: missing `rss`");
+        assert_eq!(import("elapsed_secs,rss\n0,1\n1\n").unwrap_err(), "run.csv:3: missing `rss`");
+        let e = import("elapsed_secs,rss\n0,1\n\n1,lots\n").unwrap_err();
+        assert!(e.starts_with("run.csv:4: invalid size `lots`"), "{}", e);
+        assert_eq!(
+            import("elapsed_secs,rss\nsoon,1\n").unwrap_err(),
+            "run.csv:2: invalid elapsed_secs `soon`"
+        );
+        assert_eq!(
+            import("elapsed_secs,rss\n-1,1\n").unwrap_err(),
+            "run.csv:2: invalid elapsed_secs `-1`"
+        );
+        assert_eq!(
+            import("time,rss\n2025-03-01 noon,1\n").unwrap_err(),
+            "run.csv:2: invalid time `2025-03-01 noon`"
+        );
+        assert_eq!(
+            import("elapsed_secs,rss,processes\n0,1,many\n").unwrap_err(),
+            "run.csv:2: invalid processes `many`"
+        );
+    }
+
+    #[test]
+    fn reads_json_lines_of_samples() {
+        let text = concat!(
+            r#"{"type":"sample","schema_version":1,"elapsed_secs":0.5,"rss":10,"vsz":20,"processes":[]}"#,
+            "\n",
+            r#"{"type":"mark","schema_version":1,"label":"x","elapsed_secs":1}"#,
+            "\n",
+            r#"{"type":"sample","schema_version":1,"elapsed_secs":1,"vsz":20,"processes":[]}"#,
+            "\n",
+        );
+        assert_eq!(import(text).unwrap_err(), "run.csv:3: missing field `rss`");
+        let imported = import(text.lines().next().unwrap()).unwrap();
+        assert_eq!(imported.samples[0].rss, 10);
+    }
+
+    const SAR: &str = "\
+Linux 6.8.0-45-generic (build01) \t05/01/2024 \t_x86_64_\t(32 CPU)
+
+11:50:01 PM kbmemfree   kbavail kbmemused  %memused kbbuffers  kbcached  kbcommit   %commit  kbactive   kbinact   kbdirty
+11:50:01 PM  52785344  60213556   9443780     14.36    376184   7278432  18942364     25.60   7183732   4438928       196
+12:00:01 AM  52436168  59880852   9776888     14.87    376200   7292872  19101988     25.82   7486824   4452164       248
+
+12:05:43 AM       LINUX RESTART\t(32 CPU)
+
+12:10:01 AM kbmemfree   kbavail kbmemused  %memused kbbuffers  kbcached  kbcommit   %commit  kbactive   kbinact   kbdirty
+12:10:01 AM  53785344  61213556   8443780     12.84    376184   7278432  17942364     24.25   7183732   4438928       112
+Average:     53002285  60436000   9221483     14.02    376189   7283245  18662239     25.22   7284763   4443340       185
+";
+
+    /// Seconds, RSS and VSZ in KiB of each sample
+    fn in_kib(imported: &Imported) -> Vec<(u64, u64, u64)> {
+        imported.samples.iter().map(|s| (s.elapsed.as_secs(), s.rss / 1024, s.vsz / 1024)).collect()
+    }
+
+    #[test]
+    fn reads_sar_across_midnight_and_restarts() {
+        let imported = import(SAR).unwrap();
+        assert_eq!(
+            in_kib(&imported),
+            [(0, 9443780, 18942364), (600, 9776888, 19101988), (1200, 8443780, 17942364)]
+        );
+        assert!(imported.has_vsz);
+    }
+
+    #[test]
+    fn reports_the_line_of_bad_sar_rows() {
+        let header_only = SAR.lines().take(3).collect::<Vec<_>>().join("\n");
+        assert_eq!(import(&header_only).unwrap_err(), "run.csv:1: no samples");
+        assert_eq!(
+            import("Linux 6.8.0\n\n11:50:01 PM 1 2 3\n").unwrap_err(),
+            "run.csv:3: no `kbmemused` column; expected the output of sar -r"
+        );
+        let bad = SAR.replace("9776888", "lots");
+        assert_eq!(import(&bad).unwrap_err(), "run.csv:5: invalid kbmemused `lots`");
+        let short = SAR.replace("12:00:01 AM  52436168  59880852", "12:00:01");
+        assert_eq!(import(&short).unwrap_err(), "run.csv:5: fewer columns than the header");
+        let late = SAR.replace("12:00:01 AM  5", "25:00:01 AM  5");
+        assert_eq!(import(&late).unwrap_err(), "run.csv:5: invalid time `25:00:01`");
+    }
+
+    const SADF: &str = "\
+# hostname;interval;timestamp;kbmemfree;kbavail;kbmemused;%memused;kbbuffers;kbcached;kbcommit;%commit
+build01;600;2024-05-01 00:10:01 UTC;52785344;60213556;9443780;14.36;376184;7278432;18942364;25.60
+build01;-1;2024-05-01 00:15:43 UTC;LINUX-RESTART\t(32 CPU)
+# hostname;interval;timestamp;kbmemfree;kbavail;kbmemused;%memused;kbbuffers;kbcached;kbcommit;%commit
+build01;600;2024-05-01 00:20:01 UTC;52436168;59880852;9776888;14.87;376200;7292872;19101988;25.82
+";
+
+    #[test]
+    fn reads_sadf_across_restarts() {
+        let imported = import(SADF).unwrap();
+        assert_eq!(in_kib(&imported), [(0, 9443780, 18942364), (600, 9776888, 19101988)]);
+        assert_eq!(imported.samples[0].time, record::parse_time("2024-05-01T00:10:01Z"));
+    }
+
+    #[test]
+    fn reports_the_line_of_bad_sadf_rows() {
+        let header_only = SADF.lines().next().unwrap();
+        assert_eq!(import(header_only).unwrap_err(), "run.csv:1: no samples");
+        let missing = SADF.replace(";9776888;14.87;376200;7292872;19101988;25.82", "");
+        assert_eq!(import(&missing).unwrap_err(), "run.csv:5: missing `kbmemused`");
+        let bad = SADF.replace("9443780", "-1");
+        assert_eq!(import(&bad).unwrap_err(), "run.csv:2: invalid kbmemused `-1`");
+        let stamp = SADF.replace("2024-05-01 00:20:01 UTC", "yesterday");
+        assert_eq!(import(&stamp).unwrap_err(), "run.csv:5: invalid timestamp `yesterday`");
+    }
+
+    const ATOP: &str = "\
+RESET
+MEM build01 1714522201 2024/05/01 00:10:01 600 4096 16384000 13196336 1819608 94046 402359 49 269923
+SWP build01 1714522201 2024/05/01 00:10:01 600 4096 2097151 2097151 0 4735591 10289151
+SEP
+MEM build01 1714522801 2024/05/01 00:20:01 600 4096 16384000 13096336 1819608 94046 402359 49 269923
+SEP
+";
+
+    #[test]
+    fn reads_atop_pages_as_bytes() {
+        let imported = import(ATOP).unwrap();
+        let used = 16384000 - 13196336 - 1819608 - 94046 - 269923;
+        let samples: Vec<_> =
+            imported.samples.iter().map(|s| (s.elapsed.as_secs(), s.rss, s.vsz)).collect();
+        assert_eq!(samples, [(0, used * 4096, 4735591 * 4096), (600, (used + 100000) * 4096, 0)]);
+        assert!(imported.has_vsz);
+    }
+
+    #[test]
+    fn reports_the_line_of_bad_atop_rows() {
+        assert_eq!(import("RESET\nSEP\n").unwrap_err(), "run.csv:1: no samples");
+        let bad = ATOP.replace("13096336", "many");
+        assert_eq!(
+            import(&bad).unwrap_err(),
+            "run.csv:5: invalid MEM line, expected the output of atop -P"
+        );
+        let short = ATOP.replace(" 4735591 10289151", "");
+        assert_eq!(
+            import(&short).unwrap_err(),
+            "run.csv:3: invalid SWP line, expected the output of atop -P"
+        );
+        let earlier = ATOP.replace("1714522801", "1714521601");
+        assert_eq!(import(&earlier).unwrap().samples[1].elapsed, Duration::ZERO);
+    }
+}
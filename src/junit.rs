@@ -0,0 +1,69 @@
+//! JUnit XML for CI systems that only display test results
+
+use std::{
+    fs,
+    io,
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    assertions::Outcome,
+    summary::Summary,
+};
+
+/// Write one test case per assertion outcome to `path`
+pub fn write(
+    path: &Path,
+    summary: &Summary,
+    elapsed: Duration,
+    outcomes: &[Outcome],
+) -> io::Result<()> {
+    let failures = outcomes.iter().filter(|outcome| !outcome.passed).count();
+    let classname = escape(&summary.command_line());
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites>\n  <testsuite name=\"memwatch\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\" hostname=\"{}\">\n",
+        outcomes.len(),
+        failures,
+        elapsed.as_secs_f64(),
+        escape(&summary.host)
+    ));
+    for outcome in outcomes {
+        xml.push_str(&format!(
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\">\n",
+            classname,
+            escape(&outcome.name),
+            elapsed.as_secs_f64()
+        ));
+        if !outcome.passed {
+            xml.push_str(&format!(
+                "      <failure message=\"{}\" type=\"memory_budget\">{}</failure>\n",
+                escape(&outcome.description),
+                escape(&outcome.detail)
+            ));
+        }
+        xml.push_str(&format!(
+            "      <system-out>{}</system-out>\n    </testcase>\n",
+            escape(&outcome.description)
+        ));
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+
+    fs::write(path, xml)
+}
+
+fn escape(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_control() || *c == '\t')
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,268 @@
+//! Java heap usage from `jcmd <pid> GC.heap_info`, with `--jvm`
+//!
+//! RSS of a JVM is mostly heap it has committed; what is left over is
+//! native memory (metaspace, threads, JIT, direct buffers). Comparing the
+//! two tells whether a growing RSS is a heap problem or a native one.
+
+use std::{
+    process::{
+        Command,
+        Stdio,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    json::Value,
+    size::{
+        format_bytes,
+        parse_size,
+    },
+    tree::TreeSample,
+};
+
+/// Least time between two rounds of `jcmd`, which starts a JVM of its own
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Native memory growth reported once it is past both of these
+const NATIVE_GROWTH_MIN: u64 = 64 * 1024 * 1024;
+const NATIVE_GROWTH_SHARE: f64 = 0.2;
+
+/// Heap of every JVM in the tree, summed
+#[derive(Clone, Copy, Default)]
+struct Heap {
+    used: u64,
+    committed: u64,
+    /// RSS of the JVMs queried
+    rss: u64,
+}
+
+impl Heap {
+    fn native(&self) -> u64 {
+        self.rss.saturating_sub(self.committed)
+    }
+}
+
+/// Result of the latest round of queries
+#[derive(Default)]
+struct Poll {
+    heap: Option<Heap>,
+    error: Option<String>,
+}
+
+/// Peaks reported in the summary
+pub struct JvmStats {
+    pub heap_used_peak: u64,
+    pub heap_committed_peak: u64,
+    pub native_peak: u64,
+    /// Native memory grew while the heap did not account for it
+    pub native_growing: bool,
+}
+
+impl JvmStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("heap_used_peak".into(), self.heap_used_peak.into()),
+            ("heap_committed_peak".into(), self.heap_committed_peak.into()),
+            ("native_peak".into(), self.native_peak.into()),
+            ("native_growing".into(), self.native_growing.into()),
+        ])
+    }
+}
+
+/// Polls the Java processes of the tree on a background thread
+pub struct JvmProbe {
+    poll: Arc<Mutex<Poll>>,
+    busy: Arc<AtomicBool>,
+    last_poll: Option<Instant>,
+    latest: Option<Heap>,
+    /// First reading, which growth is measured from
+    baseline: Option<Heap>,
+    stats: JvmStats,
+    error_reported: bool,
+}
+
+impl Default for JvmProbe {
+    fn default() -> Self {
+        Self {
+            poll: Arc::default(),
+            busy: Arc::default(),
+            last_poll: None,
+            latest: None,
+            baseline: None,
+            stats: JvmStats {
+                heap_used_peak: 0,
+                heap_committed_peak: 0,
+                native_peak: 0,
+                native_growing: false,
+            },
+            error_reported: false,
+        }
+    }
+}
+
+impl JvmProbe {
+    /// Start a round of queries if one is due; returns notices to print
+    pub fn update(&mut self, sample: &TreeSample) -> Vec<String> {
+        let mut notices = Vec::new();
+        let (heap, error) = {
+            let mut poll = self.poll.lock().unwrap();
+            (poll.heap.take(), poll.error.take())
+        };
+        if let Some(error) = error {
+            if !std::mem::replace(&mut self.error_reported, true) {
+                notices.push(format!("memwatch: --jvm: {}", error));
+            }
+        }
+        if let Some(heap) = heap {
+            notices.extend(self.record(heap));
+        }
+
+        let due = self.last_poll.is_none_or(|last| last.elapsed() >= POLL_INTERVAL);
+        let jvms: Vec<(i32, u64)> = sample
+            .processes
+            .iter()
+            .filter(|p| p.name == "java")
+            .map(|p| (p.pid, p.rss))
+            .collect();
+        if due && !jvms.is_empty() && !self.busy.swap(true, Ordering::SeqCst) {
+            self.last_poll = Some(Instant::now());
+            let (poll, busy) = (self.poll.clone(), self.busy.clone());
+            thread::spawn(move || {
+                let result = query(&jvms);
+                let mut poll = poll.lock().unwrap();
+                match result {
+                    Ok(heap) => poll.heap = Some(heap),
+                    Err(e) => poll.error = Some(e),
+                }
+                busy.store(false, Ordering::SeqCst);
+            });
+        }
+        notices
+    }
+
+    fn record(&mut self, heap: Heap) -> Option<String> {
+        self.latest = Some(heap);
+        let stats = &mut self.stats;
+        stats.heap_used_peak = stats.heap_used_peak.max(heap.used);
+        stats.heap_committed_peak = stats.heap_committed_peak.max(heap.committed);
+        stats.native_peak = stats.native_peak.max(heap.native());
+
+        let baseline = *self.baseline.get_or_insert(heap);
+        let native_growth = heap.native().saturating_sub(baseline.native());
+        let heap_growth = heap.committed.saturating_sub(baseline.committed);
+        let growing = native_growth > NATIVE_GROWTH_MIN
+            && native_growth as f64 > baseline.native() as f64 * NATIVE_GROWTH_SHARE
+            && native_growth > heap_growth;
+        if !growing || std::mem::replace(&mut stats.native_growing, true) {
+            return None;
+        }
+        Some(format!(
+            "memwatch: JVM native (non-heap) memory grew by {} while committed heap grew by {}",
+            format_bytes(native_growth),
+            format_bytes(heap_growth)
+        ))
+    }
+
+    /// Heap and native memory for the status line
+    pub fn status(&self) -> Vec<String> {
+        let Some(heap) = self.latest else {
+            return Vec::new();
+        };
+        vec![
+            format!("Heap: {} / {}", format_bytes(heap.used), format_bytes(heap.committed)),
+            format!(
+                "Native: {}{}",
+                format_bytes(heap.native()),
+                if self.stats.native_growing { " ↑" } else { "" }
+            ),
+        ]
+    }
+
+    /// Peaks, if any JVM was ever queried
+    pub fn finish(self) -> Option<JvmStats> {
+        self.baseline.map(|_| self.stats)
+    }
+}
+
+/// Query every JVM; the heaps of those that answer are summed
+fn query(jvms: &[(i32, u64)]) -> Result<Heap, String> {
+    let mut total = Heap::default();
+    let mut answered = false;
+    let mut last_error = None;
+    for &(pid, rss) in jvms {
+        let output = Command::new("jcmd")
+            .args([&pid.to_string(), "GC.heap_info"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|e| format!("failed to run jcmd: {}", e))?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        match parse_heap_info(&text) {
+            Some((used, committed)) if output.status.success() => {
+                total.used += used;
+                total.committed += committed;
+                total.rss += rss;
+                answered = true;
+            }
+            _ => last_error = Some(format!("jcmd {} GC.heap_info gave no heap sizes", pid)),
+        }
+    }
+    match (answered, last_error) {
+        (false, Some(e)) => Err(e),
+        _ => Ok(total),
+    }
+}
+
+/// Used and committed heap from `GC.heap_info`, summed over generations
+///
+/// Serial, Parallel and G1 print `total <committed>, used <used>` per
+/// generation, ZGC `used <used>, capacity <committed>` and Shenandoah
+/// `<committed> committed, <used> used`. Metaspace is not heap.
+fn parse_heap_info(text: &str) -> Option<(u64, u64)> {
+    let mut found = false;
+    let (mut used, mut committed) = (0, 0);
+    for line in text.lines().map(str::trim) {
+        if line.starts_with("Metaspace") || line.starts_with("class space") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let value = |key: &str| {
+            fields.iter().find_map(|field| {
+                let size = field
+                    .strip_prefix(key)
+                    .or_else(|| field.split_once(key).map(|(_, size)| size))?;
+                size.split_whitespace().next().and_then(|s| parse_size(s).ok())
+            })
+        };
+        let suffixed = |key: &str| {
+            fields.iter().find_map(|field| {
+                let size = field.strip_suffix(key)?;
+                parse_size(size.trim()).ok()
+            })
+        };
+        let sizes = match (value("total "), value("used "), value("capacity ")) {
+            (Some(total), Some(u), _) => Some((u, total)),
+            (None, Some(u), Some(capacity)) => Some((u, capacity)),
+            _ => suffixed(" used").zip(suffixed(" committed")),
+        };
+        if let Some((u, c)) = sizes {
+            used += u;
+            committed += c;
+            found = true;
+        }
+    }
+    found.then_some((used, committed))
+}
@@ -0,0 +1,18 @@
+//! memwatch as a library: sampling the memory of a process tree and typed
+//! records of the results
+
+pub mod alert;
+pub mod exit;
+pub mod json;
+pub mod monitor;
+pub mod observer;
+pub mod record;
+pub mod schema;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod size;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod taskstats;
+pub mod ticks;
+pub mod tree;
@@ -0,0 +1,121 @@
+//! `-v`: diagnostics about memwatch itself
+//!
+//! Leveled lines with the time since memwatch started, written to stderr
+//! or to `--log-file`. Logging is off unless asked for, and a disabled
+//! level costs one atomic load: the macros format nothing.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{
+        self,
+        IsTerminal,
+        Write,
+    },
+    path::Path,
+    sync::{
+        atomic::{
+            AtomicU8,
+            Ordering,
+        },
+        Mutex,
+        OnceLock,
+    },
+    time::Instant,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    /// `-v`: tree changes, exporter failures and other events
+    Info = 1,
+    /// `-vv`: also every sample, with its timing and `/proc` scan size
+    Debug = 2,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(0);
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+struct Sink {
+    start: Instant,
+    /// `--log-file`, or stderr without one
+    file: Option<Mutex<File>>,
+}
+
+/// Log at `verbosity`, the number of `-v` given, to `file` or stderr
+pub fn init(verbosity: u8, file: Option<&Path>) -> io::Result<()> {
+    if verbosity == 0 {
+        return Ok(());
+    }
+    let file = match file {
+        Some(path) => Some(Mutex::new(File::create(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot create {}: {}", path.display(), e))
+        })?)),
+        None => None,
+    };
+    let _ = SINK.set(Sink {
+        start: Instant::now(),
+        file,
+    });
+    LEVEL.store(verbosity, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn enabled(level: Level) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level as u8
+}
+
+/// Write one line; use the [`info`] and [`debug`] macros instead
+pub fn write(level: Level, args: fmt::Arguments) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let line = format!(
+        "{:>10.3} {:<5} {}\n",
+        sink.start.elapsed().as_secs_f64(),
+        level.name(),
+        args
+    );
+    match &sink.file {
+        Some(file) => {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.write_all(line.as_bytes());
+            }
+        }
+        None => {
+            let mut stderr = io::stderr().lock();
+            if stderr.is_terminal() {
+                // Clear the status line; it is redrawn with the next sample
+                let _ = stderr.write_all(b"\r\x1b[K");
+            }
+            let _ = stderr.write_all(line.as_bytes());
+        }
+    }
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Info) {
+            $crate::log::write($crate::log::Level::Info, format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use debug;
+pub(crate) use info;
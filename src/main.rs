@@ -1,61 +1,837 @@
 use std::{
-    collections::HashMap,
+    cmp,
     io::{
         self,
-        Write,
+        IsTerminal,
+        Read,
+    },
+    os::unix::process::CommandExt,
+    path::{
+        Path,
+        PathBuf,
     },
     process::{
+        Child,
         Command,
+        ExitCode,
         Stdio,
     },
     sync::{
         atomic::{
             AtomicBool,
+            AtomicU64,
             Ordering,
         },
         mpsc,
         Arc,
+        Mutex,
     },
     thread,
     time::{
         Duration,
         Instant,
+        SystemTime,
     },
 };
 
-use clap::Parser;
-use procfs::process::{
-    all_processes,
-    Process,
-};
 use termion::{
-    clear,
-    cursor,
+    color,
+    event::Key,
 };
 
-enum OutputMsg {
-    Stdout(String),
-    Stderr(String),
-}
+use clap::Parser;
+
+mod anomaly;
+mod assertions;
+mod ballast;
+mod baseline;
+mod bench;
+mod budget;
+mod bundle;
+mod cgroup;
+mod churn;
+mod clock;
+mod compare;
+mod config;
+mod ctl;
+mod diff;
+mod doctor;
+mod email;
+mod exporter;
+mod expr;
+mod extract;
+mod fleet;
+mod floor;
+mod freeze;
+mod gha;
+mod graphite;
+mod guard;
+mod histogram;
+mod http;
+mod import;
+mod junit;
+mod jvm;
+mod log;
+mod multi;
+mod net;
+mod notify;
+mod otel;
+mod output;
+mod output_log;
+mod overhead;
+mod pattern;
+mod perfetto;
+mod power;
+mod proc_dump;
+mod pty;
+mod recording;
+mod redact;
+mod render;
+mod replay;
+mod report;
+mod rolling;
+mod runtime;
+mod selftest;
+mod stats;
+mod status_file;
+mod status_line;
+mod steps;
+mod stress;
+mod summary;
+mod swap;
+mod system_delta;
+mod target;
+mod thermal;
+mod threads;
+mod timeline;
+mod timestamps;
+mod tmpfs;
+mod trace;
+mod tui;
+mod watch_dir;
+mod window;
+mod working_set;
+
+use memwatch::{
+    alert,
+    exit,
+    json,
+    observer,
+    record,
+    schema,
+    size,
+    taskstats,
+    ticks,
+    tree,
+};
+
+use alert::{
+    Alert,
+    Rearm,
+};
+use anomaly::Detector;
+use assertions::{
+    Assertion,
+    Metric,
+};
+use ballast::Ballast;
+use baseline::Baseline;
+use bench::{
+    BenchArgs,
+    BenchCommand,
+};
+use bundle::Bundles;
+use cgroup::Cgroup;
+use churn::Churn;
+use clock::Clock;
+use compare::CompareArgs;
+use config::Config;
+use ctl::{
+    Control,
+    CtlArgs,
+    Request,
+};
+use diff::DiffArgs;
+use email::Mailer;
+use exit::ExitInfo;
+use exporter::CommandExporter;
+use extract::Extractor;
+use fleet::Fleet;
+use floor::{
+    FloorEvent,
+    SystemFloor,
+};
+use freeze::Decision;
+use graphite::GraphiteExporter;
+use guard::GuardArgs;
+use jvm::JvmProbe;
+use multi::MultiArgs;
+use net::NetProbe;
+use notify::Webhook;
+use observer::{
+    Crossing,
+    IntervalChange,
+    Mark,
+    Observer,
+};
+use otel::OtelExporter;
+use overhead::TickCost;
+use output::{
+    OutputFilter,
+    Stream,
+};
+use output_log::{
+    OutputLog,
+    Tee,
+};
+use pattern::Pattern;
+use perfetto::PerfettoRecorder;
+use power::PowerMeter;
+use pty::Pty;
+use recording::Recorder;
+use record::Sample;
+use replay::ReplayArgs;
+use report::ReportArgs;
+use selftest::SelftestArgs;
+use rolling::Rolling;
+use runtime::RuntimeHeaps;
+use render::{
+    OutputMsg,
+    Renderer,
+    Title,
+};
+use size::NumberFormat;
+use status_file::StatusFile;
+use status_line::{
+    Priority,
+    StatusLine,
+};
+use steps::StepDetector;
+use stress::StressArgs;
+use summary::Summary;
+use swap::SwapProbe;
+use system_delta::SystemDelta;
+use target::Target;
+use ticks::Ticks;
+use timeline::Timeline;
+use timestamps::OutputTimestamps;
+use tmpfs::TmpfsTracker;
+use trace::TraceRecorder;
+use tree::{
+    Denied,
+    ProcessEvent,
+    ProcessTracker,
+    Sampler,
+    TreeSample,
+};
+use watch_dir::{
+    DirSeries,
+    DirWatcher,
+};
+use window::{
+    Schedule,
+    Window,
+};
+use working_set::WorkingSet;
 
 #[derive(Parser, Debug)]
 #[command(
     name = "memwatch",
     about = "Run a command and watch its memory (Linux)"
 )]
-#[command(version)]
+#[command(version, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    mode: Option<Mode>,
+
+    /// Without a subcommand, `memwatch [OPTIONS] -- <command>` is `run`
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Log what memwatch itself does: -v for tree changes and exporter
+    /// failures, -vv also every sample with its timing and /proc scan size
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Write the -v log to this file instead of stderr
+    #[arg(long, value_name = "FILE", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// How sizes are written in status lines, summaries and reports
+    #[arg(long, value_enum, global = true, default_value_t = NumberFormat::Binary)]
+    number_format: NumberFormat,
+
+    /// Write exact byte counts with no units, for scripts; short for
+    /// --number-format plain
+    #[arg(long, global = true, conflicts_with = "number_format")]
+    raw: bool,
+
+    /// Replace what matches PATTERN in the command lines of summaries,
+    /// recordings and bundles with ***, besides passwords and tokens;
+    /// repeatable
+    #[arg(long, value_name = "PATTERN", global = true, value_parser = Pattern::parse)]
+    redact: Vec<Pattern>,
+}
+
+/// How often resizes and requests for samples are checked for between
+/// samples
+const WAKE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options of `run` that only watching a single tree applies; benchmark
+/// mode measures each run with only the interval, and a fleet is only
+/// sampled for its totals
+const TREE_ONLY: [&str; 70] = [
+    "rolling", "delta", "focus_max", "overlay_baseline", "detect_anomalies", "detect_steps",
+    "warn_rss", "max_rss", "system_floor", "pause_below_floor", "between", "ballast",
+    "alert_cooldown", "alert_hysteresis", "notify_url", "email", "email_after", "notify_desktop",
+    "osc", "title", "status_file", "control_socket", "gha", "otel", "graphite", "graphite_prefix",
+    "exporter_cmd", "freeze_on_limit", "bundle_dir", "tui", "series", "kill_tree", "jvm",
+    "working_set", "swap", "churn", "net", "power", "system_delta", "tmpfs", "watch_dir", "cheap",
+    "proc_events", "assert_peak_rss", "assert_peak_vsz", "assert_expr", "budget_file", "no_budget",
+    "junit", "report_md", "export_trace", "export_perfetto", "self_stats", "timestamps", "record",
+    "summary_fd", "cgroup", "memory_high", "reclaim_every", "interactive", "no_output",
+    "max_output_rate", "tag_lines", "runtime_heap", "extract", "extract_csv", "collapse_repeats",
+    "output_log", "log_rotate", "log_gzip",
+];
+
+/// Options of `run` that benchmark mode leaves unused besides `TREE_ONLY`
+const NOT_BENCHED: [&str; 7] = ["watch_for", "schema", "threads_of", "user_all", "matching", "top", "slice"];
+
+/// Options of `run`: what to watch for and how to spawn the command
+#[derive(clap::Args, Debug)]
+#[command(group(
+    clap::ArgGroup::new("tree_only")
+        .args(TREE_ONLY)
+        .multiple(true)
+        .conflicts_with("BenchArgs")
+))]
+#[command(group(
+    clap::ArgGroup::new("not_benched")
+        .args(NOT_BENCHED)
+        .multiple(true)
+        .conflicts_with("BenchArgs")
+))]
+struct RunArgs {
+    #[command(flatten)]
+    watch: WatchArgs,
+
+    #[command(flatten)]
+    spawn: SpawnArgs,
+
+    /// Print the JSON Schema of samples or of the summary and exit
+    #[arg(long, value_enum, value_name = "RECORD")]
+    schema: Option<SchemaRecord>,
+
+    /// Print the threads of PID with their CPU time, faults and stacks and
+    /// exit
+    #[arg(long, value_name = "PID")]
+    threads_of: Option<i32>,
+
+    /// Watch every process of the user NAME (or UID) instead of a command,
+    /// until Ctrl+C
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = ["command", "threads_of"],
+        conflicts_with_all = TREE_ONLY
+    )]
+    user_all: Option<String>,
+
+    /// Watch every process whose command line matches PATTERN, now and
+    /// later, instead of a command, until Ctrl+C
+    #[arg(
+        long = "match",
+        value_name = "PATTERN",
+        value_parser = Pattern::parse,
+        conflicts_with_all = ["command", "threads_of", "user_all"],
+        conflicts_with_all = TREE_ONLY
+    )]
+    matching: Option<Pattern>,
+
+    /// Without a command, list the N largest processes of the system by
+    /// RSS, with their PSS and recent history, until Ctrl+C
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        default_missing_value = "20",
+        conflicts_with_all = ["command", "threads_of", "user_all", "matching"],
+        conflicts_with_all = TREE_ONLY
+    )]
+    top: Option<usize>,
+
+    /// Watch every process of the existing cgroup PATH and the cgroups
+    /// below it, such as user.slice or machine.slice, until Ctrl+C
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["command", "threads_of", "user_all", "matching", "top"],
+        conflicts_with_all = TREE_ONLY
+    )]
+    slice: Option<String>,
+
+    #[command(flatten)]
+    bench: BenchArgs,
+
+    /// Command to run (everything after `--`)
+    #[arg(trailing_var_arg = true, required_unless_present_any = ["schema", "threads_of", "user_all", "matching", "top", "slice"])]
+    command: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct AttachArgs {
+    #[command(flatten)]
+    watch: WatchArgs,
+
+    /// Process to watch, with all of its descendants
+    pid: i32,
+}
+
+/// Options shared by `run` and `attach`
+#[derive(clap::Args, Debug)]
+struct WatchArgs {
     /// Update interval in milliseconds
     #[arg(short, long, default_value_t = 1000)]
     interval: u64,
 
-    /// Command to run (everything after `--`)
-    #[arg(trailing_var_arg = true, required = true)]
-    command: Vec<String>,
+    /// Clock at the start of status lines: time since the start, local
+    /// time of day, or both
+    #[arg(long, value_enum, default_value_t = Clock::Elapsed)]
+    clock: Clock,
+
+    /// Show the average and maximum RSS over these recent windows next to
+    /// the current value (e.g. 1m,5m)
+    #[arg(
+        long,
+        value_name = "WINDOWS",
+        value_delimiter = ',',
+        value_parser = clock::parse_duration
+    )]
+    rolling: Vec<Duration>,
+
+    /// Show the change in RSS since the previous sample, red when it grew
+    /// and green when it shrank
+    #[arg(long)]
+    delta: bool,
+
+    /// Show the name and RSS of the largest process of the tree
+    #[arg(long)]
+    focus_max: bool,
+
+    /// Show the RSS of a recorded run at the same elapsed time next to the
+    /// current value, with the difference in percent
+    #[arg(long, value_name = "FILE")]
+    overlay_baseline: Option<PathBuf>,
+
+    /// Report samples whose RSS strays more than SIGMA standard deviations
+    /// from its recent level [default: 4]
+    #[arg(
+        long,
+        value_name = "SIGMA",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "4",
+        value_parser = anomaly::parse_sigma
+    )]
+    detect_anomalies: Option<f64>,
+
+    /// List the largest jumps in RSS in the final report, with the output
+    /// line before each; a jump is at least SIZE, or 10% of RSS without it
+    #[arg(
+        long,
+        value_name = "SIZE",
+        num_args = 0..=1,
+        require_equals = true,
+        value_parser = size::parse_size
+    )]
+    detect_steps: Option<Option<u64>>,
+
+    /// Print a warning when total RSS exceeds this size (e.g. 512M, 2G)
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    warn_rss: Option<u64>,
+
+    /// Kill the command when total RSS exceeds this size (e.g. 512M, 2G)
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    max_rss: Option<u64>,
+
+    /// Stop after this long and report: a command is stopped as on Ctrl+C,
+    /// an attached process is left running (e.g. 10m)
+    #[arg(long = "for", value_name = "TIME", value_parser = clock::parse_duration)]
+    watch_for: Option<Duration>,
+
+    /// Warn when the machine's MemAvailable falls below this size (e.g. 1G)
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    system_floor: Option<u64>,
+
+    /// Stop the tree while MemAvailable is below --system-floor, and
+    /// continue it once memory is back
+    #[arg(long, requires = "system_floor", conflicts_with = "freeze_on_limit")]
+    pause_below_floor: bool,
+
+    /// Only count samples and look for breaches in this window of local
+    /// time, e.g. 22:00-06:00; repeatable
+    #[arg(long, value_name = "HH:MM-HH:MM", value_parser = window::parse_window)]
+    between: Vec<Window>,
+
+    /// Allocate and hold SIZE of memory in memwatch itself while the command
+    /// runs, to see how it copes with less memory (e.g. 2G)
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    ballast: Option<u64>,
+
+    /// Let --warn-rss and a resumed --max-rss fire again, at most once per
+    /// TIME (e.g. 5m)
+    #[arg(long, value_name = "TIME", value_parser = clock::parse_duration)]
+    alert_cooldown: Option<Duration>,
+
+    /// Let --warn-rss and a resumed --max-rss fire again once RSS falls this
+    /// far below the limit (e.g. 100M or 10%)
+    #[arg(long, value_name = "SIZE", value_parser = alert::parse_hysteresis)]
+    alert_hysteresis: Option<alert::Hysteresis>,
+
+    /// POST a JSON payload to this URL on threshold crossings and on exit
+    #[arg(long, value_name = "URL")]
+    notify_url: Option<String>,
+
+    /// Email this address on a --max-rss breach and when the command
+    /// finishes, through the [smtp] settings of memwatch.toml; repeatable
+    #[arg(long, value_name = "ADDRESS")]
+    email: Vec<String>,
+
+    /// Email about a finished command only if it ran at least this long
+    #[arg(long, value_name = "TIME", requires = "email", value_parser = clock::parse_duration)]
+    email_after: Option<Duration>,
+
+    /// Show a desktop notification when the command finishes
+    #[arg(long)]
+    notify_desktop: bool,
+
+    /// Show a native terminal notification (OSC 9/777) on threshold crossings and exit
+    #[arg(long)]
+    osc: bool,
+
+    /// Mirror elapsed time and RSS into the terminal window title
+    #[arg(long)]
+    title: bool,
+
+    /// Keep a one-line status in this file (and JSON in FILE.json), e.g. for tmux
+    #[arg(long, value_name = "FILE")]
+    status_file: Option<PathBuf>,
+
+    /// Take samples on demand from `memwatch ctl SOCKET sample` through this
+    /// Unix socket; SIGUSR1 takes one without it
+    #[arg(long, value_name = "SOCKET")]
+    control_socket: Option<PathBuf>,
+
+    /// Print GitHub Actions annotations on threshold crossings and add a
+    /// table of the run to the job summary
+    #[arg(long)]
+    gha: bool,
+
+    /// Push samples as OTLP metrics to this collector (e.g. http://localhost:4318)
+    #[arg(long, value_name = "ENDPOINT")]
+    otel: Option<String>,
+
+    /// Send samples to a Graphite/Carbon plaintext receiver
+    #[arg(long, value_name = "HOST:PORT")]
+    graphite: Option<String>,
+
+    /// Graphite metric path prefix [default: memwatch.<program>]
+    #[arg(long, value_name = "PATH", requires = "graphite")]
+    graphite_prefix: Option<String>,
+
+    /// Stream samples as JSON lines to the stdin of this shell command, for
+    /// exporters written in any language; repeatable
+    #[arg(long, value_name = "COMMAND")]
+    exporter_cmd: Vec<String>,
+
+    /// On --max-rss breach, freeze the tree for inspection instead of killing it
+    #[arg(long, requires = "max_rss")]
+    freeze_on_limit: bool,
+
+    /// Copy the /proc files of every process in the tree to a new directory
+    /// under DIR on threshold crossings, Ctrl+C and exit
+    #[arg(long, value_name = "DIR")]
+    bundle_dir: Option<PathBuf>,
+
+    /// Full-screen UI plotting two metrics above the command's output
+    #[arg(long, conflicts_with = "freeze_on_limit")]
+    tui: bool,
+
+    /// Metrics plotted on the left and right axes with --tui, from rss, vsz,
+    /// cpu, minflt, majflt and procs; keys 1 and 2 cycle them
+    #[arg(long, value_name = "LEFT,RIGHT", default_value = "rss,cpu", value_parser = tui::parse_series)]
+    series: [tui::Series; 2],
+
+    /// On exit, interrupt or limit breach, kill all descendants, not just the command
+    #[arg(long)]
+    kill_tree: bool,
+
+    /// Poll Java processes with `jcmd` to show heap next to RSS and flag
+    /// native memory growth
+    #[arg(long)]
+    jvm: bool,
+
+    /// Estimate how much of RSS is touched between samples with idle page
+    /// tracking, showing hot memory next to RSS (root)
+    #[arg(long)]
+    working_set: bool,
+
+    /// Show what the tree has swapped out and how much zswap and zram hold
+    /// in RAM next to RSS
+    #[arg(long)]
+    swap: bool,
+
+    /// Show how many processes the tree starts and execs per second and
+    /// count them in the summary
+    #[arg(long)]
+    churn: bool,
+
+    /// Show the sockets the tree holds, and its traffic when it runs in a
+    /// network namespace of its own
+    #[arg(long)]
+    net: bool,
+
+    /// Show the machine's power draw from RAPL counters and report the
+    /// energy used during the run (root)
+    #[arg(long)]
+    power: bool,
+
+    /// Compare how far MemAvailable falls with how far the tree's RSS
+    /// grows, to catch kernel slab and tmpfs the tree is behind
+    #[arg(long)]
+    system_delta: bool,
+
+    /// Count files created under DIR during the run, /dev/shm without it,
+    /// as memory outside RSS; repeatable
+    #[arg(
+        long,
+        value_name = "DIR",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "/dev/shm"
+    )]
+    tmpfs: Vec<PathBuf>,
+
+    /// Track the disk space taken by DIR over the run, e.g. a scratch
+    /// directory; repeatable
+    #[arg(long, value_name = "DIR")]
+    watch_dir: Vec<PathBuf>,
+
+    /// Read only statm of known processes between once-a-second rescans of
+    /// /proc, for fine intervals; tree membership may lag behind
+    #[arg(long)]
+    cheap: bool,
+
+    /// Print a line whenever a process joins or leaves the tree
+    #[arg(long)]
+    proc_events: bool,
+
+    /// Fail (exit status 1) if the peak RSS of the run exceeds this size
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    assert_peak_rss: Option<u64>,
+
+    /// Fail (exit status 1) if the peak VSZ of the run exceeds this size
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    assert_peak_vsz: Option<u64>,
+
+    /// Fail (exit status 1) unless the expression holds for the run, e.g.
+    /// 'peak_rss < 2G && avg_cpu < 150%'; repeatable
+    #[arg(long = "assert", value_name = "EXPR", value_parser = expr::parse)]
+    assert_expr: Vec<expr::Expr>,
+
+    /// Read budgets and SMTP settings from this file instead of
+    /// memwatch.toml in the current directory or a parent
+    #[arg(long, value_name = "FILE", conflicts_with = "no_budget")]
+    budget_file: Option<PathBuf>,
+
+    /// Ignore the budgets of memwatch.toml
+    #[arg(long)]
+    no_budget: bool,
+
+    /// Write the results of assertions and budgets as a JUnit XML report to
+    /// this file
+    #[arg(long, value_name = "FILE")]
+    junit: Option<PathBuf>,
+
+    /// Write a Markdown report with a chart and per-process peaks to this file
+    #[arg(long, value_name = "FILE")]
+    report_md: Option<PathBuf>,
+
+    /// Write the timeline as a Chrome Trace Event file (Perfetto UI, speedscope)
+    #[arg(long, value_name = "FILE")]
+    export_trace: Option<PathBuf>,
+
+    /// Write per-process RSS counter tracks as a Perfetto protobuf trace
+    #[arg(long, value_name = "FILE")]
+    export_perfetto: Option<PathBuf>,
+
+    /// Report memwatch's own CPU time, memory and sampling cost per tick
+    #[arg(long)]
+    self_stats: bool,
+
+    /// Clocks of sample times per output format, as FORMAT=KIND with json or
+    /// csv and elapsed, wall or both; repeatable [default: json=both csv=elapsed]
+    #[arg(long, value_name = "FORMAT=KIND", value_parser = timestamps::parse_setting)]
+    timestamps: Vec<timestamps::Setting>,
+
+    /// Record every sample and the summary to this file, for `memwatch
+    /// replay`, `report` and `diff`
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// Write the final JSON summary to this file
+    #[arg(long, value_name = "FILE")]
+    summary_json: Option<PathBuf>,
+
+    /// Write the final JSON summary to this already-open file descriptor
+    #[arg(long, value_name = "FD", value_parser = summary::parse_fd)]
+    summary_fd: Option<i32>,
 }
 
-fn main() -> io::Result<()> {
-    let args = Args::parse();
+/// Options only meaningful for a command memwatch starts itself
+#[derive(clap::Args, Debug, Default)]
+struct SpawnArgs {
+    /// Run the command in a dedicated cgroup to account short-lived processes
+    #[arg(long)]
+    cgroup: bool,
+
+    /// Throttle the command's cgroup above SIZE by writing memory.high, to
+    /// see how it copes near a limit (cgroup v2)
+    #[arg(long, value_name = "SIZE", requires = "cgroup", value_parser = size::parse_size)]
+    memory_high: Option<u64>,
+
+    /// Make the kernel reclaim what it can of the command's cgroup every
+    /// TIME and show how far RSS falls, e.g. 30s (cgroup v2, Linux 5.19)
+    #[arg(long, value_name = "TIME", requires = "cgroup", value_parser = clock::parse_duration)]
+    reclaim_every: Option<Duration>,
+
+    /// Run the command on a pseudo-terminal for REPLs and prompts; the status
+    /// line is pinned to the bottom row
+    #[arg(long, conflicts_with_all = ["freeze_on_limit", "tui"])]
+    interactive: bool,
+
+    /// Show none of the command's output, only the memory display; it can
+    /// still go to --output-log
+    #[arg(long, conflicts_with_all = ["interactive", "max_output_rate", "tag_lines", "collapse_repeats"])]
+    no_output: bool,
+
+    /// Render at most this many lines of the command's output per second
+    #[arg(long, value_name = "LINES", conflicts_with = "interactive")]
+    max_output_rate: Option<u32>,
+
+    /// Append the current RSS to every Nth line of the command's output,
+    /// e.g. `[rss=1.23G]`
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        default_missing_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["interactive", "tui"]
+    )]
+    tag_lines: Option<u32>,
+
+    /// Inject helpers into Python and Node processes to show their managed
+    /// heap next to RSS
+    #[arg(long)]
+    runtime_heap: bool,
+
+    /// Record numbers matched in the command's output as an extra metric,
+    /// e.g. `heap:heap_used=(\d+)`; repeatable
+    #[arg(long, value_name = "NAME:PATTERN", value_parser = extract::parse_extract)]
+    extract: Vec<extract::ExtractSpec>,
+
+    /// Write values found by --extract to a CSV file
+    #[arg(long, value_name = "PATH", requires = "extract")]
+    extract_csv: Option<PathBuf>,
+
+    /// Collapse runs of identical output lines into a repeat count
+    #[arg(long, conflicts_with = "interactive")]
+    collapse_repeats: bool,
+
+    /// Also write the command's output to this file
+    #[arg(long, value_name = "FILE")]
+    output_log: Option<PathBuf>,
+
+    /// Rotate --output-log once it reaches SIZE, keeping COUNT rotated
+    /// files (e.g. 100M,5)
+    #[arg(
+        long,
+        value_name = "SIZE,COUNT",
+        requires = "output_log",
+        value_parser = output_log::parse_rotation
+    )]
+    log_rotate: Option<output_log::Rotation>,
+
+    /// Compress rotated --output-log files with gzip
+    #[arg(long, requires = "log_rotate")]
+    log_gzip: bool,
+}
+
+/// Records `--schema` can describe
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum SchemaRecord {
+    Sample,
+    Summary,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Mode {
+    /// Run a command and watch its memory; the default without a subcommand
+    Run(Box<RunArgs>),
+    /// Watch a process that is already running, until it exits or Ctrl+C
+    Attach(Box<AttachArgs>),
+    /// Guard a service found through its pid file, across restarts, and act
+    /// when it uses too much memory
+    Guard(GuardArgs),
+    /// Measure a command over several runs and parameter values
+    Bench(BenchCommand),
+    /// Play back a run recorded with --record
+    Replay(ReplayArgs),
+    /// Compare two JSON summaries
+    Diff(DiffArgs),
+    /// Report on a recorded run, a JSON summary or imported samples
+    Report(ReportArgs),
+    /// Run several commands at once with a combined dashboard
+    Multi(MultiArgs),
+    /// Run two or more commands and compare their memory use
+    Compare(CompareArgs),
+    /// Ask a running memwatch for a sample through its --control-socket
+    Ctl(CtlArgs),
+    /// Check which metric sources work here and how to enable the rest
+    Doctor,
+    /// Watch a workload of known memory use and check what memwatch sees
+    Selftest(SelftestArgs),
+    /// Use memory in a known pattern, for memwatch or anything else to watch
+    Stress(StressArgs),
+}
+
+/// What `watch` is pointed at
+enum Watched<'a> {
+    /// A command to spawn
+    Command(&'a [String]),
+    /// A running process, for `attach`
+    Attached(Target),
+}
+
+fn main() -> ExitCode {
+    match run_mode(Args::parse()) {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("memwatch: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run what `args` ask for; the status to exit with
+fn run_mode(args: Args) -> io::Result<ExitCode> {
+    log::init(args.verbose, args.log_file.as_deref())?;
+    size::set_number_format(match args.raw {
+        true => NumberFormat::Plain,
+        false => args.number_format,
+    });
+    redact::set_patterns(args.redact.clone());
 
     // Shared flag for Ctrl+C signal
     let terminated = Arc::new(AtomicBool::new(false));
@@ -65,200 +841,1389 @@ fn main() -> io::Result<()> {
     })
     .expect("Error setting Ctrl+C handler");
 
-    // Spawn child, inherit stdio so you see its output
-    let mut cmd_iter = args.command.iter();
-    let prog = cmd_iter.next().unwrap();
-    let child_args: Vec<&str> = cmd_iter.map(|s| s.as_str()).collect();
+    match args.mode {
+        Some(Mode::Run(run)) => run_command(*run, terminated),
+        None => run_command(args.run, terminated),
+        Some(Mode::Attach(attach)) => watch(
+            &attach.watch,
+            &SpawnArgs::default(),
+            Watched::Attached(Target::attach(attach.pid)?),
+            terminated,
+        ),
+        Some(Mode::Guard(guard)) => guard::run(guard, &terminated).map(|()| ExitCode::SUCCESS),
+        Some(Mode::Bench(bench)) => bench::run_command(bench, &terminated).map(|()| ExitCode::SUCCESS),
+        Some(Mode::Replay(replay)) => replay::run(replay, &terminated).map(|()| ExitCode::SUCCESS),
+        Some(Mode::Diff(diff)) => diff::run(diff).map(|()| ExitCode::SUCCESS),
+        Some(Mode::Report(report)) => report::run(report).map(|()| ExitCode::SUCCESS),
+        Some(Mode::Multi(multi)) => multi::run(multi, terminated).map(|()| ExitCode::SUCCESS),
+        Some(Mode::Compare(compare)) => compare::run(compare, &terminated).map(|()| ExitCode::SUCCESS),
+        Some(Mode::Ctl(ctl)) => ctl::run(ctl).map(|()| ExitCode::SUCCESS),
+        Some(Mode::Doctor) => doctor::run().map(|()| ExitCode::SUCCESS),
+        Some(Mode::Selftest(selftest)) => selftest::run(selftest),
+        Some(Mode::Stress(stress)) => stress::run(stress, &terminated).map(|()| ExitCode::SUCCESS),
+    }
+}
 
-    let mut child = Command::new(prog)
-        .args(&child_args)
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| io::Error::new(e.kind(), format!("failed to spawn `{}`: {}", prog, e)))?;
+fn run_command(args: RunArgs, terminated: Arc<AtomicBool>) -> io::Result<ExitCode> {
+    if let Some(record) = args.schema {
+        print!(
+            "{}",
+            match record {
+                SchemaRecord::Sample => schema::SAMPLE,
+                SchemaRecord::Summary => schema::SUMMARY,
+            }
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(pid) = args.threads_of {
+        for line in threads::report(pid)? {
+            println!("{}", line);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let fleet = match (&args.user_all, args.matching, args.top, &args.slice) {
+        (Some(name), ..) => Some(Fleet::user(name)?),
+        (None, Some(pattern), ..) => Some(Fleet::Match(pattern)),
+        (None, None, Some(rows), _) => Some(Fleet::Top(rows)),
+        (None, None, None, Some(path)) => Some(Fleet::Slice {
+            path: path.clone(),
+            cgroup: Cgroup::existing(path)?,
+        }),
+        (None, None, None, None) => None,
+    };
+    if let Some(fleet) = fleet {
+        let interval = Duration::from_millis(args.watch.interval);
+        let summary_json = args.watch.summary_json.as_deref();
+        let (clock, watch_for) = (args.watch.clock, args.watch.watch_for);
+        fleet::run(fleet, interval, clock, watch_for, summary_json, &terminated)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    // Benchmark flags without the subcommand, as before `bench` existed
+    if args.bench.enabled() {
+        let interval = Duration::from_millis(args.watch.interval);
+        let summary_json = args.watch.summary_json.as_deref();
+        bench::run(&args.bench, &args.command, interval, summary_json, &terminated)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    watch(&args.watch, &args.spawn, Watched::Command(&args.command), terminated)
+}
+
+/// Watch a process tree until it is gone, then report on it; fails when
+/// an assertion does
+fn watch(
+    args: &WatchArgs,
+    spawn: &SpawnArgs,
+    watched: Watched,
+    terminated: Arc<AtomicBool>,
+) -> io::Result<ExitCode> {
+    // Subscribe before spawning so no early exit is missed; without
+    // CAP_NET_ADMIN per-process peaks fall back to sampled VmHWM
+    let exit_records = taskstats::listen().ok();
+    // Before spawning, so a recording that cannot be written stops the run
+    let recorder = args.record.clone().map(Recorder::create).transpose()?;
+    let mut control = Control::new(args.control_socket.as_deref())?;
+    let output_log = spawn
+        .output_log
+        .as_deref()
+        .map(|path| OutputLog::create(path, spawn.log_rotate, spawn.log_gzip))
+        .transpose()?
+        .map(|log| Arc::new(Mutex::new(log)));
+    let baseline = args.overlay_baseline.as_deref().map(Baseline::read).transpose()?;
+    let mut bundles = args.bundle_dir.as_deref().map(Bundles::create).transpose()?;
+    let config_file = match &args.budget_file {
+        Some(path) => Some(path.clone()),
+        None if args.no_budget && args.email.is_empty() => None,
+        None => config::find(),
+    };
+    let config = match &config_file {
+        Some(path) => {
+            log::info!("settings from {}", path.display());
+            config::load(path)?
+        }
+        None => Config::default(),
+    };
+    let budgets = if args.no_budget { Vec::new() } else { config.budgets };
+    let mut mailer = match config.smtp {
+        _ if args.email.is_empty() => None,
+        Some(smtp) => Some(Mailer::new(smtp, args.email.clone())),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--email needs an [smtp] table in {}", config::FILE_NAME),
+            ))
+        }
+    };
+
+    let cgroup = if spawn.cgroup {
+        Some(Cgroup::create()?)
+    } else {
+        None
+    };
+    if let (Some(cgroup), Some(high)) = (&cgroup, spawn.memory_high) {
+        cgroup.set_memory_high(high)?;
+    }
+    let mut working_set = args.working_set.then(WorkingSet::open).transpose()?;
+    // Before the command takes anything
+    let mut system_delta = args.system_delta.then(SystemDelta::start).transpose()?;
+    let mut power = args.power.then(PowerMeter::start).transpose()?;
+    let mut tmpfs = (!args.tmpfs.is_empty()).then(|| TmpfsTracker::start(args.tmpfs.clone()));
+
+    // Raw mode goes on before spawning so the child starts with the
+    // terminal already in its final state
+    let (mut renderer, pty) = if spawn.interactive {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return Err(io::Error::other("--interactive requires a terminal"));
+        }
+        let (renderer, cols, rows) = Renderer::reserved_line()?;
+        (renderer, Some(Pty::open(cols, rows)?))
+    } else if args.tui {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return Err(io::Error::other("--tui requires a terminal"));
+        }
+        (Renderer::tui(args.series, terminated.clone())?, None)
+    } else {
+        (Renderer::plain(), None)
+    };
+
+    // Held until the end of the run
+    let _ballast = match args.ballast {
+        Some(size) => {
+            let ballast = Ballast::new(size)?;
+            renderer.notice(&format!("Ballast: {} held by memwatch", size::format_bytes(size)));
+            Some(ballast)
+        }
+        None => None,
+    };
+
+    let (mut target, mut runtime_heap, command) = match watched {
+        Watched::Command(command_line) => {
+            let (child, runtime_heap) =
+                spawn_command(command_line, spawn, pty.as_ref(), args.tui, cgroup.as_ref())?;
+            (Target::Child(child), runtime_heap, command_line.to_vec())
+        }
+        Watched::Attached(target) => {
+            let command = target.command_line();
+            (target, None, command)
+        }
+    };
+    let pty = pty.map(|pty| pty.master);
 
-    let pid = child.id() as i32;
+    let pid = target.pid();
     let interval = Duration::from_millis(args.interval);
     let start = Instant::now();
 
-    // Channel for output lines
-    let (tx, rx) = mpsc::channel::<OutputMsg>();
+    // Channel for output lines; bounded so a flood of output cannot grow
+    // memwatch's own memory
+    let (tx, rx) = mpsc::sync_channel::<OutputMsg>(output::BACKLOG);
+    let dropped = Arc::new(AtomicU64::new(0));
+
+    // Threads reading child's stdout and stderr
+    if let Target::Child(child) = &mut target {
+        if let Some(stdout) = child.stdout.take() {
+            let (tx_out, dropped, log) = (tx.clone(), dropped.clone(), output_log.clone());
+            thread::spawn(move || match log {
+                Some(log) => output::forward(Tee::new(stdout, log), Stream::Stdout, tx_out, dropped),
+                None => output::forward(stdout, Stream::Stdout, tx_out, dropped),
+            });
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let (tx_err, dropped, log) = (tx.clone(), dropped.clone(), output_log.clone());
+            thread::spawn(move || match log {
+                Some(log) => output::forward(Tee::new(stderr, log), Stream::Stderr, tx_err, dropped),
+                None => output::forward(stderr, Stream::Stderr, tx_err, dropped),
+            });
+        }
+    }
 
-    // Thread reading child's stdout
-    if let Some(stdout) = child.stdout.take() {
-        let tx_out = tx.clone();
+    // Threads shuttling bytes between our terminal and the child's
+    if let Some(master) = &pty {
+        let mut master_in = master.try_clone()?;
         thread::spawn(move || {
-            use std::io::BufRead;
-            let reader = io::BufReader::new(stdout);
-            for line in reader.lines() {
-                if let Ok(l) = line {
-                    // Ignore send errors (main thread may have exited)
-                    let _ = tx_out.send(OutputMsg::Stdout(l));
-                } else {
-                    break;
-                }
-            }
+            let _ = io::copy(&mut io::stdin().lock(), &mut master_in);
         });
-    }
 
-    // Thread reading child's stderr
-    if let Some(stderr) = child.stderr.take() {
-        let tx_err = tx.clone();
+        let master_out = master.try_clone()?;
+        let mut master_out: Box<dyn Read + Send> = match output_log.clone() {
+            Some(log) => Box::new(Tee::new(master_out, log)),
+            None => Box::new(master_out),
+        };
+        let tx_pty = tx.clone();
         thread::spawn(move || {
-            use std::io::BufRead;
-            let reader = io::BufReader::new(stderr);
-            for line in reader.lines() {
-                if let Ok(l) = line {
-                    let _ = tx_err.send(OutputMsg::Stderr(l));
-                } else {
+            let mut buf = [0u8; 4096];
+            // Terminal output is never dropped; blocking here slows the
+            // child down like a real terminal would.
+            // Reading fails with EIO once the child side is closed
+            while let Ok(n @ 1..) = master_out.read(&mut buf) {
+                if tx_pty.send(OutputMsg::Pty(buf[..n].to_vec())).is_err() {
                     break;
                 }
             }
         });
     }
 
+    if args.tui {
+        tui::forward_keys(tx.clone());
+    }
+
+    // An attached process writes nowhere near us; an open channel keeps
+    // waiting for output pacing the loop
+    let idle = (!target.is_child()).then(|| tx.clone());
     drop(tx); // Close the original Sender in the main thread
 
-    // Hide cursor during monitoring
-    print!("{}", cursor::Hide);
-    io::stdout().flush().ok();
-
-    // Ensure cursor is shown on exit
-    let _guard = CursorGuard;
-
-    // No need to buffer previously printed non-empty lines; we print immediately
-    loop {
-        // First, drain all available messages without blocking
-        while let Ok(msg) = rx.try_recv() {
-            // Before printing a program line, clear the status line
-            print!("\r{}", clear::CurrentLine);
-            match msg {
-                OutputMsg::Stdout(l) => {
-                    println!("{}", l);
-                }
-                OutputMsg::Stderr(l) => {
-                    // Visually distinguish stderr
-                    eprintln!("{}", l);
+    let mut summary = Summary::new(&command);
+    let timestamps = OutputTimestamps::new(&args.timestamps);
+    // Kept for CSV rows should JSON leave it out
+    let started_at = summary.started_at;
+    if !timestamps.json.wall() {
+        summary.started_at = None;
+    }
+    let mut tracker = ProcessTracker::default();
+    let mut webhook = args.notify_url.clone().map(Webhook::new);
+    let rearm = Rearm::from_args(args.alert_cooldown, args.alert_hysteresis, Rearm::Never);
+    let mut rss_warning = args.warn_rss.map(|limit| Alert::new(limit, rearm));
+    let mut rss_limit = args.max_rss.map(|limit| Alert::new(limit, rearm));
+    let mut rss_limit_hit = false;
+    let mut floor = args
+        .system_floor
+        .map(|floor| SystemFloor::new(floor, args.alert_cooldown, args.alert_hysteresis));
+    let mut floor_paused = false;
+    let mut schedule = (!args.between.is_empty()).then(|| Schedule::new(args.between.clone()));
+    let mut extractor = Extractor::new(spawn.extract.clone());
+    let mut jvm = args.jvm.then(JvmProbe::default);
+    let mut swap = args.swap.then(SwapProbe::start);
+    let mut churn = args.churn.then(Churn::default);
+    let mut net = args.net.then(|| NetProbe::new(pid));
+    let mut watched_dirs = DirWatcher::new(args.watch_dir.clone());
+    let mut filter = OutputFilter::new(
+        spawn.no_output,
+        spawn.max_output_rate,
+        spawn.collapse_repeats,
+        spawn.tag_lines,
+    );
+    let mut tick_cost = TickCost::default();
+    let mut ticks = Ticks::new(start, interval);
+    let mut sampler = Sampler::new(args.cheap);
+    let mut next_reclaim = spawn.reclaim_every;
+    let mut timeline = Timeline::default();
+    let mut rolling = Rolling::new(&args.rolling);
+    let mut previous_rss = None;
+    let mut detector = args.detect_anomalies.map(Detector::new);
+    let mut steps = args.detect_steps.map(StepDetector::new);
+    let keep_timeline = args.report_md.is_some();
+    let mut title = args.title.then(Title::open).flatten();
+
+    // Sinks that only need the samples and the outcome of the run
+    let mut observers: Vec<Box<dyn Observer>> = Vec::new();
+    if let Some(endpoint) = &args.otel {
+        observers.push(Box::new(OtelExporter::new(endpoint, &summary)));
+    }
+    if let Some(addr) = &args.graphite {
+        let prefix = args
+            .graphite_prefix
+            .clone()
+            .unwrap_or_else(|| GraphiteExporter::default_prefix(&command));
+        observers.push(Box::new(GraphiteExporter::new(addr.clone(), prefix)));
+    }
+    for exporter in &args.exporter_cmd {
+        observers.push(Box::new(CommandExporter::spawn(exporter)));
+    }
+    if let Some(path) = &args.export_trace {
+        observers.push(Box::new(TraceRecorder::new(path.clone())));
+    }
+    if let Some(path) = &args.export_perfetto {
+        observers.push(Box::new(PerfettoRecorder::new(path.clone(), start.elapsed())));
+    }
+    if let Some(path) = &args.status_file {
+        observers.push(Box::new(StatusFile::new(path.clone(), &summary.run_id)));
+    }
+    if let Some(recorder) = recorder {
+        observers.push(Box::new(recorder));
+    }
+
+    let mut detached = false;
+    // Ctrl+C is bundled once, however long the command takes to stop
+    let mut interrupt_bundled = false;
+    // Set once --for is over, after which the run ends as on Ctrl+C
+    let mut time_up = false;
+    let exit = loop {
+        if !time_up && args.watch_for.is_some_and(|limit| ticks.elapsed() >= limit) {
+            time_up = true;
+            terminated.store(true, Ordering::SeqCst);
+        }
+        // Check for process termination / Ctrl+C signal; an attached
+        // process is left running
+        if terminated.load(Ordering::SeqCst) {
+            if !target.is_child() {
+                detached = true;
+                break ExitInfo {
+                    code: None,
+                    signal: None,
+                    core_dumped: false,
+                };
+            }
+            if let Some(bundles) = bundles.as_mut().filter(|_| !interrupt_bundled) {
+                interrupt_bundled = true;
+                let now = ticks.elapsed();
+                let reason = if time_up { "time_limit" } else { "interrupt" };
+                save_bundle(bundles, &mut renderer, &summary, reason, now, Some(pid), &tracker);
+            }
+            stop_command(&mut target, args.kill_tree, cgroup.as_ref(), &tracker, false);
+        }
+
+        if let Some(exit) = target.try_wait()? {
+            break exit;
+        }
+
+        // Refresh status line on each interval
+        let tick_start = Instant::now();
+        let now = ticks.elapsed();
+        let sample = sampler.sample(pid);
+        log_sample(now, &sample, tick_start.elapsed());
+        let (rss, vsz) = (sample.rss, sample.vsz);
+        let denied = sample.denied.since(summary.denied);
+        if denied.any() {
+            renderer.notice(&format_denied(denied));
+        }
+        let (inside, window_event) = schedule
+            .as_mut()
+            .map_or((true, None), |schedule| schedule.update(SystemTime::now()));
+        if inside {
+            summary.record(&sample);
+        }
+        if let Some(swap) = swap.as_mut() {
+            swap.update(&sample);
+        }
+        if let Some(net) = net.as_mut() {
+            net.update(&sample);
+        }
+        if let Some(power) = power.as_mut() {
+            power.update();
+        }
+        if let Some(tmpfs) = tmpfs.as_mut() {
+            tmpfs.update();
+        }
+        watched_dirs.update(now);
+        if let Some(notice) = working_set.as_mut().and_then(|w| w.update(&sample)) {
+            renderer.notice(&notice);
+        }
+        let mut observed = Sample::new(now, sample.clone());
+        observed.hot = working_set.as_ref().and_then(WorkingSet::hot);
+        let stamp = args.clock.stamp(now, observed.time);
+        if let Some(event) = &window_event {
+            renderer.notice(&format!("{} {}", stamp, window::format_event(event)));
+            let mark = Mark {
+                label: window::format_event(event),
+                elapsed: now,
+            };
+            for observer in &mut observers {
+                observer.on_mark(&mark);
+            }
+        }
+        if let Some(notice) = system_delta.as_mut().and_then(|s| s.update(rss, &stamp)) {
+            renderer.notice(&notice);
+        }
+        if !timestamps.json.wall() {
+            observed.time = None;
+        }
+        renderer.on_sample(&observed);
+        filter.set_rss(rss);
+        extractor.set_rss(rss);
+        rolling.record(now, rss);
+        if let Some(steps) = steps.as_mut().filter(|_| inside) {
+            steps.update(now, rss);
+        }
+        if keep_timeline && inside {
+            timeline.record(now, rss);
+        }
+        if let Some(cgroup) = &cgroup {
+            summary.record_cgroup(cgroup.memory_current());
+        }
+
+        let mut events = Vec::new();
+        if let Some(exit_records) = &exit_records {
+            while let Ok(record) = exit_records.try_recv() {
+                events.extend(tracker.record_exit(pid, record));
+            }
+        }
+        events.extend(tracker.update(&sample));
+        summary.processes_seen = tracker.seen;
+        summary.record_exit_peak(tracker.exit_peak);
+        if let Some(churn) = churn.as_mut() {
+            churn.update(now, tracker.seen, tracker.execs);
+        }
+        tick_cost.record(tick_start.elapsed());
+        for event in &events {
+            log::info!("{}", format_process_event(&stamp, event));
+        }
+        for observer in &mut observers {
+            for event in &events {
+                observer.on_process(now, event);
+            }
+            if inside {
+                observer.on_sample(&observed);
+            }
+        }
+        if args.proc_events {
+            for event in &events {
+                renderer.notice(&format_process_event(&stamp, event));
+            }
+        }
+        if let (Some(cgroup), Some(due)) = (&cgroup, next_reclaim) {
+            if now >= due {
+                next_reclaim = spawn.reclaim_every.map(|every| now + every);
+                match reclaim(cgroup, &mut sampler, pid, rss) {
+                    Ok(response) => {
+                        renderer.notice(&format!("{} Reclaim: {}", stamp, response));
+                        let mark = Mark {
+                            label: format!("reclaim: {}", response),
+                            elapsed: now,
+                        };
+                        for observer in &mut observers {
+                            observer.on_mark(&mark);
+                        }
+                    }
+                    Err(e) => {
+                        renderer.notice(&format!("memwatch: cannot reclaim memory: {}", e));
+                        next_reclaim = None;
+                    }
                 }
             }
         }
+        if let Some(runtime_heap) = runtime_heap.as_mut() {
+            runtime_heap.update(&sample);
+        }
+        if let Some(jvm) = jvm.as_mut() {
+            for notice in jvm.update(&sample) {
+                renderer.notice(&notice);
+            }
+        }
 
-        // Check for process termination / Ctrl+C signal
-        if terminated.load(Ordering::SeqCst) {
-            let _ = child.kill();
+        if let Some(anomaly) = detector.as_mut().filter(|_| inside).and_then(|d| d.update(now, rss)) {
+            summary.anomalies += 1;
+            for observer in &mut observers {
+                observer.on_anomaly(&anomaly);
+            }
+            renderer.notice(&format!("{} {}", stamp, anomaly::format_anomaly(&anomaly)));
         }
 
-        if let Some(status) = child.try_wait()? {
-            // Process finished: print final status line and message
-            println!();
-            io::stdout().flush().ok();
+        if let Some(alert) = rss_warning.as_mut().filter(|_| inside) {
+            let limit = alert.limit();
+            if alert.update(now, rss) {
+                let crossing = Crossing {
+                    name: "warn_rss".into(),
+                    limit,
+                    rss,
+                    elapsed: now,
+                };
+                for observer in &mut observers {
+                    observer.on_threshold(&crossing);
+                }
+                if let Some(webhook) = webhook.as_mut() {
+                    webhook.threshold(&summary, now, "warn_rss", limit, rss);
+                }
+                if let Some(bundles) = bundles.as_mut() {
+                    save_bundle(bundles, &mut renderer, &summary, "warn_rss", now, Some(pid), &tracker);
+                }
+                let text = format!(
+                    "Warning: RSS {} exceeded {}",
+                    size::format_bytes(rss),
+                    size::format_bytes(limit)
+                );
+                if args.osc {
+                    notify::osc("memwatch: RSS warning", &text);
+                }
+                if args.gha {
+                    let line = gha::annotation(gha::Level::Warning, "memwatch", &text);
+                    renderer.output(OutputMsg::Stdout(line));
+                }
+                renderer.notice(&text);
+            }
+        }
+
+        if let Some(alert) = rss_limit.as_mut().filter(|_| inside) {
+            let limit = alert.limit();
+            if alert.update(now, rss) {
+                rss_limit_hit = true;
+                let crossing = Crossing {
+                    name: "max_rss".into(),
+                    limit,
+                    rss,
+                    elapsed: now,
+                };
+                for observer in &mut observers {
+                    observer.on_threshold(&crossing);
+                }
+                if let Some(webhook) = webhook.as_mut() {
+                    webhook.threshold(&summary, now, "max_rss", limit, rss);
+                }
+                if let Some(mailer) = mailer.as_mut() {
+                    mailer.breach(&summary, now, limit, rss);
+                }
+                if let Some(bundles) = bundles.as_mut() {
+                    save_bundle(bundles, &mut renderer, &summary, "max_rss", now, Some(pid), &tracker);
+                }
+                let text = format!(
+                    "RSS {} exceeded limit {}",
+                    size::format_bytes(rss),
+                    size::format_bytes(limit)
+                );
+                if args.osc {
+                    notify::osc("memwatch: RSS limit exceeded", &text);
+                }
+                if args.gha {
+                    let line = gha::annotation(gha::Level::Error, "memwatch", &text);
+                    renderer.output(OutputMsg::Stdout(line));
+                }
+
+                let resume = args.freeze_on_limit && {
+                    freeze::freeze(pid, &tracker.live(), cgroup.as_ref());
+                    renderer.notice(&text);
+                    matches!(freeze::prompt(pid, &terminated), Decision::Resume)
+                };
+
+                if resume {
+                    // The limit stays fired until RSS is under its clear
+                    // level, otherwise the next sample would freeze the
+                    // command again right away
+                    freeze::thaw(pid, &tracker.live(), cgroup.as_ref());
+                    rss_limit_hit = false;
+                    renderer.notice(&match alert.rearm() {
+                        Rearm::Never => String::from("Resumed; --max-rss is disarmed for the rest of the run"),
+                        Rearm::After { .. } => format!(
+                            "Resumed; --max-rss is armed again once RSS is under {}",
+                            size::format_bytes(alert.clear_level())
+                        ),
+                    });
+                } else {
+                    stop_command(&mut target, args.kill_tree, cgroup.as_ref(), &tracker, false);
+                    if args.freeze_on_limit {
+                        // Survivors outside --kill-tree must not stay stopped
+                        freeze::thaw(pid, &tracker.live(), cgroup.as_ref());
+                    }
+                }
+            }
+        }
 
-            if let Some(code) = status.code() {
-                eprintln!("Process exited with status: {}", code);
+        match floor.as_mut().and_then(|floor| floor.update(now)) {
+            Some(FloorEvent::Below(available)) => {
+                let mut text = format!(
+                    "System MemAvailable {} fell below the floor of {}",
+                    size::format_bytes(available),
+                    size::format_bytes(args.system_floor.unwrap_or_default())
+                );
+                if args.pause_below_floor {
+                    freeze::freeze(pid, &tracker.live(), cgroup.as_ref());
+                    floor_paused = true;
+                    let level = floor.as_ref().map_or(0, SystemFloor::clear_level);
+                    text.push_str(&format!("; command paused until {} is available", size::format_bytes(level)));
+                }
+                let mark = Mark {
+                    label: format!("system floor: {} available", size::format_bytes(available)),
+                    elapsed: now,
+                };
+                for observer in &mut observers {
+                    observer.on_mark(&mark);
+                }
+                if args.osc {
+                    notify::osc("memwatch: system memory low", &text);
+                }
+                if args.gha {
+                    let line = gha::annotation(gha::Level::Warning, "memwatch", &text);
+                    renderer.output(OutputMsg::Stdout(line));
+                }
+                renderer.notice(&format!("{} {}", stamp, text));
             }
-            if terminated.load(Ordering::SeqCst) {
-                eprintln!("Interrupted (Ctrl+C)");
+            Some(FloorEvent::Recovered(available)) => {
+                let resumed = floor_paused;
+                if floor_paused {
+                    freeze::thaw(pid, &tracker.live(), cgroup.as_ref());
+                    floor_paused = false;
+                    if let Some(floor) = floor.as_mut() {
+                        floor.resumed(now);
+                    }
+                }
+                renderer.notice(&format!(
+                    "{} System MemAvailable back at {}{}",
+                    stamp,
+                    size::format_bytes(available),
+                    if resumed { "; command resumed" } else { "" }
+                ));
             }
-            break;
+            None => {}
         }
 
-        // Refresh status line on each interval
-        let (rss, vsz) = meminfo(pid).unwrap_or((0, 0));
-        let status_line = format_status_line(start.elapsed(), rss, vsz);
-        print!("\r{}{}", clear::CurrentLine, status_line);
-        io::stdout().flush().ok();
-
-        // Wait for interval or a new line (block at most for 'interval')
-        match rx.recv_timeout(interval) {
-            Ok(msg) => {
-                // Got a line before the timer: print it and immediately redraw status
-                print!("\r{}", clear::CurrentLine);
-                match msg {
-                    OutputMsg::Stdout(l) => println!("{}", l),
-                    OutputMsg::Stderr(l) => eprintln!("{}", l),
+        report_dropped(&mut renderer, &mut summary, dropped.load(Ordering::Relaxed));
+
+        let mut status_line = format_status_line(
+            &stamp,
+            rss,
+            vsz,
+            sample.processes.len(),
+            sample.is_incomplete(),
+        );
+        if let (true, previous) = (args.delta, previous_rss.replace(rss)) {
+            status_line.push(Priority::Low, format_delta(previous.unwrap_or(rss), rss));
+        }
+        if let (true, Some(largest)) = (args.focus_max, sample.processes.iter().max_by_key(|p| p.rss)) {
+            status_line.push(
+                Priority::High,
+                format!("top: {} {}", largest.name, size::format_bytes_short(largest.rss)),
+            );
+        }
+        if let Some(baseline) = &baseline {
+            status_line.push(Priority::High, baseline.status(now, rss));
+        }
+        status_line.extend(Priority::Low, rolling.status());
+        status_line.extend(Priority::Low, extractor.status());
+        status_line.extend(Priority::Low, jvm.as_ref().map(JvmProbe::status).unwrap_or_default());
+        status_line.extend(
+            Priority::Low,
+            working_set.as_ref().map(WorkingSet::status).unwrap_or_default(),
+        );
+        status_line.extend(Priority::Low, swap.as_ref().map(SwapProbe::status).unwrap_or_default());
+        status_line.extend(Priority::Low, churn.as_ref().map(Churn::status).unwrap_or_default());
+        status_line.extend(Priority::Low, net.as_ref().map(NetProbe::status).unwrap_or_default());
+        status_line.extend(Priority::Low, power.as_ref().map(PowerMeter::status).unwrap_or_default());
+        status_line.extend(Priority::Low, floor.as_ref().map(SystemFloor::status).unwrap_or_default());
+        status_line.extend(Priority::High, schedule.as_ref().map(Schedule::status).unwrap_or_default());
+        status_line.extend(Priority::Low, tmpfs.as_ref().map(TmpfsTracker::status).unwrap_or_default());
+        status_line.extend(Priority::Low, watched_dirs.status());
+        status_line.extend(
+            Priority::Low,
+            runtime_heap.as_ref().map(RuntimeHeaps::status).unwrap_or_default(),
+        );
+        if let (Some(master), Some((cols, rows))) = (&pty, renderer.check_resize()) {
+            pty::resize(master, cols, rows);
+        }
+        renderer.status(&status_line.fit(renderer.width()));
+        if let Some(title) = title.as_mut() {
+            title.set(&format_title(&stamp, rss, &command));
+        }
+
+        // Forward output until the next tick; sampling runs on its own
+        // schedule however chatty the command is
+        let missed = ticks.missed();
+        let mut deadline = ticks.advance();
+        if ticks.missed() > missed {
+            log::info!(
+                "sampling fell behind, skipped {} ticks",
+                ticks.missed() - missed
+            );
+        }
+        loop {
+            while let Some((request, reply)) = control.next() {
+                match request {
+                    Request::Sample => {
+                        // Off the grid, at the moment it was asked for
+                        let now = start.elapsed();
+                        let tree = sampler.sample(pid);
+                        summary.record(&tree);
+                        let mut on_demand = Sample::new(now, tree);
+                        renderer.notice(&format!(
+                            "{} Sample on demand: RSS {} | VSZ {} | Procs {}",
+                            args.clock.stamp(now, on_demand.time),
+                            size::format_bytes(on_demand.rss),
+                            size::format_bytes(on_demand.vsz),
+                            on_demand.processes.len()
+                        ));
+                        if !timestamps.json.wall() {
+                            on_demand.time = None;
+                        }
+                        reply.send(&on_demand.to_json());
+                        for observer in &mut observers {
+                            observer.on_sample(&on_demand);
+                        }
+                    }
+                    Request::Current => reply.send(&observed.to_json()),
+                    Request::Summary => {
+                        let mut fields = vec![
+                            ("schema_version".into(), record::SCHEMA_VERSION.into()),
+                            ("running".into(), true.into()),
+                        ];
+                        fields.extend(summary.json_fields(start.elapsed()));
+                        reply.send(&json::Value::Object(fields));
+                    }
+                    Request::Interval(interval) => {
+                        ticks.set_interval(interval);
+                        let change = IntervalChange {
+                            interval,
+                            elapsed: ticks.elapsed(),
+                        };
+                        deadline = ticks.advance();
+                        renderer.notice(&format!(
+                            "{} Sampling every {} from now on",
+                            stamp,
+                            clock::format_span(interval)
+                        ));
+                        for observer in &mut observers {
+                            observer.on_interval(&change);
+                        }
+                        reply.ok();
+                    }
+                    Request::Mark(label) => {
+                        let mark = Mark {
+                            label,
+                            elapsed: start.elapsed(),
+                        };
+                        renderer.notice(&format!("{} Mark: {}", stamp, mark.label));
+                        for observer in &mut observers {
+                            observer.on_mark(&mark);
+                        }
+                        reply.ok();
+                    }
                 }
-                continue; // Loop back to redraw the status without extra delay
+                renderer.status(&status_line.fit(renderer.width()));
             }
-            Err(mpsc::RecvTimeoutError::Timeout) => {
-                // Nothing arrived – just next tick
+            // Wake up now and then for a resize or a sample on demand
+            let wait = deadline.saturating_duration_since(Instant::now()).min(WAKE_INTERVAL);
+            match rx.recv_timeout(wait) {
+                Ok(msg) => {
+                    if let OutputMsg::Key(key) = &msg {
+                        if let Some(request) = ctl::key_request(key, ticks.interval()) {
+                            control.push(request);
+                            continue;
+                        }
+                        if *key == Key::Char('t') {
+                            show_threads(&mut renderer, &observed);
+                            continue;
+                        }
+                    }
+                    extractor.scan(&msg, start.elapsed());
+                    if let Some(steps) = steps.as_mut() {
+                        steps.output(&msg);
+                    }
+                    filter.output(&mut renderer, msg);
+                    filter.tick(&mut renderer);
+                    renderer.status(&status_line.fit(renderer.width()));
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) if Instant::now() < deadline => {
+                    if renderer.resized() {
+                        if let (Some(master), Some((cols, rows))) = (&pty, renderer.check_resize()) {
+                            pty::resize(master, cols, rows);
+                        }
+                        renderer.status(&status_line.fit(renderer.width()));
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    // All reader threads closed – child likely exited; wait
+                    // out the tick unless it has
+                    while Instant::now() < deadline && matches!(target.try_wait(), Ok(None)) {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    break;
+                }
             }
-            Err(mpsc::RecvTimeoutError::Disconnected) => {
-                // All reader threads closed – child likely exited; loop will confirm
-                continue;
+        }
+    };
+
+    // Whatever is left of the tree, or an attached process, must not stay
+    // stopped
+    if floor_paused {
+        freeze::thaw(pid, &tracker.live(), cgroup.as_ref());
+        if let Some(floor) = floor.as_mut() {
+            floor.resumed(ticks.elapsed());
+        }
+    }
+
+    if let Some(bundles) = bundles.as_mut() {
+        // What is left of the tree once the command is gone
+        let (reason, root) = if detached { ("detach", Some(pid)) } else { ("exit", None) };
+        save_bundle(bundles, &mut renderer, &summary, reason, ticks.elapsed(), root, &tracker);
+    }
+
+    drop(idle);
+
+    // Forward output still in flight; background processes may keep the
+    // pipes open, so only wait briefly
+    while let Ok(msg) = rx.recv_timeout(Duration::from_millis(50)) {
+        extractor.scan(&msg, start.elapsed());
+        filter.output(&mut renderer, msg);
+    }
+    filter.finish(&mut renderer);
+    if let Some(Err(e)) = output_log.map(|log| log.lock().unwrap().finish()) {
+        renderer.notice(&format!("memwatch: failed to write --output-log: {}", e));
+    }
+    report_dropped(&mut renderer, &mut summary, dropped.load(Ordering::Relaxed));
+
+    // Process finished: restore the terminal before the final messages
+    drop(renderer);
+    drop(title);
+
+    let elapsed = start.elapsed();
+    summary.extracted = extractor.finish();
+    summary.jvm = jvm.and_then(JvmProbe::finish);
+    summary.working_set = working_set.and_then(WorkingSet::finish);
+    summary.swap = swap.map(SwapProbe::finish);
+    summary.churn = churn.map(Churn::finish);
+    summary.net = net.map(NetProbe::finish);
+    summary.power = power.map(PowerMeter::finish);
+    summary.system_floor = floor.map(SystemFloor::finish);
+    summary.between = schedule.map(Schedule::finish);
+    summary.watched_dirs = watched_dirs.finish();
+    summary.runtime_heap = runtime_heap.and_then(RuntimeHeaps::finish);
+    summary.steps = steps.map(StepDetector::finish);
+
+    // Descendants may outlive the command itself
+    if args.kill_tree && !detached {
+        stop_command(&mut target, true, cgroup.as_ref(), &tracker, true);
+    }
+    let termination = if rss_limit_hit {
+        Some("rss_limit")
+    } else if detached {
+        Some("detached")
+    } else if time_up {
+        Some("time_limit")
+    } else if terminated.load(Ordering::SeqCst) {
+        Some("interrupted")
+    } else {
+        None
+    };
+
+    if let Some(cgroup) = &cgroup {
+        summary.record_cgroup(cgroup.memory_peak());
+    }
+    // Once the command is gone, which shows what it left behind
+    summary.system_delta = system_delta.map(SystemDelta::finish);
+    summary.tmpfs = tmpfs.map(TmpfsTracker::finish);
+    if let Some(exit_records) = &exit_records {
+        // Give the kernel a moment to deliver the exit of the command itself
+        while let Ok(record) = exit_records.recv_timeout(Duration::from_millis(50)) {
+            tracker.record_exit(pid, record);
+        }
+    }
+    summary.processes_seen = tracker.seen;
+    summary.record_exit_peak(tracker.exit_peak);
+    summary.top_processes = tracker.top();
+    if args.self_stats {
+        summary.overhead = Some(tick_cost.finish(elapsed, ticks.missed()));
+    }
+
+    if let (true, Some(limit)) = (detached && time_up, args.watch_for) {
+        eprintln!(
+            "Stopped watching process {} after {} (still running)",
+            pid,
+            clock::format_duration(limit)
+        );
+    } else if detached {
+        eprintln!("Detached from process {} (still running)", pid);
+    } else {
+        eprintln!("Process {}", exit.describe());
+    }
+    eprintln!("{}", format_peak_line(&summary));
+    if summary.incomplete_samples > 0 {
+        let errors = summary.read_errors;
+        eprintln!(
+            "Read errors: {} of {} samples incomplete (denied: {}, transient: {}, gone: {})",
+            summary.incomplete_samples,
+            summary.samples,
+            errors.denied,
+            errors.transient,
+            errors.gone
+        );
+    }
+    if summary.denied.any() {
+        eprintln!(
+            "Incomplete: {} were unavailable for some processes (permission denied)",
+            summary.denied.missing().join("; ")
+        );
+    }
+    if summary.top_processes.len() > 1 {
+        if let Some(top) = summary.top_processes.first() {
+            eprintln!(
+                "Largest process: {} ({}) peak RSS {}",
+                top.name,
+                top.pid,
+                size::format_bytes(top.peak_rss)
+            );
+        }
+    }
+    if let Some(steps) = summary.steps.as_deref().filter(|steps| !steps.is_empty()) {
+        eprintln!("Steps in RSS:");
+        for step in steps {
+            eprintln!("  {}", steps::format_step(step));
+        }
+    }
+    if summary.anomalies > 0 {
+        eprintln!("Anomalies: {} (see above)", summary.anomalies);
+    }
+    if let Some(swap) = &summary.swap {
+        let mut line = format!("Swap: peak {} swapped out by the tree", size::format_bytes(swap.peak_swap));
+        for (name, stats) in [("zswap", &swap.zswap), ("zram", &swap.zram)] {
+            if let Some(stats) = stats.filter(|s| s.stored_peak > 0) {
+                line.push_str(&format!(" | {}", stats.format(name)));
             }
         }
+        eprintln!("{}", line);
+    }
+    if let Some(churn) = &summary.churn {
+        eprintln!("{}", churn.format());
+    }
+    if let Some(net) = &summary.net {
+        eprintln!("{}", net.format());
+    }
+    if let Some(power) = &summary.power {
+        eprintln!("{}", power.format());
+    }
+    if let Some(between) = &summary.between {
+        eprintln!("{}", between.format());
+    }
+    if let Some(floor) = &summary.system_floor {
+        eprintln!("{}", floor.format());
+    }
+    for dir in summary.watched_dirs.iter().filter_map(DirSeries::format) {
+        eprintln!("Directory {}", dir);
+    }
+    if let Some(tmpfs) = &summary.tmpfs {
+        eprintln!("{}", tmpfs.format());
+    }
+    if let Some(system_delta) = &summary.system_delta {
+        eprintln!("{}", system_delta.format());
+    }
+    if let Some(working_set) = &summary.working_set {
+        eprintln!(
+            "Working set: peak {} hot | {:.0}% of resident memory hot on average",
+            size::format_bytes(working_set.peak_hot),
+            working_set.average_hot_share * 100.0
+        );
+    }
+    if let Some(histogram) = summary.histogram.format() {
+        eprintln!("{}", histogram);
+    }
+    if let Some(overhead) = &summary.overhead {
+        eprintln!(
+            "memwatch overhead: CPU {:.2}s ({:.1}%) | Peak RSS: {} | Sampling: {:.2} ms/tick (max {:.2} ms)",
+            overhead.cpu.as_secs_f64(),
+            overhead.cpu_share * 100.0,
+            size::format_bytes(overhead.peak_rss),
+            overhead.tick_avg.as_secs_f64() * 1e3,
+            overhead.tick_max.as_secs_f64() * 1e3
+        );
+        if overhead.missed_ticks > 0 {
+            eprintln!(
+                "memwatch fell behind: {} ticks skipped; consider a longer --interval",
+                overhead.missed_ticks
+            );
+        }
+    }
+    match termination {
+        Some("rss_limit") => eprintln!("Killed: RSS limit exceeded"),
+        Some("detached") => {}
+        Some("time_limit") => eprintln!(
+            "Stopped after {} (--for)",
+            clock::format_duration(args.watch_for.unwrap_or_default())
+        ),
+        Some(_) => eprintln!("Interrupted (Ctrl+C)"),
+        None => {}
     }
 
-    Ok(())
-}
+    let mut assertions: Vec<Assertion> = [
+        (Metric::PeakRss, args.assert_peak_rss),
+        (Metric::PeakVsz, args.assert_peak_vsz),
+    ]
+    .into_iter()
+    .filter_map(|(metric, limit)| {
+        limit.map(|limit| Assertion {
+            metric,
+            limit: limit as f64,
+            budget: None,
+        })
+    })
+    .collect();
+    assertions.extend(budget::assertions(&budgets, &summary.command_line()));
+    let outcomes = assertions::check(&assertions, &args.assert_expr, &summary, elapsed);
+    for outcome in &outcomes {
+        eprintln!(
+            "Assertion {}: {}",
+            if outcome.passed { "passed" } else { "FAILED" },
+            outcome.description
+        );
+    }
+    if let Some(path) = &args.junit {
+        if let Err(e) = junit::write(path, &summary, elapsed, &outcomes) {
+            eprintln!("memwatch: failed to write JUnit report to {}: {}", path.display(), e);
+        }
+    }
+
+    let run_summary = summary.run_summary(elapsed, &exit, termination);
+    if let Some(path) = &args.report_md {
+        if let Err(e) = report::write_markdown(path, &run_summary, &timeline) {
+            eprintln!("memwatch: failed to write report to {}: {}", path.display(), e);
+        }
+    }
+
+    if let Some(path) = &spawn.extract_csv {
+        if let Err(e) = extract::write_csv(path, &summary.extracted, started_at, timestamps.csv) {
+            eprintln!("memwatch: failed to write extracted values to {}: {}", path.display(), e);
+        }
+    }
+
+    for observer in &mut observers {
+        observer.on_exit(&run_summary);
+    }
+
+    if args.gha {
+        if let Err(e) = gha::write_step_summary(&run_summary) {
+            eprintln!("memwatch: failed to write the job summary: {}", e);
+        }
+    }
 
-fn meminfo(root_pid: i32) -> procfs::ProcResult<(u64, u64)> {
-    let page_size = procfs::page_size();
-
-    let mut children_map: HashMap<i32, Vec<i32>> = HashMap::new();
-    for proc in all_processes()? {
-        if let Ok(proc) = proc {
-            if let Ok(stat) = proc.stat() {
-                children_map
-                    .entry(stat.ppid)
-                    .or_insert_with(Vec::new)
-                    .push(stat.pid);
+    if args.summary_json.is_some() || args.summary_fd.is_some() {
+        let report = summary.report(elapsed, &exit, termination);
+        if let Some(path) = &args.summary_json {
+            if let Err(e) = summary::write_file(path, &report) {
+                eprintln!("memwatch: failed to write summary to {}: {}", path.display(), e);
+            }
+        }
+        if let Some(fd) = args.summary_fd {
+            if let Err(e) = summary::write_fd(fd, &report) {
+                eprintln!("memwatch: failed to write summary to fd {}: {}", fd, e);
             }
         }
     }
 
-    let mut total_rss = 0u64;
-    let mut total_vsz = 0u64;
+    if let Some(mut webhook) = webhook.take() {
+        webhook.exit(&summary, elapsed, &exit);
+        webhook.finish();
+    }
+    if let Some(mut mailer) = mailer.take() {
+        if args.email_after.is_none_or(|after| elapsed >= after) {
+            mailer.exit(&run_summary);
+        }
+        mailer.finish();
+    }
+    if args.notify_desktop {
+        notify::desktop(&summary, elapsed, &exit);
+    }
+    if args.osc {
+        notify::osc_exit(&summary, elapsed, &exit);
+    }
 
-    let mut stack = vec![root_pid];
+    // Returned rather than exited with, so that the terminal, the cgroup
+    // and the control socket are cleaned up on the way out
+    Ok(match outcomes.iter().any(|outcome| !outcome.passed) {
+        true => ExitCode::FAILURE,
+        false => ExitCode::SUCCESS,
+    })
+}
 
-    while let Some(pid) = stack.pop() {
-        if let Ok(proc) = Process::new(pid) {
-            if let Ok(statm) = proc.statm() {
-                total_vsz = total_vsz.saturating_add(statm.size * page_size);
-                total_rss = total_rss.saturating_add(statm.resident * page_size);
+/// `-vv` line for each sample, `-v` when it went wrong
+fn log_sample(now: Duration, sample: &TreeSample, took: Duration) {
+    if sample.stale {
+        log::info!("scan of /proc failed; repeating the previous sample");
+    }
+    let errors = sample.errors;
+    if errors.denied + errors.transient > 0 {
+        log::info!(
+            "read errors: denied {}, transient {}, gone {}",
+            errors.denied,
+            errors.transient,
+            errors.gone
+        );
+    }
+    log::debug!(
+        "sample at {:.3}s: {} processes, RSS {}, VSZ {}, {} /proc entries scanned, took {:.2} ms",
+        now.as_secs_f64(),
+        sample.processes.len(),
+        sample.rss,
+        sample.vsz,
+        sample.scanned,
+        took.as_secs_f64() * 1e3
+    );
+}
+
+/// Tell about output lines dropped since the last call
+fn report_dropped(renderer: &mut Renderer, summary: &mut Summary, total: u64) {
+    if total > summary.output_lines_dropped {
+        renderer.notice(&format!(
+            "… {} lines dropped, output backlog full …",
+            total - summary.output_lines_dropped
+        ));
+        summary.output_lines_dropped = total;
+    }
+}
+
+/// Kill the command, and with `kill_tree` everything it spawned
+///
+/// `reaped` tells that the command itself has already been waited for.
+fn stop_command(
+    target: &mut Target,
+    kill_tree: bool,
+    cgroup: Option<&Cgroup>,
+    tracker: &ProcessTracker,
+    reaped: bool,
+) {
+    if kill_tree {
+        match cgroup {
+            Some(cgroup) => cgroup.kill_all(),
+            None => {
+                let root = (!reaped).then_some(target.pid());
+                tree::kill_tree(root, &tracker.live());
             }
         }
+    }
+    if !reaped {
+        target.kill();
+    }
+}
+
+/// Copy the tree's /proc files into a new bundle and tell where it went
+fn save_bundle(
+    bundles: &mut Bundles,
+    renderer: &mut Renderer,
+    summary: &Summary,
+    reason: &str,
+    now: Duration,
+    root_pid: Option<i32>,
+    tracker: &ProcessTracker,
+) {
+    match bundles.take(summary, reason, now, root_pid, &tracker.live()) {
+        Ok((dir, count)) => renderer.notice(&format!(
+            "Saved /proc files of {} process{} to {}",
+            count,
+            if count == 1 { "" } else { "es" },
+            dir.display()
+        )),
+        Err(e) => renderer.notice(&format!("memwatch: failed to save a bundle: {}", e)),
+    }
+}
 
-        if let Some(children) = children_map.get(&pid) {
-            stack.extend(children);
+/// Start the command with its output going to memwatch, or to `pty`
+fn spawn_command(
+    command_line: &[String],
+    spawn: &SpawnArgs,
+    pty: Option<&Pty>,
+    tui: bool,
+    cgroup: Option<&Cgroup>,
+) -> io::Result<(Child, Option<RuntimeHeaps>)> {
+    let prog = &command_line[0];
+    let mut command = Command::new(prog);
+    command.args(&command_line[1..]);
+    match pty {
+        Some(pty) => {
+            command
+                .stdin(pty.slave.try_clone()?)
+                .stdout(pty.slave.try_clone()?)
+                .stderr(pty.slave.try_clone()?);
+            // SAFETY: the hook only performs async-signal-safe syscalls
+            unsafe {
+                command.pre_exec(pty::controlling_terminal_hook);
+            }
+        }
+        None => {
+            // Key presses belong to the UI
+            let stdin = if tui {
+                Stdio::null()
+            } else {
+                Stdio::inherit()
+            };
+            command
+                .stdin(stdin)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+        }
+    }
+    if let Some(cgroup) = cgroup {
+        // SAFETY: the hook only performs async-signal-safe syscalls
+        unsafe {
+            command.pre_exec(cgroup.join_hook());
         }
     }
+    let runtime_heap = if spawn.runtime_heap {
+        let runtime_heap = RuntimeHeaps::setup()?;
+        runtime_heap.configure(&mut command);
+        Some(runtime_heap)
+    } else {
+        None
+    };
+
+    let child = command
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to spawn `{}`: {}", prog, e)))?;
+    // Close our copies of the slave so reads on the master end with the child
+    drop(command);
+    Ok((child, runtime_heap))
+}
+
+fn format_title(clock: &str, rss_bytes: u64, command: &[String]) -> String {
+    let program = Path::new(&command[0])
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    format!("{} {} {}", clock, size::format_bytes(rss_bytes), program)
+}
+
+/// Status line after `clock`, a [`Clock::stamp`]
+///
+/// `incomplete` marks the sizes with `?`: they leave out memory that could
+/// not be read.
+/// `[00:12] RSS:  183.52 MB | VSZ:  410.04 MB | Procs:   3`, the numbers
+/// in fixed widths so the line keeps still as they change
+fn format_status_line(
+    clock: &str,
+    rss_bytes: u64,
+    vsz_bytes: u64,
+    processes: usize,
+    incomplete: bool,
+) -> StatusLine {
+    let fixed = size::format_bytes_fixed;
+    let mark = if incomplete { "?" } else { "" };
 
-    Ok((total_rss, total_vsz))
+    let mut line = StatusLine::new(clock);
+    line.push_short(
+        Priority::Essential,
+        format!("RSS: {}{}", fixed(rss_bytes), mark),
+        format!("RSS {}{}", size::format_bytes_short(rss_bytes), mark),
+    );
+    line.push_short(
+        Priority::High,
+        format!("VSZ: {}{}", fixed(vsz_bytes), mark),
+        format!("VSZ {}{}", size::format_bytes_short(vsz_bytes), mark),
+    );
+    line.push(Priority::High, format!("Procs: {:>3}", processes));
+    line
 }
 
-/// Guard to ensure cursor is shown on exit (even on panic or Ctrl+C)
-struct CursorGuard;
+/// Threads of the largest process of the latest sample, for `t` in `--tui`
+fn show_threads(renderer: &mut Renderer, sample: &Sample) {
+    let Some(largest) = sample.processes.iter().max_by_key(|p| p.rss) else {
+        return;
+    };
+    match threads::report(largest.pid) {
+        Ok(lines) => {
+            for line in lines {
+                renderer.notice(&line);
+            }
+        }
+        Err(e) => renderer.notice(&format!("memwatch: {}", e)),
+    }
+}
 
-impl Drop for CursorGuard {
-    fn drop(&mut self) {
-        print!("{}", cursor::Show);
-        let _ = io::stdout().flush();
+/// `--reclaim-every`: reclaim what the kernel can of the cgroup and sample
+/// the tree again, e.g. `RSS 1.20 GB → 800.00 MB, cgroup 1.50 GB → 900.00 MB`
+fn reclaim(cgroup: &Cgroup, sampler: &mut Sampler, pid: i32, rss: u64) -> io::Result<String> {
+    let charged = cgroup.memory_current();
+    cgroup.reclaim()?;
+    let mut response = format!(
+        "RSS {} → {}",
+        size::format_bytes(rss),
+        size::format_bytes(sampler.sample(pid).rss)
+    );
+    if let (Some(before), Some(after)) = (charged, cgroup.memory_current()) {
+        response.push_str(&format!(
+            ", cgroup {} → {}",
+            size::format_bytes(before),
+            size::format_bytes(after)
+        ));
     }
+    Ok(response)
+}
+
+/// `Δ +12.40 MB`, colored by direction
+fn format_delta(previous: u64, rss: u64) -> String {
+    let (sign, color) = match rss.cmp(&previous) {
+        cmp::Ordering::Greater => ("+", format!("{}", color::Fg(color::Red))),
+        cmp::Ordering::Less => ("-", format!("{}", color::Fg(color::Green))),
+        cmp::Ordering::Equal => ("±", String::new()),
+    };
+    format!(
+        "Δ {}{}{}{}",
+        color,
+        sign,
+        size::format_bytes(rss.abs_diff(previous)),
+        color::Fg(color::Reset)
+    )
 }
 
-fn format_status_line(elapsed: Duration, rss_bytes: u64, vsz_bytes: u64) -> String {
-    let (rss_val, rss_unit) = format_bytes_unit(rss_bytes);
-    let (vsz_val, vsz_unit) = format_bytes_unit(vsz_bytes);
-    let (mm, ss) = (elapsed.as_secs() / 60, elapsed.as_secs() % 60);
+fn format_peak_line(summary: &Summary) -> String {
+    let mut line = format!(
+        "Peak RSS: {} (kernel-reported: {}) | Peak VSZ: {} (kernel-reported: {})",
+        size::format_bytes(summary.peak_rss),
+        size::format_bytes(summary.kernel_peak_rss),
+        size::format_bytes(summary.peak_vsz),
+        size::format_bytes(summary.kernel_peak_vsz)
+    );
+    if let Some(peak) = summary.cgroup_peak {
+        line.push_str(&format!(" | Cgroup peak: {}", size::format_bytes(peak)));
+    }
+    line
+}
 
+/// One-time warning about `/proc` files that turned out unreadable
+fn format_denied(denied: Denied) -> String {
+    let files: Vec<String> = denied
+        .files()
+        .iter()
+        .map(|file| format!("/proc/PID/{}", file))
+        .collect();
     format!(
-        "[{:02}:{:02}] RSS: {:.2} {} | VSZ: {:.2} {}",
-        mm, ss, rss_val, rss_unit, vsz_val, vsz_unit
+        "Warning: cannot read {} of some processes (permission denied); {} are missing. \
+         Run memwatch as the owner of the processes or as root for complete figures",
+        files.join(", "),
+        denied.missing().join("; ")
     )
 }
 
-/// Format to chosen unit (auto/kb/mb/gb)
-fn format_bytes_unit(bytes: u64) -> (f64, &'static str) {
-    if bytes >= 1024_u64.pow(3) {
-        (bytes as f64 / 1024f64.powi(3), "GB")
-    } else if bytes >= 1024_u64.pow(2) {
-        (bytes as f64 / 1024f64.powi(2), "MB")
-    } else if bytes >= 1024 {
-        (bytes as f64 / 1024.0, "KB")
-    } else {
-        (bytes as f64, "B")
+fn format_process_event(clock: &str, event: &ProcessEvent) -> String {
+    match event {
+        ProcessEvent::Started { pid, name } => {
+            format!("{} + {} ({}) started", clock, name, pid)
+        }
+        ProcessEvent::Exited {
+            pid,
+            name,
+            lifetime,
+            peak_rss,
+        } => format!(
+            "{} - {} ({}) exited after {}, peak RSS {}",
+            clock,
+            name,
+            pid,
+            clock::format_duration(*lifetime),
+            size::format_bytes(*peak_rss)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn status(incomplete: bool) -> StatusLine {
+        format_status_line("[00:12]", 183 * MIB + 532 * 1024, 410 * MIB, 3, incomplete)
+    }
+
+    #[test]
+    fn status_line_in_fixed_widths() {
+        assert_eq!(
+            status(false).fit(200),
+            "[00:12] RSS:  183.52 MB | VSZ:  410.00 MB | Procs:   3"
+        );
+        assert_eq!(
+            format_status_line("[01:02:03]", 5 * 1024, 0, 120, false).fit(200),
+            "[01:02:03] RSS:    5.00 KB | VSZ:    0.00 B  | Procs: 120"
+        );
+    }
+
+    #[test]
+    fn status_line_marks_incomplete_sizes() {
+        assert_eq!(
+            status(true).fit(200),
+            "[00:12] RSS:  183.52 MB? | VSZ:  410.00 MB? | Procs:   3"
+        );
+    }
+
+    #[test]
+    fn status_line_gives_way_on_narrow_terminals() {
+        // Columns with a short form take it first, then the clock and the
+        // rest go, rightmost first; the RSS is cut at the edge
+        assert_eq!(status(false).fit(50), "[00:12] RSS:  183.52 MB | VSZ 410.00M | Procs:   3");
+        assert_eq!(status(false).fit(47), "RSS:  183.52 MB | VSZ 410.00M | Procs:   3");
+        assert_eq!(status(false).fit(40), "RSS:  183.52 MB | VSZ 410.00M");
+        assert_eq!(status(false).fit(16), "RSS:  183.52 MB");
+        assert_eq!(status(false).fit(12), "RSS 183.52M");
+        assert_eq!(status(false).fit(10), "RSS 183.52");
     }
 }
@@ -0,0 +1,362 @@
+//! Monitoring a command from another program
+//!
+//! [`Monitor::spawn`] starts the command and samples its process tree on a
+//! background thread until it exits; samples are queued for the caller
+//! and folded into a [`RunSummary`]. [`Monitor::spawn_with`] also calls
+//! [`Observer`]s on that thread as the run happens.
+
+use std::{
+    collections::VecDeque,
+    io,
+    process::{
+        Child,
+        Command,
+    },
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+    },
+    task::Waker,
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+use crate::{
+    alert::Alert,
+    exit::{
+        signal_name,
+        ExitInfo,
+    },
+    observer::{
+        Crossing,
+        Observer,
+        Threshold,
+    },
+    record::{
+        self,
+        Environment,
+        RunSummary,
+        Sample,
+    },
+    ticks::Ticks,
+    tree::{
+        ProcessEvent,
+        ProcessTracker,
+        Sampler,
+        TreeSample,
+    },
+};
+
+/// Samples kept for a caller that does not read them; older ones are
+/// dropped first
+const QUEUE_LIMIT: usize = 1024;
+
+/// A running command and the thread sampling it
+pub struct Monitor {
+    pid: i32,
+    pub(crate) shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+pub(crate) struct Shared {
+    pub(crate) state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+pub(crate) struct State {
+    pub(crate) samples: VecDeque<Sample>,
+    /// Set once the command has exited, taken by the first waiter
+    pub(crate) summary: Option<io::Result<RunSummary>>,
+    pub(crate) finished: bool,
+    /// Tasks waiting for a sample or the summary
+    pub(crate) wakers: Vec<Waker>,
+}
+
+impl Shared {
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state);
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+        self.changed.notify_all();
+    }
+}
+
+impl Monitor {
+    /// Spawn `command` and sample it every `interval`
+    pub fn spawn(command: &mut Command, interval: Duration) -> io::Result<Self> {
+        Self::spawn_with(command, interval, Vec::new(), Vec::new())
+    }
+
+    /// Like [`Monitor::spawn`], also calling `observers` on every sample
+    /// and when the tree's RSS goes above one of `thresholds`: the first
+    /// time, and again after it re-arms, see [`Threshold::rearm`]
+    pub fn spawn_with(
+        command: &mut Command,
+        interval: Duration,
+        thresholds: Vec<Threshold>,
+        observers: Vec<Box<dyn Observer + Send>>,
+    ) -> io::Result<Self> {
+        let args: Vec<String> = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let child = command.spawn()?;
+        let pid = child.id() as i32;
+        let shared = Arc::new(Shared::default());
+        let thread = {
+            let shared = shared.clone();
+            let sampling = Sampling {
+                interval,
+                thresholds: thresholds
+                    .into_iter()
+                    .map(|threshold| {
+                        let alert = Alert::new(threshold.limit, threshold.rearm);
+                        (threshold, alert)
+                    })
+                    .collect(),
+                observers,
+            };
+            thread::spawn(move || run(child, &args, sampling, &shared))
+        };
+        Ok(Self {
+            pid,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Next sample, waiting for it; `None` once the command has exited and
+    /// every sample was taken
+    pub fn next_sample(&self) -> Option<Sample> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(sample) = state.samples.pop_front() {
+                return Some(sample);
+            }
+            if state.finished {
+                return None;
+            }
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Wait for the command to exit and return the summary of the run
+    pub fn wait(mut self) -> io::Result<RunSummary> {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .summary
+            .take()
+            .unwrap_or_else(|| Err(io::Error::other("summary already taken")))
+    }
+}
+
+/// What the sampling thread does on each tick
+struct Sampling {
+    interval: Duration,
+    thresholds: Vec<(Threshold, Alert)>,
+    observers: Vec<Box<dyn Observer + Send>>,
+}
+
+/// Body of the sampling thread
+fn run(mut child: Child, args: &[String], mut sampling: Sampling, shared: &Shared) {
+    let pid = child.id() as i32;
+    let start = Instant::now();
+    let mut ticks = Ticks::new(start, sampling.interval);
+    let exit_wait = ExitWait::new(pid);
+    let mut sampler = Sampler::new(false);
+    let mut tracker = ProcessTracker::default();
+    let mut summary = RunSummary {
+        host: record::hostname(),
+        command: record::command_line(args),
+        run_id: record::new_run_id(),
+        started_at: Some(SystemTime::now()),
+        environment: Some(Environment::collect()),
+        ..RunSummary::default()
+    };
+
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) => {}
+            Err(e) => break Err(e),
+        }
+        let tree = sampler.sample(pid);
+        let events = tracker.update(&tree);
+        fold(&mut summary, &tree);
+        summary.processes_seen = tracker.seen;
+        let sample = Sample::new(ticks.elapsed(), tree);
+        sampling.observe(&sample, &events);
+        shared.update(|state| {
+            if state.samples.len() == QUEUE_LIMIT {
+                state.samples.pop_front();
+            }
+            state.samples.push_back(sample);
+        });
+        let deadline = ticks.advance();
+        exit_wait.wait(deadline.saturating_duration_since(Instant::now()));
+    };
+
+    let result = status.map(|status| {
+        let exit = ExitInfo::from_status(status);
+        summary.elapsed = start.elapsed();
+        summary.exit_code = exit.code;
+        summary.signal = exit.signal.map(signal_name);
+        summary.core_dumped = exit.core_dumped;
+        summary.top_processes = tracker.top();
+        for observer in &mut sampling.observers {
+            observer.on_exit(&summary);
+        }
+        summary
+    });
+    shared.update(|state| {
+        state.summary = Some(result);
+        state.finished = true;
+    });
+}
+
+impl Sampling {
+    fn observe(&mut self, sample: &Sample, events: &[ProcessEvent]) {
+        let mut crossed = Vec::new();
+        for (threshold, alert) in &mut self.thresholds {
+            if alert.update(sample.elapsed, sample.rss) {
+                crossed.push(Crossing {
+                    name: threshold.name.clone(),
+                    limit: threshold.limit,
+                    rss: sample.rss,
+                    elapsed: sample.elapsed,
+                });
+            }
+        }
+        for observer in &mut self.observers {
+            for event in events {
+                observer.on_process(sample.elapsed, event);
+            }
+            observer.on_sample(sample);
+            for crossing in &crossed {
+                observer.on_threshold(crossing);
+            }
+        }
+    }
+}
+
+/// Fold one sample into the peaks of `summary`
+fn fold(summary: &mut RunSummary, tree: &TreeSample) {
+    let hwm: u64 = tree.processes.iter().map(|p| p.hwm).sum();
+    let vm_peak: u64 = tree.processes.iter().map(|p| p.vm_peak).sum();
+    summary.peak_rss = summary.peak_rss.max(tree.rss);
+    summary.peak_vsz = summary.peak_vsz.max(tree.vsz);
+    summary.kernel_peak_rss = summary.kernel_peak_rss.max(hwm);
+    summary.kernel_peak_vsz = summary.kernel_peak_vsz.max(vm_peak);
+    summary.peak_processes = summary.peak_processes.max(tree.processes.len() as u64);
+    summary.samples += 1;
+}
+
+/// Sleeps between samples, waking early when the command exits
+struct ExitWait {
+    /// pidfd of the command, readable once it has exited
+    pidfd: Option<i32>,
+}
+
+impl ExitWait {
+    fn new(pid: i32) -> Self {
+        // SAFETY: pidfd_open takes a PID and flags and returns a new fd
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        Self {
+            pidfd: (fd >= 0).then_some(fd as i32),
+        }
+    }
+
+    fn wait(&self, timeout: Duration) {
+        let Some(fd) = self.pidfd else {
+            thread::sleep(timeout);
+            return;
+        };
+        let mut poll = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: one valid pollfd for the duration of the call
+        unsafe { libc::poll(&mut poll, 1, millis) };
+    }
+}
+
+impl Drop for ExitWait {
+    fn drop(&mut self) {
+        if let Some(fd) = self.pidfd {
+            // SAFETY: the fd was opened by us and is closed once
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::alert::Hysteresis;
+
+    struct Crossings(mpsc::Sender<Duration>);
+
+    impl Observer for Crossings {
+        fn on_threshold(&mut self, crossing: &Crossing) {
+            self.0.send(crossing.elapsed).unwrap();
+        }
+    }
+
+    fn crossings(threshold: Threshold, rss: &[u64]) -> Vec<u64> {
+        let (sender, receiver) = mpsc::channel();
+        let mut sampling = Sampling {
+            interval: Duration::from_secs(1),
+            thresholds: vec![(threshold.clone(), Alert::new(threshold.limit, threshold.rearm))],
+            observers: vec![Box::new(Crossings(sender))],
+        };
+        for (i, &rss) in rss.iter().enumerate() {
+            let sample = Sample {
+                elapsed: Duration::from_secs(i as u64),
+                rss,
+                ..Sample::default()
+            };
+            sampling.observe(&sample, &[]);
+        }
+        drop(sampling);
+        receiver.iter().map(|elapsed| elapsed.as_secs()).collect()
+    }
+
+    #[test]
+    fn thresholds_fire_once_by_default() {
+        assert_eq!(crossings(Threshold::new("warn", 100), &[50, 150, 50, 150]), [1]);
+    }
+
+    #[test]
+    fn rearmed_thresholds_fire_again_after_hysteresis_and_cooldown() {
+        let threshold = Threshold::new("warn", 100).rearm(Hysteresis::Bytes(20), Duration::from_secs(3));
+        // 90 is not below the clear level of 80; the crossing at 6 falls in
+        // the cooldown of the one at 4
+        let rss = [150, 90, 150, 70, 150, 70, 150, 150];
+        assert_eq!(crossings(threshold, &rss), [0, 4, 7]);
+    }
+}
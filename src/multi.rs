@@ -0,0 +1,303 @@
+//! `memwatch multi`: several commands side by side with a combined total
+
+use std::{
+    io,
+    path::PathBuf,
+    process::{
+        Child,
+        Command,
+        Stdio,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            Ordering,
+        },
+        mpsc,
+        Arc,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    clock::format_elapsed,
+    exit::ExitInfo,
+    json::Value,
+    record::SCHEMA_VERSION,
+    output::{
+        self,
+        Stream,
+    },
+    render::{
+        OutputMsg,
+        Renderer,
+    },
+    size::format_bytes,
+    summary::{
+        self,
+        Summary,
+    },
+    ticks::Ticks,
+    tree::{
+        self,
+        ProcessTracker,
+        ReadError,
+        TreeSample,
+    },
+};
+
+/// Longest command label shown in the dashboard
+const LABEL_WIDTH: usize = 24;
+
+#[derive(clap::Args, Debug)]
+pub struct MultiArgs {
+    /// Command to run through `sh -c`; repeat for each command
+    #[arg(long = "cmd", value_name = "COMMAND", required = true)]
+    commands: Vec<String>,
+
+    /// Update interval in milliseconds
+    #[arg(short, long, default_value_t = 1000)]
+    interval: u64,
+
+    /// On exit or interrupt, kill all descendants of each command
+    #[arg(long)]
+    kill_tree: bool,
+
+    /// Write the final JSON summary to this file
+    #[arg(long, value_name = "FILE")]
+    summary_json: Option<PathBuf>,
+}
+
+/// One of the monitored commands
+struct Job {
+    label: String,
+    child: Child,
+    pid: i32,
+    summary: Summary,
+    tracker: ProcessTracker,
+    last: TreeSample,
+    /// Exit status and time, once the command is done
+    exit: Option<(ExitInfo, Duration)>,
+}
+
+impl Job {
+    fn stop(&mut self, kill_tree: bool) {
+        if kill_tree {
+            tree::kill_tree(Some(self.pid), &self.tracker.live());
+        }
+        let _ = self.child.kill();
+    }
+}
+
+pub fn run(args: MultiArgs, terminated: Arc<AtomicBool>) -> io::Result<()> {
+    let interval = Duration::from_millis(args.interval);
+    let start = Instant::now();
+    let mut ticks = Ticks::new(start, interval);
+
+    let (tx, rx) = mpsc::sync_channel::<(usize, OutputMsg)>(output::BACKLOG);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let mut jobs = Vec::new();
+
+    for (index, command) in args.commands.iter().enumerate() {
+        let mut child = Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to spawn `{}`: {}", command, e)))?;
+
+        let streams = [
+            child.stdout.take().map(|s| (Box::new(s) as Box<dyn io::Read + Send>, Stream::Stdout)),
+            child.stderr.take().map(|s| (Box::new(s) as Box<dyn io::Read + Send>, Stream::Stderr)),
+        ];
+        for (reader, stream) in streams.into_iter().flatten() {
+            // Tag each message with its command on the way to the
+            // shared channel
+            let (job_tx, job_rx) = mpsc::sync_channel(output::BACKLOG);
+            let dropped = dropped.clone();
+            thread::spawn(move || output::forward(reader, stream, job_tx, dropped));
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for msg in job_rx {
+                    if tx.send((index, msg)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        jobs.push(Job {
+            label: label(command),
+            pid: child.id() as i32,
+            child,
+            summary: Summary::new(&["sh".into(), "-c".into(), command.clone()]),
+            tracker: ProcessTracker::default(),
+            last: TreeSample::default(),
+            exit: None,
+        });
+    }
+    drop(tx);
+
+    let mut renderer = Renderer::plain();
+    let mut combined_peak_rss = 0u64;
+    let mut combined_peak_processes = 0usize;
+
+    loop {
+        let interrupted = terminated.load(Ordering::SeqCst);
+        for job in jobs.iter_mut().filter(|job| job.exit.is_none()) {
+            if interrupted {
+                job.stop(args.kill_tree);
+            }
+            if let Some(status) = job.child.try_wait()? {
+                job.exit = Some((ExitInfo::from_status(status), start.elapsed()));
+                job.last = TreeSample::default();
+                if args.kill_tree {
+                    tree::kill_tree(None, &job.tracker.live());
+                }
+            }
+        }
+        if jobs.iter().all(|job| job.exit.is_some()) {
+            break;
+        }
+
+        for job in jobs.iter_mut().filter(|job| job.exit.is_none()) {
+            job.last = tree::sample(job.pid)
+                .unwrap_or_else(|e| job.last.stale(ReadError::of(&e)));
+            job.summary.record(&job.last);
+            job.tracker.update(&job.last);
+            job.summary.processes_seen = job.tracker.seen;
+        }
+        let total_rss: u64 = jobs.iter().map(|job| job.last.rss).sum();
+        let total_processes: usize = jobs.iter().map(|job| job.last.processes.len()).sum();
+        combined_peak_rss = combined_peak_rss.max(total_rss);
+        combined_peak_processes = combined_peak_processes.max(total_processes);
+
+        let dashboard = format_dashboard(start.elapsed(), &jobs);
+        renderer.status(&dashboard);
+
+        let deadline = ticks.advance();
+        loop {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((index, msg)) => {
+                    renderer.output(tag(&(index + 1).to_string(), msg));
+                    renderer.status(&dashboard);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    // Every command closed its output; poll for their exit
+                    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    break;
+                }
+            }
+        }
+    }
+
+    while let Ok((index, msg)) = rx.recv_timeout(Duration::from_millis(50)) {
+        renderer.output(tag(&(index + 1).to_string(), msg));
+    }
+    drop(renderer);
+
+    let elapsed = start.elapsed();
+    for (index, job) in jobs.iter_mut().enumerate() {
+        job.summary.top_processes = job.tracker.top();
+        let (exit, _) = job.exit.as_ref().expect("all jobs have exited");
+        eprintln!(
+            "[{}] {}: {} | Peak RSS: {}",
+            index + 1,
+            job.label,
+            exit.describe(),
+            format_bytes(job.summary.peak_rss)
+        );
+    }
+    eprintln!(
+        "Combined peak RSS: {} | Peak processes: {}",
+        format_bytes(combined_peak_rss),
+        combined_peak_processes
+    );
+    let interrupted = terminated.load(Ordering::SeqCst);
+    if interrupted {
+        eprintln!("Interrupted (Ctrl+C)");
+    }
+
+    if let Some(path) = &args.summary_json {
+        let termination = interrupted.then_some("interrupted");
+        let commands = jobs
+            .iter()
+            .map(|job| {
+                let (exit, job_elapsed) = job.exit.as_ref().expect("all jobs have exited");
+                job.summary.report(*job_elapsed, exit, termination)
+            })
+            .collect();
+        let report = Value::Object(vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("elapsed_secs".into(), elapsed.as_secs_f64().into()),
+            ("peak_rss".into(), combined_peak_rss.into()),
+            ("peak_processes".into(), (combined_peak_processes as u64).into()),
+            ("output_lines_dropped".into(), dropped.load(Ordering::Relaxed).into()),
+            ("commands".into(), Value::Array(commands)),
+        ]);
+        if let Err(e) = summary::write_file(path, &report) {
+            eprintln!("memwatch: failed to write summary to {}: {}", path.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prefix a line of output with the label of the command it came from
+pub fn tag(label: &str, msg: OutputMsg) -> OutputMsg {
+    match msg {
+        OutputMsg::Stdout(l) => OutputMsg::Stdout(format!("[{}] {}", label, l)),
+        OutputMsg::Stderr(l) => OutputMsg::Stderr(format!("[{}] {}", label, l)),
+        OutputMsg::Progress(l) => OutputMsg::Progress(format!("[{}] {}", label, l)),
+        msg @ (OutputMsg::Pty(_) | OutputMsg::Key(_)) => msg,
+    }
+}
+
+fn label(command: &str) -> String {
+    let mut label: String = command.chars().take(LABEL_WIDTH).collect();
+    if command.chars().count() > LABEL_WIDTH {
+        label.pop();
+        label.push('…');
+    }
+    label
+}
+
+fn format_dashboard(elapsed: Duration, jobs: &[Job]) -> String {
+    let total_rss: u64 = jobs.iter().map(|job| job.last.rss).sum();
+    let total_vsz: u64 = jobs.iter().map(|job| job.last.vsz).sum();
+    let total_processes: usize = jobs.iter().map(|job| job.last.processes.len()).sum();
+
+    let mut lines = vec![format!(
+        "[{}] Total RSS: {} | VSZ: {} | Procs: {}",
+        format_elapsed(elapsed),
+        format_bytes(total_rss),
+        format_bytes(total_vsz),
+        total_processes
+    )];
+    for (index, job) in jobs.iter().enumerate() {
+        let state = match &job.exit {
+            Some((exit, _)) => exit.describe(),
+            None => format!(
+                "RSS: {} | Procs: {}",
+                format_bytes(job.last.rss),
+                job.last.processes.len()
+            ),
+        };
+        lines.push(format!(
+            "  [{}] {:<width$} {} | Peak: {}",
+            index + 1,
+            job.label,
+            state,
+            format_bytes(job.summary.peak_rss),
+            width = LABEL_WIDTH
+        ));
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,323 @@
+//! `--net`: sockets the tree holds, and its traffic when it has a network
+//! namespace of its own
+//!
+//! Sockets are the `socket:[inode]` links in `/proc/<pid>/fd` of every
+//! process of the tree, counted once when processes share them, and their
+//! kind and queues come from the tables in `/proc/<pid>/net` of the
+//! command. Bytes waiting in the send and receive queues of TCP and UDP
+//! sockets are kernel memory in no RSS. Linux keeps no traffic counters
+//! per process, only per network namespace, so traffic is shown when the
+//! command runs in a namespace other than memwatch's, e.g. under
+//! `unshare -n` or in a container, and is left out otherwise.
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fs,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    json::Value,
+    size::{
+        format_bytes,
+        format_bytes_short,
+    },
+    tree::TreeSample,
+};
+
+/// Least time between two scans
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// TCP state `01` in `/proc/net/tcp`
+const TCP_ESTABLISHED: &str = "01";
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Tcp,
+    Udp,
+    Unix,
+}
+
+/// A socket of the socket tables
+struct Socket {
+    kind: Kind,
+    established: bool,
+    /// Bytes in the send and receive queues
+    queued: u64,
+}
+
+/// Sockets of the tree at one scan
+#[derive(Clone, Copy, Default)]
+struct Counts {
+    total: usize,
+    tcp: usize,
+    established: usize,
+    udp: usize,
+    unix: usize,
+    queued: u64,
+}
+
+/// Reported in the summary
+#[derive(Default)]
+pub struct NetStats {
+    pub peak_sockets: usize,
+    pub peak_tcp: usize,
+    pub peak_established: usize,
+    pub peak_udp: usize,
+    pub peak_unix: usize,
+    pub peak_queued: u64,
+    /// Traffic of the command's network namespace over the run, when it
+    /// has one of its own
+    pub received: Option<u64>,
+    pub sent: Option<u64>,
+}
+
+impl NetStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("peak_sockets".into(), (self.peak_sockets as u64).into()),
+            ("peak_tcp".into(), (self.peak_tcp as u64).into()),
+            ("peak_established".into(), (self.peak_established as u64).into()),
+            ("peak_udp".into(), (self.peak_udp as u64).into()),
+            ("peak_unix".into(), (self.peak_unix as u64).into()),
+            ("peak_queued".into(), self.peak_queued.into()),
+            ("received".into(), self.received.into()),
+            ("sent".into(), self.sent.into()),
+        ])
+    }
+
+    /// `Network: peak 40 sockets (tcp 32, 30 established, udp 2, unix 6),
+    /// 1.20 MB queued | 120.00 MB received, 4.00 MB sent`
+    pub fn format(&self) -> String {
+        let mut line = format!(
+            "Network: peak {} socket{} (tcp {}, {} established, udp {}, unix {}), {} queued",
+            self.peak_sockets,
+            if self.peak_sockets == 1 { "" } else { "s" },
+            self.peak_tcp,
+            self.peak_established,
+            self.peak_udp,
+            self.peak_unix,
+            format_bytes(self.peak_queued)
+        );
+        if let (Some(received), Some(sent)) = (self.received, self.sent) {
+            line.push_str(&format!(
+                " | {} received, {} sent",
+                format_bytes(received),
+                format_bytes(sent)
+            ));
+        }
+        line
+    }
+}
+
+/// Received and sent bytes of a network namespace, loopback left out
+fn read_traffic(pid: i32) -> Option<(u64, u64)> {
+    let dev = fs::read_to_string(format!("/proc/{}/net/dev", pid)).ok()?;
+    let mut total = (0, 0);
+    // Two header lines, then `eth0: rx_bytes ... (8 fields) tx_bytes ...`
+    for line in dev.lines().skip(2) {
+        let Some((name, counters)) = line.split_once(':') else {
+            continue;
+        };
+        if name.trim() == "lo" {
+            continue;
+        }
+        let fields: Vec<u64> = counters.split_whitespace().filter_map(|f| f.parse().ok()).collect();
+        if let (Some(received), Some(sent)) = (fields.first(), fields.get(8)) {
+            total.0 += received;
+            total.1 += sent;
+        }
+    }
+    Some(total)
+}
+
+/// The command runs in a network namespace of its own
+fn own_namespace(pid: i32) -> bool {
+    match (
+        fs::read_link("/proc/self/ns/net"),
+        fs::read_link(format!("/proc/{}/ns/net", pid)),
+    ) {
+        (Ok(ours), Ok(theirs)) => ours != theirs,
+        _ => false,
+    }
+}
+
+/// Sockets of the tables in the namespace of `pid`, by inode
+fn read_sockets(pid: i32) -> HashMap<u64, Socket> {
+    let mut sockets = HashMap::new();
+    for (table, kind) in [
+        ("tcp", Kind::Tcp),
+        ("tcp6", Kind::Tcp),
+        ("udp", Kind::Udp),
+        ("udp6", Kind::Udp),
+    ] {
+        let Ok(text) = fs::read_to_string(format!("/proc/{}/net/{}", pid, table)) else {
+            continue;
+        };
+        // `sl local remote st tx_queue:rx_queue tr:when retrnsmt uid timeout inode`
+        for line in text.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (Some(state), Some(queues), Some(Ok(inode))) =
+                (fields.get(3), fields.get(4), fields.get(9).map(|f| f.parse()))
+            else {
+                continue;
+            };
+            let queued = queues
+                .split(':')
+                .filter_map(|q| u64::from_str_radix(q, 16).ok())
+                .sum();
+            sockets.insert(
+                inode,
+                Socket {
+                    kind,
+                    established: matches!(kind, Kind::Tcp) && *state == TCP_ESTABLISHED,
+                    queued,
+                },
+            );
+        }
+    }
+    if let Ok(text) = fs::read_to_string(format!("/proc/{}/net/unix", pid)) {
+        // `Num RefCount Protocol Flags Type St Inode Path`
+        for line in text.lines().skip(1) {
+            if let Some(Ok(inode)) = line.split_whitespace().nth(6).map(str::parse) {
+                sockets.insert(
+                    inode,
+                    Socket {
+                        kind: Kind::Unix,
+                        established: false,
+                        queued: 0,
+                    },
+                );
+            }
+        }
+    }
+    sockets
+}
+
+/// Inodes of the sockets open in the processes of `sample`
+fn tree_sockets(sample: &TreeSample) -> HashSet<u64> {
+    let mut inodes = HashSet::new();
+    for process in &sample.processes {
+        let Ok(fds) = fs::read_dir(format!("/proc/{}/fd", process.pid)) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = fs::read_link(fd.path()) else {
+                continue;
+            };
+            let target = target.to_string_lossy();
+            if let Some(Ok(inode)) = target
+                .strip_prefix("socket:[")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .map(str::parse)
+            {
+                inodes.insert(inode);
+            }
+        }
+    }
+    inodes
+}
+
+pub struct NetProbe {
+    pid: i32,
+    last_scan: Option<Instant>,
+    counts: Counts,
+    /// Traffic of the command's namespace when it was first seen, and the
+    /// latest reading with its time
+    traffic_start: Option<(u64, u64)>,
+    traffic: Option<(Instant, u64, u64)>,
+    /// Received and sent bytes per second over the last scan
+    rates: Option<(f64, f64)>,
+    stats: NetStats,
+}
+
+impl NetProbe {
+    /// Watch the sockets of the tree of `pid`
+    pub fn new(pid: i32) -> Self {
+        Self {
+            pid,
+            last_scan: None,
+            counts: Counts::default(),
+            traffic_start: None,
+            traffic: None,
+            rates: None,
+            stats: NetStats::default(),
+        }
+    }
+
+    /// Scan the tree's sockets if a scan is due
+    pub fn update(&mut self, sample: &TreeSample) {
+        if self.last_scan.is_some_and(|last| last.elapsed() < SCAN_INTERVAL) {
+            return;
+        }
+        let now = Instant::now();
+        self.last_scan = Some(now);
+
+        let table = read_sockets(self.pid);
+        let mut counts = Counts::default();
+        for inode in tree_sockets(sample) {
+            counts.total += 1;
+            let Some(socket) = table.get(&inode) else {
+                continue;
+            };
+            match socket.kind {
+                Kind::Tcp => counts.tcp += 1,
+                Kind::Udp => counts.udp += 1,
+                Kind::Unix => counts.unix += 1,
+            }
+            counts.established += usize::from(socket.established);
+            counts.queued += socket.queued;
+        }
+        self.counts = counts;
+        let stats = &mut self.stats;
+        stats.peak_sockets = stats.peak_sockets.max(counts.total);
+        stats.peak_tcp = stats.peak_tcp.max(counts.tcp);
+        stats.peak_established = stats.peak_established.max(counts.established);
+        stats.peak_udp = stats.peak_udp.max(counts.udp);
+        stats.peak_unix = stats.peak_unix.max(counts.unix);
+        stats.peak_queued = stats.peak_queued.max(counts.queued);
+
+        let Some((received, sent)) = read_traffic(self.pid).filter(|_| own_namespace(self.pid)) else {
+            self.rates = None;
+            return;
+        };
+        let (received_start, sent_start) = *self.traffic_start.get_or_insert((received, sent));
+        if let Some((at, last_received, last_sent)) = self.traffic {
+            let secs = now.duration_since(at).as_secs_f64();
+            self.rates = Some((
+                received.saturating_sub(last_received) as f64 / secs,
+                sent.saturating_sub(last_sent) as f64 / secs,
+            ));
+        }
+        self.traffic = Some((now, received, sent));
+        stats.received = Some(received.saturating_sub(received_start));
+        stats.sent = Some(sent.saturating_sub(sent_start));
+    }
+
+    /// Sockets, then traffic when there is any, for the status line
+    pub fn status(&self) -> Vec<String> {
+        let counts = &self.counts;
+        let mut columns = vec![format!(
+            "Sockets: {} (tcp {}, udp {}, unix {})",
+            counts.total, counts.tcp, counts.udp, counts.unix
+        )];
+        if let Some((received, sent)) = self.rates {
+            columns.push(format!(
+                "net ↓{}/s ↑{}/s",
+                format_bytes_short(received as u64),
+                format_bytes_short(sent as u64)
+            ));
+        }
+        columns
+    }
+
+    pub fn finish(self) -> NetStats {
+        self.stats
+    }
+}
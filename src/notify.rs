@@ -0,0 +1,225 @@
+//! Webhook, desktop and terminal notifications for threshold crossings and exit
+
+use std::{
+    env,
+    fs::OpenOptions,
+    io::Write,
+    process::{
+        Command,
+        Stdio,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use crate::{
+    clock::format_duration,
+    exit::ExitInfo,
+    http::{
+        origin,
+        post_json,
+    },
+    json::Value,
+    record::{
+        self,
+        SCHEMA_VERSION,
+    },
+    size::format_bytes,
+    summary::Summary,
+};
+
+/// POSTs JSON payloads to a webhook URL via `curl`
+///
+/// Requests run on background threads so a slow endpoint never delays
+/// sampling; call [`Webhook::finish`] before exit to deliver pending ones.
+pub struct Webhook {
+    url: String,
+    pending: Vec<JoinHandle<()>>,
+}
+
+impl Webhook {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Notify that a size threshold was crossed
+    pub fn threshold(
+        &mut self,
+        summary: &Summary,
+        elapsed: Duration,
+        name: &str,
+        limit: u64,
+        rss: u64,
+    ) {
+        let text = format!(
+            "memwatch: `{}` on {} crossed {} ({} > {})",
+            summary.command_line(),
+            summary.host,
+            name,
+            format_bytes(rss),
+            format_bytes(limit)
+        );
+
+        let mut fields = vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("event".into(), "threshold".into()),
+            ("time".into(), record::format_time(SystemTime::now()).into()),
+            ("text".into(), text.into()),
+            ("threshold".into(), name.into()),
+            ("limit".into(), limit.into()),
+            ("rss".into(), rss.into()),
+        ];
+        fields.extend(summary.json_fields(elapsed));
+        self.post(Value::Object(fields));
+    }
+
+    /// Notify that the monitored command finished
+    pub fn exit(&mut self, summary: &Summary, elapsed: Duration, exit: &ExitInfo) {
+        let text = format!(
+            "memwatch: `{}` on {} {} after {}, peak RSS {}",
+            summary.command_line(),
+            summary.host,
+            exit.describe(),
+            format_duration(elapsed),
+            format_bytes(summary.peak_rss)
+        );
+
+        let mut fields = vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("event".into(), "exit".into()),
+            ("time".into(), record::format_time(SystemTime::now()).into()),
+            ("text".into(), text.into()),
+        ];
+        fields.extend(exit.json_fields());
+        fields.extend(summary.json_fields(elapsed));
+        self.post(Value::Object(fields));
+    }
+
+    /// Wait for all in-flight requests to complete
+    pub fn finish(self) {
+        for handle in self.pending {
+            let _ = handle.join();
+        }
+    }
+
+    fn post(&mut self, payload: Value) {
+        let url = self.url.clone();
+        let body = payload.to_string();
+        self.pending.push(thread::spawn(move || {
+            if let Err(e) = post_json(&url, &body) {
+                eprintln!("\rmemwatch: webhook to {} failed: {}", origin(&url), e);
+            }
+        }));
+    }
+}
+
+/// Show a freedesktop notification that the command finished
+///
+/// Goes through `notify-send` and falls back to a raw D-Bus call via
+/// `gdbus` when libnotify tools are not installed.
+pub fn desktop(summary: &Summary, elapsed: Duration, exit: &ExitInfo) {
+    let title = exit_title(exit);
+    let body = format!(
+        "{}\nDuration: {}\nPeak RSS: {}",
+        summary.command_line(),
+        format_duration(elapsed),
+        format_bytes(summary.peak_rss)
+    );
+
+    let notify_send = Command::new("notify-send")
+        .args(["--app-name", "memwatch", &title, &body])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if matches!(notify_send, Ok(status) if status.success()) {
+        return;
+    }
+
+    let gdbus = Command::new("gdbus")
+        .args([
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.Notifications",
+            "--object-path",
+            "/org/freedesktop/Notifications",
+            "--method",
+            "org.freedesktop.Notifications.Notify",
+            "memwatch",
+            "0",
+            "",
+            &gvariant_str(&title),
+            &gvariant_str(&body),
+            "[]",
+            "{}",
+            "-1",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if !matches!(gdbus, Ok(status) if status.success()) {
+        eprintln!("memwatch: desktop notification failed (notify-send/gdbus unavailable)");
+    }
+}
+
+/// Ask the terminal for a native notification about the finished command
+pub fn osc_exit(summary: &Summary, elapsed: Duration, exit: &ExitInfo) {
+    let body = format!(
+        "{} | Duration: {} | Peak RSS: {}",
+        summary.command_line(),
+        format_duration(elapsed),
+        format_bytes(summary.peak_rss)
+    );
+    osc(&exit_title(exit), &body);
+}
+
+/// Ask the terminal for a native notification with an OSC escape sequence
+///
+/// iTerm2, WezTerm and kitty understand OSC 9, VTE-based terminals, foot and
+/// urxvt OSC 777; only one is sent so no terminal shows it twice. Written
+/// to the controlling terminal, so it works with stdout redirected too.
+pub fn osc(title: &str, body: &str) {
+    let Ok(mut tty) = OpenOptions::new().write(true).open("/dev/tty") else {
+        return;
+    };
+    let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+    let term = env::var("TERM").unwrap_or_default();
+    let osc9 = matches!(term_program.as_str(), "iTerm.app" | "WezTerm") || term == "xterm-kitty";
+    let sequence = if osc9 {
+        format!("\x1b]9;{}: {}\x07", osc_text(title), osc_text(body))
+    } else {
+        // Fields are separated by `;`, so the title must not contain one
+        format!(
+            "\x1b]777;notify;{};{}\x07",
+            osc_text(title).replace(';', ","),
+            osc_text(body)
+        )
+    };
+    tty.write_all(sequence.as_bytes()).ok();
+}
+
+/// Control characters would end the sequence early
+fn osc_text(s: &str) -> String {
+    s.chars().filter(|c| !c.is_control()).collect()
+}
+
+fn exit_title(exit: &ExitInfo) -> String {
+    match exit.code {
+        Some(0) => String::from("memwatch: command finished"),
+        _ => format!("memwatch: command {}", exit.describe()),
+    }
+}
+
+/// Quote a string as a GVariant text literal for `gdbus`
+fn gvariant_str(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
@@ -0,0 +1,198 @@
+//! Hooks for code that consumes a run as it happens
+//!
+//! An [`Observer`] sees every sample, process churn, threshold crossings,
+//! anomalies, marks, changes of the sampling interval and the final
+//! summary. The CLI's renderer and exporters are observers; embedders
+//! pass their own to [`Monitor::spawn_with`].
+//!
+//! [`Monitor::spawn_with`]: crate::monitor::Monitor::spawn_with
+
+use std::time::Duration;
+
+use crate::{
+    alert::{
+        Hysteresis,
+        Rearm,
+    },
+    json::Value,
+    record::{
+        self,
+        RunSummary,
+        Sample,
+        SCHEMA_VERSION,
+    },
+    tree::ProcessEvent,
+};
+
+/// An RSS limit to report crossings of
+#[derive(Clone, Debug)]
+pub struct Threshold {
+    /// Name passed on in [`Crossing::name`], e.g. `warn_rss`
+    pub name: String,
+    pub limit: u64,
+    /// Whether it fires again after the first crossing
+    pub rearm: Rearm,
+}
+
+impl Threshold {
+    /// A threshold that fires the first time RSS goes above `limit`
+    pub fn new(name: impl Into<String>, limit: u64) -> Self {
+        Self {
+            name: name.into(),
+            limit,
+            rearm: Rearm::Never,
+        }
+    }
+
+    /// Fire again once RSS fell to the limit less `hysteresis` and went
+    /// above it anew, at most once per `cooldown`
+    pub fn rearm(self, hysteresis: Hysteresis, cooldown: Duration) -> Self {
+        Self {
+            rearm: Rearm::After { hysteresis, cooldown },
+            ..self
+        }
+    }
+}
+
+/// Total RSS of the tree went above a [`Threshold`]
+#[derive(Clone, Debug)]
+pub struct Crossing {
+    pub name: String,
+    pub limit: u64,
+    pub rss: u64,
+    /// Time since the command started
+    pub elapsed: Duration,
+}
+
+impl Crossing {
+    pub fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("threshold".into(), self.name.as_str().into()),
+            ("limit".into(), self.limit.into()),
+            ("rss".into(), self.rss.into()),
+            ("elapsed_secs".into(), self.elapsed.as_secs_f64().into()),
+        ])
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        record::check_version(value)?;
+        Ok(Self {
+            name: record::string(value, "threshold")?,
+            limit: record::uint(value, "limit")?,
+            rss: record::uint(value, "rss")?,
+            elapsed: record::secs(value, "elapsed_secs")?,
+        })
+    }
+}
+
+/// Total RSS of the tree strayed far from its recent level
+#[derive(Clone, Debug)]
+pub struct Anomaly {
+    pub rss: u64,
+    /// Recent level the sample was expected near
+    pub expected: u64,
+    /// Distance from `expected` in standard deviations, negative for a drop
+    pub score: f64,
+    /// Time since the command started
+    pub elapsed: Duration,
+}
+
+impl Anomaly {
+    pub fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("rss".into(), self.rss.into()),
+            ("expected".into(), self.expected.into()),
+            ("score".into(), self.score.into()),
+            ("elapsed_secs".into(), self.elapsed.as_secs_f64().into()),
+        ])
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        record::check_version(value)?;
+        Ok(Self {
+            rss: record::uint(value, "rss")?,
+            expected: record::uint(value, "expected")?,
+            score: record::float(value, "score")?,
+            elapsed: record::secs(value, "elapsed_secs")?,
+        })
+    }
+}
+
+/// A moment of the run labelled from outside, e.g. through the control
+/// socket of the CLI
+#[derive(Clone, Debug)]
+pub struct Mark {
+    pub label: String,
+    /// Time since the command started
+    pub elapsed: Duration,
+}
+
+impl Mark {
+    pub fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("label".into(), self.label.as_str().into()),
+            ("elapsed_secs".into(), self.elapsed.as_secs_f64().into()),
+        ])
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        record::check_version(value)?;
+        Ok(Self {
+            label: record::string(value, "label")?,
+            elapsed: record::secs(value, "elapsed_secs")?,
+        })
+    }
+}
+
+/// Sampling went on at a new interval, e.g. as asked for through the
+/// control socket of the CLI
+#[derive(Clone, Debug)]
+pub struct IntervalChange {
+    pub interval: Duration,
+    /// Time since the command started
+    pub elapsed: Duration,
+}
+
+impl IntervalChange {
+    pub fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("interval_secs".into(), self.interval.as_secs_f64().into()),
+            ("elapsed_secs".into(), self.elapsed.as_secs_f64().into()),
+        ])
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        record::check_version(value)?;
+        Ok(Self {
+            interval: record::secs(value, "interval_secs")?,
+            elapsed: record::secs(value, "elapsed_secs")?,
+        })
+    }
+}
+
+/// Receives a run as it happens; every method defaults to doing nothing
+pub trait Observer {
+    fn on_sample(&mut self, _sample: &Sample) {}
+
+    /// A process joined or left the tree, `elapsed` after the start
+    fn on_process(&mut self, _elapsed: Duration, _event: &ProcessEvent) {}
+
+    /// Called when a threshold is crossed: the first time, and again
+    /// after RSS fell back below its hysteresis and the cooldown is over
+    /// if the threshold re-arms, as one built with [`Threshold::rearm`]
+    /// does
+    fn on_threshold(&mut self, _crossing: &Crossing) {}
+
+    /// Called at the onset of each anomaly, if the caller looks for them
+    fn on_anomaly(&mut self, _anomaly: &Anomaly) {}
+
+    fn on_mark(&mut self, _mark: &Mark) {}
+
+    fn on_interval(&mut self, _change: &IntervalChange) {}
+
+    fn on_exit(&mut self, _summary: &RunSummary) {}
+}
@@ -0,0 +1,206 @@
+//! OpenTelemetry metrics export over OTLP/HTTP (JSON encoding)
+
+use std::{
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use crate::{
+    http::{
+        origin,
+        post_json,
+    },
+    json::Value,
+    observer::Observer,
+    record::{
+        RunSummary,
+        Sample,
+    },
+    summary::Summary,
+};
+
+/// How often buffered samples are pushed to the collector
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Point {
+    time_unix_nano: u128,
+    rss: u64,
+    vsz: u64,
+}
+
+/// Buffers samples and pushes them as OTLP gauge metrics
+pub struct OtelExporter {
+    url: String,
+    resource: Value,
+    points: Vec<Point>,
+    last_flush: Instant,
+    /// The push in flight, if any, and what came of it
+    pending: Option<JoinHandle<Result<(), String>>>,
+    warned: bool,
+}
+
+impl OtelExporter {
+    /// `endpoint` is the collector base URL, e.g. `http://localhost:4318`
+    pub fn new(endpoint: &str, summary: &Summary) -> Self {
+        let endpoint = endpoint.trim_end_matches('/');
+        let url = if endpoint.ends_with("/v1/metrics") {
+            endpoint.to_string()
+        } else {
+            format!("{}/v1/metrics", endpoint)
+        };
+
+        let resource = Value::Object(vec![(
+            "attributes".into(),
+            Value::Array(vec![
+                attribute("service.name", "memwatch"),
+                attribute("host.name", &summary.host),
+                attribute("process.command_line", &summary.command_line()),
+                attribute("memwatch.run_id", &summary.run_id),
+            ]),
+        )]);
+
+        Self {
+            url,
+            resource,
+            points: Vec::new(),
+            last_flush: Instant::now(),
+            pending: None,
+            warned: false,
+        }
+    }
+
+    fn flush(&mut self) {
+        self.last_flush = Instant::now();
+        if self.points.is_empty() {
+            return;
+        }
+
+        // Keep at most one request in flight; while a slow collector holds
+        // one up, points wait for the push after it
+        if self.pending.as_ref().is_some_and(|handle| !handle.is_finished()) {
+            return;
+        }
+        self.finish_push();
+
+        let points = std::mem::take(&mut self.points);
+        let body = self.payload(&points).to_string();
+        let url = self.url.clone();
+        self.pending = Some(thread::spawn(move || post_json(&url, &body)));
+    }
+
+    /// Wait for the push in flight and report a failure, on the terminal
+    /// the first time and in the log after that
+    fn finish_push(&mut self) {
+        let Some(handle) = self.pending.take() else {
+            return;
+        };
+        let Ok(Err(e)) = handle.join() else {
+            return;
+        };
+        if !self.warned {
+            self.warned = true;
+            eprintln!("\rmemwatch: OTLP export to {} failed: {}", origin(&self.url), e);
+        } else {
+            crate::log::info!("OTLP export to {} failed: {}", origin(&self.url), e);
+        }
+    }
+
+    fn payload(&self, points: &[Point]) -> Value {
+        let metrics = vec![
+            gauge(
+                "process.memory.rss",
+                "Resident set size of the process tree",
+                points.iter().map(|p| (p.time_unix_nano, p.rss)),
+            ),
+            gauge(
+                "process.memory.virtual",
+                "Virtual memory size of the process tree",
+                points.iter().map(|p| (p.time_unix_nano, p.vsz)),
+            ),
+        ];
+
+        let scope = Value::Object(vec![
+            ("name".into(), "memwatch".into()),
+            ("version".into(), env!("CARGO_PKG_VERSION").into()),
+        ]);
+
+        Value::Object(vec![(
+            "resourceMetrics".into(),
+            Value::Array(vec![Value::Object(vec![
+                ("resource".into(), self.resource.clone()),
+                (
+                    "scopeMetrics".into(),
+                    Value::Array(vec![Value::Object(vec![
+                        ("scope".into(), scope),
+                        ("metrics".into(), Value::Array(metrics)),
+                    ])]),
+                ),
+            ])]),
+        )])
+    }
+}
+
+impl Observer for OtelExporter {
+    fn on_sample(&mut self, sample: &Sample) {
+        let time_unix_nano = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        self.points.push(Point {
+            time_unix_nano,
+            rss: sample.rss,
+            vsz: sample.vsz,
+        });
+
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush();
+        }
+    }
+
+    /// Push remaining samples and wait for delivery
+    fn on_exit(&mut self, _summary: &RunSummary) {
+        self.finish_push();
+        self.flush();
+        self.finish_push();
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    Value::Object(vec![
+        ("key".into(), key.into()),
+        (
+            "value".into(),
+            Value::Object(vec![("stringValue".into(), value.into())]),
+        ),
+    ])
+}
+
+fn gauge(name: &str, description: &str, points: impl Iterator<Item = (u128, u64)>) -> Value {
+    // OTLP/JSON encodes 64-bit integers as strings
+    let data_points = points
+        .map(|(time, value)| {
+            Value::Object(vec![
+                ("timeUnixNano".into(), time.to_string().into()),
+                ("asInt".into(), value.to_string().into()),
+            ])
+        })
+        .collect();
+
+    Value::Object(vec![
+        ("name".into(), name.into()),
+        ("description".into(), description.into()),
+        ("unit".into(), "By".into()),
+        (
+            "gauge".into(),
+            Value::Object(vec![("dataPoints".into(), Value::Array(data_points))]),
+        ),
+    ])
+}
@@ -0,0 +1,257 @@
+//! Reading the child's output streams
+//!
+//! Lines ending in a bare carriage return are progress-bar redraws (pip,
+//! cargo, wget); they are forwarded separately so the renderer can keep them
+//! on their own row instead of colliding with the `\r`-based status line.
+
+use std::{
+    io::Read,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        mpsc::{
+            SyncSender,
+            TrySendError,
+        },
+        Arc,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    render::{
+        OutputMsg,
+        Renderer,
+    },
+    size::format_bytes_short,
+};
+
+/// Messages buffered between the readers and the renderer; past this,
+/// lines are dropped rather than queued
+pub const BACKLOG: usize = 4096;
+
+/// Longest line kept in memory; longer ones are split
+pub const MAX_LINE: usize = 64 * 1024;
+
+/// Which of the child's streams a reader forwards
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Split `reader` into lines and progress updates and send them to `tx`
+///
+/// The stream is drained at full speed so the child never blocks on its
+/// output; whatever does not fit the channel is counted in `dropped`.
+/// Returns when the stream closes or the receiver is gone.
+pub fn forward(
+    mut reader: impl Read,
+    stream: Stream,
+    tx: SyncSender<OutputMsg>,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut buf = [0u8; 8192];
+    let mut segment = Vec::new();
+    // A `\r` may be the first half of `\r\n`; decided by the next byte
+    let mut pending_cr = false;
+    // The current line was redrawn with `\r` at least once
+    let mut in_progress = false;
+    // The segment was already sent as a progress update
+    let mut flushed = false;
+
+    let line = |segment: &mut Vec<u8>| {
+        let text = String::from_utf8_lossy(segment).into_owned();
+        segment.clear();
+        match stream {
+            Stream::Stdout => OutputMsg::Stdout(text),
+            Stream::Stderr => OutputMsg::Stderr(text),
+        }
+    };
+    // False once the receiver is gone
+    let send = |msg: OutputMsg| match tx.try_send(msg) {
+        Ok(()) => true,
+        Err(TrySendError::Full(OutputMsg::Progress(_))) => true,
+        Err(TrySendError::Full(_)) => {
+            dropped.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    };
+
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+
+        for &b in &buf[..n] {
+            if std::mem::take(&mut pending_cr) && b != b'\n' {
+                in_progress = true;
+                if !segment.is_empty() && !flushed {
+                    let text = String::from_utf8_lossy(&segment).into_owned();
+                    if !send(OutputMsg::Progress(text)) {
+                        return;
+                    }
+                }
+                segment.clear();
+            }
+
+            match b {
+                b'\n' => {
+                    in_progress = false;
+                    if !send(line(&mut segment)) {
+                        return;
+                    }
+                }
+                b'\r' => pending_cr = true,
+                _ => {
+                    flushed = false;
+                    segment.push(b);
+                    if segment.len() >= MAX_LINE && !send(line(&mut segment)) {
+                        return;
+                    }
+                }
+            }
+        }
+
+        // Show the latest redraw right away rather than on the next `\r`
+        if in_progress && !segment.is_empty() && !flushed {
+            let text = String::from_utf8_lossy(&segment).into_owned();
+            if !send(OutputMsg::Progress(text)) {
+                return;
+            }
+            flushed = true;
+        }
+    }
+
+    if !segment.is_empty() {
+        send(line(&mut segment));
+    }
+}
+
+/// Limits on how much of the child's line output gets rendered
+pub struct OutputFilter {
+    /// Render none of it
+    hide: bool,
+    /// Lines rendered per second at most
+    max_rate: Option<u32>,
+    /// Collapse runs of identical lines into a count
+    collapse: bool,
+    window_start: Instant,
+    window_lines: u32,
+    suppressed: u64,
+    last: Option<String>,
+    repeats: u64,
+    /// Append the tree's RSS to every this many rendered lines
+    tag_every: Option<u32>,
+    tagged: u64,
+    rss: u64,
+}
+
+impl OutputFilter {
+    pub fn new(hide: bool, max_rate: Option<u32>, collapse: bool, tag_every: Option<u32>) -> Self {
+        Self {
+            hide,
+            max_rate,
+            collapse,
+            window_start: Instant::now(),
+            window_lines: 0,
+            suppressed: 0,
+            last: None,
+            repeats: 0,
+            tag_every,
+            tagged: 0,
+            rss: 0,
+        }
+    }
+
+    /// RSS of the latest sample, for tagging lines
+    pub fn set_rss(&mut self, rss: u64) {
+        self.rss = rss;
+    }
+
+    /// Render `msg` unless it falls over the limits
+    pub fn output(&mut self, renderer: &mut Renderer, msg: OutputMsg) {
+        let line = match &msg {
+            OutputMsg::Stdout(_) | OutputMsg::Stderr(_) | OutputMsg::Progress(_) if self.hide => return,
+            OutputMsg::Stdout(line) | OutputMsg::Stderr(line) => line,
+            OutputMsg::Progress(_) | OutputMsg::Pty(_) | OutputMsg::Key(_) => {
+                renderer.output(msg);
+                return;
+            }
+        };
+
+        if self.collapse {
+            if self.last.as_ref() == Some(line) {
+                self.repeats += 1;
+                return;
+            }
+            self.report_repeats(renderer);
+            self.last = Some(line.clone());
+        }
+
+        self.tick(renderer);
+        if let Some(max_rate) = self.max_rate {
+            if self.window_lines >= max_rate {
+                self.suppressed += 1;
+                return;
+            }
+            self.window_lines += 1;
+        }
+        renderer.output(self.tag(msg));
+    }
+
+    fn tag(&mut self, msg: OutputMsg) -> OutputMsg {
+        let Some(every) = self.tag_every else {
+            return msg;
+        };
+        self.tagged += 1;
+        if !(self.tagged - 1).is_multiple_of(every as u64) {
+            return msg;
+        }
+        let tag = format!(" [rss={}]", format_bytes_short(self.rss));
+        match msg {
+            OutputMsg::Stdout(line) => OutputMsg::Stdout(line + &tag),
+            OutputMsg::Stderr(line) => OutputMsg::Stderr(line + &tag),
+            msg => msg,
+        }
+    }
+
+    /// Report suppressed lines once their one-second window is over
+    pub fn tick(&mut self, renderer: &mut Renderer) {
+        if self.window_start.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        self.window_start = Instant::now();
+        self.window_lines = 0;
+        self.report_suppressed(renderer);
+    }
+
+    /// Report anything still pending when the command is done
+    pub fn finish(&mut self, renderer: &mut Renderer) {
+        self.report_repeats(renderer);
+        self.report_suppressed(renderer);
+    }
+
+    fn report_repeats(&mut self, renderer: &mut Renderer) {
+        match std::mem::take(&mut self.repeats) {
+            0 => {}
+            1 => renderer.notice("… previous line repeated 1 more time …"),
+            n => renderer.notice(&format!("… previous line repeated {} more times …", n)),
+        }
+    }
+
+    fn report_suppressed(&mut self, renderer: &mut Renderer) {
+        match std::mem::take(&mut self.suppressed) {
+            0 => {}
+            1 => renderer.notice("… 1 line suppressed …"),
+            n => renderer.notice(&format!("… {} lines suppressed …", n)),
+        }
+    }
+}
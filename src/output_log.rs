@@ -0,0 +1,226 @@
+//! `--output-log`: the command's output kept in a file, rotated by size
+//!
+//! Output is copied as the reader threads get it, ahead of the channel to
+//! the renderer, so none of it is lost to a flood of output.
+//!
+//! Rotation works like logrotate: the full file becomes `FILE.1`, the
+//! previous `FILE.1` becomes `FILE.2` and so on, and the oldest beyond the
+//! count kept is removed. With `--log-gzip` each rotated file is compressed
+//! by `gzip` in the background into `FILE.1.gz`.
+
+use std::{
+    fs::{
+        self,
+        File,
+    },
+    io::{
+        self,
+        BufWriter,
+        Read,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        Child,
+        Command,
+        Stdio,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use crate::size;
+
+/// When to rotate and how many rotated files to keep
+#[derive(Clone, Copy, Debug)]
+pub struct Rotation {
+    pub size: u64,
+    pub keep: usize,
+}
+
+/// `SIZE,COUNT`, e.g. `100M,5`
+pub fn parse_rotation(s: &str) -> Result<Rotation, String> {
+    let (size, keep) = s
+        .split_once(',')
+        .ok_or_else(|| format!("invalid rotation `{}`, expected SIZE,COUNT such as 100M,5", s))?;
+    let size = size::parse_size(size)?;
+    if size == 0 {
+        return Err(String::from("rotation size must be above zero"));
+    }
+    let keep = keep
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&keep| keep > 0)
+        .ok_or_else(|| format!("invalid count of rotated files `{}`", keep.trim()))?;
+    Ok(Rotation { size, keep })
+}
+
+pub struct OutputLog {
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// Bytes in the current file
+    written: u64,
+    rotation: Option<Rotation>,
+    gzip: bool,
+    /// `gzip` still compressing the last rotated file
+    compressing: Option<Child>,
+    /// First failure, after which nothing more is written
+    error: Option<io::Error>,
+}
+
+impl OutputLog {
+    /// Start `path` afresh
+    pub fn create(path: &Path, rotation: Option<Rotation>, gzip: bool) -> io::Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot create {}: {}", path.display(), e)))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            written: 0,
+            rotation,
+            gzip,
+            compressing: None,
+            error: None,
+        })
+    }
+
+    /// Flush the file and wait for compression to finish; the first
+    /// failure of the run, if any
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.file.flush()?;
+        if let Some(mut gzip) = self.compressing.take() {
+            gzip.wait()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = self.file.write_all(bytes).and_then(|()| {
+            self.written += bytes.len() as u64;
+            match self.rotation {
+                Some(rotation) if self.written >= rotation.size => self.rotate(rotation.keep),
+                _ => Ok(()),
+            }
+        });
+        self.error = result.err();
+    }
+
+    fn rotate(&mut self, keep: usize) -> io::Result<()> {
+        self.file.flush()?;
+        // Renaming the file gzip still reads would lose its output
+        if let Some(mut gzip) = self.compressing.take() {
+            gzip.wait()?;
+        }
+
+        let suffix = if self.gzip { ".gz" } else { "" };
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}{}", self.path.display(), n, suffix));
+        match fs::remove_file(rotated(keep)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        for n in (1..keep).rev() {
+            match fs::rename(rotated(n), rotated(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        let first = PathBuf::from(format!("{}.1", self.path.display()));
+        fs::rename(&self.path, &first)?;
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+
+        if self.gzip {
+            self.compressing = Some(
+                Command::new("gzip")
+                    .arg("-f")
+                    .arg(&first)
+                    .stdin(Stdio::null())
+                    .spawn()
+                    .map_err(|e| io::Error::new(e.kind(), format!("failed to run gzip: {}", e)))?,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Reader copying what it reads from `inner` into the log
+pub struct Tee<R> {
+    inner: R,
+    log: Arc<Mutex<OutputLog>>,
+}
+
+impl<R> Tee<R> {
+    pub fn new(inner: R, log: Arc<Mutex<OutputLog>>) -> Self {
+        Self { inner, log }
+    }
+}
+
+impl<R: Read> Read for Tee<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Ok(mut log) = self.log.lock() {
+            log.write(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_size_and_count() {
+        let rotation = parse_rotation("100M,5").unwrap();
+        assert_eq!((rotation.size, rotation.keep), (100 << 20, 5));
+        let rotation = parse_rotation("1k, 1").unwrap();
+        assert_eq!((rotation.size, rotation.keep), (1024, 1));
+
+        assert_eq!(parse_rotation("0,1").unwrap_err(), "rotation size must be above zero");
+        assert_eq!(parse_rotation("1M,0").unwrap_err(), "invalid count of rotated files `0`");
+        assert_eq!(parse_rotation("1M,some").unwrap_err(), "invalid count of rotated files `some`");
+        assert_eq!(
+            parse_rotation("100M").unwrap_err(),
+            "invalid rotation `100M`, expected SIZE,COUNT such as 100M,5"
+        );
+        assert!(parse_rotation("big,5").is_err());
+    }
+
+    #[test]
+    fn rotates_and_drops_the_oldest_beyond_the_count() {
+        let dir = std::env::temp_dir().join(format!("memwatch-output-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("output");
+        let rotated = |n: usize| dir.join(format!("output.{}", n));
+
+        let rotation = Rotation { size: 4, keep: 2 };
+        let mut log = OutputLog::create(&path, Some(rotation), false).unwrap();
+        log.write(b"aaaa");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "aaaa");
+        log.write(b"bbbb");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "bbbb");
+        assert_eq!(fs::read_to_string(rotated(2)).unwrap(), "aaaa");
+        log.write(b"cccc");
+        log.write(b"dd");
+        log.finish().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dd");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "cccc");
+        assert_eq!(fs::read_to_string(rotated(2)).unwrap(), "bbbb");
+        assert!(!rotated(3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+//! memwatch's own resource usage, to judge whether monitoring perturbs
+//! the measurement
+
+use std::{
+    mem::MaybeUninit,
+    time::Duration,
+};
+
+use crate::json::Value;
+
+/// Cost of the sampling work done on each tick
+#[derive(Default)]
+pub struct TickCost {
+    ticks: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl TickCost {
+    pub fn record(&mut self, cost: Duration) {
+        self.ticks += 1;
+        self.total += cost;
+        self.max = self.max.max(cost);
+    }
+
+    /// Read memwatch's own usage at the end of a run lasting `elapsed`,
+    /// in which sampling fell `missed_ticks` behind
+    pub fn finish(&self, elapsed: Duration, missed_ticks: u64) -> Overhead {
+        let usage = rusage();
+        let cpu = usage.map(|u| timeval(u.ru_utime) + timeval(u.ru_stime));
+        Overhead {
+            cpu: cpu.unwrap_or_default(),
+            cpu_share: cpu
+                .map(|cpu| cpu.as_secs_f64() / elapsed.as_secs_f64().max(1e-9))
+                .unwrap_or(0.0),
+            // ru_maxrss is in kilobytes
+            peak_rss: usage.map(|u| u.ru_maxrss as u64 * 1024).unwrap_or(0),
+            tick_avg: self.total.checked_div(self.ticks as u32).unwrap_or_default(),
+            tick_max: self.max,
+            missed_ticks,
+        }
+    }
+}
+
+/// memwatch's own CPU time, memory and per-tick sampling cost
+pub struct Overhead {
+    pub cpu: Duration,
+    /// CPU time as a fraction of wall-clock time
+    pub cpu_share: f64,
+    pub peak_rss: u64,
+    pub tick_avg: Duration,
+    pub tick_max: Duration,
+    /// Ticks skipped because a tick took longer than the interval
+    pub missed_ticks: u64,
+}
+
+impl Overhead {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("cpu_secs".into(), self.cpu.as_secs_f64().into()),
+            ("cpu_share".into(), self.cpu_share.into()),
+            ("peak_rss".into(), self.peak_rss.into()),
+            ("tick_avg_ms".into(), (self.tick_avg.as_secs_f64() * 1e3).into()),
+            ("tick_max_ms".into(), (self.tick_max.as_secs_f64() * 1e3).into()),
+            ("missed_ticks".into(), self.missed_ticks.into()),
+        ])
+    }
+}
+
+fn rusage() -> Option<libc::rusage> {
+    let mut usage = MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: the buffer is valid for writes of a full rusage
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: getrusage succeeded and filled the structure
+    Some(unsafe { usage.assume_init() })
+}
+
+fn timeval(tv: libc::timeval) -> Duration {
+    Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+}
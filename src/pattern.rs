@@ -0,0 +1,597 @@
+//! A small regular expression dialect, for `--extract` and `--redact`
+//!
+//! Literals, `.`, classes such as `[0-9.]` or `[^ ]`, `\d`, `\w`, `\s` and
+//! their negations, the quantifiers `*`, `+`, `?` and `{n,m}`, groups, `|`
+//! and the anchors `^` and `$`; a leading `(?i)` ignores the case of ASCII
+//! letters. Patterns compile to a program run by a Pike VM, which steps
+//! every candidate match forward one character at a time: matching takes
+//! time linear in the text and a fixed amount of stack, however long the
+//! line.
+
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    /// Capture index, if the group captures
+    Group(Option<usize>, Vec<Node>),
+    Alternation(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+/// A compiled pattern, run by a Pike VM
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    source: String,
+    program: Vec<Inst>,
+    groups: usize,
+}
+
+/// More repetitions or instructions than this make a pattern invalid,
+/// bounding the time spent on each character
+const MAX_REPEAT: usize = 1000;
+const MAX_PROGRAM: usize = 1 << 16;
+
+#[derive(Clone, Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    /// Continue at both, preferring the first
+    Split(usize, usize),
+    Jump(usize),
+    /// Record the position in a slot: the whole match in 0 and 1, then the
+    /// start and end of each capture group
+    Save(usize),
+    Match,
+}
+
+type Slots = Vec<Option<usize>>;
+
+impl Pattern {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (ignore_case, body) = match s.strip_prefix("(?i)") {
+            Some(body) => (true, body),
+            None => (false, s),
+        };
+        let mut parser = Parser {
+            chars: body.chars().collect(),
+            pos: 0,
+            groups: 0,
+            ignore_case,
+        };
+        let nodes = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("invalid pattern '{}': unmatched ')'", s));
+        }
+        let mut program = vec![Inst::Save(0)];
+        compile(&nodes, &mut program);
+        program.extend([Inst::Save(1), Inst::Match]);
+        if program.len() > MAX_PROGRAM {
+            return Err(format!("invalid pattern '{}': too large once repetitions are expanded", s));
+        }
+        Ok(Self {
+            source: s.to_string(),
+            program,
+            groups: parser.groups,
+        })
+    }
+
+    /// The pattern as written
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        self.find(&text, 0).is_some()
+    }
+
+    /// Text of the first capture group that took part in the leftmost match
+    /// in `line`, or of the whole match
+    pub fn capture(&self, line: &str) -> Option<String> {
+        let text: Vec<char> = line.chars().collect();
+        let slots = self.find(&text, 0)?;
+        let (from, to) = self.group(&slots);
+        Some(text[from..to].iter().collect())
+    }
+
+    /// `text` with every match replaced by `with`; of a match with a
+    /// capture group that took part, only the group is replaced
+    pub fn replace_all(&self, text: &str, with: &str) -> String {
+        let text: Vec<char> = text.chars().collect();
+        let mut replaced = String::new();
+        let mut pos = 0;
+        while let Some(slots) = self.find(&text, pos) {
+            let (start, end) = (slots[0].unwrap_or(pos), slots[1].unwrap_or(pos));
+            replaced.extend(&text[pos..start]);
+            if end == start {
+                // An empty match replaces nothing
+                replaced.extend(text.get(start));
+                pos = start + 1;
+                continue;
+            }
+            let (from, to) = self.group(&slots);
+            replaced.extend(&text[start..from]);
+            replaced.push_str(with);
+            replaced.extend(&text[to..end]);
+            pos = end;
+        }
+        replaced.extend(text.get(pos..).unwrap_or_default());
+        replaced
+    }
+
+    /// The first capture group that took part in a match, or the match
+    fn group(&self, slots: &Slots) -> (usize, usize) {
+        (1..=self.groups)
+            .chain([0])
+            .find_map(|group| Some((slots[2 * group]?, slots[2 * group + 1]?)))
+            .unwrap_or_default()
+    }
+
+    /// Slots of the leftmost match starting at or after `from`, preferring
+    /// what a backtracking matcher would find first; every thread advances
+    /// one character at a time, so neither time nor stack depth depends on
+    /// how the pattern backtracks
+    fn find(&self, text: &[char], from: usize) -> Option<Slots> {
+        let slots = 2 * (self.groups + 1);
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut matched = None;
+        for pos in from..=text.len() {
+            if matched.is_none() {
+                // A start here ranks below every thread started earlier
+                self.add(&mut current, 0, pos, text, vec![None; slots]);
+            }
+            if current.list.is_empty() {
+                break;
+            }
+            for (pc, thread) in current.list.drain(..) {
+                match &self.program[pc] {
+                    Inst::Match => {
+                        // Threads after this one rank lower
+                        matched = Some(thread);
+                        break;
+                    }
+                    inst => {
+                        let Some(&c) = text.get(pos) else { continue };
+                        let accepted = match inst {
+                            Inst::Char(expected) => c == *expected,
+                            Inst::Any => true,
+                            Inst::Class { ranges, negated } => {
+                                ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&c)) != *negated
+                            }
+                            _ => unreachable!(),
+                        };
+                        if accepted {
+                            self.add(&mut next, pc + 1, pos + 1, text, thread);
+                        }
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        matched
+    }
+
+    /// Follow jumps, splits, saves and anchors from `pc` at `pos`, adding
+    /// each instruction that consumes a character or matches in order of
+    /// preference
+    fn add(&self, threads: &mut Threads, pc: usize, pos: usize, text: &[char], slots: Slots) {
+        let mut stack = vec![(pc, slots)];
+        while let Some((pc, mut slots)) = stack.pop() {
+            if std::mem::replace(&mut threads.seen[pc], true) {
+                continue;
+            }
+            match self.program[pc] {
+                Inst::Jump(to) => stack.push((to, slots)),
+                Inst::Split(first, second) => {
+                    stack.push((second, slots.clone()));
+                    stack.push((first, slots));
+                }
+                Inst::Save(slot) => {
+                    slots[slot] = Some(pos);
+                    stack.push((pc + 1, slots));
+                }
+                Inst::Start => {
+                    if pos == 0 {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::End => {
+                    if pos == text.len() {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                _ => threads.list.push((pc, slots)),
+            }
+        }
+    }
+}
+
+/// Threads waiting at one position, in order of preference
+struct Threads {
+    list: Vec<(usize, Slots)>,
+    /// Instructions already reached at this position; a later thread there
+    /// could only match what an earlier one does
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Self {
+            list: Vec::new(),
+            seen: vec![false; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.fill(false);
+    }
+}
+
+/// Append the instructions for `nodes` to `program`
+fn compile(nodes: &[Node], program: &mut Vec<Inst>) {
+    for node in nodes {
+        compile_node(node, program);
+    }
+}
+
+fn compile_node(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class { ranges, negated } => program.push(Inst::Class {
+            ranges: ranges.clone(),
+            negated: *negated,
+        }),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Group(index, inner) => {
+            if let Some(index) = index {
+                program.push(Inst::Save(2 * index + 2));
+            }
+            compile(inner, program);
+            if let Some(index) = index {
+                program.push(Inst::Save(2 * index + 3));
+            }
+        }
+        Node::Alternation(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                let last = i + 1 == branches.len();
+                let split = program.len();
+                if !last {
+                    program.push(Inst::Split(split + 1, 0));
+                }
+                compile(branch, program);
+                if !last {
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile_node(node, program);
+                if program.len() > MAX_PROGRAM {
+                    return;
+                }
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile_node(node, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        compile_node(node, program);
+                        if program.len() > MAX_PROGRAM {
+                            return;
+                        }
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+    ignore_case: bool,
+}
+
+impl Parser {
+    fn error(&self, what: &str) -> String {
+        let pattern: String = self.chars.iter().collect();
+        format!("invalid pattern '{}': {}", pattern, what)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        self.pos += found as usize;
+        found
+    }
+
+    /// Branches separated by `|` up to the end of the pattern or a
+    /// closing `)`
+    fn alternation(&mut self) -> Result<Vec<Node>, String> {
+        let mut branches = vec![self.sequence()?];
+        while self.eat('|') {
+            branches.push(self.sequence()?);
+        }
+        Ok(match branches.len() {
+            1 => branches.remove(0),
+            _ => vec![Node::Alternation(branches)],
+        })
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            let atom = match c {
+                ')' | '|' => break,
+                '*' | '+' | '?' | '{' => return Err(self.error("nothing to repeat")),
+                _ => self.atom()?,
+            };
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.chars[self.pos];
+        self.pos += 1;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '[' => self.class()?,
+            '(' => {
+                let index = if self.eat('?') {
+                    if !self.eat(':') {
+                        return Err(self.error("only (?:...) groups are supported"));
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups - 1)
+                };
+                let inner = self.alternation()?;
+                if !self.eat(')') {
+                    return Err(self.error("unclosed '('"));
+                }
+                Node::Group(index, inner)
+            }
+            '\\' => self.escape()?,
+            c if self.ignore_case && c.is_ascii_alphabetic() => {
+                let (lower, upper) = (c.to_ascii_lowercase(), c.to_ascii_uppercase());
+                Node::Class {
+                    ranges: vec![(lower, lower), (upper, upper)],
+                    negated: false,
+                }
+            }
+            c => Node::Char(c),
+        })
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or_else(|| self.error("trailing '\\'"))?;
+        self.pos += 1;
+        let class = |ranges: &[(char, char)], negated| Node::Class {
+            ranges: ranges.to_vec(),
+            negated,
+        };
+        const DIGIT: &[(char, char)] = &[('0', '9')];
+        const WORD: &[(char, char)] = &[('0', '9'), ('a', 'z'), ('A', 'Z'), ('_', '_')];
+        const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+        Ok(match c {
+            'd' => class(DIGIT, false),
+            'D' => class(DIGIT, true),
+            'w' => class(WORD, false),
+            'W' => class(WORD, true),
+            's' => class(SPACE, false),
+            'S' => class(SPACE, true),
+            't' => Node::Char('\t'),
+            c => Node::Char(c),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("unclosed '['"))?;
+            self.pos += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = match c {
+                '\\' => match self.escape()? {
+                    Node::Char(c) => c,
+                    Node::Class { ranges: more, .. } => {
+                        ranges.extend(more);
+                        continue;
+                    }
+                    _ => unreachable!(),
+                },
+                c => c,
+            };
+            let range = self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']');
+            let hi = if range {
+                self.pos += 1;
+                let hi = self.peek().ok_or_else(|| self.error("unclosed '['"))?;
+                self.pos += 1;
+                hi
+            } else {
+                lo
+            };
+            if hi < lo {
+                return Err(self.error("invalid class range"));
+            }
+            ranges.push((lo, hi));
+        }
+        if self.ignore_case {
+            // The letters of each range in the other case too
+            let swapped: Vec<(char, char)> = ranges
+                .iter()
+                .flat_map(|&(lo, hi)| {
+                    [('a', 'z'), ('A', 'Z')].into_iter().filter_map(move |(from, to)| {
+                        let (lo, hi) = (lo.max(from), hi.min(to));
+                        // Flipping bit 5 swaps the case of an ASCII letter
+                        let other = |c: char| (c as u8 ^ 0x20) as char;
+                        (lo <= hi).then(|| (other(lo), other(hi)))
+                    })
+                })
+                .collect();
+            ranges.extend(swapped);
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn quantified(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.pos += 1;
+                let end = self.chars[self.pos..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .ok_or_else(|| self.error("unclosed '{'"))?;
+                let body: String = self.chars[self.pos..self.pos + end].iter().collect();
+                self.pos += end;
+                let number = |s: &str| {
+                    s.trim()
+                        .parse::<usize>()
+                        .map_err(|_| self.error("invalid repetition count"))
+                };
+                match body.split_once(',') {
+                    None => {
+                        let n = number(&body)?;
+                        (n, Some(n))
+                    }
+                    Some((min, "")) => (number(min)?, None),
+                    Some((min, max)) => (number(min)?, Some(number(max)?)),
+                }
+            }
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error("invalid repetition count"));
+        }
+        if min.max(max.unwrap_or(0)) > MAX_REPEAT {
+            return Err(self.error(&format!("repetition count above {}", MAX_REPEAT)));
+        }
+        if matches!(atom, Node::Start | Node::End) {
+            return Err(self.error("anchors cannot be repeated"));
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(pattern: &str, text: &str) -> Option<String> {
+        Pattern::parse(pattern).unwrap().capture(text)
+    }
+
+    #[test]
+    fn matches_classes_and_quantifiers() {
+        assert_eq!(capture(r"\d+", "took 1234 ms"), Some("1234".into()));
+        assert_eq!(capture(r"[0-9.]+s", "in 1.25s"), Some("1.25s".into()));
+        assert_eq!(capture(r"[^ ]+$", "a b last"), Some("last".into()));
+        assert_eq!(capture(r"x{2,3}", "xxxxx"), Some("xxx".into()));
+        assert_eq!(capture(r"ab?c", "ac"), Some("ac".into()));
+        assert_eq!(capture(r"\w+\s\W", "foo_1 !"), Some("foo_1 !".into()));
+        assert_eq!(capture(r"^b", "ab"), None);
+    }
+
+    #[test]
+    fn captures_the_first_group_that_took_part() {
+        assert_eq!(capture(r"loss=(\d+\.\d+)", "step 3 loss=0.125"), Some("0.125".into()));
+        assert_eq!(capture(r"(?:a|b)+(c)?", "abab"), Some("abab".into()));
+        assert_eq!(capture(r"(x)|(y)", "y"), Some("y".into()));
+    }
+
+    #[test]
+    fn alternation_and_case() {
+        let pattern = Pattern::parse("(?i)error|warn").unwrap();
+        assert!(pattern.is_match("a WARNING"));
+        assert!(pattern.is_match("Error: x"));
+        assert!(!pattern.is_match("info"));
+        assert_eq!(pattern.as_str(), "(?i)error|warn");
+    }
+
+    #[test]
+    fn replaces_matches_or_their_group() {
+        let token = Pattern::parse(r"token=(\w+)").unwrap();
+        assert_eq!(token.replace_all("a token=abc b token=d", "***"), "a token=*** b token=***");
+        assert_eq!(Pattern::parse(r"\d").unwrap().replace_all("a1b22", "#"), "a#b##");
+        assert_eq!(Pattern::parse("x*").unwrap().replace_all("abc", "-"), "abc");
+    }
+
+    #[test]
+    fn refuses_invalid_patterns() {
+        for pattern in ["(abc", "abc)", "*a", "[a-", "(?=a)", "a{2", "a{3,2}", "a{2000}"] {
+            assert!(Pattern::parse(pattern).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn matches_lines_of_the_longest_kept_length() {
+        let line = format!("x:{}", "a".repeat(crate::output::MAX_LINE));
+        let captured = capture("x:(.*)", &line).unwrap();
+        assert_eq!(captured.len(), crate::output::MAX_LINE);
+        assert_eq!(capture("(a|b)*c", &line), None);
+        let replaced = Pattern::parse("a+").unwrap().replace_all(&line, "-");
+        assert_eq!(replaced, "x:-");
+    }
+}
@@ -0,0 +1,178 @@
+//! Perfetto protobuf trace export
+//!
+//! Hand-encodes the few `TracePacket` fields needed for counter tracks.
+//! Timestamps use `CLOCK_BOOTTIME`, Perfetto's default trace clock, so the
+//! file can be merged with a system trace recorded during the same run.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::{
+    observer::Observer,
+    record::{
+        RunSummary,
+        Sample,
+    },
+};
+
+/// Track of the whole tree's total RSS
+const TREE_TRACK: u64 = 1;
+
+/// Writer sequence all packets belong to
+const SEQUENCE_ID: u64 = 1;
+
+/// `TracePacket.sequence_flags`: SEQ_INCREMENTAL_STATE_CLEARED
+const SEQ_INCREMENTAL_STATE_CLEARED: u64 = 1;
+
+/// `TrackEvent.type`: TYPE_COUNTER
+const TYPE_COUNTER: u64 = 4;
+
+/// `CounterDescriptor.unit`: UNIT_SIZE_BYTES
+const UNIT_SIZE_BYTES: u64 = 3;
+
+/// Collects RSS counter packets for the tree and each process in it,
+/// written to `path` when the run ends
+pub struct PerfettoRecorder {
+    path: PathBuf,
+    /// Boot time at which the command started
+    origin: Duration,
+    trace: Vec<u8>,
+    described: HashSet<i32>,
+}
+
+impl PerfettoRecorder {
+    pub fn new(path: PathBuf, started_ago: Duration) -> Self {
+        let mut recorder = Self {
+            path,
+            origin: boottime().saturating_sub(started_ago),
+            trace: Vec::new(),
+            described: HashSet::new(),
+        };
+        let mut descriptor = Vec::new();
+        field_varint(&mut descriptor, 1, TREE_TRACK);
+        field_bytes(&mut descriptor, 2, b"memwatch tree RSS");
+        field_bytes(&mut descriptor, 8, &counter_descriptor());
+        recorder.packet(None, &track_descriptor_packet(&descriptor), true);
+        recorder
+    }
+
+    /// A process track (merged with the system trace's by pid) and an RSS
+    /// counter track below it
+    fn describe_process(&mut self, pid: i32, name: &str) {
+        let mut process = Vec::new();
+        field_varint(&mut process, 1, pid as u64);
+        field_bytes(&mut process, 6, name.as_bytes());
+        let mut descriptor = Vec::new();
+        field_varint(&mut descriptor, 1, process_track(pid));
+        field_bytes(&mut descriptor, 3, &process);
+        self.packet(None, &track_descriptor_packet(&descriptor), false);
+
+        let mut descriptor = Vec::new();
+        field_varint(&mut descriptor, 1, counter_track(pid));
+        field_varint(&mut descriptor, 5, process_track(pid));
+        field_bytes(&mut descriptor, 2, b"memwatch RSS");
+        field_bytes(&mut descriptor, 8, &counter_descriptor());
+        self.packet(None, &track_descriptor_packet(&descriptor), false);
+    }
+
+    fn counter(&mut self, ts: Duration, track: u64, value: u64) {
+        let mut event = Vec::new();
+        field_varint(&mut event, 9, TYPE_COUNTER);
+        field_varint(&mut event, 11, track);
+        field_varint(&mut event, 30, value);
+        let mut body = Vec::new();
+        field_bytes(&mut body, 11, &event);
+        self.packet(Some(ts), &body, false);
+    }
+
+    /// Append a `Trace.packet` with the sequence id and optional timestamp
+    fn packet(&mut self, ts: Option<Duration>, body: &[u8], first: bool) {
+        let mut packet = body.to_vec();
+        if let Some(ts) = ts {
+            field_varint(&mut packet, 8, ts.as_nanos() as u64);
+        }
+        field_varint(&mut packet, 10, SEQUENCE_ID);
+        if first {
+            field_varint(&mut packet, 13, SEQ_INCREMENTAL_STATE_CLEARED);
+        }
+        field_bytes(&mut self.trace, 1, &packet);
+    }
+}
+
+impl Observer for PerfettoRecorder {
+    fn on_sample(&mut self, sample: &Sample) {
+        let ts = self.origin + sample.elapsed;
+        self.counter(ts, TREE_TRACK, sample.rss);
+        for process in &sample.processes {
+            if self.described.insert(process.pid) {
+                self.describe_process(process.pid, &process.name);
+            }
+            self.counter(ts, counter_track(process.pid), process.rss);
+        }
+    }
+
+    fn on_exit(&mut self, _summary: &RunSummary) {
+        if let Err(e) = fs::write(&self.path, &self.trace) {
+            eprintln!(
+                "memwatch: failed to write Perfetto trace to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn process_track(pid: i32) -> u64 {
+    (pid as u64) << 8 | 2
+}
+
+fn counter_track(pid: i32) -> u64 {
+    (pid as u64) << 8 | 3
+}
+
+fn counter_descriptor() -> Vec<u8> {
+    let mut counter = Vec::new();
+    field_varint(&mut counter, 3, UNIT_SIZE_BYTES);
+    counter
+}
+
+fn track_descriptor_packet(descriptor: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    field_bytes(&mut body, 60, descriptor);
+    body
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn field_varint(out: &mut Vec<u8>, field: u64, value: u64) {
+    varint(out, field << 3);
+    varint(out, value);
+}
+
+fn field_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    varint(out, field << 3 | 2);
+    varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn boottime() -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: the timespec is valid for writes
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts);
+    }
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
@@ -0,0 +1,217 @@
+//! `--power`: energy used during the run, from RAPL
+//!
+//! Intel and AMD processors count the energy of their packages and memory
+//! in microjoules, exposed as `energy_uj` of the zones under
+//! `/sys/class/powercap`. The counters are machine-wide, so everything else
+//! running takes its share, and they wrap around at `max_energy_range_uj`.
+//! Since Linux 5.10 only root can read them.
+
+use std::{
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Instant,
+};
+
+use crate::json::Value;
+
+const POWERCAP: &str = "/sys/class/powercap";
+
+/// Top-level zone holding the others, left out of the total
+const PLATFORM_ZONE: &str = "psys";
+
+struct Zone {
+    name: String,
+    path: PathBuf,
+    /// The counter wraps around past this
+    range: u64,
+    last: u64,
+    /// Microjoules since the start
+    used: u64,
+    /// Counted in the total: a top-level zone other than the platform's
+    top_level: bool,
+}
+
+fn read_number(path: &Path) -> io::Result<u64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{}: not a number", path.display())))
+}
+
+/// `name` of a zone, e.g. `package-0` for `intel-rapl:0`
+fn zone_name(id: &str) -> String {
+    fs::read_to_string(Path::new(POWERCAP).join(id).join("name"))
+        .map_or_else(|_| id.to_string(), |name| name.trim().to_string())
+}
+
+/// Energy of one zone over the run
+pub struct ZoneEnergy {
+    pub name: String,
+    pub joules: f64,
+}
+
+/// Reported in the summary
+pub struct PowerStats {
+    pub zones: Vec<ZoneEnergy>,
+    pub joules: f64,
+    pub average_watts: f64,
+    pub peak_watts: f64,
+}
+
+impl PowerStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("joules".into(), self.joules.into()),
+            ("average_watts".into(), self.average_watts.into()),
+            ("peak_watts".into(), self.peak_watts.into()),
+            (
+                "zones".into(),
+                Value::Array(
+                    self.zones
+                        .iter()
+                        .map(|zone| {
+                            Value::Object(vec![
+                                ("name".into(), zone.name.as_str().into()),
+                                ("joules".into(), zone.joules.into()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+
+    /// `Energy: 4210.5 J, 38.2 W on average, peak 61.0 W | package-0
+    /// 3902.1 J, dram 308.4 J`
+    pub fn format(&self) -> String {
+        let zones: Vec<String> = self
+            .zones
+            .iter()
+            .map(|zone| format!("{} {:.1} J", zone.name, zone.joules))
+            .collect();
+        format!(
+            "Energy: {:.1} J, {:.1} W on average, peak {:.1} W | {}",
+            self.joules,
+            self.average_watts,
+            self.peak_watts,
+            zones.join(", ")
+        )
+    }
+}
+
+pub struct PowerMeter {
+    zones: Vec<Zone>,
+    started: Instant,
+    /// Time and total microjoules of the previous reading
+    last: (Instant, u64),
+    watts: f64,
+    peak_watts: f64,
+}
+
+impl PowerMeter {
+    /// Find the zones and take the starting reading, before the command
+    /// starts
+    pub fn start() -> io::Result<Self> {
+        let mut zones = Vec::new();
+        let entries = fs::read_dir(POWERCAP).map_err(|e| {
+            io::Error::new(e.kind(), format!("--power needs RAPL counters in {}: {}", POWERCAP, e))
+        })?;
+        for entry in entries.flatten() {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            // `intel-rapl:0` is a package, `intel-rapl:0:1` one of its
+            // parts; `intel-rapl` itself is the control type
+            let Some((_, index)) = id.split_once(':') else {
+                continue;
+            };
+            let path = entry.path();
+            let mut name = zone_name(&id);
+            // Every package has a `core`; tell them apart by their package
+            if let Some((parent, _)) = id.rsplit_once(':').filter(|_| index.contains(':')) {
+                name = format!("{}/{}", zone_name(parent), name);
+            }
+            let last = read_number(&path.join("energy_uj")).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("cannot read the energy of {}: {} (root only since Linux 5.10)", name, e),
+                )
+            })?;
+            zones.push(Zone {
+                top_level: !index.contains(':') && name != PLATFORM_ZONE,
+                range: read_number(&path.join("max_energy_range_uj")).unwrap_or(u64::MAX),
+                name,
+                path,
+                last,
+                used: 0,
+            });
+        }
+        if zones.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("--power needs RAPL counters, none found in {}", POWERCAP),
+            ));
+        }
+        zones.sort_by(|a, b| a.path.cmp(&b.path));
+        let now = Instant::now();
+        Ok(Self {
+            zones,
+            started: now,
+            last: (now, 0),
+            watts: 0.0,
+            peak_watts: 0.0,
+        })
+    }
+
+    fn total(&self) -> u64 {
+        self.zones.iter().filter(|zone| zone.top_level).map(|zone| zone.used).sum()
+    }
+
+    pub fn update(&mut self) {
+        for zone in &mut self.zones {
+            let Ok(now) = read_number(&zone.path.join("energy_uj")) else {
+                continue;
+            };
+            zone.used += if now >= zone.last {
+                now - zone.last
+            } else {
+                zone.range - zone.last + now
+            };
+            zone.last = now;
+        }
+        let (at, total) = (Instant::now(), self.total());
+        let secs = at.duration_since(self.last.0).as_secs_f64();
+        if secs > 0.0 {
+            self.watts = total.saturating_sub(self.last.1) as f64 / 1e6 / secs;
+            self.peak_watts = self.peak_watts.max(self.watts);
+        }
+        self.last = (at, total);
+    }
+
+    /// Power over the last interval, for the status line
+    pub fn status(&self) -> Vec<String> {
+        vec![format!("Power: {:.1} W", self.watts)]
+    }
+
+    /// Take the ending reading, once the command is done
+    pub fn finish(mut self) -> PowerStats {
+        self.update();
+        let joules = self.total() as f64 / 1e6;
+        let secs = self.started.elapsed().as_secs_f64();
+        PowerStats {
+            zones: self
+                .zones
+                .iter()
+                .map(|zone| ZoneEnergy {
+                    name: zone.name.clone(),
+                    joules: zone.used as f64 / 1e6,
+                })
+                .collect(),
+            joules,
+            average_watts: if secs > 0.0 { joules / secs } else { 0.0 },
+            peak_watts: self.peak_watts,
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! `memwatch report --from-proc-dump`: samples from copies of `/proc`
+//!
+//! A snapshot is a directory holding a directory per process with at least
+//! its `status` file, as `--bundle-dir` writes them or as copied out of
+//! `/proc` by a collection script. A dump is a snapshot, or a directory
+//! with snapshots anywhere below it; each snapshot is one sample. Samples
+//! are put in order by the time of `bundle.json` where there is one, and
+//! otherwise by when the snapshot's directory was written.
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::SystemTime,
+};
+
+use memwatch::{
+    json::{
+        self,
+        Value,
+    },
+    record::{
+        self,
+        Sample,
+    },
+    tree::ProcessSample,
+};
+
+use crate::import::Imported;
+
+/// How deep below the dump snapshots are looked for
+const MAX_DEPTH: usize = 4;
+
+/// Samples of the snapshots under `dir`; with `root`, only that process
+/// and its descendants count
+pub fn read(dir: &Path, root: Option<i32>) -> io::Result<Imported> {
+    let mut snapshots = Vec::new();
+    find_snapshots(dir, MAX_DEPTH, &mut snapshots)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", dir.display(), e)))?;
+    let mut snapshots: Vec<(SystemTime, PathBuf)> = snapshots
+        .into_iter()
+        .map(|path| (taken_at(&path), path))
+        .collect();
+    snapshots.sort();
+
+    let started = snapshots.first().map(|(time, _)| *time);
+    let mut samples = Vec::new();
+    for (time, path) in &snapshots {
+        let mut processes = read_processes(path);
+        if let Some(root) = root {
+            processes = descendants(processes, root);
+        }
+        samples.push(Sample {
+            elapsed: started.and_then(|started| time.duration_since(started).ok()).unwrap_or_default(),
+            rss: processes.iter().map(|(p, _, _)| p.rss).sum(),
+            vsz: processes.iter().map(|(_, _, vsz)| vsz).sum(),
+            processes: processes.into_iter().map(|(p, _, _)| p).collect(),
+            time: Some(*time),
+            ..Sample::default()
+        });
+    }
+    if samples.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no snapshots of /proc, expected directories of <pid>/status", dir.display()),
+        ));
+    }
+    let peak_processes = samples.iter().map(|s| s.processes.len() as u64).max();
+    Ok(Imported {
+        samples,
+        has_vsz: true,
+        peak_processes,
+    })
+}
+
+/// Directories under `dir` that hold process directories
+fn find_snapshots(dir: &Path, depth: usize, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut subdirs = Vec::new();
+    let mut is_snapshot = false;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let numeric = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.parse::<i32>().is_ok());
+        if numeric && path.join("status").is_file() {
+            is_snapshot = true;
+        } else {
+            subdirs.push(path);
+        }
+    }
+    if is_snapshot {
+        out.push(dir.to_path_buf());
+    } else if depth > 0 {
+        for subdir in subdirs {
+            find_snapshots(&subdir, depth - 1, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Time of `bundle.json`, or when the directory was written
+fn taken_at(snapshot: &Path) -> SystemTime {
+    let from_bundle = fs::read_to_string(snapshot.join("bundle.json"))
+        .ok()
+        .and_then(|text| json::parse(text.trim()).ok())
+        .and_then(|value| value.get("time").and_then(Value::as_str).and_then(record::parse_time));
+    from_bundle
+        .or_else(|| fs::metadata(snapshot).and_then(|m| m.modified()).ok())
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Processes of a snapshot with their parent PIDs and VSZ; unreadable
+/// ones are left out
+fn read_processes(snapshot: &Path) -> Vec<(ProcessSample, i32, u64)> {
+    let Ok(entries) = fs::read_dir(snapshot) else {
+        return Vec::new();
+    };
+    let mut processes: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| read_process(&entry.path()))
+        .collect();
+    processes.sort_by_key(|(p, _, _)| p.pid);
+    processes
+}
+
+fn read_process(dir: &Path) -> Option<(ProcessSample, i32, u64)> {
+    let pid: i32 = dir.file_name()?.to_str()?.parse().ok()?;
+    let status = fs::read_to_string(dir.join("status")).ok()?;
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .map(str::trim)
+    };
+    // `VmRSS:   1234 kB`
+    let kb = |name: &str| {
+        field(name)
+            .and_then(|value| value.trim_end_matches("kB").trim().parse::<u64>().ok())
+            .map_or(0, |kb| kb * 1024)
+    };
+    let ppid = field("PPid").and_then(|ppid| ppid.parse().ok()).unwrap_or(0);
+
+    let mut process = ProcessSample {
+        pid,
+        name: field("Name").unwrap_or_default().to_string(),
+        rss: kb("VmRSS"),
+        hwm: kb("VmHWM"),
+        vm_peak: kb("VmPeak"),
+        starttime: 0,
+        cpu_ticks: 0,
+        minor_faults: 0,
+        major_faults: 0,
+        swap: kb("VmSwap"),
+    };
+    // Fields after the parenthesized name, from the state on
+    if let Ok(stat) = fs::read_to_string(dir.join("stat")) {
+        let rest = stat.rsplit_once(')').map_or("", |(_, rest)| rest);
+        let fields: Vec<u64> = rest
+            .split_whitespace()
+            .map(|field| field.parse().unwrap_or(0))
+            .collect();
+        let at = |index: usize| fields.get(index).copied().unwrap_or(0);
+        process.minor_faults = at(7);
+        process.major_faults = at(9);
+        process.cpu_ticks = at(11) + at(12);
+        process.starttime = at(19);
+    }
+    Some((process, ppid, kb("VmSize")))
+}
+
+/// `root` and the processes descending from it
+fn descendants(processes: Vec<(ProcessSample, i32, u64)>, root: i32) -> Vec<(ProcessSample, i32, u64)> {
+    let mut children: HashMap<i32, Vec<i32>> = HashMap::new();
+    for (process, ppid, _) in &processes {
+        children.entry(*ppid).or_default().push(process.pid);
+    }
+    let mut members = HashSet::new();
+    let mut stack = vec![root];
+    while let Some(pid) = stack.pop() {
+        if members.insert(pid) {
+            stack.extend(children.get(&pid).into_iter().flatten());
+        }
+    }
+    processes
+        .into_iter()
+        .filter(|(process, _, _)| members.contains(&process.pid))
+        .collect()
+}
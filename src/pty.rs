@@ -0,0 +1,78 @@
+//! Pseudo-terminal for interactive commands
+
+use std::{
+    fs::File,
+    io,
+    os::fd::{
+        AsRawFd,
+        FromRawFd,
+    },
+};
+
+pub struct Pty {
+    pub master: File,
+    pub slave: File,
+}
+
+impl Pty {
+    pub fn open(cols: u16, rows: u16) -> io::Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+        let size = winsize(cols, rows);
+        // SAFETY: out-pointers are valid; name and termios are optional
+        let rc = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openpty returned two fresh descriptors we now own
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+        // The child receives the slave through stdio; the master stays ours
+        // SAFETY: FD_CLOEXEC on a descriptor we own
+        unsafe {
+            libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        Ok(Self { master, slave })
+    }
+
+}
+
+/// Tell the child about a new terminal size; the kernel sends it SIGWINCH
+pub fn resize(master: &File, cols: u16, rows: u16) {
+    let size = winsize(cols, rows);
+    // SAFETY: TIOCSWINSZ reads a winsize from a valid pointer
+    unsafe {
+        libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+    }
+}
+
+/// Hook for `CommandExt::pre_exec` making the slave on stdin the child's
+/// controlling terminal in a new session
+pub fn controlling_terminal_hook() -> io::Result<()> {
+    // SAFETY: setsid and ioctl are async-signal-safe
+    unsafe {
+        if libc::setsid() < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn winsize(cols: u16, rows: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
@@ -0,0 +1,830 @@
+//! Typed records of a monitored run, as written to and read from JSON
+//!
+//! These are the stable contract for tools consuming memwatch output.
+//! Every document carries `schema_version`; fields are only ever added
+//! within a version, so readers should ignore fields they do not know.
+//! A reader refuses documents with a newer version than its own.
+
+use std::{
+    ffi::CStr,
+    fs,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use crate::{
+    json::Value,
+    tree::{
+        ProcessPeak,
+        ProcessSample,
+        TreeSample,
+    },
+};
+
+/// Version of the JSON records below
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// One sample of the monitored process tree
+#[derive(Clone, Debug, Default)]
+pub struct Sample {
+    /// Time since the command started
+    pub elapsed: Duration,
+    /// Total resident set size of the tree in bytes
+    pub rss: u64,
+    /// Total virtual memory size of the tree in bytes
+    pub vsz: u64,
+    pub processes: Vec<ProcessSample>,
+    /// Wall-clock time of the sample, unless it was left out
+    pub time: Option<SystemTime>,
+    /// Totals leave out memory that could not be read, see
+    /// [`TreeSample::is_incomplete`]
+    pub incomplete: bool,
+    /// Bytes of the tree's resident memory touched since the previous
+    /// sample, when the CLI estimates the working set
+    pub hot: Option<u64>,
+}
+
+impl Sample {
+    pub fn new(elapsed: Duration, tree: TreeSample) -> Self {
+        Self {
+            elapsed,
+            incomplete: tree.is_incomplete(),
+            rss: tree.rss,
+            vsz: tree.vsz,
+            processes: tree.processes,
+            time: Some(SystemTime::now()),
+            hot: None,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut fields = vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("elapsed_secs".into(), self.elapsed.as_secs_f64().into()),
+            ("rss".into(), self.rss.into()),
+            ("vsz".into(), self.vsz.into()),
+            (
+                "processes".into(),
+                Value::Array(self.processes.iter().map(process_sample_json).collect()),
+            ),
+            ("time".into(), self.time.map(format_time).into()),
+            ("incomplete".into(), self.incomplete.into()),
+        ];
+        // Only there when measured, to keep other samples as they were
+        if let Some(hot) = self.hot {
+            fields.push(("hot".into(), hot.into()));
+        }
+        Value::Object(fields)
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        check_version(value)?;
+        Ok(Self {
+            elapsed: secs(value, "elapsed_secs")?,
+            rss: uint(value, "rss")?,
+            vsz: uint(value, "vsz")?,
+            processes: array(value, "processes")?
+                .iter()
+                .map(process_sample_from_json)
+                .collect::<Result<_, _>>()?,
+            time: optional(value, "time", |v| v.as_str().and_then(parse_time))?,
+            incomplete: optional(value, "incomplete", Value::as_bool)?.unwrap_or(false),
+            hot: optional(value, "hot", Value::as_u64)?,
+        })
+    }
+}
+
+/// Summary of a finished run
+#[derive(Clone, Debug, Default)]
+pub struct RunSummary {
+    pub host: String,
+    /// Command line as a single shell-quoted string
+    pub command: String,
+    /// Unique identifier of the run
+    pub run_id: String,
+    /// Wall-clock time the command started
+    pub started_at: Option<SystemTime>,
+    pub elapsed: Duration,
+    pub peak_rss: u64,
+    pub peak_vsz: u64,
+    /// Largest sum of per-process `VmHWM` seen
+    pub kernel_peak_rss: u64,
+    /// Largest sum of per-process `VmPeak` seen
+    pub kernel_peak_vsz: u64,
+    pub peak_processes: u64,
+    pub processes_seen: u64,
+    /// High-water mark of the command's cgroup, with `--cgroup`
+    pub cgroup_peak: Option<u64>,
+    pub samples: u64,
+    pub exit_code: Option<i32>,
+    /// Signal name such as `SIGKILL` when the command was killed
+    pub signal: Option<String>,
+    pub core_dumped: bool,
+    /// Why memwatch stopped watching: `rss_limit`, `interrupted`, or
+    /// `detached` from a process it attached to
+    pub termination: Option<String>,
+    pub output_lines_dropped: u64,
+    /// Processes with the highest peak RSS
+    pub top_processes: Vec<ProcessPeak>,
+    /// The machine the run was on, unless it is unknown
+    pub environment: Option<Environment>,
+}
+
+impl RunSummary {
+    /// Fields of the summary, for callers adding their own after them
+    pub fn json_fields(&self) -> Vec<(String, Value)> {
+        vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("host".into(), self.host.as_str().into()),
+            ("command".into(), self.command.as_str().into()),
+            ("run_id".into(), self.run_id.as_str().into()),
+            ("started_at".into(), self.started_at.map(format_time).into()),
+            ("elapsed_secs".into(), self.elapsed.as_secs_f64().into()),
+            ("peak_rss".into(), self.peak_rss.into()),
+            ("peak_vsz".into(), self.peak_vsz.into()),
+            ("kernel_peak_rss".into(), self.kernel_peak_rss.into()),
+            ("kernel_peak_vsz".into(), self.kernel_peak_vsz.into()),
+            ("peak_processes".into(), self.peak_processes.into()),
+            ("processes_seen".into(), self.processes_seen.into()),
+            ("cgroup_peak".into(), self.cgroup_peak.into()),
+            ("samples".into(), self.samples.into()),
+            ("exit_code".into(), self.exit_code.into()),
+            ("signal".into(), self.signal.as_deref().into()),
+            ("core_dumped".into(), self.core_dumped.into()),
+            ("termination".into(), self.termination.as_deref().into()),
+            ("output_lines_dropped".into(), self.output_lines_dropped.into()),
+            (
+                "top_processes".into(),
+                Value::Array(self.top_processes.iter().map(process_peak_json).collect()),
+            ),
+            (
+                "environment".into(),
+                self.environment.as_ref().map(Environment::to_json).unwrap_or(Value::Null),
+            ),
+        ]
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.json_fields())
+    }
+
+    /// How the command ended, e.g. `exited with status 1` or
+    /// `killed by SIGSEGV (core dumped)`
+    pub fn describe_exit(&self) -> String {
+        if self.termination.as_deref() == Some("detached") {
+            return String::from("still running (detached)");
+        }
+        match (self.exit_code, &self.signal) {
+            (Some(code), _) => format!("exited with status {}", code),
+            (None, Some(signal)) if self.core_dumped => format!("killed by {} (core dumped)", signal),
+            (None, Some(signal)) => format!("killed by {}", signal),
+            (None, None) => String::from("terminated"),
+        }
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        check_version(value)?;
+        Ok(Self {
+            host: string(value, "host")?,
+            command: string(value, "command")?,
+            run_id: string(value, "run_id")?,
+            started_at: optional(value, "started_at", |v| v.as_str().and_then(parse_time))?,
+            elapsed: secs(value, "elapsed_secs")?,
+            peak_rss: uint(value, "peak_rss")?,
+            peak_vsz: uint(value, "peak_vsz")?,
+            kernel_peak_rss: uint(value, "kernel_peak_rss")?,
+            kernel_peak_vsz: uint(value, "kernel_peak_vsz")?,
+            peak_processes: uint(value, "peak_processes")?,
+            processes_seen: uint(value, "processes_seen")?,
+            cgroup_peak: optional(value, "cgroup_peak", Value::as_u64)?,
+            samples: uint(value, "samples")?,
+            exit_code: optional(value, "exit_code", |v| {
+                v.as_i64().and_then(|c| i32::try_from(c).ok())
+            })?,
+            signal: optional(value, "signal", |v| v.as_str().map(String::from))?,
+            core_dumped: field(value, "core_dumped", Value::as_bool)?,
+            termination: optional(value, "termination", |v| v.as_str().map(String::from))?,
+            output_lines_dropped: uint(value, "output_lines_dropped")?,
+            top_processes: array(value, "top_processes")?
+                .iter()
+                .map(process_peak_from_json)
+                .collect::<Result<_, _>>()?,
+            // Not in summaries written before it was recorded
+            environment: match value.get("environment") {
+                None | Some(Value::Null) => None,
+                Some(environment) => Some(Environment::from_json(environment)?),
+            },
+        })
+    }
+}
+
+/// What results depend on besides the command: the kernel, memory, cgroup
+/// support, transparent huge pages and the processor
+///
+/// Fields memwatch could not read are `None`, or empty for the list of
+/// cgroup controllers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Environment {
+    /// Kernel release, e.g. `6.8.0-45-generic`
+    pub kernel: Option<String>,
+    /// `MemTotal` and `SwapTotal` in bytes
+    pub memory_total: Option<u64>,
+    pub swap_total: Option<u64>,
+    /// `v1` or `v2`
+    pub cgroup: Option<String>,
+    /// Controllers available on the cgroup hierarchy, e.g. `memory`
+    pub cgroup_controllers: Vec<String>,
+    /// Transparent huge pages mode: `always`, `madvise` or `never`
+    pub thp: Option<String>,
+    pub cpu_model: Option<String>,
+    /// CPUs online
+    pub cpus: Option<u64>,
+    /// Frequency scaling governor of the first CPU, e.g. `performance`
+    pub governor: Option<String>,
+}
+
+impl Environment {
+    /// Read the environment of this machine
+    pub fn collect() -> Self {
+        let read = |path: &str| fs::read_to_string(path).ok().map(|s| s.trim().to_string());
+        let meminfo = read("/proc/meminfo").unwrap_or_default();
+        let kb = |name: &str| {
+            meminfo.lines().find_map(|line| {
+                let value = line.strip_prefix(name)?.strip_prefix(':')?;
+                value.trim().trim_end_matches("kB").trim().parse::<u64>().ok().map(|kb| kb * 1024)
+            })
+        };
+        let (cgroup, cgroup_controllers) = match read("/sys/fs/cgroup/cgroup.controllers") {
+            Some(controllers) => (
+                Some(String::from("v2")),
+                controllers.split_whitespace().map(String::from).collect(),
+            ),
+            // `subsys_name hierarchy num_cgroups enabled`
+            None => {
+                let controllers: Vec<String> = read("/proc/cgroups")
+                    .unwrap_or_default()
+                    .lines()
+                    .filter(|line| !line.starts_with('#'))
+                    .filter_map(|line| {
+                        let fields: Vec<&str> = line.split_whitespace().collect();
+                        (fields.get(3) == Some(&"1")).then(|| fields[0].to_string())
+                    })
+                    .collect();
+                ((!controllers.is_empty()).then(|| String::from("v1")), controllers)
+            }
+        };
+        // `always [madvise] never`, the mode in brackets
+        let thp = read("/sys/kernel/mm/transparent_hugepage/enabled").and_then(|modes| {
+            let (_, rest) = modes.split_once('[')?;
+            Some(rest.split_once(']')?.0.to_string())
+        });
+        let cpu_model = read("/proc/cpuinfo").and_then(|cpuinfo| {
+            cpuinfo.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                (key.trim() == "model name").then(|| value.trim().to_string())
+            })
+        });
+        // SAFETY: sysconf has no preconditions
+        let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+        Self {
+            kernel: read("/proc/sys/kernel/osrelease"),
+            memory_total: kb("MemTotal"),
+            swap_total: kb("SwapTotal"),
+            cgroup,
+            cgroup_controllers,
+            thp,
+            cpu_model,
+            cpus: u64::try_from(cpus).ok().filter(|&cpus| cpus > 0),
+            governor: read("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("kernel".into(), self.kernel.as_deref().into()),
+            ("memory_total".into(), self.memory_total.into()),
+            ("swap_total".into(), self.swap_total.into()),
+            ("cgroup".into(), self.cgroup.as_deref().into()),
+            (
+                "cgroup_controllers".into(),
+                Value::Array(self.cgroup_controllers.iter().map(|c| c.as_str().into()).collect()),
+            ),
+            ("thp".into(), self.thp.as_deref().into()),
+            ("cpu_model".into(), self.cpu_model.as_deref().into()),
+            ("cpus".into(), self.cpus.into()),
+            ("governor".into(), self.governor.as_deref().into()),
+        ])
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let text = |key| optional(value, key, |v| v.as_str().map(String::from));
+        Ok(Self {
+            kernel: text("kernel")?,
+            memory_total: optional(value, "memory_total", Value::as_u64)?,
+            swap_total: optional(value, "swap_total", Value::as_u64)?,
+            cgroup: text("cgroup")?,
+            cgroup_controllers: array(value, "cgroup_controllers")?
+                .iter()
+                .map(|c| c.as_str().map(String::from))
+                .collect::<Option<_>>()
+                .ok_or("field `cgroup_controllers` has the wrong type")?,
+            thp: text("thp")?,
+            cpu_model: text("cpu_model")?,
+            cpus: optional(value, "cpus", Value::as_u64)?,
+            governor: text("governor")?,
+        })
+    }
+
+    /// One line for reports, e.g. `Linux 6.8.0, 8 × Intel(R) Core(TM)
+    /// i7-8650U, 15.50 GB RAM, 2.00 GB swap, cgroup v2, THP madvise,
+    /// governor powersave`
+    pub fn describe(&self) -> String {
+        let bytes = crate::size::format_bytes;
+        let mut parts = Vec::new();
+        parts.extend(self.kernel.as_ref().map(|kernel| format!("Linux {}", kernel)));
+        match (self.cpus, &self.cpu_model) {
+            (Some(cpus), Some(model)) => parts.push(format!("{} × {}", cpus, model)),
+            (Some(cpus), None) => parts.push(format!("{} CPUs", cpus)),
+            (None, Some(model)) => parts.push(model.clone()),
+            (None, None) => {}
+        }
+        parts.extend(self.memory_total.map(|total| format!("{} RAM", bytes(total))));
+        parts.extend(self.swap_total.map(|total| format!("{} swap", bytes(total))));
+        parts.extend(self.cgroup.as_ref().map(|version| format!("cgroup {}", version)));
+        parts.extend(self.thp.as_ref().map(|thp| format!("THP {}", thp)));
+        parts.extend(self.governor.as_ref().map(|governor| format!("governor {}", governor)));
+        parts.join(", ")
+    }
+
+    /// Fields that differ from `other`, e.g. `kernel 6.1.0 → 6.8.0`
+    pub fn differences(&self, other: &Self) -> Vec<String> {
+        let show = |value: Option<String>| value.unwrap_or_else(|| String::from("unknown"));
+        let bytes = |value: Option<u64>| value.map(crate::size::format_bytes);
+        let controllers = |env: &Self| Some(env.cgroup_controllers.join(","));
+        [
+            ("kernel", self.kernel.clone(), other.kernel.clone()),
+            ("memory", bytes(self.memory_total), bytes(other.memory_total)),
+            ("swap", bytes(self.swap_total), bytes(other.swap_total)),
+            ("cgroup", self.cgroup.clone(), other.cgroup.clone()),
+            ("cgroup controllers", controllers(self), controllers(other)),
+            ("THP", self.thp.clone(), other.thp.clone()),
+            ("CPU", self.cpu_model.clone(), other.cpu_model.clone()),
+            ("CPUs", self.cpus.map(|n| n.to_string()), other.cpus.map(|n| n.to_string())),
+            ("governor", self.governor.clone(), other.governor.clone()),
+        ]
+        .into_iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(name, before, after)| format!("{} {} → {}", name, show(before), show(after)))
+        .collect()
+    }
+}
+
+pub(crate) fn process_sample_json(p: &ProcessSample) -> Value {
+    Value::Object(vec![
+        ("pid".into(), p.pid.into()),
+        ("starttime".into(), p.starttime.into()),
+        ("name".into(), p.name.as_str().into()),
+        ("rss".into(), p.rss.into()),
+        ("hwm".into(), p.hwm.into()),
+        ("vm_peak".into(), p.vm_peak.into()),
+        ("cpu_ticks".into(), p.cpu_ticks.into()),
+        ("minor_faults".into(), p.minor_faults.into()),
+        ("major_faults".into(), p.major_faults.into()),
+        ("swap".into(), p.swap.into()),
+    ])
+}
+
+pub(crate) fn process_sample_from_json(value: &Value) -> Result<ProcessSample, String> {
+    Ok(ProcessSample {
+        pid: pid(value)?,
+        starttime: uint(value, "starttime")?,
+        name: string(value, "name")?,
+        rss: uint(value, "rss")?,
+        hwm: uint(value, "hwm")?,
+        vm_peak: uint(value, "vm_peak")?,
+        cpu_ticks: uint(value, "cpu_ticks")?,
+        minor_faults: uint(value, "minor_faults")?,
+        major_faults: uint(value, "major_faults")?,
+        // Not in recordings made before it was sampled
+        swap: optional(value, "swap", Value::as_u64)?.unwrap_or(0),
+    })
+}
+
+fn process_peak_json(peak: &ProcessPeak) -> Value {
+    Value::Object(vec![
+        ("pid".into(), peak.pid.into()),
+        ("name".into(), peak.name.as_str().into()),
+        ("peak_rss".into(), peak.peak_rss.into()),
+        ("lifetime_secs".into(), peak.lifetime.as_secs_f64().into()),
+        ("exact".into(), peak.exact.into()),
+    ])
+}
+
+fn process_peak_from_json(value: &Value) -> Result<ProcessPeak, String> {
+    Ok(ProcessPeak {
+        pid: pid(value)?,
+        name: string(value, "name")?,
+        peak_rss: uint(value, "peak_rss")?,
+        lifetime: secs(value, "lifetime_secs")?,
+        exact: field(value, "exact", Value::as_bool)?,
+    })
+}
+
+pub(crate) fn check_version(value: &Value) -> Result<(), String> {
+    match uint(value, "schema_version")? {
+        version if version > SCHEMA_VERSION => Err(format!(
+            "schema_version {} is newer than the supported {}",
+            version, SCHEMA_VERSION
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Required field converted with `convert`
+fn field<T>(value: &Value, key: &str, convert: impl Fn(&Value) -> Option<T>) -> Result<T, String> {
+    let v = value.get(key).ok_or_else(|| format!("missing field `{}`", key))?;
+    convert(v).ok_or_else(|| format!("field `{}` has the wrong type", key))
+}
+
+/// Field that may be null or absent
+fn optional<T>(
+    value: &Value,
+    key: &str,
+    convert: impl Fn(&Value) -> Option<T>,
+) -> Result<Option<T>, String> {
+    match value.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => convert(v)
+            .map(Some)
+            .ok_or_else(|| format!("field `{}` has the wrong type", key)),
+    }
+}
+
+pub(crate) fn uint(value: &Value, key: &str) -> Result<u64, String> {
+    field(value, key, Value::as_u64)
+}
+
+pub(crate) fn float(value: &Value, key: &str) -> Result<f64, String> {
+    field(value, key, Value::as_f64)
+}
+
+fn pid(value: &Value) -> Result<i32, String> {
+    field(value, "pid", |v| v.as_i64().and_then(|p| i32::try_from(p).ok()))
+}
+
+pub(crate) fn string(value: &Value, key: &str) -> Result<String, String> {
+    field(value, key, |v| v.as_str().map(String::from))
+}
+
+pub(crate) fn secs(value: &Value, key: &str) -> Result<Duration, String> {
+    field(value, key, |v| {
+        v.as_f64().and_then(|s| Duration::try_from_secs_f64(s).ok())
+    })
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> Result<&'a [Value], String> {
+    value
+        .get(key)
+        .ok_or_else(|| format!("missing field `{}`", key))?
+        .as_array()
+        .ok_or_else(|| format!("field `{}` has the wrong type", key))
+}
+
+/// Command line as a single shell-like string
+pub fn command_line(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Clocks a sample's time is given by in an output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timestamps {
+    /// Time since the command started, from the monotonic clock
+    Elapsed,
+    /// Wall-clock time, for lining up with logs of other systems
+    Wall,
+    Both,
+}
+
+impl Timestamps {
+    pub fn elapsed(self) -> bool {
+        self != Self::Wall
+    }
+
+    pub fn wall(self) -> bool {
+        self != Self::Elapsed
+    }
+}
+
+/// RFC 3339 UTC timestamp with milliseconds, e.g. `2025-03-01T12:00:00.250Z`
+pub fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Parse an RFC 3339 timestamp with `Z` or a numeric offset
+pub fn parse_time(text: &str) -> Option<SystemTime> {
+    let bytes = text.as_bytes();
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = text.get(range)?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+    if !matches!(bytes[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    // From the first of this month to the first of the next
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    let days_in_month = days_from_civil(next_year, next_month, 1) - days_from_civil(year, month, 1);
+    if !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+
+    let mut rest = &text[19..];
+    let mut nanos = 0u32;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        // Keep nanosecond precision, drop the rest
+        let kept = &fraction[..digits.min(9)];
+        nanos = kept.parse::<u32>().ok()? * 10u32.pow(9 - kept.len() as u32);
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let digits = |range: std::ops::Range<usize>| {
+                let digits = &rest.as_bytes()[range];
+                digits.iter().all(u8::is_ascii_digit).then(|| {
+                    digits.iter().fold(0, |n, digit| n * 10 + i64::from(digit - b'0'))
+                })
+            };
+            let (hours, minutes) = (digits(1..3)?, digits(4..6)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+        _ => return None,
+    };
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    let secs = u64::try_from(secs).ok()?;
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Date of a day counted from 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Unique identifier for a new run
+pub fn new_run_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    format!("{:016x}{:08x}", nanos, std::process::id())
+}
+
+/// Name of this host, `unknown` if it cannot be read
+pub fn hostname() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    // SAFETY: buffer is valid for its full length and NUL-terminated below
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len() - 1) };
+    if rc != 0 {
+        return String::from("unknown");
+    }
+    // SAFETY: the last byte is never written by gethostname and stays NUL
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+
+    fn at(secs: u64, millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis)
+    }
+
+    fn process(pid: i32, rss: u64) -> ProcessSample {
+        ProcessSample {
+            pid,
+            starttime: 4242,
+            name: format!("worker-{}", pid),
+            rss,
+            hwm: rss * 2,
+            vm_peak: rss * 3,
+            cpu_ticks: 17,
+            minor_faults: 100,
+            major_faults: 2,
+            swap: 4096,
+        }
+    }
+
+    /// Through the text of the document, as a reader gets it
+    fn reparse(value: &Value) -> Value {
+        json::parse(&value.to_string()).unwrap()
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let sample = Sample {
+            elapsed: Duration::from_millis(1500),
+            rss: 3 << 20,
+            vsz: 9 << 20,
+            processes: vec![process(10, 1 << 20), process(11, 2 << 20)],
+            time: Some(at(1_740_830_400, 250)),
+            incomplete: true,
+            hot: Some(512 << 10),
+        };
+        let json = sample.to_json();
+        let parsed = Sample::from_json(&reparse(&json)).unwrap();
+        assert_eq!(parsed.to_json(), json);
+        assert_eq!(parsed.time, sample.time);
+        assert_eq!(parsed.processes[1].name, "worker-11");
+        assert_eq!(parsed.processes[1].swap, 4096);
+    }
+
+    #[test]
+    fn summaries_round_trip() {
+        let summary = RunSummary {
+            host: "build01".into(),
+            command: "make -j8".into(),
+            run_id: new_run_id(),
+            started_at: Some(at(1_740_820_360, 318)),
+            elapsed: Duration::from_millis(312_250),
+            peak_rss: 3_350_134_784,
+            peak_vsz: 5_033_164_800,
+            kernel_peak_rss: 3_412_017_152,
+            kernel_peak_vsz: 5_100_273_664,
+            peak_processes: 9,
+            processes_seen: 412,
+            cgroup_peak: Some(1 << 30),
+            samples: 313,
+            exit_code: None,
+            signal: Some("SIGSEGV".into()),
+            core_dumped: true,
+            termination: Some("rss_limit".into()),
+            output_lines_dropped: 3,
+            top_processes: vec![ProcessPeak {
+                pid: 48211,
+                name: "cc1plus".into(),
+                peak_rss: 1_288_490_188,
+                lifetime: Duration::from_millis(41_250),
+                exact: true,
+            }],
+            environment: Some(Environment {
+                kernel: Some("6.8.0-45-generic".into()),
+                memory_total: Some(67_108_864_000),
+                cgroup_controllers: vec!["cpu".into(), "memory".into()],
+                cpus: Some(32),
+                ..Environment::default()
+            }),
+        };
+        let json = summary.to_json();
+        let parsed = RunSummary::from_json(&reparse(&json)).unwrap();
+        assert_eq!(parsed.to_json(), json);
+        assert_eq!(parsed.environment, summary.environment);
+        assert_eq!(parsed.describe_exit(), "killed by SIGSEGV (core dumped)");
+    }
+
+    #[test]
+    fn refuses_newer_versions_and_missing_fields() {
+        let mut json = RunSummary::default().to_json();
+        assert!(RunSummary::from_json(&json).is_ok());
+        if let Value::Object(fields) = &mut json {
+            fields[0].1 = (SCHEMA_VERSION + 1).into();
+        }
+        let e = RunSummary::from_json(&json).unwrap_err();
+        assert!(e.contains("newer than the supported"), "{}", e);
+
+        let sample = json::parse(r#"{"schema_version":1,"elapsed_secs":1,"vsz":0,"processes":[]}"#);
+        assert_eq!(Sample::from_json(&sample.unwrap()).unwrap_err(), "missing field `rss`");
+    }
+
+    #[test]
+    fn formats_utc_with_milliseconds() {
+        assert_eq!(format_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_time(at(1_740_830_400, 250)), "2025-03-01T12:00:00.250Z");
+        assert_eq!(format_time(at(951_782_400, 999)), "2000-02-29T00:00:00.999Z");
+    }
+
+    #[test]
+    fn parses_offsets_and_fractions() {
+        let noon = at(1_740_830_400, 0);
+        assert_eq!(parse_time("2025-03-01T12:00:00Z"), Some(noon));
+        assert_eq!(parse_time("2025-03-01t12:00:00z"), Some(noon));
+        assert_eq!(parse_time("2025-03-01 12:00:00Z"), Some(noon));
+        assert_eq!(parse_time("2025-03-01T13:30:00+01:30"), Some(noon));
+        assert_eq!(parse_time("2025-03-01T07:00:00-05:00"), Some(noon));
+        assert_eq!(parse_time("2025-03-01T12:00:00.25Z"), Some(at(1_740_830_400, 250)));
+        assert_eq!(
+            parse_time("2025-03-01T12:00:00.123456789123Z"),
+            Some(noon + Duration::from_nanos(123_456_789))
+        );
+        assert_eq!(parse_time("2000-02-29T00:00:00.000Z"), Some(at(951_782_400, 0)));
+        for time in [at(0, 0), at(1_740_830_400, 250), at(4_102_444_799, 1)] {
+            assert_eq!(parse_time(&format_time(time)), Some(time));
+        }
+    }
+
+    #[test]
+    fn refuses_malformed_stamps() {
+        for stamp in [
+            "",
+            "2025-03-01",
+            "2025-03-01T12:00:00",
+            "2025-03-01T12:00Z",
+            "2025/03/01T12:00:00Z",
+            "2025-03-01X12:00:00Z",
+            "2025-13-01T12:00:00Z",
+            "2025-02-29T12:00:00Z",
+            "2025-04-31T12:00:00Z",
+            "2025-03-00T12:00:00Z",
+            "2025-03-01T24:00:00Z",
+            "2025-03-01T12:60:00Z",
+            "2025-03-01T12:00:00.Z",
+            "2025-03-01T12:00:00+0100",
+            "2025-03-01T12:00:00+-1:00",
+            "2025-03-01T12:00:00+24:00",
+            "2025-03-01T12:00:00*01:00",
+            "2025-03-01T12:00:00Z junk",
+            "+025-03-01T12:00:00Z",
+            "1969-12-31T23:59:59Z",
+            "2025-03-01T12:00:0\u{e9}Z",
+        ] {
+            assert_eq!(parse_time(stamp), None, "{}", stamp);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Recordings of a run, with `--record`, for `replay`, `report` and `diff`
+//!
+//! A recording is the `--exporter-cmd` stream written to a file: one JSON
+//! object per line, tagged with `"type"`, ending with the summary. A run
+//! memwatch did not get to finish leaves a recording without one.
+
+use std::{
+    fs::{
+        self,
+        File,
+    },
+    io::{
+        self,
+        BufWriter,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use crate::{
+    json::{
+        self,
+        Value,
+    },
+    observer::{
+        Anomaly,
+        Crossing,
+        IntervalChange,
+        Mark,
+        Observer,
+    },
+    record::{
+        RunSummary,
+        Sample,
+    },
+};
+
+/// Writes the run to a file as it happens
+pub struct Recorder {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        let file = File::create(&path).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot record to {}: {}", path.display(), e))
+        })?;
+        Ok(Self {
+            path,
+            file: Some(BufWriter::new(file)),
+        })
+    }
+
+    /// Write one line; flushed right away so a recording survives memwatch
+    /// being killed
+    fn write(&mut self, kind: &str, record: Value) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let written = writeln!(file, "{}", tagged(kind, record)).and_then(|()| file.flush());
+        if let Err(e) = written {
+            eprintln!(
+                "\rmemwatch: failed to write recording to {} ({}); no longer recording",
+                self.path.display(),
+                e
+            );
+            self.file = None;
+        }
+    }
+}
+
+impl Observer for Recorder {
+    fn on_sample(&mut self, sample: &Sample) {
+        self.write("sample", sample.to_json());
+    }
+
+    fn on_threshold(&mut self, crossing: &Crossing) {
+        self.write("threshold", crossing.to_json());
+    }
+
+    fn on_anomaly(&mut self, anomaly: &Anomaly) {
+        self.write("anomaly", anomaly.to_json());
+    }
+
+    fn on_mark(&mut self, mark: &Mark) {
+        self.write("mark", mark.to_json());
+    }
+
+    fn on_interval(&mut self, change: &IntervalChange) {
+        self.write("interval", change.to_json());
+    }
+
+    fn on_exit(&mut self, summary: &RunSummary) {
+        self.write("summary", summary.to_json());
+    }
+}
+
+/// One line of the stream: `record` with `"type": kind` in front
+pub fn tagged(kind: &str, record: Value) -> String {
+    let mut fields = vec![("type".to_string(), kind.into())];
+    if let Value::Object(record) = record {
+        fields.extend(record);
+    }
+    Value::Object(fields).to_string()
+}
+
+/// A run read back from a recording
+#[derive(Default)]
+pub struct Recording {
+    pub samples: Vec<Sample>,
+    pub crossings: Vec<Crossing>,
+    pub anomalies: Vec<Anomaly>,
+    pub marks: Vec<Mark>,
+    pub interval_changes: Vec<IntervalChange>,
+    pub summary: Option<RunSummary>,
+}
+
+impl Recording {
+    /// Read a recording, or a `--summary-json` file as a recording of only
+    /// the summary
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))?;
+        Self::parse(path, &text)
+    }
+
+    /// [`Recording::read`] of `text` already read from `path`
+    pub fn parse(path: &Path, text: &str) -> io::Result<Self> {
+        let invalid = |line: usize, e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path.display(), line, e),
+            )
+        };
+
+        if let Ok(value) = json::parse(text) {
+            if value.get("type").is_none() {
+                let summary = RunSummary::from_json(&value).map_err(|e| invalid(1, e))?;
+                return Ok(Self {
+                    summary: Some(summary),
+                    ..Self::default()
+                });
+            }
+        }
+
+        let mut recording = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value = json::parse(line).map_err(|e| invalid(index + 1, e))?;
+            match value.get("type").and_then(Value::as_str) {
+                Some("sample") => recording
+                    .samples
+                    .push(Sample::from_json(&value).map_err(|e| invalid(index + 1, e))?),
+                Some("threshold") => recording
+                    .crossings
+                    .push(Crossing::from_json(&value).map_err(|e| invalid(index + 1, e))?),
+                Some("anomaly") => recording
+                    .anomalies
+                    .push(Anomaly::from_json(&value).map_err(|e| invalid(index + 1, e))?),
+                Some("mark") => recording
+                    .marks
+                    .push(Mark::from_json(&value).map_err(|e| invalid(index + 1, e))?),
+                Some("interval") => recording
+                    .interval_changes
+                    .push(IntervalChange::from_json(&value).map_err(|e| invalid(index + 1, e))?),
+                Some("summary") => {
+                    recording.summary =
+                        Some(RunSummary::from_json(&value).map_err(|e| invalid(index + 1, e))?)
+                }
+                // Kinds added by later versions
+                _ => {}
+            }
+        }
+        Ok(recording)
+    }
+
+    /// The summary, or an error naming `path` for a recording without one
+    pub fn into_summary(self, path: &Path) -> io::Result<RunSummary> {
+        self.summary.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has no summary; the run did not finish", path.display()),
+            )
+        })
+    }
+}
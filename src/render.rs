@@ -0,0 +1,458 @@
+//! Terminal output: child output, memwatch notices and the status line
+
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        self,
+        Stdout,
+        Write,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+};
+
+use termion::{
+    clear,
+    cursor,
+    event::Key,
+    raw::{
+        IntoRawMode,
+        RawTerminal,
+    },
+};
+
+use crate::{
+    observer::Observer,
+    record::Sample,
+    tui::{
+        Screen,
+        Series,
+    },
+};
+
+/// Output captured from the child, and key presses for the TUI
+pub enum OutputMsg {
+    Stdout(String),
+    Stderr(String),
+    /// A line the child keeps redrawing with `\r`, e.g. a progress bar
+    Progress(String),
+    /// Raw bytes from the child's pseudo-terminal
+    Pty(Vec<u8>),
+    /// A key pressed in the TUI
+    Key(Key),
+}
+
+enum Mode {
+    /// Status redrawn in place with `\r` after the child's line output
+    Plain {
+        /// A progress line is shown on the row above the status
+        progress: bool,
+        /// Rows taken by the status, the cursor being on the last one
+        status_rows: u16,
+    },
+    /// Raw terminal with the status pinned to the bottom row, outside the
+    /// scroll region the child writes to
+    Reserved {
+        terminal: RawTerminal<Stdout>,
+        rows: u16,
+    },
+    /// Full-screen plot and output pane
+    Tui(Box<Screen>),
+}
+
+pub struct Renderer {
+    mode: Mode,
+}
+
+/// Set by SIGWINCH, for the status to be laid out again
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_resize(_: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Catch SIGWINCH; the command gets the default action back on exec
+fn watch_resize() {
+    // SAFETY: the handler only stores to an atomic
+    unsafe {
+        libc::signal(libc::SIGWINCH, on_resize as *const () as libc::sighandler_t);
+    }
+}
+
+impl Renderer {
+    pub fn plain() -> Self {
+        watch_resize();
+        // Hide cursor during monitoring
+        print!("{}", cursor::Hide);
+        io::stdout().flush().ok();
+        Self {
+            mode: Mode::Plain {
+                progress: false,
+                status_rows: 1,
+            },
+        }
+    }
+
+    /// Take over the terminal with the full-screen UI
+    pub fn tui(series: [Series; 2], terminated: Arc<AtomicBool>) -> io::Result<Self> {
+        watch_resize();
+        Ok(Self {
+            mode: Mode::Tui(Box::new(Screen::new(series, terminated)?)),
+        })
+    }
+
+    /// Put the terminal in raw mode and reserve its last row for the status
+    ///
+    /// Returns the renderer and the number of rows left for the child.
+    pub fn reserved_line() -> io::Result<(Self, u16, u16)> {
+        let (cols, rows) = termion::terminal_size()?;
+        if rows < 2 {
+            return Err(io::Error::other("terminal is too small"));
+        }
+        let mut terminal = io::stdout().into_raw_mode()?;
+        watch_resize();
+        // Make sure the cursor is not on the row we are about to reserve
+        write!(terminal, "\n{}", cursor::Up(1))?;
+        let mut renderer = Self {
+            mode: Mode::Reserved { terminal, rows: 0 },
+        };
+        renderer.resize(rows)?;
+        Ok((renderer, cols, rows - 1))
+    }
+
+    /// Re-layout after the terminal size changed; returns child rows/cols if so
+    pub fn check_resize(&mut self) -> Option<(u16, u16)> {
+        let Mode::Reserved { rows, .. } = &self.mode else {
+            return None;
+        };
+        let (new_cols, new_rows) = termion::terminal_size().ok()?;
+        if new_rows == *rows || new_rows < 2 {
+            return None;
+        }
+        self.resize(new_rows).ok()?;
+        Some((new_cols, new_rows - 1))
+    }
+
+    fn resize(&mut self, new_rows: u16) -> io::Result<()> {
+        if let Mode::Reserved { terminal, rows } = &mut self.mode {
+            *rows = new_rows;
+            // DECSTBM homes the cursor, so keep it where the child left it
+            write!(terminal, "\x1b7\x1b[1;{}r\x1b8", new_rows - 1)?;
+            terminal.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the terminal was resized since the last call
+    pub fn resized(&self) -> bool {
+        RESIZED.swap(false, Ordering::Relaxed)
+    }
+
+    /// Columns the status line may take
+    pub fn width(&self) -> usize {
+        terminal_width()
+    }
+
+    /// Forward output of the child
+    pub fn output(&mut self, msg: OutputMsg) {
+        match (&mut self.mode, msg) {
+            (
+                Mode::Plain {
+                    progress,
+                    status_rows,
+                },
+                OutputMsg::Progress(text),
+            ) => {
+                // Rewrite the progress row and leave the cursor on the
+                // status row below it
+                clear_plain(progress, status_rows);
+                println!("{}", fit_width(&text, terminal_width()));
+                io::stdout().flush().ok();
+                *progress = true;
+            }
+            (
+                Mode::Plain {
+                    progress,
+                    status_rows,
+                },
+                OutputMsg::Stdout(l),
+            ) => {
+                clear_plain(progress, status_rows);
+                println!("{}", l);
+            }
+            (
+                Mode::Plain {
+                    progress,
+                    status_rows,
+                },
+                OutputMsg::Stderr(l),
+            ) => {
+                clear_plain(progress, status_rows);
+                io::stdout().flush().ok();
+                eprintln!("{}", l);
+            }
+            (Mode::Reserved { terminal, .. }, OutputMsg::Pty(bytes)) => {
+                terminal.write_all(&bytes).ok();
+                terminal.flush().ok();
+            }
+            (_, OutputMsg::Pty(bytes)) => {
+                io::stdout().write_all(&bytes).ok();
+            }
+            (Mode::Tui(screen), OutputMsg::Key(key)) => screen.key(key),
+            (_, OutputMsg::Key(_)) => {}
+            (
+                Mode::Tui(screen),
+                OutputMsg::Stdout(l) | OutputMsg::Stderr(l) | OutputMsg::Progress(l),
+            ) => screen.line(l, false),
+            (
+                Mode::Reserved { terminal, .. },
+                OutputMsg::Stdout(l) | OutputMsg::Stderr(l) | OutputMsg::Progress(l),
+            ) => {
+                write!(terminal, "{}\r\n", l).ok();
+            }
+        }
+    }
+
+    /// Print a message from memwatch itself without garbling the status
+    pub fn notice(&mut self, text: &str) {
+        match &mut self.mode {
+            Mode::Plain {
+                progress,
+                status_rows,
+            } => {
+                clear_plain(progress, status_rows);
+                io::stdout().flush().ok();
+                eprintln!("{}", text);
+            }
+            Mode::Reserved { terminal, .. } => {
+                // Raw mode disables newline translation
+                write!(terminal, "{}\r\n", text.replace('\n', "\r\n")).ok();
+                terminal.flush().ok();
+            }
+            Mode::Tui(screen) => {
+                for line in text.lines() {
+                    screen.line(line.to_string(), true);
+                }
+            }
+        }
+    }
+
+    /// Redraw the status line
+    ///
+    /// In plain mode the status may span several lines; each is cut to the
+    /// terminal width so the rows can be found again for the next redraw.
+    pub fn status(&mut self, line: &str) {
+        match &mut self.mode {
+            Mode::Plain { status_rows, .. } => {
+                print!("\r");
+                if *status_rows > 1 {
+                    print!("{}", cursor::Up(*status_rows - 1));
+                }
+                let width = terminal_width();
+                let lines: Vec<_> = line.lines().map(|l| fit_width(l, width)).collect();
+                print!("{}{}", clear::AfterCursor, lines.join("\n"));
+                *status_rows = lines.len().max(1) as u16;
+                io::stdout().flush().ok();
+            }
+            Mode::Reserved { terminal, rows } => {
+                write!(
+                    terminal,
+                    "\x1b7{}{}{}\x1b8",
+                    cursor::Goto(1, *rows),
+                    clear::CurrentLine,
+                    line
+                )
+                .ok();
+                terminal.flush().ok();
+            }
+            Mode::Tui(screen) => screen.status(line),
+        }
+    }
+}
+
+/// Samples feed the plot, if there is one
+impl Observer for Renderer {
+    fn on_sample(&mut self, sample: &Sample) {
+        if let Mode::Tui(screen) = &mut self.mode {
+            screen.sample(sample);
+        }
+    }
+}
+
+impl Drop for Renderer {
+    /// Restore the terminal, even on panic or Ctrl+C
+    fn drop(&mut self) {
+        match &mut self.mode {
+            Mode::Plain { .. } => {
+                // Keep the final status line visible
+                println!();
+                print!("{}", cursor::Show);
+                let _ = io::stdout().flush();
+            }
+            Mode::Reserved { terminal, rows } => {
+                // Reset the scroll region and leave the status below the output
+                let _ = write!(terminal, "\x1b[r{}\r\n", cursor::Goto(1, *rows));
+                let _ = terminal.flush();
+            }
+            // The screen restores the terminal itself
+            Mode::Tui(_) => {}
+        }
+    }
+}
+
+/// The terminal window title, restored on drop
+pub struct Title {
+    tty: File,
+}
+
+impl Title {
+    /// Save the current title; `None` without a controlling terminal
+    pub fn open() -> Option<Self> {
+        let mut tty = OpenOptions::new().write(true).open("/dev/tty").ok()?;
+        // XTWINOPS: push the title on the terminal's stack
+        tty.write_all(b"\x1b[22;0t").ok()?;
+        Some(Self { tty })
+    }
+
+    pub fn set(&mut self, title: &str) {
+        let title: String = title.chars().filter(|c| !c.is_control()).collect();
+        write!(self.tty, "\x1b]0;{}\x07", title).ok();
+    }
+}
+
+impl Drop for Title {
+    fn drop(&mut self) {
+        // Terminals without a title stack keep the last title
+        let _ = self.tty.write_all(b"\x1b[23;0t");
+    }
+}
+
+/// Clear the status rows and, if shown, the progress row above them,
+/// leaving the cursor at the start of the first cleared row
+fn clear_plain(progress: &mut bool, status_rows: &mut u16) {
+    let up = std::mem::replace(status_rows, 1) - 1 + std::mem::take(progress) as u16;
+    print!("\r");
+    if up > 0 {
+        print!("{}", cursor::Up(up));
+    }
+    print!("{}", clear::AfterCursor);
+}
+
+/// Columns usable without wrapping onto the next row
+fn terminal_width() -> usize {
+    let cols = termion::terminal_size().map(|(cols, _)| cols).unwrap_or(80);
+    cols.saturating_sub(1) as usize
+}
+
+/// Columns a character takes: none for combining marks and zero-width
+/// characters, two for wide East Asian characters and emoji
+fn char_width(c: char) -> usize {
+    match u32::from(c) {
+        0x0300..=0x036F | 0x200B..=0x200F | 0x20D0..=0x20FF | 0xFE00..=0xFE0F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// Columns `text` takes on the terminal, escape sequences aside
+pub fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Same sequences fit_width passes through
+            match chars.next() {
+                Some('[') => {
+                    chars.by_ref().find(|c| ('@'..='~').contains(c));
+                }
+                Some(']') => {
+                    let mut previous = ' ';
+                    chars.by_ref().find(|&c| {
+                        let end = c == '\x07' || (previous == '\x1b' && c == '\\');
+                        previous = c;
+                        end
+                    });
+                }
+                _ => {}
+            }
+        } else if !c.is_control() {
+            width += char_width(c);
+        }
+    }
+    width
+}
+
+/// Truncate `text` to `width` visible columns, passing escape sequences
+/// through uncounted so colored progress bars keep their colors
+pub fn fit_width(text: &str, width: usize) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut visible = 0;
+    let mut escaped = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            escaped = true;
+            out.push(c);
+            match chars.next() {
+                // CSI: parameters up to a final byte in @..~
+                Some('[') => {
+                    out.push('[');
+                    for c in chars.by_ref() {
+                        out.push(c);
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: up to BEL or ST
+                Some(']') => {
+                    out.push(']');
+                    while let Some(c) = chars.next() {
+                        out.push(c);
+                        if c == '\x07' || (c == '\x1b' && chars.peek() == Some(&'\\')) {
+                            break;
+                        }
+                    }
+                }
+                Some(c) => out.push(c),
+                None => {}
+            }
+            continue;
+        }
+        if c.is_control() {
+            continue;
+        }
+        if visible + char_width(c) > width {
+            break;
+        }
+        visible += char_width(c);
+        out.push(c);
+    }
+
+    if escaped {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
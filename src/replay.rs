@@ -0,0 +1,131 @@
+//! `memwatch replay`: play back a run recorded with `--record`
+
+use std::{
+    io,
+    path::PathBuf,
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    anomaly::format_anomaly,
+    clock::{
+        self,
+        Clock,
+    },
+    recording::Recording,
+    render::Renderer,
+    size::format_bytes,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ReplayArgs {
+    /// Play back this many times faster than the run happened
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f64,
+
+    /// Clock at the start of status lines, as with `run`; wall-clock time
+    /// needs a recording that has it
+    #[arg(long, value_enum, default_value_t = Clock::Elapsed)]
+    clock: Clock,
+
+    /// Recording made with --record
+    #[arg(value_name = "FILE")]
+    file: PathBuf,
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("expected a positive number, got '{}'", s)),
+    }
+}
+
+pub fn run(args: ReplayArgs, terminated: &AtomicBool) -> io::Result<()> {
+    let recording = Recording::read(&args.file)?;
+    if recording.samples.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has no samples to replay", args.file.display()),
+        ));
+    }
+
+    let mut renderer = Renderer::plain();
+    let mut crossings = recording.crossings.iter().peekable();
+    let mut anomalies = recording.anomalies.iter().peekable();
+    let mut marks = recording.marks.iter().peekable();
+    let mut interval_changes = recording.interval_changes.iter().peekable();
+    let start = Instant::now();
+    for sample in &recording.samples {
+        let due = sample.elapsed.div_f64(args.speed);
+        while start.elapsed() < due {
+            if terminated.load(Ordering::SeqCst) {
+                drop(renderer);
+                eprintln!("Interrupted (Ctrl+C)");
+                return Ok(());
+            }
+            let left = due.saturating_sub(start.elapsed());
+            thread::sleep(left.min(Duration::from_millis(100)));
+        }
+        while let Some(crossing) = crossings.next_if(|c| c.elapsed <= sample.elapsed) {
+            renderer.notice(&format!(
+                "{}: RSS {} exceeded {}",
+                crossing.name,
+                format_bytes(crossing.rss),
+                format_bytes(crossing.limit)
+            ));
+        }
+        while let Some(anomaly) = anomalies.next_if(|a| a.elapsed <= sample.elapsed) {
+            renderer.notice(&format!(
+                "{} {}",
+                args.clock.stamp(anomaly.elapsed, sample.time),
+                format_anomaly(anomaly)
+            ));
+        }
+        while let Some(mark) = marks.next_if(|m| m.elapsed <= sample.elapsed) {
+            renderer.notice(&format!(
+                "{} Mark: {}",
+                args.clock.stamp(mark.elapsed, sample.time),
+                mark.label
+            ));
+        }
+        while let Some(change) = interval_changes.next_if(|c| c.elapsed <= sample.elapsed) {
+            renderer.notice(&format!(
+                "{} Sampling every {} from now on",
+                args.clock.stamp(change.elapsed, sample.time),
+                clock::format_span(change.interval)
+            ));
+        }
+        let status_line = crate::format_status_line(
+            &args.clock.stamp(sample.elapsed, sample.time),
+            sample.rss,
+            sample.vsz,
+            sample.processes.len(),
+            sample.incomplete,
+        );
+        renderer.status(&status_line.fit(renderer.width()));
+    }
+    drop(renderer);
+
+    match &recording.summary {
+        Some(summary) => {
+            eprintln!("Process {}", summary.describe_exit());
+            eprintln!(
+                "Peak RSS: {} (kernel-reported: {}) | Peak VSZ: {} (kernel-reported: {})",
+                format_bytes(summary.peak_rss),
+                format_bytes(summary.kernel_peak_rss),
+                format_bytes(summary.peak_vsz),
+                format_bytes(summary.kernel_peak_vsz)
+            );
+        }
+        None => eprintln!("Recording ends without a summary; the run did not finish"),
+    }
+    Ok(())
+}
@@ -0,0 +1,703 @@
+//! Human-readable reports of a finished run, and `memwatch report`
+//!
+//! A report is made either from a recording or summary of memwatch, or
+//! from samples imported with `--from`, see [`crate::import`], or with
+//! `--from-proc-dump`, see [`crate::proc_dump`]. `--merge` puts several
+//! runs side by side instead.
+
+use std::{
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use crate::{
+    clock::format_duration,
+    import::{
+        self,
+        Imported,
+    },
+    proc_dump,
+    record::RunSummary,
+    recording::Recording,
+    size::format_bytes,
+    timeline::Timeline,
+    tree::ProcessPeak,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ReportArgs {
+    /// Print Markdown, as written by --report-md, instead of plain text
+    #[arg(long, conflicts_with = "html")]
+    md: bool,
+
+    /// Print a self-contained HTML page instead of plain text
+    #[arg(long)]
+    html: bool,
+
+    /// Report on samples in a CSV or JSON-lines file instead, such as one
+    /// exported by another tool
+    #[arg(long, value_name = "FILE", conflicts_with = "file")]
+    from: Option<PathBuf>,
+
+    /// Report on snapshots of /proc in this directory instead, such as
+    /// those of --bundle-dir
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["file", "from"])]
+    from_proc_dump: Option<PathBuf>,
+
+    /// With --from-proc-dump, count only this process and its descendants
+    #[arg(long, value_name = "PID", requires = "from_proc_dump")]
+    root: Option<i32>,
+
+    /// Compare two or more recordings in one table, with their RSS on one
+    /// chart
+    #[arg(long, value_name = "FILE", num_args = 2.., conflicts_with_all = ["file", "from", "from_proc_dump"])]
+    merge: Vec<PathBuf>,
+
+    /// Recording made with --record, or a --summary-json file
+    #[arg(value_name = "FILE", required_unless_present_any = ["from", "from_proc_dump", "merge"])]
+    file: Option<PathBuf>,
+}
+
+/// What every format of report shows
+struct Report<'a> {
+    /// The command, or the file samples were imported from
+    title: String,
+    rows: Vec<(&'static str, String)>,
+    timeline: &'a Timeline,
+    processes: &'a [ProcessPeak],
+}
+
+impl<'a> Report<'a> {
+    fn of_run(summary: &'a RunSummary, timeline: &'a Timeline) -> Self {
+        Self {
+            title: summary.command.clone(),
+            rows: summary_rows(summary),
+            timeline,
+            processes: &summary.top_processes,
+        }
+    }
+}
+
+/// One of the runs of `--merge`, labelled the way `memwatch compare`
+/// labels commands
+struct Run {
+    label: char,
+    path: PathBuf,
+    summary: RunSummary,
+    timeline: Timeline,
+}
+
+impl Run {
+    fn read(label: char, path: &Path) -> io::Result<Self> {
+        let recording = Recording::read(path)?;
+        let mut timeline = Timeline::default();
+        for sample in &recording.samples {
+            timeline.record(sample.elapsed, sample.rss);
+        }
+        Ok(Self {
+            label,
+            path: path.to_path_buf(),
+            summary: recording.into_summary(path)?,
+            timeline,
+        })
+    }
+}
+
+/// Width of the RSS chart in columns
+const CHART_WIDTH: usize = 60;
+
+/// Height of the RSS chart in rows
+const CHART_HEIGHT: usize = 8;
+
+/// Eighth blocks, from empty to full
+pub const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Metric/value rows describing the run
+pub fn summary_rows(summary: &RunSummary) -> Vec<(&'static str, String)> {
+    let mut rows = vec![
+        ("Result", summary.describe_exit()),
+        ("Duration", format_duration(summary.elapsed)),
+        ("Peak RSS", format_bytes(summary.peak_rss)),
+        ("Peak RSS (kernel-reported)", format_bytes(summary.kernel_peak_rss)),
+        ("Peak VSZ", format_bytes(summary.peak_vsz)),
+        ("Peak processes", summary.peak_processes.to_string()),
+        ("Processes seen", summary.processes_seen.to_string()),
+    ];
+    if let Some(peak) = summary.cgroup_peak {
+        rows.push(("Cgroup peak", format_bytes(peak)));
+    }
+    if let Some(top) = summary.top_processes.first() {
+        rows.push((
+            "Largest process",
+            format!("{} ({}) {}", top.name, top.pid, format_bytes(top.peak_rss)),
+        ));
+    }
+    match summary.termination.as_deref() {
+        Some("rss_limit") => rows.push(("Stopped by memwatch", "RSS limit exceeded".into())),
+        Some("detached") => {}
+        Some("time_limit") => rows.push(("Stopped by memwatch", "time limit of --for".into())),
+        Some(_) => rows.push(("Stopped by memwatch", "interrupted".into())),
+        None => {}
+    }
+    if let Some(environment) = &summary.environment {
+        rows.push(("Environment", environment.describe()));
+    }
+    rows
+}
+
+/// Markdown `| Metric | Value |` table of `rows`
+pub fn markdown_table(rows: &[(&str, String)]) -> String {
+    let mut table = String::from("| Metric | Value |\n| --- | --- |\n");
+    for (metric, value) in rows {
+        table.push_str(&format!("| {} | {} |\n", metric, value.replace('|', "\\|")));
+    }
+    table
+}
+
+/// Block chart of RSS over time with the peak and duration on the axes
+pub fn rss_chart(timeline: &Timeline) -> Vec<String> {
+    let buckets = timeline.rss_buckets(CHART_WIDTH);
+    let peak = buckets.iter().copied().max().unwrap_or(0);
+    let Some(last) = timeline.points.last() else {
+        return Vec::new();
+    };
+
+    let top_label = format_bytes(peak);
+    let bottom_label = format_bytes(0);
+    let label_width = top_label.len().max(bottom_label.len());
+    // Heights in eighths of a row
+    let heights: Vec<usize> = buckets
+        .iter()
+        .map(|&rss| match peak {
+            0 => 0,
+            _ => (rss as f64 / peak as f64 * (CHART_HEIGHT * 8) as f64).round() as usize,
+        })
+        .collect();
+
+    let mut lines = Vec::new();
+    for row in (0..CHART_HEIGHT).rev() {
+        let label = match row {
+            r if r == CHART_HEIGHT - 1 => top_label.as_str(),
+            0 => bottom_label.as_str(),
+            _ => "",
+        };
+        let bars: String = heights
+            .iter()
+            .map(|&h| BLOCKS[h.saturating_sub(row * 8).min(8)])
+            .collect();
+        lines.push(format!("{:>width$} ┤{}", label, bars, width = label_width));
+    }
+    let end = format!("{:.0}s", last.at.as_secs_f64());
+    lines.push(format!(
+        "{:>width$} └{}{:>rest$}",
+        "",
+        "0s",
+        end,
+        width = label_width,
+        rest = buckets.len().saturating_sub(2)
+    ));
+    lines
+}
+
+/// Metric/value rows of imported samples: only what they tell, with no
+/// word on how the command ended
+fn imported_rows(imported: &Imported) -> Vec<(&'static str, String)> {
+    let samples = &imported.samples;
+    let peak_rss = samples.iter().map(|s| s.rss).max().unwrap_or(0);
+    let total: u128 = samples.iter().map(|s| u128::from(s.rss)).sum();
+    let elapsed = samples.iter().map(|s| s.elapsed).max().unwrap_or_default()
+        - samples.iter().map(|s| s.elapsed).min().unwrap_or_default();
+    let mut rows = vec![
+        ("Samples", samples.len().to_string()),
+        ("Duration", format_duration(elapsed)),
+        ("Peak RSS", format_bytes(peak_rss)),
+        ("Average RSS", format_bytes((total / samples.len().max(1) as u128) as u64)),
+        ("Final RSS", format_bytes(samples.last().map_or(0, |s| s.rss))),
+    ];
+    if imported.has_vsz {
+        let peak_vsz = samples.iter().map(|s| s.vsz).max().unwrap_or(0);
+        rows.push(("Peak VSZ", format_bytes(peak_vsz)));
+    }
+    if let Some(peak) = imported.peak_processes {
+        rows.push(("Peak processes", peak.to_string()));
+    }
+    rows
+}
+
+/// Write a Markdown report: summary table, RSS chart, per-process peaks
+pub fn write_markdown(path: &Path, summary: &RunSummary, timeline: &Timeline) -> io::Result<()> {
+    fs::write(path, markdown(&Report::of_run(summary, timeline)))
+}
+
+fn markdown(report: &Report) -> String {
+    let mut md = format!(
+        "## memwatch report: `{}`\n\n",
+        report.title.replace('`', "'")
+    );
+    md.push_str(&markdown_table(&report.rows));
+
+    let chart = rss_chart(report.timeline);
+    if !chart.is_empty() {
+        md.push_str("\n### RSS over time\n\n```\n");
+        for line in chart {
+            md.push_str(&line);
+            md.push('\n');
+        }
+        md.push_str("```\n");
+    }
+
+    if !report.processes.is_empty() {
+        md.push_str("\n### Largest processes\n\n");
+        md.push_str("| PID | Name | Peak RSS | Lifetime | Source |\n| --- | --- | --- | --- | --- |\n");
+        for process in report.processes {
+            md.push_str(&format!(
+                "| {} | {} | {} | {} | {} |\n",
+                process.pid,
+                process.name.replace('|', "\\|"),
+                format_bytes(process.peak_rss),
+                format_duration(process.lifetime),
+                if process.exact { "exit accounting" } else { "sampled VmHWM" }
+            ));
+        }
+    }
+
+    md
+}
+
+/// Plain-text report: the same rows, chart and processes as the Markdown
+fn text(report: &Report) -> String {
+    let mut text = format!("memwatch report: {}\n\n", report.title);
+    let width = report.rows.iter().map(|(metric, _)| metric.len()).max().unwrap_or(0);
+    for (metric, value) in &report.rows {
+        text.push_str(&format!("{:<width$}  {}\n", metric, value, width = width));
+    }
+
+    let chart = rss_chart(report.timeline);
+    if !chart.is_empty() {
+        text.push_str("\nRSS over time\n\n");
+        for line in chart {
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+
+    if !report.processes.is_empty() {
+        text.push_str("\nLargest processes\n\n");
+        for process in report.processes {
+            text.push_str(&format!(
+                "{:>8}  {:<16} {:>10}  {:>10}{}\n",
+                process.pid,
+                process.name,
+                format_bytes(process.peak_rss),
+                format_duration(process.lifetime),
+                if process.exact { "" } else { "  (sampled)" }
+            ));
+        }
+    }
+    text
+}
+
+/// Rows of `summary_rows` of every run, a cell per run and `-` where a
+/// run has no such row, then average RSS and the change of peak RSS
+/// relative to the first run
+fn merged_rows(runs: &[Run]) -> Vec<(&'static str, Vec<String>)> {
+    let mut rows: Vec<(&'static str, Vec<String>)> = Vec::new();
+    for (i, run) in runs.iter().enumerate() {
+        for (metric, value) in summary_rows(&run.summary) {
+            let at = match rows.iter().position(|(m, _)| *m == metric) {
+                Some(at) => at,
+                None => {
+                    rows.push((metric, vec![String::from("-"); runs.len()]));
+                    rows.len() - 1
+                }
+            };
+            rows[at].1[i] = value;
+        }
+    }
+    rows.push((
+        "Average RSS",
+        runs.iter()
+            .map(|run| {
+                let points = &run.timeline.points;
+                match points.len() {
+                    0 => String::from("-"),
+                    n => format_bytes(
+                        (points.iter().map(|p| u128::from(p.rss)).sum::<u128>() / n as u128) as u64,
+                    ),
+                }
+            })
+            .collect(),
+    ));
+    let base = runs[0].summary.peak_rss;
+    rows.push((
+        "Peak RSS change",
+        runs.iter()
+            .enumerate()
+            .map(|(i, run)| match (i, base) {
+                (0, _) | (_, 0) => String::from("-"),
+                _ => format!(
+                    "{:+.1}%",
+                    (run.summary.peak_rss as f64 - base as f64) / base as f64 * 100.0
+                ),
+            })
+            .collect(),
+    ));
+    rows
+}
+
+/// Chart of every run's RSS over the time of the longest, drawn with the
+/// runs' labels; `*` marks where runs meet
+fn overlay_chart(runs: &[Run]) -> Vec<String> {
+    let Some(span) = runs
+        .iter()
+        .filter_map(|run| run.timeline.points.last())
+        .map(|p| p.at)
+        .max()
+    else {
+        return Vec::new();
+    };
+    let series: Vec<Vec<Option<u64>>> = runs
+        .iter()
+        .map(|run| run.timeline.rss_buckets_over(span, CHART_WIDTH))
+        .collect();
+    let peak = series.iter().flatten().flatten().copied().max().unwrap_or(0);
+
+    let mut grid = vec![vec![' '; CHART_WIDTH]; CHART_HEIGHT];
+    for (run, buckets) in runs.iter().zip(&series) {
+        for (column, rss) in buckets.iter().enumerate() {
+            let Some(rss) = rss else {
+                continue;
+            };
+            let row = match peak {
+                0 => 0,
+                _ => (*rss as f64 / peak as f64 * (CHART_HEIGHT - 1) as f64).round() as usize,
+            };
+            let cell = &mut grid[row][column];
+            *cell = if *cell == ' ' { run.label } else { '*' };
+        }
+    }
+
+    let top_label = format_bytes(peak);
+    let bottom_label = format_bytes(0);
+    let label_width = top_label.len().max(bottom_label.len());
+    let mut lines = Vec::new();
+    for row in (0..CHART_HEIGHT).rev() {
+        let label = match row {
+            r if r == CHART_HEIGHT - 1 => top_label.as_str(),
+            0 => bottom_label.as_str(),
+            _ => "",
+        };
+        let cells: String = grid[row].iter().collect();
+        lines.push(format!("{:>width$} ┤{}", label, cells, width = label_width));
+    }
+    lines.push(format!(
+        "{:>width$} └{}{:>rest$}",
+        "",
+        "0s",
+        format!("{:.0}s", span.as_secs_f64()),
+        width = label_width,
+        rest = CHART_WIDTH - 2
+    ));
+    lines
+}
+
+/// `A  make -j8 (before.ndjson)` for each run
+fn legend(runs: &[Run]) -> Vec<String> {
+    runs.iter()
+        .map(|run| format!("{}  {} ({})", run.label, run.summary.command, run.path.display()))
+        .collect()
+}
+
+fn merged_text(runs: &[Run]) -> String {
+    let mut text = format!("memwatch report: {} runs\n\n", runs.len());
+    for line in legend(runs) {
+        text.push_str(&line);
+        text.push('\n');
+    }
+
+    let rows = merged_rows(runs);
+    let metric_width = rows.iter().map(|(metric, _)| metric.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..runs.len())
+        .map(|i| rows.iter().map(|(_, cells)| cells[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    text.push_str(&format!("\n{:<width$}", "", width = metric_width));
+    for (run, width) in runs.iter().zip(&widths) {
+        text.push_str(&format!("  {:>width$}", run.label, width = width));
+    }
+    text.push('\n');
+    for (metric, cells) in &rows {
+        text.push_str(&format!("{:<width$}", metric, width = metric_width));
+        for (cell, width) in cells.iter().zip(&widths) {
+            text.push_str(&format!("  {:>width$}", cell, width = width));
+        }
+        text.push('\n');
+    }
+
+    let chart = overlay_chart(runs);
+    if !chart.is_empty() {
+        text.push_str("\nRSS over time\n\n");
+        for line in chart {
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+    text
+}
+
+fn merged_markdown(runs: &[Run]) -> String {
+    let mut md = format!("## memwatch report: {} runs\n\n", runs.len());
+    for run in runs {
+        md.push_str(&format!(
+            "- **{}**: `{}` ({})\n",
+            run.label,
+            run.summary.command.replace('`', "'"),
+            run.path.display()
+        ));
+    }
+
+    md.push_str("\n| Metric |");
+    for run in runs {
+        md.push_str(&format!(" {} |", run.label));
+    }
+    md.push_str(&format!("\n| --- |{}\n", " --- |".repeat(runs.len())));
+    for (metric, cells) in merged_rows(runs) {
+        md.push_str(&format!("| {} |", metric));
+        for cell in cells {
+            md.push_str(&format!(" {} |", cell.replace('|', "\\|")));
+        }
+        md.push('\n');
+    }
+
+    let chart = overlay_chart(runs);
+    if !chart.is_empty() {
+        md.push_str("\n### RSS over time\n\n```\n");
+        for line in chart {
+            md.push_str(&line);
+            md.push('\n');
+        }
+        md.push_str("```\n");
+    }
+    md
+}
+
+fn merged_html(runs: &[Run]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>memwatch report: {} runs</title>\n<style>\n{}</style>\n</head>\n<body>\n\
+         <h1>memwatch report: {} runs</h1>\n<ul>\n",
+        runs.len(),
+        HTML_STYLE,
+        runs.len()
+    );
+    for (i, run) in runs.iter().enumerate() {
+        html.push_str(&format!(
+            "<li><b style=\"color: {}\">{}</b> <code>{}</code> ({})</li>\n",
+            SVG_COLORS[i % SVG_COLORS.len()],
+            run.label,
+            escape_html(&run.summary.command),
+            escape_html(&run.path.display().to_string())
+        ));
+    }
+    html.push_str("</ul>\n<table>\n<tr><th></th>");
+    for run in runs {
+        html.push_str(&format!("<th>{}</th>", run.label));
+    }
+    html.push_str("</tr>\n");
+    for (metric, cells) in merged_rows(runs) {
+        html.push_str(&format!("<tr><th>{}</th>", metric));
+        for cell in cells {
+            html.push_str(&format!("<td>{}</td>", escape_html(&cell)));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+
+    let timelines: Vec<&Timeline> = runs.iter().map(|run| &run.timeline).collect();
+    if let Some(svg) = svg_chart(&timelines) {
+        html.push_str("<h2>RSS over time</h2>\n");
+        html.push_str(&svg);
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Standalone HTML page: the table, an SVG chart of RSS and the processes
+fn html(report: &Report) -> String {
+    let title = escape_html(&report.title);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>memwatch report: {}</title>\n<style>\n{}</style>\n</head>\n<body>\n\
+         <h1>memwatch report: <code>{}</code></h1>\n<table>\n",
+        title, HTML_STYLE, title
+    );
+    for (metric, value) in &report.rows {
+        html.push_str(&format!(
+            "<tr><th>{}</th><td>{}</td></tr>\n",
+            metric,
+            escape_html(value)
+        ));
+    }
+    html.push_str("</table>\n");
+
+    if let Some(svg) = svg_chart(&[report.timeline]) {
+        html.push_str("<h2>RSS over time</h2>\n");
+        html.push_str(&svg);
+    }
+
+    if !report.processes.is_empty() {
+        html.push_str("<h2>Largest processes</h2>\n<table>\n");
+        html.push_str("<tr><th>PID</th><th>Name</th><th>Peak RSS</th><th>Lifetime</th><th>Source</th></tr>\n");
+        for process in report.processes {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                process.pid,
+                escape_html(&process.name),
+                format_bytes(process.peak_rss),
+                format_duration(process.lifetime),
+                if process.exact { "exit accounting" } else { "sampled VmHWM" }
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+const HTML_STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { text-align: left; padding: 0.2em 1em 0.2em 0; }
+svg text { font-size: 12px; fill: #555; }
+";
+
+/// Width and height of the HTML chart's plot area, in pixels
+const SVG_SIZE: (f64, f64) = (720.0, 240.0);
+
+/// Room around the plot area for the axis labels
+const SVG_MARGIN: f64 = 80.0;
+
+/// Line colors of the runs on a chart, in turn
+const SVG_COLORS: [&str; 6] = ["#36c", "#d33", "#393", "#f90", "#939", "#099"];
+
+/// Every sample of each timeline as a line over time, with the peak and
+/// the longest duration on the axes; `None` without samples
+fn svg_chart(timelines: &[&Timeline]) -> Option<String> {
+    let points = || timelines.iter().flat_map(|timeline| &timeline.points);
+    let peak = points().map(|p| p.rss).max()?.max(1);
+    let span = points().map(|p| p.at).max()?.as_secs_f64().max(f64::EPSILON);
+    let (width, height) = SVG_SIZE;
+    let lines: String = timelines
+        .iter()
+        .zip(SVG_COLORS.iter().cycle())
+        .map(|(timeline, color)| {
+            let points: Vec<String> = timeline
+                .points
+                .iter()
+                .map(|p| {
+                    format!(
+                        "{:.1},{:.1}",
+                        SVG_MARGIN + p.at.as_secs_f64() / span * width,
+                        height - p.rss as f64 / peak as f64 * height + 10.0
+                    )
+                })
+                .collect();
+            format!(
+                "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>\n",
+                color,
+                points.join(" ")
+            )
+        })
+        .collect();
+    Some(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\">\n\
+         {lines}\
+         <line x1=\"{m}\" y1=\"10\" x2=\"{m}\" y2=\"{bottom}\" stroke=\"#999\"/>\n\
+         <line x1=\"{m}\" y1=\"{bottom}\" x2=\"{right}\" y2=\"{bottom}\" stroke=\"#999\"/>\n\
+         <text x=\"{label}\" y=\"20\" text-anchor=\"end\">{peak}</text>\n\
+         <text x=\"{label}\" y=\"{bottom}\" text-anchor=\"end\">{zero}</text>\n\
+         <text x=\"{m}\" y=\"{axis}\">0s</text>\n\
+         <text x=\"{right}\" y=\"{axis}\" text-anchor=\"end\">{end:.0}s</text>\n\
+         </svg>\n",
+        w = width + SVG_MARGIN + 10.0,
+        h = height + 40.0,
+        lines = lines,
+        m = SVG_MARGIN,
+        bottom = height + 10.0,
+        right = SVG_MARGIN + width,
+        label = SVG_MARGIN - 6.0,
+        axis = height + 28.0,
+        peak = format_bytes(peak),
+        zero = format_bytes(0),
+        end = span,
+    ))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `memwatch report`: describe a finished run from its files, or imported
+/// samples
+pub fn run(args: ReportArgs) -> io::Result<()> {
+    if !args.merge.is_empty() {
+        let runs = args
+            .merge
+            .iter()
+            .zip('A'..='Z')
+            .map(|(path, label)| Run::read(label, path))
+            .collect::<io::Result<Vec<_>>>()?;
+        if args.md {
+            print!("{}", merged_markdown(&runs));
+        } else if args.html {
+            print!("{}", merged_html(&runs));
+        } else {
+            print!("{}", merged_text(&runs));
+        }
+        return Ok(());
+    }
+
+    let mut timeline = Timeline::default();
+    let summary;
+    let from = match (&args.from, &args.from_proc_dump) {
+        (Some(path), _) => Some((path, import::read(path)?)),
+        (None, Some(dir)) => Some((dir, proc_dump::read(dir, args.root)?)),
+        (None, None) => None,
+    };
+    let report = match (from, &args.file) {
+        (Some((path, imported)), _) => {
+            for sample in &imported.samples {
+                timeline.record(sample.elapsed, sample.rss);
+            }
+            Report {
+                title: path.display().to_string(),
+                rows: imported_rows(&imported),
+                timeline: &timeline,
+                processes: &[],
+            }
+        }
+        (None, Some(path)) => {
+            let recording = Recording::read(path)?;
+            for sample in &recording.samples {
+                timeline.record(sample.elapsed, sample.rss);
+            }
+            summary = recording.into_summary(path)?;
+            Report::of_run(&summary, &timeline)
+        }
+        (None, None) => unreachable!("clap requires a FILE, --from or --from-proc-dump"),
+    };
+    if args.md {
+        print!("{}", markdown(&report));
+    } else if args.html {
+        print!("{}", html(&report));
+    } else {
+        print!("{}", text(&report));
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! `--rolling`: average and maximum RSS over recent windows of time, to
+//! tell a transient spike from a new steady state
+
+use std::{
+    collections::VecDeque,
+    time::Duration,
+};
+
+use crate::{
+    clock::format_span,
+    size::format_bytes_short,
+};
+
+/// RSS over the last `span` of samples
+struct Window {
+    span: Duration,
+    /// Samples inside the window, oldest first
+    points: VecDeque<(Duration, u64)>,
+    sum: u128,
+    /// Candidates for the maximum: decreasing RSS, oldest first
+    maxima: VecDeque<(Duration, u64)>,
+}
+
+impl Window {
+    fn new(span: Duration) -> Self {
+        Self {
+            span,
+            points: VecDeque::new(),
+            sum: 0,
+            maxima: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, at: Duration, rss: u64) {
+        self.points.push_back((at, rss));
+        self.sum += u128::from(rss);
+        while self.maxima.back().is_some_and(|&(_, max)| max <= rss) {
+            self.maxima.pop_back();
+        }
+        self.maxima.push_back((at, rss));
+
+        let cutoff = at.saturating_sub(self.span);
+        while let Some(&(oldest, old_rss)) = self.points.front() {
+            if oldest > cutoff || self.points.len() == 1 {
+                break;
+            }
+            self.points.pop_front();
+            self.sum -= u128::from(old_rss);
+        }
+        while self.maxima.front().is_some_and(|&(oldest, _)| oldest <= cutoff) {
+            self.maxima.pop_front();
+        }
+    }
+
+    fn average(&self) -> u64 {
+        (self.sum / self.points.len().max(1) as u128) as u64
+    }
+
+    fn max(&self) -> u64 {
+        self.maxima.front().map_or(0, |&(_, max)| max)
+    }
+}
+
+pub struct Rolling {
+    windows: Vec<Window>,
+}
+
+impl Rolling {
+    pub fn new(spans: &[Duration]) -> Self {
+        Self {
+            windows: spans.iter().copied().map(Window::new).collect(),
+        }
+    }
+
+    pub fn record(&mut self, at: Duration, rss: u64) {
+        for window in &mut self.windows {
+            window.record(at, rss);
+        }
+    }
+
+    /// Status line columns, e.g. `1m avg 790.12M max 1.10G`, one per
+    /// window
+    pub fn status(&self) -> Vec<String> {
+        self.windows
+            .iter()
+            .map(|window| {
+                format!(
+                    "{} avg {} max {}",
+                    format_span(window.span),
+                    format_bytes_short(window.average()),
+                    format_bytes_short(window.max())
+                )
+            })
+            .collect()
+    }
+}
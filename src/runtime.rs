@@ -0,0 +1,296 @@
+//! Managed heap of Python and Node.js processes, with `--runtime-heap`
+//!
+//! Small helpers are injected into the command's environment: a
+//! `sitecustomize` module for Python that starts `tracemalloc`, and a
+//! `--require` preload for Node that reads `process.memoryUsage()`. Each
+//! process of the tree then writes its heap size to `<dir>/<pid>` once a
+//! second, which memwatch reads on every sample.
+
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{
+        CString,
+        OsString,
+    },
+    fs::{
+        self,
+        OpenOptions,
+    },
+    io::{
+        self,
+        Write,
+    },
+    os::unix::{
+        ffi::OsStringExt,
+        fs::OpenOptionsExt,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::Command,
+};
+
+use crate::{
+    json::Value,
+    size::format_bytes,
+    tree::TreeSample,
+};
+
+const SITECUSTOMIZE: &str = r#"# Injected by memwatch --runtime-heap
+import os, sys, threading, time, tracemalloc
+
+_dir = os.environ.get("MEMWATCH_RUNTIME_DIR")
+_here = os.path.dirname(os.path.abspath(__file__))
+
+
+def _report():
+    path = os.path.join(_dir, str(os.getpid()))
+    while True:
+        current, peak = tracemalloc.get_traced_memory()
+        try:
+            with open(path + ".tmp", "w") as f:
+                f.write("runtime=python heap_used=%d heap_peak=%d\n" % (current, peak))
+            os.replace(path + ".tmp", path)
+        except OSError:
+            return
+        time.sleep(1)
+
+
+def _start():
+    threading.Thread(target=_report, name="memwatch", daemon=True).start()
+
+
+if _dir:
+    tracemalloc.start()
+    _start()
+    os.register_at_fork(after_in_child=_start)
+
+# Keep a sitecustomize further down the path working
+import importlib.machinery, importlib.util
+_path = [p for p in sys.path if os.path.abspath(p or ".") != _here]
+_spec = importlib.machinery.PathFinder.find_spec("sitecustomize", _path)
+if _spec is not None:
+    _module = importlib.util.module_from_spec(_spec)
+    _spec.loader.exec_module(_module)
+"#;
+
+const NODE_PRELOAD: &str = r#"// Injected by memwatch --runtime-heap
+const fs = require('fs');
+const path = require('path');
+const dir = process.env.MEMWATCH_RUNTIME_DIR;
+if (dir) {
+    const file = path.join(dir, String(process.pid));
+    const report = () => {
+        const m = process.memoryUsage();
+        try {
+            fs.writeFileSync(file + '.tmp',
+                `runtime=node heap_used=${m.heapUsed} heap_total=${m.heapTotal} external=${m.external}\n`);
+            fs.renameSync(file + '.tmp', file);
+        } catch (e) {}
+    };
+    report();
+    setInterval(report, 1000).unref();
+}
+"#;
+
+/// Heap reported by one process
+#[derive(Clone, Copy)]
+enum Report {
+    Python { used: u64 },
+    Node { used: u64, total: u64 },
+}
+
+impl Report {
+    fn parse(text: &str) -> Option<Self> {
+        let fields: HashMap<&str, &str> = text
+            .split_whitespace()
+            .filter_map(|field| field.split_once('='))
+            .collect();
+        let number = |key| fields.get(key)?.parse::<u64>().ok();
+        match *fields.get("runtime")? {
+            "python" => Some(Self::Python {
+                used: number("heap_used")?,
+            }),
+            "node" => Some(Self::Node {
+                used: number("heap_used")?,
+                total: number("heap_total")?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Memory the runtime holds for its heap, part of the process's RSS
+    fn committed(&self) -> u64 {
+        match *self {
+            Self::Python { used } => used,
+            Self::Node { total, .. } => total,
+        }
+    }
+}
+
+/// Heaps of the tree at one sample, summed per runtime
+#[derive(Clone, Copy, Default)]
+struct Heaps {
+    python: Option<u64>,
+    node: Option<(u64, u64)>,
+    /// RSS of reporting processes minus their heaps
+    native: u64,
+}
+
+/// Peaks reported in the summary
+#[derive(Default)]
+pub struct RuntimeStats {
+    pub python_heap_peak: Option<u64>,
+    pub node_heap_used_peak: Option<u64>,
+    pub node_heap_total_peak: Option<u64>,
+    pub native_peak: u64,
+}
+
+impl RuntimeStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("python_heap_peak".into(), self.python_heap_peak.into()),
+            ("node_heap_used_peak".into(), self.node_heap_used_peak.into()),
+            ("node_heap_total_peak".into(), self.node_heap_total_peak.into()),
+            ("native_peak".into(), self.native_peak.into()),
+        ])
+    }
+}
+
+pub struct RuntimeHeaps {
+    dir: PathBuf,
+    latest: Option<Heaps>,
+    stats: RuntimeStats,
+}
+
+impl RuntimeHeaps {
+    /// Write the helpers to a private directory
+    pub fn setup() -> io::Result<Self> {
+        let dir = private_dir()?;
+        write_new(&dir.join("sitecustomize.py"), SITECUSTOMIZE)?;
+        write_new(&dir.join("memwatch-preload.js"), NODE_PRELOAD)?;
+        Ok(Self {
+            dir,
+            latest: None,
+            stats: RuntimeStats::default(),
+        })
+    }
+
+    /// Point Python and Node at the helpers, keeping the user's settings
+    pub fn configure(&self, command: &mut Command) {
+        let dir = self.dir.display().to_string();
+        let python_path = match env::var("PYTHONPATH") {
+            Ok(path) if !path.is_empty() => format!("{}:{}", dir, path),
+            _ => dir.clone(),
+        };
+        let preload = format!("--require \"{}/memwatch-preload.js\"", dir);
+        let node_options = match env::var("NODE_OPTIONS") {
+            Ok(options) if !options.is_empty() => format!("{} {}", options, preload),
+            _ => preload,
+        };
+        command
+            .env("MEMWATCH_RUNTIME_DIR", &self.dir)
+            .env("PYTHONPATH", python_path)
+            .env("NODE_OPTIONS", node_options);
+    }
+
+    /// Collect the latest reports of processes still in the tree
+    pub fn update(&mut self, sample: &TreeSample) {
+        let mut heaps = Heaps::default();
+        let mut any = false;
+        for process in &sample.processes {
+            let Ok(text) = fs::read_to_string(self.dir.join(process.pid.to_string())) else {
+                continue;
+            };
+            let Some(report) = Report::parse(&text) else {
+                continue;
+            };
+            match report {
+                Report::Python { used } => *heaps.python.get_or_insert(0) += used,
+                Report::Node { used, total } => {
+                    let node = heaps.node.get_or_insert((0, 0));
+                    node.0 += used;
+                    node.1 += total;
+                }
+            }
+            heaps.native += process.rss.saturating_sub(report.committed());
+            any = true;
+        }
+        if !any {
+            self.latest = None;
+            return;
+        }
+
+        let stats = &mut self.stats;
+        if let Some(used) = heaps.python {
+            stats.python_heap_peak = Some(stats.python_heap_peak.unwrap_or(0).max(used));
+        }
+        if let Some((used, total)) = heaps.node {
+            stats.node_heap_used_peak = Some(stats.node_heap_used_peak.unwrap_or(0).max(used));
+            stats.node_heap_total_peak = Some(stats.node_heap_total_peak.unwrap_or(0).max(total));
+        }
+        stats.native_peak = stats.native_peak.max(heaps.native);
+        self.latest = Some(heaps);
+    }
+
+    /// Managed heaps and native memory for the status line
+    pub fn status(&self) -> Vec<String> {
+        let Some(heaps) = self.latest else {
+            return Vec::new();
+        };
+        let mut status = Vec::new();
+        if let Some(used) = heaps.python {
+            status.push(format!("Python heap: {}", format_bytes(used)));
+        }
+        if let Some((used, total)) = heaps.node {
+            status.push(format!("Node heap: {} / {}", format_bytes(used), format_bytes(total)));
+        }
+        status.push(format!("Native: {}", format_bytes(heaps.native)));
+        status
+    }
+
+    /// Peaks, if any process ever reported
+    pub fn finish(mut self) -> Option<RuntimeStats> {
+        let stats = &self.stats;
+        let reported = stats.python_heap_peak.is_some() || stats.node_heap_used_peak.is_some();
+        reported.then(|| std::mem::take(&mut self.stats))
+    }
+}
+
+/// A new directory only memwatch's user can enter, under a random name
+/// no other user can have made first; the helpers in it run inside every
+/// Python and Node process of the command
+fn private_dir() -> io::Result<PathBuf> {
+    let template = env::temp_dir().join("memwatch-runtime-XXXXXX");
+    let template = CString::new(template.into_os_string().into_vec())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "temporary directory has a NUL byte"))?;
+    let mut template = template.into_bytes_with_nul();
+    // SAFETY: template is a NUL-terminated buffer ending in XXXXXX, which
+    // mkdtemp replaces in place; it creates the directory with mode 0700
+    if unsafe { libc::mkdtemp(template.as_mut_ptr().cast()) }.is_null() {
+        let e = io::Error::last_os_error();
+        return Err(io::Error::new(e.kind(), format!("cannot create a directory for --runtime-heap: {}", e)));
+    }
+    template.pop();
+    Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+/// Write `contents` to a file that must not exist yet, not following a
+/// symbolic link in its place
+fn write_new(path: &Path, contents: &str) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+impl Drop for RuntimeHeaps {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
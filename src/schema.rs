@@ -0,0 +1,111 @@
+//! JSON Schemas of the records in [`crate::record`], printed by `--schema`
+//!
+//! Both allow properties not listed here: newer memwatch versions add
+//! fields without bumping `schema_version`, and the CLI's summary carries
+//! optional sections (`self_stats`, `extracted`, `jvm`, `runtime_heap`,
+//! `working_set`, `swap`, `system_delta`, `system_floor`, `between`,
+//! `tmpfs`, `watched_dirs`, `churn`, `net`, `power`, `read_errors`,
+//! `anomalies`, `steps`, `unreadable`).
+
+/// Schema of one [`Sample`](crate::record::Sample)
+pub const SAMPLE: &str = r##"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "$id": "urn:memwatch:schema:1:sample",
+  "title": "memwatch sample",
+  "type": "object",
+  "required": ["schema_version", "elapsed_secs", "rss", "vsz", "processes"],
+  "properties": {
+    "schema_version": { "const": 1 },
+    "elapsed_secs": { "type": "number", "minimum": 0, "description": "Time since the command started" },
+    "rss": { "type": "integer", "minimum": 0, "description": "Total resident set size of the tree in bytes" },
+    "vsz": { "type": "integer", "minimum": 0, "description": "Total virtual memory size of the tree in bytes" },
+    "processes": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["pid", "starttime", "name", "rss", "hwm", "vm_peak", "cpu_ticks", "minor_faults", "major_faults"],
+        "properties": {
+          "pid": { "type": "integer" },
+          "starttime": { "type": "integer", "minimum": 0, "description": "Start time in clock ticks after boot" },
+          "name": { "type": "string" },
+          "rss": { "type": "integer", "minimum": 0 },
+          "hwm": { "type": "integer", "minimum": 0, "description": "VmHWM, the kernel's peak RSS of the process" },
+          "vm_peak": { "type": "integer", "minimum": 0 },
+          "cpu_ticks": { "type": "integer", "minimum": 0 },
+          "minor_faults": { "type": "integer", "minimum": 0 },
+          "major_faults": { "type": "integer", "minimum": 0 },
+          "swap": { "type": "integer", "minimum": 0, "description": "VmSwap, bytes swapped out" }
+        }
+      }
+    },
+    "time": { "type": ["string", "null"], "format": "date-time", "description": "Wall-clock time of the sample, RFC 3339 UTC" },
+    "incomplete": { "type": "boolean", "description": "Totals leave out memory that could not be read" },
+    "hot": { "type": "integer", "minimum": 0, "description": "Resident bytes touched since the previous sample, with --working-set" }
+  }
+}
+"##;
+
+/// Schema of a [`RunSummary`](crate::record::RunSummary)
+pub const SUMMARY: &str = r##"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "$id": "urn:memwatch:schema:1:summary",
+  "title": "memwatch run summary",
+  "type": "object",
+  "required": [
+    "schema_version", "host", "command", "run_id", "elapsed_secs", "peak_rss", "peak_vsz",
+    "kernel_peak_rss", "kernel_peak_vsz", "peak_processes", "processes_seen", "cgroup_peak",
+    "samples", "exit_code", "signal", "core_dumped", "termination", "output_lines_dropped",
+    "top_processes"
+  ],
+  "properties": {
+    "schema_version": { "const": 1 },
+    "host": { "type": "string" },
+    "command": { "type": "string", "description": "Command line as a single shell-quoted string" },
+    "run_id": { "type": "string" },
+    "started_at": { "type": ["string", "null"], "format": "date-time", "description": "Wall-clock time the command started, RFC 3339 UTC" },
+    "elapsed_secs": { "type": "number", "minimum": 0 },
+    "peak_rss": { "type": "integer", "minimum": 0 },
+    "peak_vsz": { "type": "integer", "minimum": 0 },
+    "kernel_peak_rss": { "type": "integer", "minimum": 0, "description": "Largest sum of per-process VmHWM" },
+    "kernel_peak_vsz": { "type": "integer", "minimum": 0, "description": "Largest sum of per-process VmPeak" },
+    "peak_processes": { "type": "integer", "minimum": 0 },
+    "processes_seen": { "type": "integer", "minimum": 0 },
+    "cgroup_peak": { "type": ["integer", "null"], "minimum": 0 },
+    "samples": { "type": "integer", "minimum": 0 },
+    "exit_code": { "type": ["integer", "null"] },
+    "signal": { "type": ["string", "null"], "description": "Signal name such as SIGKILL" },
+    "core_dumped": { "type": "boolean" },
+    "termination": { "enum": ["rss_limit", "interrupted", "detached", "time_limit", null] },
+    "output_lines_dropped": { "type": "integer", "minimum": 0 },
+    "top_processes": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["pid", "name", "peak_rss", "lifetime_secs", "exact"],
+        "properties": {
+          "pid": { "type": "integer" },
+          "name": { "type": "string" },
+          "peak_rss": { "type": "integer", "minimum": 0 },
+          "lifetime_secs": { "type": "number", "minimum": 0 },
+          "exact": { "type": "boolean", "description": "Peak reported by taskstats rather than sampled" }
+        }
+      }
+    },
+    "environment": {
+      "type": ["object", "null"],
+      "description": "The machine the run was on; fields memwatch could not read are null",
+      "properties": {
+        "kernel": { "type": ["string", "null"], "description": "Kernel release" },
+        "memory_total": { "type": ["integer", "null"], "minimum": 0, "description": "MemTotal in bytes" },
+        "swap_total": { "type": ["integer", "null"], "minimum": 0, "description": "SwapTotal in bytes" },
+        "cgroup": { "enum": ["v1", "v2", null] },
+        "cgroup_controllers": { "type": "array", "items": { "type": "string" } },
+        "thp": { "type": ["string", "null"], "description": "Transparent huge pages mode" },
+        "cpu_model": { "type": ["string", "null"] },
+        "cpus": { "type": ["integer", "null"], "minimum": 1, "description": "CPUs online" },
+        "governor": { "type": ["string", "null"], "description": "Frequency scaling governor of the first CPU" }
+      }
+    }
+  }
+}
+"##;
@@ -0,0 +1,240 @@
+//! `memwatch selftest`: watch a workload whose memory use is known and
+//! check that memwatch sees it
+//!
+//! The workload is memwatch itself, started again with the hidden
+//! `--workload`: with the memory of `memwatch stress`, it grows in steps
+//! of `STEP`, holding each, releases it all, starts a child that takes
+//! `CHILD` and exits, and exits in turn. The run is sampled through
+//! [`Monitor`] as any program using memwatch would, and every figure has
+//! to be within the tolerance, a share of `STEP`, of what the workload
+//! did.
+
+use std::{
+    io,
+    process::{
+        Command,
+        ExitCode,
+    },
+    thread,
+    time::Duration,
+};
+
+use memwatch::{
+    monitor::Monitor,
+    record::Sample,
+};
+
+use crate::{
+    size::format_bytes,
+    stress::Memory,
+};
+
+const MIB: u64 = 1024 * 1024;
+/// Memory added by each step of the workload
+const STEP: u64 = 32 * MIB;
+const STEPS: u64 = 4;
+/// Memory mapped by the workload's child
+const CHILD: u64 = 64 * MIB;
+/// How long the workload holds each level; many samples long
+const HOLD: Duration = Duration::from_millis(500);
+const INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(clap::Args, Debug)]
+pub struct SelftestArgs {
+    /// Largest error allowed, in percent of a step of the workload
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.0)]
+    tolerance: f64,
+
+    /// Be the workload rather than run it
+    #[arg(long, hide = true, value_enum)]
+    workload: Option<Workload>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Workload {
+    Parent,
+    Child,
+}
+
+struct Check {
+    what: String,
+    expected: String,
+    measured: String,
+    passed: bool,
+}
+
+pub fn run(args: SelftestArgs) -> io::Result<ExitCode> {
+    match args.workload {
+        Some(Workload::Parent) => return parent().map(|()| ExitCode::SUCCESS),
+        Some(Workload::Child) => return child().map(|()| ExitCode::SUCCESS),
+        None => {}
+    }
+    let tolerance = (STEP as f64 * args.tolerance / 100.0) as u64;
+    println!(
+        "Workload: {} steps of {}, then a child of {}; sampling every {} ms, tolerance {}",
+        STEPS,
+        format_bytes(STEP),
+        format_bytes(CHILD),
+        INTERVAL.as_millis(),
+        format_bytes(tolerance)
+    );
+
+    let monitor = Monitor::spawn(
+        Command::new(std::env::current_exe()?).args(["selftest", "--workload", "parent"]),
+        INTERVAL,
+    )?;
+    let root = monitor.pid();
+    let mut samples = Vec::new();
+    while let Some(sample) = monitor.next_sample() {
+        samples.push(sample);
+    }
+    let summary = monitor.wait()?;
+    if samples.len() < 2 {
+        return Err(io::Error::other("the workload exited before it could be sampled"));
+    }
+
+    // Before the first step and after the release: the median of the
+    // root's samples less than half a step over the lowest, so that the
+    // few that catch it starting up or exiting do not count
+    let mut low: Vec<u64> = samples
+        .iter()
+        .filter_map(|sample| root_rss(sample, root))
+        .filter(|&rss| rss > 0)
+        .collect();
+    let lowest = low.iter().copied().min().unwrap_or(0);
+    low.retain(|&rss| rss < lowest + STEP / 2);
+    low.sort_unstable();
+    let baseline = low.get(low.len() / 2).copied().unwrap_or(0);
+    let near = |measured: u64, expected: u64| measured.abs_diff(expected) <= tolerance;
+    let closest = |expected: u64, rss: &dyn Fn(&Sample) -> Option<u64>| {
+        samples
+            .iter()
+            .filter_map(rss)
+            .min_by_key(|measured| measured.abs_diff(expected))
+            .unwrap_or(0)
+    };
+    let mut checks = Vec::new();
+    let mut check = |what: String, expected: u64, measured: u64| {
+        checks.push(Check {
+            what,
+            expected: format_bytes(expected),
+            measured: format_bytes(measured),
+            passed: near(measured, expected),
+        })
+    };
+
+    for step in 1..=STEPS {
+        let expected = baseline + step * STEP;
+        check(
+            format!("Step {} of {}", step, STEPS),
+            expected,
+            closest(expected, &|sample| root_rss(sample, root)),
+        );
+    }
+    check("Peak RSS".into(), baseline + STEPS * STEP, summary.peak_rss);
+    // The summary's kernel peak adds the child's VmHWM to the parent's;
+    // the parent's alone is what the steps did
+    let hwm = samples
+        .iter()
+        .flat_map(|sample| &sample.processes)
+        .filter(|process| process.pid == root)
+        .map(|process| process.hwm)
+        .max()
+        .unwrap_or(0);
+    check("Kernel peak (VmHWM)".into(), baseline + STEPS * STEP, hwm);
+    // Closest once the last step was reached
+    let top = baseline + STEPS * STEP - tolerance;
+    let released = samples
+        .iter()
+        .filter_map(|sample| root_rss(sample, root))
+        .skip_while(|&rss| rss < top)
+        .min_by_key(|rss| rss.abs_diff(baseline))
+        .unwrap_or(0);
+    check("Released".into(), baseline, released);
+    // The child is memwatch too, so it starts out about as large as the
+    // parent did
+    let child = samples
+        .iter()
+        .flat_map(|sample| &sample.processes)
+        .filter(|process| process.pid != root)
+        .map(|process| process.rss)
+        .max()
+        .unwrap_or(0);
+    check("Child".into(), baseline + CHILD, child);
+    let tree = closest(2 * baseline + CHILD, &|sample| {
+        (sample.processes.len() == 2).then_some(sample.rss)
+    });
+    check("Tree with the child".into(), 2 * baseline + CHILD, tree);
+    checks.push(Check {
+        what: "Processes seen".into(),
+        expected: "2".into(),
+        measured: summary.processes_seen.to_string(),
+        passed: summary.processes_seen == 2,
+    });
+    checks.push(Check {
+        what: "Exit".into(),
+        expected: "0".into(),
+        measured: summary.describe_exit(),
+        passed: summary.exit_code == Some(0),
+    });
+
+    println!();
+    println!("{:<6}  {:<22} {:>12} {:>12}", "", "", "Expected", "Measured");
+    for check in &checks {
+        println!(
+            "{:<6}  {:<22} {:>12} {:>12}",
+            if check.passed { "ok" } else { "FAILED" },
+            check.what,
+            check.expected,
+            check.measured
+        );
+    }
+    let failed = checks.iter().filter(|check| !check.passed).count();
+    println!();
+    if failed > 0 {
+        println!(
+            "{} of {} checks failed; `memwatch doctor` tells what this environment lacks",
+            failed,
+            checks.len()
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+    println!("All {} checks passed over {} samples.", checks.len(), samples.len());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// RSS of the workload's own process in `sample`
+fn root_rss(sample: &Sample, root: i32) -> Option<u64> {
+    sample
+        .processes
+        .iter()
+        .find(|process| process.pid == root)
+        .map(|process| process.rss)
+}
+
+/// The workload: steps up, release, then the child
+fn parent() -> io::Result<()> {
+    thread::sleep(HOLD);
+    let mut memory = Memory::default();
+    for step in 1..=STEPS {
+        memory.resize(step * STEP)?;
+        thread::sleep(HOLD);
+    }
+    memory.resize(0)?;
+    thread::sleep(HOLD);
+    let status = Command::new(std::env::current_exe()?)
+        .args(["selftest", "--workload", "child"])
+        .status()?;
+    thread::sleep(HOLD);
+    match status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("the workload's child failed: {}", status))),
+    }
+}
+
+fn child() -> io::Result<()> {
+    let mut memory = Memory::default();
+    memory.resize(CHILD)?;
+    thread::sleep(2 * HOLD);
+    Ok(())
+}
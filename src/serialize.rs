@@ -0,0 +1,212 @@
+//! `Serialize` and `Deserialize` for the records, with the `serde` feature
+//!
+//! A record serializes to the document its `to_json` makes and
+//! deserializes through its `from_json`, so the versioned schema of
+//! [`crate::record`] stays the one contract whatever format carries it:
+//! `elapsed_secs` in seconds, times as RFC 3339 text, `schema_version`
+//! checked on the way in.
+
+use std::fmt;
+
+use serde::{
+    de::{
+        self,
+        MapAccess,
+        SeqAccess,
+        Visitor,
+    },
+    ser::{
+        SerializeMap,
+        SerializeSeq,
+    },
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+
+use crate::{
+    json::Value,
+    observer::{
+        Anomaly,
+        Crossing,
+        IntervalChange,
+        Mark,
+    },
+    record::{
+        self,
+        Environment,
+        RunSummary,
+        Sample,
+    },
+    tree::ProcessSample,
+};
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(v) => serializer.serialize_bool(*v),
+            Value::Int(v) => serializer.serialize_i64(*v),
+            Value::UInt(v) => serializer.serialize_u64(*v),
+            Value::Float(v) => serializer.serialize_f64(*v),
+            Value::String(v) => serializer.serialize_str(v),
+            Value::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Value::Object(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (key, value) in fields {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        // As the JSON parser reads them: unsigned unless negative
+        Ok(u64::try_from(v).map_or(Value::Int(v), Value::UInt))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(Value::UInt(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut fields = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(field) = map.next_entry()? {
+            fields.push(field);
+        }
+        Ok(Value::Object(fields))
+    }
+}
+
+/// Serde impls going through `to_json` and `from_json`
+macro_rules! through_json {
+    ($($record:ty),* $(,)?) => {
+        $(
+            impl Serialize for $record {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    self.to_json().serialize(serializer)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $record {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    Self::from_json(&Value::deserialize(deserializer)?).map_err(de::Error::custom)
+                }
+            }
+        )*
+    };
+}
+
+through_json!(Sample, RunSummary, Environment, Crossing, Anomaly, Mark, IntervalChange);
+
+impl Serialize for ProcessSample {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        record::process_sample_json(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProcessSample {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        record::process_sample_from_json(&value).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::json;
+
+    #[test]
+    fn records_serialize_to_their_schema() {
+        let sample = Sample {
+            elapsed: Duration::from_millis(1500),
+            rss: 1 << 20,
+            ..Sample::default()
+        };
+        let text = serde_json::to_string(&sample).unwrap();
+        assert_eq!(json::parse(&text).unwrap(), sample.to_json());
+
+        let parsed: Sample = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed.to_json(), sample.to_json());
+    }
+
+    #[test]
+    fn deserializing_checks_the_schema() {
+        let summary = RunSummary {
+            command: "make -j8".into(),
+            signal: Some("SIGKILL".into()),
+            ..RunSummary::default()
+        };
+        let text = serde_json::to_string(&summary).unwrap();
+        let parsed: RunSummary = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed.to_json(), summary.to_json());
+
+        let newer = text.replace("\"schema_version\":1", "\"schema_version\":99");
+        let e = serde_json::from_str::<RunSummary>(&newer).unwrap_err();
+        assert!(e.to_string().contains("newer than the supported"), "{}", e);
+        assert!(serde_json::from_str::<ProcessSample>(r#"{"pid":1}"#).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Human-friendly byte sizes shared by every size-valued flag and output
+//!
+//! How sizes are written is set once for the process with
+//! [`set_number_format`]; every summary, report and status line goes
+//! through [`format_bytes`] and its siblings, so they all agree.
+
+use std::sync::atomic::{
+    AtomicU8,
+    Ordering,
+};
+
+/// How byte counts are written for people to read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum NumberFormat {
+    /// Bytes as a bare number, e.g. `1234567890`
+    Plain,
+    /// Bytes with thousands separators, e.g. `1,234,567,890`
+    Grouped,
+    /// A number with a binary unit, a power of 1024, e.g. `1.15 GB`
+    #[default]
+    Binary,
+    /// A number with an SI unit, a power of 1000, e.g. `1.23 GB`
+    Si,
+}
+
+static NUMBER_FORMAT: AtomicU8 = AtomicU8::new(NumberFormat::Binary as u8);
+
+/// Write sizes in `format` from now on
+pub fn set_number_format(format: NumberFormat) {
+    NUMBER_FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+pub fn number_format() -> NumberFormat {
+    match NUMBER_FORMAT.load(Ordering::Relaxed) {
+        0 => NumberFormat::Plain,
+        1 => NumberFormat::Grouped,
+        3 => NumberFormat::Si,
+        _ => NumberFormat::Binary,
+    }
+}
+
+/// Parse a size such as `1048576`, `512M`, `2G` or `1.5GiB` into bytes.
+///
+/// Units are case-insensitive and always binary: `K`, `KB` and `KiB` all
+/// mean 1024, matching how sizes are displayed in the status line.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let input = s.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    if number.is_empty() {
+        return Err(format!(
+            "invalid size `{}`: expected a number with optional unit (e.g. 512M, 2G, 1.5GiB)",
+            s
+        ));
+    }
+
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{}`: `{}` is not a number", s, number))?;
+
+    let multiplier = unit_multiplier(unit.trim()).ok_or_else(|| {
+        format!(
+            "invalid size `{}`: unknown unit `{}` (expected B, K, M, G, T with optional B/iB)",
+            s,
+            unit.trim()
+        )
+    })?;
+
+    let bytes = value * multiplier as f64;
+    if bytes > u64::MAX as f64 {
+        return Err(format!("invalid size `{}`: value is too large", s));
+    }
+
+    Ok(bytes.round() as u64)
+}
+
+fn unit_multiplier(unit: &str) -> Option<u64> {
+    let unit = unit.to_ascii_lowercase();
+    let prefix = unit
+        .strip_suffix("ib")
+        .or_else(|| unit.strip_suffix('b'))
+        .unwrap_or(&unit);
+
+    match prefix {
+        "" => Some(1),
+        "k" => Some(1024),
+        "m" => Some(1024_u64.pow(2)),
+        "g" => Some(1024_u64.pow(3)),
+        "t" => Some(1024_u64.pow(4)),
+        _ => None,
+    }
+}
+
+/// Format to chosen unit (auto/kb/mb/gb), in powers of 1000 with
+/// [`NumberFormat::Si`] and of 1024 otherwise
+pub fn format_bytes_unit(bytes: u64) -> (f64, &'static str) {
+    scale(bytes, number_format())
+}
+
+fn scale(bytes: u64, format: NumberFormat) -> (f64, &'static str) {
+    let base: u64 = match format {
+        NumberFormat::Si => 1000,
+        _ => 1024,
+    };
+    if bytes >= base.pow(3) {
+        (bytes as f64 / (base as f64).powi(3), "GB")
+    } else if bytes >= base.pow(2) {
+        (bytes as f64 / (base as f64).powi(2), "MB")
+    } else if bytes >= base {
+        (bytes as f64 / base as f64, "KB")
+    } else {
+        (bytes as f64, "B")
+    }
+}
+
+/// Format bytes as a short human-readable string, e.g. `183.52 MB`, or
+/// as the number of bytes with another [`NumberFormat`]
+pub fn format_bytes(bytes: u64) -> String {
+    format_bytes_as(bytes, number_format())
+}
+
+fn format_bytes_as(bytes: u64, format: NumberFormat) -> String {
+    match format {
+        NumberFormat::Plain => bytes.to_string(),
+        NumberFormat::Grouped => group_thousands(bytes),
+        NumberFormat::Binary | NumberFormat::Si => {
+            let (value, unit) = scale(bytes, format);
+            format!("{:.2} {}", value, unit)
+        }
+    }
+}
+
+/// Format bytes compactly for inline tags, e.g. `1.23G`; a number of
+/// bytes is not made any shorter
+pub fn format_bytes_short(bytes: u64) -> String {
+    if matches!(number_format(), NumberFormat::Plain | NumberFormat::Grouped) {
+        return format_bytes(bytes);
+    }
+    match format_bytes_unit(bytes) {
+        (value, "B") => format!("{}B", value),
+        (value, unit) => format!("{:.2}{}", value, &unit[..1]),
+    }
+}
+
+/// [`format_bytes`] in a fixed width, for columns that should not shift
+/// as the value changes, e.g. `  12.40 MB`
+pub fn format_bytes_fixed(bytes: u64) -> String {
+    match number_format() {
+        NumberFormat::Binary | NumberFormat::Si => {
+            let (value, unit) = format_bytes_unit(bytes);
+            format!("{:>7.2} {:<2}", value, unit)
+        }
+        // Up to a terabyte
+        NumberFormat::Plain => format!("{:>13}", format_bytes(bytes)),
+        NumberFormat::Grouped => format!("{:>17}", format_bytes(bytes)),
+    }
+}
+
+/// `1234567` as `1,234,567`
+fn group_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_binary_units() {
+        assert_eq!(parse_size("1048576"), Ok(1048576));
+        assert_eq!(parse_size("512M"), Ok(512 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1.5GiB"), Ok(3 * 512 * 1024 * 1024));
+        assert_eq!(parse_size("4kb"), Ok(4096));
+        assert_eq!(parse_size(" 1 T "), Ok(1024_u64.pow(4)));
+        assert_eq!(parse_size("10B"), Ok(10));
+    }
+
+    #[test]
+    fn refuses_bad_sizes() {
+        assert!(parse_size("").unwrap_err().contains("expected a number"));
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("1.2.3G").unwrap_err().contains("is not a number"));
+        assert!(parse_size("5X").unwrap_err().contains("unknown unit `X`"));
+        assert!(parse_size("99999999T").unwrap_err().contains("too large"));
+    }
+
+    #[test]
+    fn formats_in_the_default_units() {
+        assert_eq!(format_bytes(512), "512.00 B");
+        assert_eq!(format_bytes(1536), "1.50 KB");
+        assert_eq!(format_bytes(183 * 1024 * 1024), "183.00 MB");
+        assert_eq!(format_bytes_short(1024_u64.pow(3)), "1.00G");
+        assert_eq!(format_bytes_short(12), "12B");
+        assert_eq!(format_bytes_fixed(12 * 1024 * 1024 + 400 * 1024), "  12.39 MB");
+        assert_eq!(group_thousands(1234567), "1,234,567");
+        assert_eq!(group_thousands(999), "999");
+    }
+
+    #[test]
+    fn formats_in_each_number_format() {
+        let bytes = 1_234_567_890;
+        assert_eq!(format_bytes_as(bytes, NumberFormat::Si), "1.23 GB");
+        assert_eq!(format_bytes_as(bytes, NumberFormat::Binary), "1.15 GB");
+        assert_eq!(format_bytes_as(bytes, NumberFormat::Grouped), "1,234,567,890");
+        assert_eq!(format_bytes_as(bytes, NumberFormat::Plain), "1234567890");
+        assert_eq!(format_bytes_as(1500, NumberFormat::Si), "1.50 KB");
+    }
+}
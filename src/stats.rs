@@ -0,0 +1,133 @@
+//! Robust statistics over repeated runs
+
+/// Modified z-score above which a value counts as an outlier (Iglewicz and
+/// Hoaglin)
+const OUTLIER_SCORE: f64 = 3.5;
+
+/// Two-sided 95% Student's t critical values for 1..=30 degrees of freedom
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Summary statistics of a sample
+pub struct Stats {
+    pub mean: f64,
+    pub median: f64,
+    /// Median absolute deviation from the median
+    pub mad: f64,
+    /// 95% confidence interval of the mean
+    pub ci: (f64, f64),
+    /// Indices of values far from the median relative to the MAD
+    pub outliers: Vec<usize>,
+}
+
+impl Stats {
+    /// `None` for an empty sample
+    pub fn new(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let n = values.len();
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let median = median_of(values.to_vec());
+        let mad = median_of(values.iter().map(|v| (v - median).abs()).collect());
+
+        let ci = if n < 2 {
+            (mean, mean)
+        } else {
+            let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+            let t = T_95.get(n - 2).copied().unwrap_or(1.96);
+            let half = t * (variance / n as f64).sqrt();
+            (mean - half, mean + half)
+        };
+
+        let outliers = if mad == 0.0 {
+            Vec::new()
+        } else {
+            values
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| (0.6745 * (*v - median) / mad).abs() > OUTLIER_SCORE)
+                .map(|(i, _)| i)
+                .collect()
+        };
+
+        Some(Self {
+            mean,
+            median,
+            mad,
+            ci,
+            outliers,
+        })
+    }
+}
+
+fn median_of(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn empty_samples_have_no_stats() {
+        assert!(Stats::new(&[]).is_none());
+    }
+
+    #[test]
+    fn median_and_mad_resist_outliers() {
+        let stats = Stats::new(&[10.0, 12.0, 11.0, 13.0, 100.0]).unwrap();
+        assert!(close(stats.mean, 29.2));
+        assert!(close(stats.median, 12.0));
+        // Deviations 2, 0, 1, 1, 88
+        assert!(close(stats.mad, 1.0));
+        assert_eq!(stats.outliers, [4]);
+
+        let stats = Stats::new(&[4.0, 1.0, 3.0, 2.0]).unwrap();
+        assert!(close(stats.median, 2.5));
+        assert!(close(stats.mad, 1.0));
+        assert!(stats.outliers.is_empty());
+    }
+
+    #[test]
+    fn equal_values_have_no_outliers() {
+        let stats = Stats::new(&[0.0, 0.0, 0.0, 5.0]).unwrap();
+        assert!(close(stats.median, 0.0));
+        assert!(close(stats.mad, 0.0));
+        assert!(stats.outliers.is_empty());
+    }
+
+    #[test]
+    fn ci_uses_t_for_small_samples() {
+        let stats = Stats::new(&[7.0]).unwrap();
+        assert_eq!(stats.ci, (7.0, 7.0));
+
+        // Standard deviation sqrt(2), so the half width is t(1) * 1
+        let stats = Stats::new(&[1.0, 3.0]).unwrap();
+        assert!(close(stats.ci.0, 2.0 - 12.706) && close(stats.ci.1, 2.0 + 12.706));
+
+        // 30 degrees of freedom are the last in the table, past them the
+        // normal 1.96 applies
+        for (n, t) in [(31, 2.042), (32, 1.96)] {
+            let values: Vec<f64> = (0..n).map(|i| (i % 2 * 2) as f64).collect();
+            let stats = Stats::new(&values).unwrap();
+            let variance =
+                values.iter().map(|v| (v - stats.mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+            let half = t * (variance / n as f64).sqrt();
+            assert!(close(stats.ci.0, stats.mean - half) && close(stats.ci.1, stats.mean + half));
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Current status written to a file for tmux, screen or desktop widgets
+
+use std::{
+    ffi::OsString,
+    fs::{
+        self,
+        OpenOptions,
+    },
+    io::{
+        self,
+        Write,
+    },
+    os::unix::fs::OpenOptionsExt,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+
+use crate::{
+    clock::format_elapsed,
+    json::Value,
+    observer::Observer,
+    record::{
+        RunSummary,
+        Sample,
+        SCHEMA_VERSION,
+    },
+    size::format_bytes,
+};
+
+/// Keeps `<path>` and `<path>.json` up to date with the latest sample
+///
+/// Both are replaced by rename, so a reader never sees a half-written file.
+pub struct StatusFile {
+    path: PathBuf,
+    json_path: PathBuf,
+    run_id: String,
+    warned: bool,
+}
+
+impl StatusFile {
+    pub fn new(path: PathBuf, run_id: &str) -> Self {
+        let mut json_path = OsString::from(path.as_os_str());
+        json_path.push(".json");
+        Self {
+            path,
+            json_path: json_path.into(),
+            run_id: run_id.to_string(),
+            warned: false,
+        }
+    }
+
+    fn write(
+        &mut self,
+        line: &str,
+        elapsed: Duration,
+        rss: u64,
+        vsz: u64,
+        processes: usize,
+        exit: Option<&str>,
+    ) {
+        let json = Value::Object(vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("run_id".into(), self.run_id.as_str().into()),
+            ("running".into(), exit.is_none().into()),
+            ("elapsed_secs".into(), elapsed.as_secs_f64().into()),
+            ("rss".into(), rss.into()),
+            ("vsz".into(), vsz.into()),
+            ("processes".into(), (processes as u64).into()),
+            ("exit".into(), exit.into()),
+        ]);
+
+        let result = replace(&self.path, line)
+            .and_then(|()| replace(&self.json_path, &json.to_string()));
+        if let Err(e) = result {
+            if !self.warned {
+                self.warned = true;
+                eprintln!(
+                    "memwatch: failed to write status file {}: {}",
+                    self.path.display(),
+                    e
+                );
+            } else {
+                crate::log::info!("failed to write status file {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+impl Observer for StatusFile {
+    /// Write the status of a running command
+    fn on_sample(&mut self, sample: &Sample) {
+        let line = format!("[{}] RSS {}", format_elapsed(sample.elapsed), format_bytes(sample.rss));
+        let processes = sample.processes.len();
+        self.write(&line, sample.elapsed, sample.rss, sample.vsz, processes, None);
+    }
+
+    /// Write the final status once the command is gone
+    fn on_exit(&mut self, summary: &RunSummary) {
+        let exit = summary.describe_exit();
+        let line = format!(
+            "[{}] peak {} ({})",
+            format_elapsed(summary.elapsed),
+            format_bytes(summary.peak_rss),
+            exit
+        );
+        self.write(&line, summary.elapsed, 0, 0, 0, Some(&exit));
+    }
+}
+
+/// Atomically replace `path` with `text` and a newline
+///
+/// The temporary file has a name others can guess, often in `/tmp`, so it
+/// must not exist yet and a symbolic link in its place is not followed.
+fn replace(path: &Path, text: &str) -> io::Result<()> {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(format!(".tmp{}", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(format!("{}\n", text).as_bytes()));
+    match written {
+        // Not ours to remove
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        Ok(()) => {}
+    }
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn replace_writes_a_private_file_and_refuses_planted_links() {
+        let dir = std::env::temp_dir().join(format!("memwatch-status-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("status");
+
+        replace(&path, "RSS 1 MB").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "RSS 1 MB\n");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let victim = dir.join("victim");
+        fs::write(&victim, "keep").unwrap();
+        let tmp = dir.join(format!("status.tmp{}", std::process::id()));
+        std::os::unix::fs::symlink(&victim, &tmp).unwrap();
+        assert!(replace(&path, "RSS 2 MB").is_err());
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "keep");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "RSS 1 MB\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
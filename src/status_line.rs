@@ -0,0 +1,131 @@
+//! Layout of the status line in the columns the terminal has
+//!
+//! The line is made of columns such as `RSS: 1.20 GB` or `Procs: 4`, each
+//! with a priority. When the line is too wide, columns of the lowest
+//! priority give way first, rightmost first: those with a short form take
+//! it, then columns are dropped. The essential ones are never dropped:
+//! they take their short form last, and if even that does not fit they
+//! are cut at the terminal's edge.
+
+use crate::render::{
+    display_width,
+    fit_width,
+};
+
+/// How readily a column gives way on a narrow terminal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    High,
+    /// Kept whatever the width
+    Essential,
+}
+
+struct Column {
+    text: String,
+    /// Taken before the column is dropped
+    short: Option<String>,
+    priority: Priority,
+    /// Put before the column, unless it comes first
+    separator: &'static str,
+}
+
+pub struct StatusLine {
+    columns: Vec<Column>,
+}
+
+/// Between columns
+const SEPARATOR: &str = " | ";
+
+impl StatusLine {
+    /// A line led by `clock`, which is followed by a space rather than a
+    /// separator
+    pub fn new(clock: &str) -> Self {
+        Self {
+            columns: vec![Column {
+                text: clock.to_string(),
+                short: None,
+                priority: Priority::Low,
+                separator: "",
+            }],
+        }
+    }
+
+    pub fn push(&mut self, priority: Priority, text: String) {
+        self.add(priority, text, None);
+    }
+
+    /// A column shown as `short` when there is no room for `text`
+    pub fn push_short(&mut self, priority: Priority, text: String, short: String) {
+        self.add(priority, text, Some(short));
+    }
+
+    /// Columns of the same priority
+    pub fn extend(&mut self, priority: Priority, columns: impl IntoIterator<Item = String>) {
+        for text in columns {
+            self.push(priority, text);
+        }
+    }
+
+    fn add(&mut self, priority: Priority, text: String, short: Option<String>) {
+        let separator = if self.columns.len() == 1 { " " } else { SEPARATOR };
+        self.columns.push(Column {
+            text,
+            short,
+            priority,
+            separator,
+        });
+    }
+
+    /// The line in at most `width` columns
+    pub fn fit(&self, width: usize) -> String {
+        let mut shown: Vec<Option<&str>> = self.columns.iter().map(|c| Some(c.text.as_str())).collect();
+        // Columns by how readily they give way
+        let mut order: Vec<usize> = (0..self.columns.len()).rev().collect();
+        order.sort_by_key(|&i| self.columns[i].priority);
+        let (essential, others): (Vec<usize>, Vec<usize>) = order
+            .into_iter()
+            .partition(|&i| self.columns[i].priority == Priority::Essential);
+
+        for &i in &others {
+            if self.width_of(&shown) <= width {
+                break;
+            }
+            if let Some(short) = &self.columns[i].short {
+                shown[i] = Some(short);
+            }
+        }
+        for &i in &others {
+            if self.width_of(&shown) <= width {
+                break;
+            }
+            shown[i] = None;
+        }
+        for &i in &essential {
+            if self.width_of(&shown) <= width {
+                break;
+            }
+            if let Some(short) = &self.columns[i].short {
+                shown[i] = Some(short);
+            }
+        }
+        fit_width(&self.join(&shown), width)
+    }
+
+    fn join(&self, shown: &[Option<&str>]) -> String {
+        let mut line = String::new();
+        for (column, text) in self.columns.iter().zip(shown) {
+            if let Some(text) = text {
+                if !line.is_empty() {
+                    line.push_str(column.separator);
+                }
+                line.push_str(text);
+            }
+        }
+        line
+    }
+
+    fn width_of(&self, shown: &[Option<&str>]) -> usize {
+        display_width(&self.join(shown))
+    }
+}
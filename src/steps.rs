@@ -0,0 +1,153 @@
+//! `--detect-steps`: discrete jumps in RSS, for the final report
+//!
+//! A step is a change between consecutive samples of at least the minimum
+//! size; changes in the same direction over consecutive samples add up to
+//! one step. Each is paired with the last line the command printed before
+//! it, which often names what was being loaded or built.
+
+use std::time::Duration;
+
+use crate::{
+    clock::format_elapsed,
+    json::Value,
+    render::OutputMsg,
+    size::format_bytes,
+};
+
+/// Steps kept for the report, the largest ones
+const MAX_STEPS: usize = 5;
+
+/// Smallest step without an explicit size, relative to RSS before it
+const RELATIVE_MIN: u64 = 10;
+
+/// Floor of the relative minimum, so a small process's page-ins do not
+/// count as steps
+const ABSOLUTE_MIN: u64 = 1 << 20;
+
+/// Longest output line kept, in characters
+const LINE_LIMIT: usize = 120;
+
+#[derive(Clone, Debug)]
+pub struct Step {
+    /// Time since the start of the sample where the step began
+    pub at: Duration,
+    /// Signed change of RSS over the step
+    pub change: i64,
+    /// RSS at the end of the step
+    pub rss: u64,
+    /// Last line of output before the step
+    pub line: Option<String>,
+}
+
+pub struct StepDetector {
+    /// `--detect-steps=SIZE`, or relative to RSS without one
+    min: Option<u64>,
+    previous: Option<u64>,
+    /// Step that may still go on with the next sample
+    open: Option<Step>,
+    /// Largest closed steps, largest first
+    steps: Vec<Step>,
+    last_line: Option<String>,
+}
+
+impl StepDetector {
+    pub fn new(min: Option<u64>) -> Self {
+        Self {
+            min,
+            previous: None,
+            open: None,
+            steps: Vec::new(),
+            last_line: None,
+        }
+    }
+
+    /// Remember the command's latest line of output
+    pub fn output(&mut self, msg: &OutputMsg) {
+        if let OutputMsg::Stdout(line) | OutputMsg::Stderr(line) = msg {
+            let line = line.trim();
+            if !line.is_empty() {
+                self.last_line = Some(line.chars().take(LINE_LIMIT).collect());
+            }
+        }
+    }
+
+    pub fn update(&mut self, at: Duration, rss: u64) {
+        let Some(previous) = self.previous.replace(rss) else {
+            return;
+        };
+        let min = self
+            .min
+            .unwrap_or((previous / RELATIVE_MIN).max(ABSOLUTE_MIN));
+        let change = rss as i64 - previous as i64;
+        if change.unsigned_abs() < min {
+            self.close();
+            return;
+        }
+        match &mut self.open {
+            Some(open) if (open.change > 0) == (change > 0) => {
+                open.change += change;
+                open.rss = rss;
+            }
+            _ => {
+                self.close();
+                self.open = Some(Step {
+                    at,
+                    change,
+                    rss,
+                    line: self.last_line.clone(),
+                });
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(step) = self.open.take() {
+            let at = self
+                .steps
+                .iter()
+                .position(|s| s.change.unsigned_abs() < step.change.unsigned_abs())
+                .unwrap_or(self.steps.len());
+            if at < MAX_STEPS {
+                self.steps.insert(at, step);
+                self.steps.truncate(MAX_STEPS);
+            }
+        }
+    }
+
+    /// The largest steps of the run, in the order they happened
+    pub fn finish(mut self) -> Vec<Step> {
+        self.close();
+        self.steps.sort_by_key(|step| step.at);
+        self.steps
+    }
+}
+
+/// `+ 412.00 MB at 03:12 after "loading weights"`
+pub fn format_step(step: &Step) -> String {
+    let mut text = format!(
+        "{} {} at {}",
+        if step.change < 0 { "-" } else { "+" },
+        format_bytes(step.change.unsigned_abs()),
+        format_elapsed(step.at)
+    );
+    if let Some(line) = &step.line {
+        text.push_str(&format!(" after \"{}\"", line));
+    }
+    text
+}
+
+pub fn json(steps: &[Step]) -> Value {
+    Value::Array(
+        steps
+            .iter()
+            .map(|step| {
+                Value::Object(vec![
+                    ("elapsed_secs".into(), step.at.as_secs_f64().into()),
+                    ("change".into(), step.change.into()),
+                    ("rss".into(), step.rss.into()),
+                    ("line".into(), step.line.clone().into()),
+                ])
+            })
+            .collect(),
+    )
+}
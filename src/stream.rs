@@ -0,0 +1,103 @@
+//! Async access to a [`Monitor`], with the `async` feature
+//!
+//! The futures here only need a waker, so they run on tokio or any other
+//! executor without memwatch depending on one.
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+use crate::{
+    monitor::{
+        Monitor,
+        Shared,
+    },
+    record::{
+        RunSummary,
+        Sample,
+    },
+};
+
+impl Monitor {
+    /// Samples as they are taken, ending when the command exits
+    pub fn sample_stream(&self) -> SampleStream {
+        SampleStream {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Wait for the command to exit without blocking the executor
+    pub fn wait_with_summary(self) -> WaitSummary {
+        WaitSummary {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// Stream of the samples of a [`Monitor`]
+pub struct SampleStream {
+    shared: Arc<Shared>,
+}
+
+impl SampleStream {
+    /// Next sample; `None` once the command has exited and every sample
+    /// was taken
+    pub fn next_sample(&mut self) -> NextSample<'_> {
+        NextSample { stream: self }
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Sample>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(sample) = state.samples.pop_front() {
+            return Poll::Ready(Some(sample));
+        }
+        if state.finished {
+            return Poll::Ready(None);
+        }
+        state.wakers.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Future returned by [`SampleStream::next_sample`]
+pub struct NextSample<'a> {
+    stream: &'a mut SampleStream,
+}
+
+impl Future for NextSample<'_> {
+    type Output = Option<Sample>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.stream.poll_next(cx)
+    }
+}
+
+/// Future returned by [`Monitor::wait_with_summary`]
+pub struct WaitSummary {
+    shared: Arc<Shared>,
+}
+
+impl Future for WaitSummary {
+    type Output = io::Result<RunSummary>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.finished {
+            state.wakers.push(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(
+            state
+                .summary
+                .take()
+                .unwrap_or_else(|| Err(io::Error::other("summary already taken"))),
+        )
+    }
+}
@@ -0,0 +1,229 @@
+//! `memwatch stress`: memory use of a known shape, to point memwatch,
+//! dashboards, thresholds and cgroup limits at
+//!
+//! A pattern is a list of phases run one after the other, each taking the
+//! memory from where the previous one left it: `ramp:1G:60s` grows or
+//! shrinks it evenly to 1 GB over a minute, `step:512M` sets it at once,
+//! `spike:2G:5s` sets it for five seconds and goes back, and `hold:30s`
+//! keeps it. Memory is mapped in chunks of `CHUNK` with `mmap`, every page
+//! written to so that it is resident, and unmapped as it shrinks, so RSS
+//! follows the pattern to within a chunk and what is released goes back to
+//! the system rather than to an allocator.
+
+use std::{
+    io,
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    clock::{
+        self,
+        format_elapsed,
+        format_span,
+    },
+    size::{
+        self,
+        format_bytes,
+    },
+};
+
+/// Unit memory is mapped and unmapped in
+const CHUNK: u64 = 1024 * 1024;
+/// Pause between updates of a ramp
+const TICK: Duration = Duration::from_millis(10);
+/// Written to every page, so that none is left to the shared zero page
+const FILL: u8 = 0xa5;
+
+#[derive(clap::Args, Debug)]
+pub struct StressArgs {
+    /// Phases, comma-separated or repeated: ramp:SIZE:TIME, step:SIZE,
+    /// spike:SIZE:TIME or hold:TIME
+    #[arg(long, value_name = "PATTERN", required = true, value_parser = parse_pattern)]
+    pattern: Vec<Pattern>,
+
+    /// Start the pattern over once it ends, until Ctrl+C
+    #[arg(long = "loop")]
+    repeat: bool,
+
+    /// Print nothing as phases start
+    #[arg(long, short)]
+    quiet: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Pattern(Vec<Phase>);
+
+#[derive(Clone, Copy, Debug)]
+pub enum Phase {
+    /// Grow or shrink evenly to this size over this time
+    Ramp(u64, Duration),
+    /// Go to this size at once
+    Step(u64),
+    /// Go to this size for this time and back
+    Spike(u64, Duration),
+    Hold(Duration),
+}
+
+impl Phase {
+    fn describe(&self) -> String {
+        match self {
+            Phase::Ramp(size, time) => format!("ramp to {} over {}", format_bytes(*size), format_span(*time)),
+            Phase::Step(size) => format!("step to {}", format_bytes(*size)),
+            Phase::Spike(size, time) => format!("spike to {} for {}", format_bytes(*size), format_span(*time)),
+            Phase::Hold(time) => format!("hold for {}", format_span(*time)),
+        }
+    }
+}
+
+/// `ramp:1G:60s,hold:30s,step:0`
+pub fn parse_pattern(s: &str) -> Result<Pattern, String> {
+    s.split(',').map(parse_phase).collect::<Result<_, _>>().map(Pattern)
+}
+
+fn parse_phase(s: &str) -> Result<Phase, String> {
+    let fields: Vec<&str> = s.trim().split(':').collect();
+    match fields.as_slice() {
+        ["ramp", size, time] => Ok(Phase::Ramp(size::parse_size(size)?, clock::parse_duration(time)?)),
+        ["step", size] => Ok(Phase::Step(size::parse_size(size)?)),
+        ["spike", size, time] => Ok(Phase::Spike(size::parse_size(size)?, clock::parse_duration(time)?)),
+        ["hold", time] => Ok(Phase::Hold(clock::parse_duration(time)?)),
+        _ => Err(format!(
+            "invalid phase `{}`, expected ramp:SIZE:TIME, step:SIZE, spike:SIZE:TIME or hold:TIME",
+            s
+        )),
+    }
+}
+
+pub fn run(args: StressArgs, terminated: &AtomicBool) -> io::Result<()> {
+    let phases: Vec<Phase> = args.pattern.into_iter().flat_map(|pattern| pattern.0).collect();
+    let start = Instant::now();
+    let mut memory = Memory::default();
+    loop {
+        for phase in &phases {
+            if !args.quiet {
+                eprintln!("[{}] {}", format_elapsed(start.elapsed()), phase.describe());
+            }
+            if !play(*phase, &mut memory, terminated)? {
+                return Ok(());
+            }
+        }
+        if !args.repeat {
+            return Ok(());
+        }
+    }
+}
+
+/// Run `phase` on `memory`; false once interrupted
+fn play(phase: Phase, memory: &mut Memory, terminated: &AtomicBool) -> io::Result<bool> {
+    match phase {
+        Phase::Ramp(size, time) => {
+            let (from, start) = (memory.size() as f64, Instant::now());
+            loop {
+                let done = (start.elapsed().as_secs_f64() / time.as_secs_f64()).min(1.0);
+                memory.resize((from + (size as f64 - from) * done) as u64)?;
+                if done >= 1.0 {
+                    return Ok(true);
+                }
+                if !wait(TICK, terminated) {
+                    return Ok(false);
+                }
+            }
+        }
+        Phase::Step(size) => {
+            memory.resize(size)?;
+            Ok(true)
+        }
+        Phase::Spike(size, time) => {
+            let from = memory.size();
+            memory.resize(size)?;
+            let held = wait(time, terminated);
+            memory.resize(from)?;
+            Ok(held)
+        }
+        Phase::Hold(time) => Ok(wait(time, terminated)),
+    }
+}
+
+/// Sleep for `time`; false if interrupted before
+fn wait(time: Duration, terminated: &AtomicBool) -> bool {
+    let until = Instant::now() + time;
+    while !terminated.load(Ordering::SeqCst) {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        thread::sleep(left.min(Duration::from_millis(100)));
+    }
+    false
+}
+
+/// Resident anonymous memory of a chosen size
+#[derive(Default)]
+pub struct Memory {
+    chunks: Vec<Chunk>,
+}
+
+impl Memory {
+    pub fn size(&self) -> u64 {
+        self.chunks.len() as u64 * CHUNK
+    }
+
+    /// Map or unmap chunks until `size`, rounded up to a chunk, is resident
+    pub fn resize(&mut self, size: u64) -> io::Result<()> {
+        let chunks = size.div_ceil(CHUNK) as usize;
+        self.chunks.truncate(chunks);
+        while self.chunks.len() < chunks {
+            self.chunks.push(Chunk::new().map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("cannot grow to {}: {}", format_bytes(size), e),
+                )
+            })?);
+        }
+        Ok(())
+    }
+}
+
+/// `CHUNK` of anonymous memory, every page of it written to
+struct Chunk(*mut libc::c_void);
+
+impl Chunk {
+    fn new() -> io::Result<Self> {
+        // SAFETY: a fresh private anonymous mapping, not aliasing anything
+        let address = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                CHUNK as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: sysconf has no preconditions
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        for offset in (0..CHUNK as usize).step_by(page) {
+            // SAFETY: within the mapping, which is writable
+            unsafe { address.cast::<u8>().add(offset).write_volatile(FILL) };
+        }
+        Ok(Self(address))
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        // SAFETY: mapped in new and not unmapped since
+        unsafe { libc::munmap(self.0, CHUNK as usize) };
+    }
+}
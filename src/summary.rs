@@ -0,0 +1,450 @@
+//! Run statistics shared by the final report and notifications
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{
+        self,
+        Write,
+    },
+    mem::ManuallyDrop,
+    os::fd::FromRawFd,
+    path::Path,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use crate::{
+    churn::ChurnStats,
+    exit::{
+        signal_name,
+        ExitInfo,
+    },
+    extract::ExtractSeries,
+    floor::FloorStats,
+    histogram::Histogram,
+    json::Value,
+    jvm::JvmStats,
+    net::NetStats,
+    overhead::Overhead,
+    power::PowerStats,
+    redact,
+    runtime::RuntimeStats,
+    steps::{
+        self,
+        Step,
+    },
+    tree::{
+        Denied,
+        ProcessPeak,
+        ReadErrors,
+        TreeSample,
+    },
+    swap::SwapStats,
+    system_delta::SystemDeltaStats,
+    tmpfs::TmpfsStats,
+    watch_dir::DirSeries,
+    window::WindowStats,
+    working_set::WorkingSetStats,
+};
+use memwatch::record::{
+    self,
+    Environment,
+    RunSummary,
+};
+
+/// Memory statistics accumulated while the command runs
+pub struct Summary {
+    pub command: Vec<String>,
+    pub host: String,
+    /// Unique identifier of this run, for correlating exported data
+    pub run_id: String,
+    /// Wall-clock time the command started
+    pub started_at: Option<SystemTime>,
+    /// The machine, as it was at the start
+    pub environment: Environment,
+    pub peak_rss: u64,
+    pub peak_vsz: u64,
+    /// Largest sum of per-process `VmHWM` seen, the kernel's view of peak RSS
+    pub kernel_peak_rss: u64,
+    /// Largest sum of per-process `VmPeak` seen
+    pub kernel_peak_vsz: u64,
+    /// Largest number of processes alive in the tree at once
+    pub peak_processes: usize,
+    /// Distinct processes observed over the whole run
+    pub processes_seen: u64,
+    /// High-water mark of the command's cgroup, when run with `--cgroup`
+    pub cgroup_peak: Option<u64>,
+    /// Processes with the highest peak RSS, filled in at exit
+    pub top_processes: Vec<ProcessPeak>,
+    pub samples: u64,
+    /// Output lines lost because memwatch could not render them fast enough
+    pub output_lines_dropped: u64,
+    /// memwatch's own resource usage, with `--self-stats`
+    pub overhead: Option<Overhead>,
+    /// Values parsed out of the command's output with `--extract`
+    pub extracted: Vec<ExtractSeries>,
+    /// Java heap peaks, with `--jvm`
+    pub jvm: Option<JvmStats>,
+    /// Python and Node heap peaks, with `--runtime-heap`
+    pub runtime_heap: Option<RuntimeStats>,
+    /// Hot memory, with `--working-set`
+    pub working_set: Option<WorkingSetStats>,
+    /// Swapped and compressed memory, with `--swap`
+    pub swap: Option<SwapStats>,
+    /// MemAvailable against the tree's RSS, with `--system-delta`
+    pub system_delta: Option<SystemDeltaStats>,
+    /// Drops of MemAvailable below its floor, with `--system-floor`
+    pub system_floor: Option<FloorStats>,
+    /// Windows watched in and samples left out, with `--between`
+    pub between: Option<WindowStats>,
+    /// Files created on tmpfs, with `--tmpfs`
+    pub tmpfs: Option<TmpfsStats>,
+    /// Sizes of the directories given with `--watch-dir`
+    pub watched_dirs: Vec<DirSeries>,
+    /// Processes started and execs, with `--churn`
+    pub churn: Option<ChurnStats>,
+    /// Sockets and traffic, with `--net`
+    pub net: Option<NetStats>,
+    /// Energy used, with `--power`
+    pub power: Option<PowerStats>,
+    /// `/proc` files that could not be read at some point, so the figures
+    /// above leave those processes' share out
+    pub denied: Denied,
+    /// Failed `/proc` reads over the run
+    pub read_errors: ReadErrors,
+    /// Samples whose totals leave out memory that could not be read
+    pub incomplete_samples: u64,
+    /// Distribution of the tree's RSS over the samples
+    pub histogram: Histogram,
+    /// Anomalies found with `--detect-anomalies`
+    pub anomalies: u64,
+    /// Largest jumps in RSS, with `--detect-steps`
+    pub steps: Option<Vec<Step>>,
+    /// Sum of the tree's RSS over the samples, for the average
+    pub rss_total: u128,
+    /// Latest CPU time in clock ticks of every process seen, by PID and
+    /// start time
+    pub cpu_ticks: HashMap<(i32, u64), u64>,
+}
+
+impl Summary {
+    pub fn new(command: &[String]) -> Self {
+        Self {
+            command: redact::args(command),
+            host: record::hostname(),
+            run_id: record::new_run_id(),
+            started_at: Some(SystemTime::now()),
+            environment: Environment::collect(),
+            peak_rss: 0,
+            peak_vsz: 0,
+            kernel_peak_rss: 0,
+            kernel_peak_vsz: 0,
+            peak_processes: 0,
+            processes_seen: 0,
+            cgroup_peak: None,
+            top_processes: Vec::new(),
+            samples: 0,
+            output_lines_dropped: 0,
+            overhead: None,
+            extracted: Vec::new(),
+            jvm: None,
+            runtime_heap: None,
+            working_set: None,
+            swap: None,
+            system_delta: None,
+            system_floor: None,
+            between: None,
+            tmpfs: None,
+            watched_dirs: Vec::new(),
+            churn: None,
+            net: None,
+            power: None,
+            denied: Denied::default(),
+            read_errors: ReadErrors::default(),
+            incomplete_samples: 0,
+            histogram: Histogram::default(),
+            anomalies: 0,
+            steps: None,
+            rss_total: 0,
+            cpu_ticks: HashMap::new(),
+        }
+    }
+
+    /// Fold in the high-water mark the kernel reported for a process of
+    /// the tree that exited; it may have peaked between two samples, and
+    /// the tree's peak was at least as large
+    pub fn record_exit_peak(&mut self, peak_rss: u64) {
+        self.kernel_peak_rss = self.kernel_peak_rss.max(peak_rss);
+    }
+
+    /// Fold one sample of the process tree into the statistics
+    pub fn record(&mut self, sample: &TreeSample) {
+        self.peak_rss = self.peak_rss.max(sample.rss);
+        self.peak_vsz = self.peak_vsz.max(sample.vsz);
+        self.peak_processes = self.peak_processes.max(sample.processes.len());
+        self.histogram.record(sample.rss);
+        self.rss_total += u128::from(sample.rss);
+        for process in &sample.processes {
+            self.cpu_ticks.insert((process.pid, process.starttime), process.cpu_ticks);
+        }
+
+        // High-water marks are per process; their sum bounds the tree's peak
+        // from above but catches spikes between samples
+        let hwm: u64 = sample.processes.iter().map(|p| p.hwm).sum();
+        let vm_peak: u64 = sample.processes.iter().map(|p| p.vm_peak).sum();
+        self.kernel_peak_rss = self.kernel_peak_rss.max(hwm);
+        self.kernel_peak_vsz = self.kernel_peak_vsz.max(vm_peak);
+        self.denied = self.denied.union(sample.denied);
+        self.read_errors.add(sample.errors);
+        if sample.is_incomplete() {
+            self.incomplete_samples += 1;
+        }
+        self.samples += 1;
+    }
+
+    /// Mean RSS of the tree over the samples
+    pub fn avg_rss(&self) -> u64 {
+        (self.rss_total / u128::from(self.samples.max(1))) as u64
+    }
+
+    /// CPU time of the processes of the tree, as of their last sample
+    pub fn cpu_time(&self) -> Duration {
+        let ticks: u64 = self.cpu_ticks.values().sum();
+        Duration::from_secs_f64(ticks as f64 / procfs::ticks_per_second() as f64)
+    }
+
+    /// Fold a cgroup memory reading into the cgroup high-water mark
+    pub fn record_cgroup(&mut self, bytes: Option<u64>) {
+        if let Some(bytes) = bytes {
+            self.cgroup_peak = Some(self.cgroup_peak.unwrap_or(0).max(bytes));
+        }
+    }
+
+    /// Command line as a single shell-like string
+    pub fn command_line(&self) -> String {
+        record::command_line(&self.command)
+    }
+
+    /// Fields common to every machine-readable report
+    pub fn json_fields(&self, elapsed: Duration) -> Vec<(String, Value)> {
+        vec![
+            ("host".into(), self.host.as_str().into()),
+            ("command".into(), self.command_line().into()),
+            ("run_id".into(), self.run_id.as_str().into()),
+            ("started_at".into(), self.started_at.map(record::format_time).into()),
+            ("elapsed_secs".into(), elapsed.as_secs_f64().into()),
+            ("peak_rss".into(), self.peak_rss.into()),
+            ("peak_vsz".into(), self.peak_vsz.into()),
+            ("kernel_peak_rss".into(), self.kernel_peak_rss.into()),
+            ("kernel_peak_vsz".into(), self.kernel_peak_vsz.into()),
+            ("peak_processes".into(), (self.peak_processes as u64).into()),
+            ("processes_seen".into(), self.processes_seen.into()),
+            ("cgroup_peak".into(), self.cgroup_peak.into()),
+            ("samples".into(), self.samples.into()),
+        ]
+    }
+
+    /// Typed summary of the finished run
+    ///
+    /// `termination` names why memwatch stopped the command, if it did.
+    pub fn run_summary(
+        &self,
+        elapsed: Duration,
+        exit: &ExitInfo,
+        termination: Option<&str>,
+    ) -> RunSummary {
+        RunSummary {
+            host: self.host.clone(),
+            command: self.command_line(),
+            run_id: self.run_id.clone(),
+            started_at: self.started_at,
+            elapsed,
+            peak_rss: self.peak_rss,
+            peak_vsz: self.peak_vsz,
+            kernel_peak_rss: self.kernel_peak_rss,
+            kernel_peak_vsz: self.kernel_peak_vsz,
+            peak_processes: self.peak_processes as u64,
+            processes_seen: self.processes_seen,
+            cgroup_peak: self.cgroup_peak,
+            samples: self.samples,
+            exit_code: exit.code,
+            signal: exit.signal.map(signal_name),
+            core_dumped: exit.core_dumped,
+            termination: termination.map(String::from),
+            output_lines_dropped: self.output_lines_dropped,
+            top_processes: self.top_processes.clone(),
+            environment: Some(self.environment.clone()),
+        }
+    }
+
+    /// Final machine-readable summary of the run: the [`RunSummary`] and
+    /// the optional sections only the CLI fills in
+    pub fn report(
+        &self,
+        elapsed: Duration,
+        exit: &ExitInfo,
+        termination: Option<&str>,
+    ) -> Value {
+        let mut fields = self
+            .run_summary(elapsed, exit, termination)
+            .json_fields();
+        fields.push((
+            "self_stats".into(),
+            self.overhead
+                .as_ref()
+                .map(Overhead::json)
+                .unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "extracted".into(),
+            Value::Array(self.extracted.iter().map(ExtractSeries::json).collect()),
+        ));
+        fields.push((
+            "jvm".into(),
+            self.jvm.as_ref().map(JvmStats::json).unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "runtime_heap".into(),
+            self.runtime_heap
+                .as_ref()
+                .map(RuntimeStats::json)
+                .unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "working_set".into(),
+            self.working_set
+                .as_ref()
+                .map(WorkingSetStats::json)
+                .unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "swap".into(),
+            self.swap.as_ref().map(SwapStats::json).unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "system_delta".into(),
+            self.system_delta
+                .as_ref()
+                .map(SystemDeltaStats::json)
+                .unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "system_floor".into(),
+            self.system_floor
+                .as_ref()
+                .map(FloorStats::json)
+                .unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "between".into(),
+            self.between.as_ref().map(WindowStats::json).unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "tmpfs".into(),
+            self.tmpfs.as_ref().map(TmpfsStats::json).unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "watched_dirs".into(),
+            Value::Array(self.watched_dirs.iter().map(DirSeries::json).collect()),
+        ));
+        fields.push((
+            "churn".into(),
+            self.churn.as_ref().map(ChurnStats::json).unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "net".into(),
+            self.net.as_ref().map(NetStats::json).unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "power".into(),
+            self.power.as_ref().map(PowerStats::json).unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "read_errors".into(),
+            Value::Object(vec![
+                ("gone".into(), self.read_errors.gone.into()),
+                ("denied".into(), self.read_errors.denied.into()),
+                ("transient".into(), self.read_errors.transient.into()),
+                ("incomplete_samples".into(), self.incomplete_samples.into()),
+            ]),
+        ));
+        fields.push(("anomalies".into(), self.anomalies.into()));
+        fields.push((
+            "steps".into(),
+            self.steps.as_deref().map(steps::json).unwrap_or(Value::Null),
+        ));
+        fields.push((
+            "unreadable".into(),
+            Value::Array(self.denied.files().into_iter().map(Value::from).collect()),
+        ));
+        Value::Object(fields)
+    }
+}
+
+/// Write a JSON document followed by a newline to `path`
+pub fn write_file(path: &Path, value: &Value) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", value)
+}
+
+/// `--summary-fd`: a descriptor that must be open at startup, so a typo
+/// fails now rather than at the end of the run
+///
+/// Other than stdout and stderr, which the command shares on purpose, it
+/// is closed on exec: the command could write into it, or hold it open
+/// so a reader waits for EOF until every descendant exits.
+pub fn parse_fd(s: &str) -> Result<i32, String> {
+    let fd: i32 = s.parse().map_err(|_| format!("'{}' is not a file descriptor", s))?;
+    // SAFETY: F_GETFD only inspects the descriptor table
+    let flags = if fd < 0 { -1 } else { unsafe { libc::fcntl(fd, libc::F_GETFD) } };
+    if flags == -1 {
+        return Err(format!("file descriptor {} is not open", fd));
+    }
+    // SAFETY: only sets a flag of a descriptor checked to be open
+    if fd > 2 && unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+        return Err(format!("file descriptor {}: {}", fd, io::Error::last_os_error()));
+    }
+    Ok(fd)
+}
+
+/// Write a JSON document followed by a newline to an inherited descriptor
+pub fn write_fd(fd: i32, value: &Value) -> io::Result<()> {
+    // SAFETY: F_GETFD only inspects the descriptor table
+    if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "file descriptor is not open",
+        ));
+    }
+    // SAFETY: the descriptor is open and handed to us by the caller; it is
+    // left open, as it may be stdout or stderr, which the rest of the run
+    // still writes to
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    writeln!(file, "{}", value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::AsRawFd;
+
+    use super::*;
+
+    #[test]
+    fn summary_fd_must_be_open_and_is_kept_from_children() {
+        assert!(parse_fd("three").is_err());
+        assert!(parse_fd("-1").is_err());
+        assert_eq!(parse_fd("999999"), Err("file descriptor 999999 is not open".into()));
+
+        let file = File::open("/dev/null").unwrap();
+        let fd = file.as_raw_fd();
+        // SAFETY: fd is open for as long as file is
+        unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+        assert_eq!(parse_fd(&fd.to_string()), Ok(fd));
+        // SAFETY: as above
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+    }
+}
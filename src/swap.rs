@@ -0,0 +1,182 @@
+//! `--swap`: what the tree has swapped out, and compressed swap in RAM
+//!
+//! With zswap or zram, much of what is swapped out stays in memory in
+//! compressed form, so RSS alone understates how much memory a run takes.
+//! The tree's swap is the `VmSwap` of its processes. zswap (`Zswap` and
+//! `Zswapped` in `/proc/meminfo`) and zram (`mm_stat` of every
+//! `/sys/block/zram*`) are system-wide: their growth during the run is the
+//! share others had to make room for, the command's included.
+
+use std::fs;
+
+use crate::{
+    json::Value,
+    size::format_bytes,
+    tree::TreeSample,
+};
+
+/// Compressed swap of one kind
+#[derive(Clone, Copy)]
+struct Compressed {
+    /// Uncompressed size of the pages held
+    stored: u64,
+    /// RAM taken to hold them
+    used: u64,
+}
+
+impl Compressed {
+    fn status(&self, name: &str) -> String {
+        format!(
+            "{}: {} in {}",
+            name,
+            format_bytes(self.stored),
+            format_bytes(self.used)
+        )
+    }
+}
+
+/// `Zswapped` and `Zswap` of `/proc/meminfo`, on kernels that have them
+fn read_zswap() -> Option<Compressed> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let kb = |name: &str| {
+        meminfo.lines().find_map(|line| {
+            let value = line.strip_prefix(name)?.strip_prefix(':')?;
+            value.trim().trim_end_matches("kB").trim().parse::<u64>().ok()
+        })
+    };
+    Some(Compressed {
+        stored: kb("Zswapped")? * 1024,
+        used: kb("Zswap")? * 1024,
+    })
+}
+
+/// `orig_data_size` and `mem_used_total` of every zram device, summed
+fn read_zram() -> Option<Compressed> {
+    let mut total: Option<Compressed> = None;
+    for entry in fs::read_dir("/sys/block").ok()?.flatten() {
+        if !entry.file_name().to_string_lossy().starts_with("zram") {
+            continue;
+        }
+        let Ok(stat) = fs::read_to_string(entry.path().join("mm_stat")) else {
+            continue;
+        };
+        let fields: Vec<u64> = stat.split_whitespace().filter_map(|f| f.parse().ok()).collect();
+        if let (Some(&stored), Some(&used)) = (fields.first(), fields.get(2)) {
+            let total = total.get_or_insert(Compressed { stored: 0, used: 0 });
+            total.stored += stored;
+            total.used += used;
+        }
+    }
+    total
+}
+
+/// Peaks of one kind of compressed swap
+#[derive(Clone, Copy)]
+pub struct CompressedStats {
+    /// Uncompressed size held at the start of the run
+    pub stored_start: u64,
+    pub stored_peak: u64,
+    pub used_peak: u64,
+}
+
+impl CompressedStats {
+    fn new(at_start: Compressed) -> Self {
+        Self {
+            stored_start: at_start.stored,
+            stored_peak: at_start.stored,
+            used_peak: at_start.used,
+        }
+    }
+
+    fn record(&mut self, now: Compressed) {
+        self.stored_peak = self.stored_peak.max(now.stored);
+        self.used_peak = self.used_peak.max(now.used);
+    }
+
+    fn json(&self) -> Value {
+        Value::Object(vec![
+            ("stored_start".into(), self.stored_start.into()),
+            ("stored_peak".into(), self.stored_peak.into()),
+            ("used_peak".into(), self.used_peak.into()),
+        ])
+    }
+
+    /// `zram: peak 1.20 GB in 310.00 MB (+800.00 MB during the run)`
+    pub fn format(&self, name: &str) -> String {
+        format!(
+            "{}: peak {} in {} (+{} during the run)",
+            name,
+            format_bytes(self.stored_peak),
+            format_bytes(self.used_peak),
+            format_bytes(self.stored_peak.saturating_sub(self.stored_start))
+        )
+    }
+}
+
+/// Reported in the summary
+pub struct SwapStats {
+    /// Largest swap of the tree's processes, summed
+    pub peak_swap: u64,
+    pub zswap: Option<CompressedStats>,
+    pub zram: Option<CompressedStats>,
+}
+
+impl SwapStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("peak_swap".into(), self.peak_swap.into()),
+            ("zswap".into(), self.zswap.as_ref().map(CompressedStats::json).unwrap_or(Value::Null)),
+            ("zram".into(), self.zram.as_ref().map(CompressedStats::json).unwrap_or(Value::Null)),
+        ])
+    }
+}
+
+pub struct SwapProbe {
+    swap: u64,
+    zswap: Option<Compressed>,
+    zram: Option<Compressed>,
+    stats: SwapStats,
+}
+
+impl SwapProbe {
+    /// Start out from the compressed swap held now
+    pub fn start() -> Self {
+        let (zswap, zram) = (read_zswap(), read_zram());
+        Self {
+            swap: 0,
+            zswap,
+            zram,
+            stats: SwapStats {
+                peak_swap: 0,
+                zswap: zswap.map(CompressedStats::new),
+                zram: zram.map(CompressedStats::new),
+            },
+        }
+    }
+
+    pub fn update(&mut self, sample: &TreeSample) {
+        self.swap = sample.processes.iter().map(|p| p.swap).sum();
+        self.stats.peak_swap = self.stats.peak_swap.max(self.swap);
+        self.zswap = read_zswap();
+        self.zram = read_zram();
+        if let (Some(stats), Some(now)) = (&mut self.stats.zswap, self.zswap) {
+            stats.record(now);
+        }
+        if let (Some(stats), Some(now)) = (&mut self.stats.zram, self.zram) {
+            stats.record(now);
+        }
+    }
+
+    /// The tree's swap, then compressed swap in use, for the status line
+    pub fn status(&self) -> Vec<String> {
+        let mut columns = vec![format!("Swap: {}", format_bytes(self.swap))];
+        // Only once something is held, for systems that have them at all
+        columns.extend(self.zswap.filter(|z| z.stored > 0).map(|z| z.status("zswap")));
+        columns.extend(self.zram.filter(|z| z.stored > 0).map(|z| z.status("zram")));
+        columns
+    }
+
+    pub fn finish(self) -> SwapStats {
+        self.stats
+    }
+}
@@ -0,0 +1,199 @@
+//! `--system-delta`: memory the system lost that the tree's RSS does not
+//! account for
+//!
+//! Kernel slab allocated on the tree's behalf, files it writes to tmpfs and
+//! shared memory it leaves behind are in no process's RSS. memwatch reads
+//! `MemAvailable` at the start and at every sample, and compares how far it
+//! fell with how far the tree's RSS grew; a gap well beyond that is
+//! flagged, with the growth of `Slab` and `Shmem` that may explain it.
+//! Everything else running on the machine moves `MemAvailable` too, so the
+//! check is meant for otherwise quiet machines.
+
+use std::{
+    fs,
+    io,
+};
+
+use crate::{
+    json::Value,
+    size::format_bytes,
+};
+
+/// A gap is flagged once it is past both of these; the share is of the
+/// tree's peak RSS
+const UNEXPLAINED_MIN: i64 = 128 * 1024 * 1024;
+const UNEXPLAINED_SHARE: f64 = 0.25;
+
+/// Fields of `/proc/meminfo`, in bytes
+#[derive(Clone, Copy, Default)]
+struct Meminfo {
+    available: u64,
+    slab: u64,
+    shmem: u64,
+}
+
+impl Meminfo {
+    fn read() -> io::Result<Self> {
+        let meminfo = fs::read_to_string("/proc/meminfo")?;
+        let kb = |name: &str| {
+            meminfo.lines().find_map(|line| {
+                let value = line.strip_prefix(name)?.strip_prefix(':')?;
+                value.trim().trim_end_matches("kB").trim().parse::<u64>().ok()
+            })
+        };
+        let available = kb("MemAvailable").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "--system-delta needs MemAvailable in /proc/meminfo (Linux 3.14)",
+            )
+        })?;
+        Ok(Self {
+            available: available * 1024,
+            slab: kb("Slab").unwrap_or(0) * 1024,
+            shmem: kb("Shmem").unwrap_or(0) * 1024,
+        })
+    }
+}
+
+/// Difference of two sizes, negative when memory was given back
+fn growth(from: u64, to: u64) -> i64 {
+    to as i64 - from as i64
+}
+
+/// `+1.20 GB` or `-300.00 MB`
+fn format_signed(bytes: i64) -> String {
+    let sign = if bytes < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_bytes(bytes.unsigned_abs()))
+}
+
+/// The widest gap seen
+#[derive(Clone)]
+pub struct Gap {
+    pub unexplained: i64,
+    pub slab_growth: i64,
+    pub shmem_growth: i64,
+    /// Clock of the sample, as on the status line
+    pub stamp: String,
+}
+
+impl Gap {
+    /// `1.90 GB unexplained at [01:20] (slab +1.20 GB, shmem +600.00 MB)`
+    fn format(&self) -> String {
+        format!(
+            "{} unexplained at {} (slab {}, shmem {})",
+            format_bytes(self.unexplained.unsigned_abs()),
+            self.stamp,
+            format_signed(self.slab_growth),
+            format_signed(self.shmem_growth)
+        )
+    }
+}
+
+/// Reported in the summary
+pub struct SystemDeltaStats {
+    pub available_start: u64,
+    pub available_end: u64,
+    /// Growth of the tree's RSS from the first sample to its peak
+    pub rss_growth: i64,
+    pub widest: Option<Gap>,
+    /// The widest gap was past the thresholds
+    pub flagged: bool,
+}
+
+impl SystemDeltaStats {
+    pub fn json(&self) -> Value {
+        let widest = self.widest.as_ref();
+        Value::Object(vec![
+            ("available_start".into(), self.available_start.into()),
+            ("available_end".into(), self.available_end.into()),
+            ("rss_growth".into(), self.rss_growth.into()),
+            ("unexplained_peak".into(), widest.map_or(0, |gap| gap.unexplained.max(0)).into()),
+            ("slab_growth".into(), widest.map_or(0, |gap| gap.slab_growth).into()),
+            ("shmem_growth".into(), widest.map_or(0, |gap| gap.shmem_growth).into()),
+            ("flagged".into(), self.flagged.into()),
+        ])
+    }
+
+    /// For the end of the run, e.g. `System: MemAvailable 14.20 GB → 12.00 GB
+    /// (-2.20 GB) | tree RSS +300.00 MB | 1.90 GB unexplained at ...`
+    pub fn format(&self) -> String {
+        let mut line = format!(
+            "System: MemAvailable {} → {} ({}) | tree RSS {}",
+            format_bytes(self.available_start),
+            format_bytes(self.available_end),
+            format_signed(growth(self.available_start, self.available_end)),
+            format_signed(self.rss_growth)
+        );
+        if let Some(gap) = self.widest.as_ref().filter(|gap| gap.unexplained > 0) {
+            line.push_str(&format!(" | {}", gap.format()));
+            if self.flagged {
+                line.push_str(", outside process RSS");
+            }
+        }
+        line
+    }
+}
+
+pub struct SystemDelta {
+    start: Meminfo,
+    /// RSS of the tree at the first sample, part of what was in use at
+    /// the start for an attached process
+    first_rss: Option<u64>,
+    peak_rss: u64,
+    widest: Option<Gap>,
+    flagged: bool,
+}
+
+impl SystemDelta {
+    /// Take the starting reading, before the command starts
+    pub fn start() -> io::Result<Self> {
+        Ok(Self {
+            start: Meminfo::read()?,
+            first_rss: None,
+            peak_rss: 0,
+            widest: None,
+            flagged: false,
+        })
+    }
+
+    /// Compare with the tree's RSS at a sample; returns a notice the first
+    /// time the gap is flagged
+    pub fn update(&mut self, rss: u64, stamp: &str) -> Option<String> {
+        let now = Meminfo::read().ok()?;
+        let first_rss = *self.first_rss.get_or_insert(rss);
+        self.peak_rss = self.peak_rss.max(rss);
+        let fell = growth(now.available, self.start.available);
+        let gap = Gap {
+            unexplained: fell - growth(first_rss, rss),
+            slab_growth: growth(self.start.slab, now.slab),
+            shmem_growth: growth(self.start.shmem, now.shmem),
+            stamp: stamp.to_string(),
+        };
+        if self.widest.as_ref().is_some_and(|widest| widest.unexplained >= gap.unexplained) {
+            return None;
+        }
+        let limit = UNEXPLAINED_MIN.max((self.peak_rss as f64 * UNEXPLAINED_SHARE) as i64);
+        let notice = (gap.unexplained > limit && !std::mem::replace(&mut self.flagged, true)).then(|| {
+            format!(
+                "memwatch: MemAvailable fell by {} more than the tree's RSS grew (slab {}, shmem {})",
+                format_bytes(gap.unexplained.unsigned_abs()),
+                format_signed(gap.slab_growth),
+                format_signed(gap.shmem_growth)
+            )
+        });
+        self.widest = Some(gap);
+        notice
+    }
+
+    /// Take the ending reading, once the command is done
+    pub fn finish(self) -> SystemDeltaStats {
+        let end = Meminfo::read().unwrap_or_default();
+        SystemDeltaStats {
+            available_start: self.start.available,
+            available_end: end.available,
+            rss_growth: growth(self.first_rss.unwrap_or(0), self.peak_rss),
+            widest: self.widest,
+            flagged: self.flagged,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! The process memwatch watches: a command it started, or one it attached
+//! to with `memwatch attach`
+
+use std::{
+    io,
+    process::Child,
+};
+
+use procfs::{
+    process::Process,
+    ProcError,
+};
+
+use crate::exit::ExitInfo;
+
+pub enum Target {
+    Child(Child),
+    /// A process memwatch did not start, so cannot wait for
+    Attached {
+        pid: i32,
+        /// Start time in clock ticks after boot, to tell a reused PID apart;
+        /// unknown when its `stat` is not readable
+        starttime: Option<u64>,
+    },
+}
+
+impl Target {
+    /// Attach to a running process
+    pub fn attach(pid: i32) -> io::Result<Self> {
+        let starttime = match Process::new(pid).and_then(|process| process.stat()) {
+            Ok(stat) if stat.state == 'Z' => {
+                return Err(io::Error::other(format!("process {} has already exited", pid)));
+            }
+            Ok(stat) => Some(stat.starttime),
+            // A process of another user under `hidepid`; still watchable
+            // with whatever else is readable
+            Err(ProcError::PermissionDenied(_)) if exists(pid) => None,
+            Err(e) => {
+                return Err(io::Error::other(format!("cannot attach to process {}: {}", pid, e)));
+            }
+        };
+        Ok(Self::Attached { pid, starttime })
+    }
+
+    pub fn pid(&self) -> i32 {
+        match self {
+            Self::Child(child) => child.id() as i32,
+            Self::Attached { pid, .. } => *pid,
+        }
+    }
+
+    pub fn is_child(&self) -> bool {
+        matches!(self, Self::Child(_))
+    }
+
+    /// How the process ended, once it has
+    ///
+    /// Only a child's exit status is known; an attached process that is
+    /// gone reports neither code nor signal.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitInfo>> {
+        match self {
+            Self::Child(child) => Ok(child.try_wait()?.map(ExitInfo::from_status)),
+            Self::Attached { pid, starttime } => {
+                let alive = match starttime {
+                    Some(starttime) => Process::new(*pid)
+                        .and_then(|process| process.stat())
+                        .is_ok_and(|stat| stat.starttime == *starttime && stat.state != 'Z'),
+                    None => exists(*pid),
+                };
+                Ok((!alive).then_some(ExitInfo {
+                    code: None,
+                    signal: None,
+                    core_dumped: false,
+                }))
+            }
+        }
+    }
+
+    /// SIGKILL the process
+    pub fn kill(&mut self) {
+        if let Self::Child(child) = self {
+            let _ = child.kill();
+        } else if let Ok(None) = self.try_wait() {
+            // SAFETY: sending a signal has no memory-safety implications
+            unsafe {
+                libc::kill(self.pid(), libc::SIGKILL);
+            }
+        }
+    }
+
+    /// Command line of the process, for reports
+    pub fn command_line(&self) -> Vec<String> {
+        let cmdline = Process::new(self.pid()).and_then(|process| process.cmdline());
+        match cmdline {
+            Ok(cmdline) if !cmdline.is_empty() => cmdline,
+            _ => vec![format!("pid {}", self.pid())],
+        }
+    }
+}
+
+/// Whether a process with this PID exists, readable or not
+fn exists(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks that the process could be signalled
+    let sent = unsafe { libc::kill(pid, 0) } == 0;
+    sent || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
@@ -0,0 +1,288 @@
+//! Exit statistics from the kernel taskstats netlink interface
+//!
+//! Every exiting task reports its exact RSS high-water mark, which lets us
+//! attribute true per-process peaks even to processes shorter than one
+//! sampling interval. Registering for exit events requires CAP_NET_ADMIN.
+
+use std::{
+    io,
+    mem,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+const NETLINK_GENERIC: libc::c_int = 16;
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_ACK: u16 = 4;
+
+const TASKSTATS_CMD_GET: u8 = 1;
+const TASKSTATS_CMD_ATTR_REGISTER_CPUMASK: u16 = 3;
+const TASKSTATS_TYPE_PID: u16 = 1;
+const TASKSTATS_TYPE_STATS: u16 = 3;
+const TASKSTATS_TYPE_AGGR_PID: u16 = 4;
+
+const NLMSG_HDRLEN: usize = 16;
+const GENL_HDRLEN: usize = 4;
+
+// Offsets into `struct taskstats` (linux/taskstats.h), stable since v1
+const TS_COMM: usize = 80;
+const TS_COMM_LEN: usize = 32;
+const TS_PPID: usize = 132;
+const TS_ETIME: usize = 144;
+const TS_HIWATER_RSS: usize = 200;
+// Available from taskstats version 12
+const TS_TGID: usize = 368;
+
+/// Final accounting of one exited process
+pub struct ExitRecord {
+    pub pid: i32,
+    pub ppid: i32,
+    pub name: String,
+    /// Exact high-water RSS in bytes
+    pub peak_rss: u64,
+    /// Wall-clock lifetime
+    pub lifetime: Duration,
+}
+
+/// Subscribe to exit events on all CPUs and forward them from a background thread
+pub fn listen() -> io::Result<mpsc::Receiver<ExitRecord>> {
+    let socket = Socket::open()?;
+    let family = socket.resolve_family("TASKSTATS")?;
+
+    let online = std::fs::read_to_string("/sys/devices/system/cpu/online")
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|_| {
+            let cpus = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+            format!("0-{}", cpus - 1)
+        });
+    let mask = format!("{}\0", online);
+    socket.request(
+        family,
+        TASKSTATS_CMD_GET,
+        &[(TASKSTATS_CMD_ATTR_REGISTER_CPUMASK, mask.as_bytes())],
+    )?;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                // ENOBUFS means events were dropped under load; keep going
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => continue,
+                Err(_) => break,
+            };
+            for (msg_type, payload) in messages(&buf[..len]) {
+                if msg_type != family || payload.len() < GENL_HDRLEN {
+                    continue;
+                }
+                for (attr, value) in attributes(&payload[GENL_HDRLEN..]) {
+                    if attr != TASKSTATS_TYPE_AGGR_PID {
+                        continue;
+                    }
+                    if let Some(record) = parse_aggr_pid(value) {
+                        if tx.send(record).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+fn parse_aggr_pid(nested: &[u8]) -> Option<ExitRecord> {
+    let mut pid = None;
+    let mut stats = None;
+    for (attr, value) in attributes(nested) {
+        match attr {
+            TASKSTATS_TYPE_PID => pid = read_u32(value, 0).map(|v| v as i32),
+            TASKSTATS_TYPE_STATS => stats = Some(value),
+            _ => {}
+        }
+    }
+    let (pid, stats) = (pid?, stats?);
+
+    // Only the main thread stands for the process; other threads share its
+    // memory and would be counted twice
+    if let Some(tgid) = read_u32(stats, TS_TGID) {
+        if tgid as i32 != pid {
+            return None;
+        }
+    }
+
+    let comm = stats.get(TS_COMM..TS_COMM + TS_COMM_LEN)?;
+    let name_len = comm.iter().position(|&b| b == 0).unwrap_or(comm.len());
+
+    Some(ExitRecord {
+        pid,
+        ppid: read_u32(stats, TS_PPID)? as i32,
+        name: String::from_utf8_lossy(&comm[..name_len]).into_owned(),
+        peak_rss: read_u64(stats, TS_HIWATER_RSS)?.saturating_mul(1024),
+        lifetime: Duration::from_micros(read_u64(stats, TS_ETIME)?),
+    })
+}
+
+struct Socket {
+    fd: libc::c_int,
+}
+
+impl Socket {
+    fn open() -> io::Result<Self> {
+        // SAFETY: plain socket syscalls on a descriptor we own
+        unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                NETLINK_GENERIC,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = Socket { fd };
+
+            let mut addr: libc::sockaddr_nl = mem::zeroed();
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            let rc = libc::bind(
+                fd,
+                (&addr as *const libc::sockaddr_nl).cast(),
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            );
+            if rc < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(socket)
+        }
+    }
+
+    fn resolve_family(&self, name: &str) -> io::Result<u16> {
+        let name = format!("{}\0", name);
+        let reply = self.request(
+            GENL_ID_CTRL,
+            CTRL_CMD_GETFAMILY,
+            &[(CTRL_ATTR_FAMILY_NAME, name.as_bytes())],
+        )?;
+        let family = attributes(reply.get(GENL_HDRLEN..).unwrap_or_default())
+            .find(|(attr, _)| *attr == CTRL_ATTR_FAMILY_ID)
+            .and_then(|(_, value)| read_u16(value, 0));
+        family.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "taskstats family not found"))
+    }
+
+    /// Send a generic netlink request and return the first non-ACK reply body
+    fn request(&self, msg_type: u16, cmd: u8, attrs: &[(u16, &[u8])]) -> io::Result<Vec<u8>> {
+        let mut msg = vec![0u8; NLMSG_HDRLEN];
+        msg.extend_from_slice(&[cmd, 1, 0, 0]);
+        for (attr, value) in attrs {
+            let len = 4 + value.len();
+            msg.extend_from_slice(&(len as u16).to_ne_bytes());
+            msg.extend_from_slice(&attr.to_ne_bytes());
+            msg.extend_from_slice(value);
+            msg.resize(align(msg.len()), 0);
+        }
+        let total = msg.len() as u32;
+        msg[0..4].copy_from_slice(&total.to_ne_bytes());
+        msg[4..6].copy_from_slice(&msg_type.to_ne_bytes());
+        msg[6..8].copy_from_slice(&(NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+
+        // SAFETY: `msg` is a valid buffer of `msg.len()` bytes
+        let rc = unsafe { libc::send(self.fd, msg.as_ptr().cast(), msg.len(), 0) };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut body = Vec::new();
+        let mut buf = vec![0u8; 8192];
+        loop {
+            let len = self.recv(&mut buf)?;
+            for (reply_type, payload) in messages(&buf[..len]) {
+                if reply_type == NLMSG_ERROR {
+                    // The error code is negated errno; zero acknowledges success
+                    let errno = read_u32(payload, 0).map(|v| v as i32).unwrap_or(0);
+                    if errno != 0 {
+                        return Err(io::Error::from_raw_os_error(-errno));
+                    }
+                    return Ok(body);
+                }
+                if body.is_empty() {
+                    body = payload.to_vec();
+                }
+            }
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: `buf` is a valid writable buffer of `buf.len()` bytes
+        let rc = unsafe { libc::recv(self.fd, buf.as_mut_ptr().cast(), buf.len(), 0) };
+        if rc < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(rc as usize)
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor is owned by this socket
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Iterate `(type, payload)` of netlink messages in a datagram
+fn messages(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let len = read_u32(buf, 0)? as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return None;
+        }
+        let msg_type = read_u16(buf, 4)?;
+        let payload = &buf[NLMSG_HDRLEN..len];
+        buf = buf.get(align(len)..).unwrap_or_default();
+        Some((msg_type, payload))
+    })
+}
+
+/// Iterate `(type, value)` of netlink attributes
+fn attributes(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let len = read_u16(buf, 0)? as usize;
+        if len < 4 || len > buf.len() {
+            return None;
+        }
+        // Strip NLA_F_NESTED / NLA_F_NET_BYTEORDER flags
+        let attr = read_u16(buf, 2)? & 0x3fff;
+        let value = &buf[4..len];
+        buf = buf.get(align(len)..).unwrap_or_default();
+        Some((attr, value))
+    })
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset + 2)?;
+    Some(u16::from_ne_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> Option<u64> {
+    let bytes = buf.get(offset..offset + 8)?;
+    Some(u64::from_ne_bytes(bytes.try_into().ok()?))
+}
@@ -0,0 +1,172 @@
+//! Thermal state between benchmark runs, for `--cooldown`
+//!
+//! A processor still hot from one run clocks down sooner in the next, which
+//! stretches its duration and shifts when its memory is touched. The
+//! temperature is the highest of the processor's thermal zones under
+//! `/sys/class/thermal` (all zones where none is named after the CPU), and
+//! throttling is counted by the `thermal_throttle` counters of the CPUs,
+//! which only Intel processors have.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    path::PathBuf,
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::clock;
+
+const THERMAL: &str = "/sys/class/thermal";
+
+/// How often the temperature is read while waiting
+const POLL: Duration = Duration::from_secs(1);
+
+/// Settled once this close to the temperature before the first run
+const MARGIN: f64 = 2.0;
+
+/// ... or once it fell less than `PLATEAU_DROP` over `PLATEAU`, as it will
+/// not get much cooler
+const PLATEAU: Duration = Duration::from_secs(10);
+const PLATEAU_DROP: f64 = 0.5;
+
+/// Longest wait for temperatures to settle
+const MAX_WAIT: Duration = Duration::from_secs(300);
+
+/// Zone types of the processor: `x86_pkg_temp`, `cpu-thermal`, `soc_thermal`
+const CPU_ZONES: [&str; 3] = ["pkg", "cpu", "soc"];
+
+/// `--cooldown`: a fixed pause, or wait for temperatures to settle
+#[derive(Clone, Copy, Debug)]
+pub enum Cooldown {
+    Pause(Duration),
+    Auto,
+}
+
+pub fn parse_cooldown(s: &str) -> Result<Cooldown, String> {
+    match s {
+        "auto" => Ok(Cooldown::Auto),
+        _ => clock::parse_duration(s).map(Cooldown::Pause),
+    }
+}
+
+/// Thermal readings of one measured run
+#[derive(Clone, Copy, Default)]
+pub struct RunThermal {
+    /// Degrees Celsius as the run started
+    pub temperature: Option<f64>,
+    /// Throttling events during the run
+    pub throttled: Option<u64>,
+}
+
+pub struct Thermal {
+    zones: Vec<PathBuf>,
+}
+
+impl Thermal {
+    /// Find the zones to read; an error if there are none
+    pub fn open() -> io::Result<Self> {
+        let mut cpu = Vec::new();
+        let mut other = Vec::new();
+        let entries = fs::read_dir(THERMAL).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot read temperatures from {}: {}", THERMAL, e))
+        })?;
+        for entry in entries.flatten() {
+            if !entry.file_name().to_string_lossy().starts_with("thermal_zone") {
+                continue;
+            }
+            let path = entry.path().join("temp");
+            let kind = fs::read_to_string(entry.path().join("type")).unwrap_or_default();
+            if CPU_ZONES.iter().any(|name| kind.contains(name)) {
+                cpu.push(path);
+            } else {
+                other.push(path);
+            }
+        }
+        let zones = if cpu.is_empty() { other } else { cpu };
+        if zones.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no thermal zones in {}", THERMAL),
+            ));
+        }
+        Ok(Self { zones })
+    }
+
+    /// Highest temperature of the zones in degrees Celsius
+    pub fn temperature(&self) -> Option<f64> {
+        self.zones
+            .iter()
+            .filter_map(|path| fs::read_to_string(path).ok()?.trim().parse::<f64>().ok())
+            .map(|millidegrees| millidegrees / 1000.0)
+            .reduce(f64::max)
+    }
+
+    /// Wait until the temperature is back near `baseline` or stops
+    /// falling; returns the temperature reached, `None` if interrupted or
+    /// the zones could not be read
+    pub fn settle(&self, baseline: f64, terminated: &AtomicBool) -> Option<f64> {
+        let start = Instant::now();
+        // Readings within the last PLATEAU
+        let mut recent: Vec<(Instant, f64)> = Vec::new();
+        loop {
+            let now = Instant::now();
+            let temperature = self.temperature()?;
+            if temperature <= baseline + MARGIN || now.duration_since(start) >= MAX_WAIT {
+                return Some(temperature);
+            }
+            recent.retain(|&(at, _)| now.duration_since(at) <= PLATEAU);
+            if let Some(&(oldest, before)) = recent.first() {
+                if now.duration_since(oldest) >= PLATEAU - POLL && before - temperature < PLATEAU_DROP {
+                    return Some(temperature);
+                }
+            }
+            recent.push((now, temperature));
+            if !pause(POLL, terminated) {
+                return None;
+            }
+        }
+    }
+}
+
+/// Sleep for `duration` unless interrupted; false if interrupted
+pub fn pause(duration: Duration, terminated: &AtomicBool) -> bool {
+    let until = Instant::now() + duration;
+    while !terminated.load(Ordering::SeqCst) {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        thread::sleep(left.min(Duration::from_millis(100)));
+    }
+    false
+}
+
+/// Thermal throttling events so far: those of every core, and those of
+/// every package once although each of its CPUs shows them
+pub fn throttle_count() -> Option<u64> {
+    let read = |path: PathBuf| fs::read_to_string(path).ok()?.trim().parse::<u64>().ok();
+    let mut cores = None;
+    let mut packages = HashMap::new();
+    for entry in fs::read_dir("/sys/devices/system/cpu").ok()?.flatten() {
+        let dir = entry.path().join("thermal_throttle");
+        if let Some(count) = read(dir.join("core_throttle_count")) {
+            *cores.get_or_insert(0) += count;
+        }
+        if let (Some(package), Some(count)) = (
+            read(entry.path().join("topology/physical_package_id")),
+            read(dir.join("package_throttle_count")),
+        ) {
+            packages.insert(package, count);
+        }
+    }
+    cores.map(|cores| cores + packages.values().sum::<u64>())
+}
@@ -0,0 +1,183 @@
+//! Threads of a process, to tell which thread pool is behind its growth
+//!
+//! `--threads-of <pid>` prints them and exits; `t` in `--tui` prints those
+//! of the largest process of the tree into the output pane. Each thread's
+//! CPU time and page faults come from `/proc/<pid>/task/<tid>/stat`. Its
+//! stack is the mapping that holds its stack pointer, from
+//! `/proc/<pid>/task/<tid>/syscall`; that takes the right to trace the
+//! process, so stacks are left blank for processes of other users. Threads
+//! named alike, such as `worker-1` and `worker-2`, are summed up as a pool.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io,
+    time::Duration,
+};
+
+use crate::size::format_bytes;
+
+/// Pools with at least this many threads are summed up
+const MIN_POOL: usize = 2;
+
+struct Thread {
+    tid: i32,
+    name: String,
+    state: char,
+    cpu: Duration,
+    minor_faults: u64,
+    major_faults: u64,
+    /// Size and resident part of the stack mapping, if it was found
+    stack: Option<(u64, u64)>,
+}
+
+/// A mapping of `smaps` and its resident bytes
+struct Mapping {
+    start: u64,
+    end: u64,
+    rss: u64,
+}
+
+/// Lines describing the threads of `pid`, largest CPU time first
+pub fn report(pid: i32) -> io::Result<Vec<String>> {
+    let name = fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read process {}: {}", pid, e)))?;
+    let mappings = read_mappings(pid);
+    let mut threads = Vec::new();
+    for entry in fs::read_dir(format!("/proc/{}/task", pid))?.flatten() {
+        let Some(tid) = entry.file_name().to_str().and_then(|tid| tid.parse().ok()) else {
+            continue;
+        };
+        // Threads exit while they are listed
+        if let Some(thread) = read_thread(pid, tid, &mappings) {
+            threads.push(thread);
+        }
+    }
+    threads.sort_by(|a, b| b.cpu.cmp(&a.cpu).then(a.tid.cmp(&b.tid)));
+
+    let mut lines = vec![
+        format!("Threads of {} ({}): {}", pid, name.trim(), threads.len()),
+        format!(
+            "  {:>7}  {:<16} {:>1} {:>9} {:>10} {:>10} {:>9} {:>6}",
+            "TID", "NAME", "S", "CPU", "STACK", "RESIDENT", "MINFLT", "MAJFLT"
+        ),
+    ];
+    for thread in &threads {
+        let (size, rss) = match thread.stack {
+            Some((size, rss)) => (format_bytes(size), format_bytes(rss)),
+            None => (String::from("-"), String::from("-")),
+        };
+        lines.push(format!(
+            "  {:>7}  {:<16} {:>1} {:>8.2}s {:>10} {:>10} {:>9} {:>6}",
+            thread.tid,
+            thread.name,
+            thread.state,
+            thread.cpu.as_secs_f64(),
+            size,
+            rss,
+            thread.minor_faults,
+            thread.major_faults
+        ));
+    }
+
+    let mut pools: BTreeMap<String, Vec<&Thread>> = BTreeMap::new();
+    for thread in &threads {
+        pools.entry(pool_name(&thread.name)).or_default().push(thread);
+    }
+    let mut pools: Vec<_> = pools.into_iter().filter(|(_, threads)| threads.len() >= MIN_POOL).collect();
+    pools.sort_by_key(|(_, threads)| std::cmp::Reverse(threads.len()));
+    if !pools.is_empty() {
+        lines.push(String::from("Pools:"));
+    }
+    for (name, threads) in pools {
+        let cpu: Duration = threads.iter().map(|t| t.cpu).sum();
+        let stacks: Vec<(u64, u64)> = threads.iter().filter_map(|t| t.stack).collect();
+        let mut line = format!("  {} ×{}: CPU {:.2}s", name, threads.len(), cpu.as_secs_f64());
+        if !stacks.is_empty() {
+            line.push_str(&format!(
+                " | stacks {} ({} resident)",
+                format_bytes(stacks.iter().map(|s| s.0).sum()),
+                format_bytes(stacks.iter().map(|s| s.1).sum())
+            ));
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+fn read_thread(pid: i32, tid: i32, mappings: &[Mapping]) -> Option<Thread> {
+    let dir = format!("/proc/{}/task/{}", pid, tid);
+    let stat = fs::read_to_string(format!("{}/stat", dir)).ok()?;
+    // The name is in parentheses and may hold spaces of its own
+    let (head, rest) = stat.rsplit_once(')')?;
+    let name = head.split_once('(')?.1.to_string();
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let number = |index: usize| fields.get(index).and_then(|f| f.parse::<u64>().ok()).unwrap_or(0);
+    let ticks = procfs::ticks_per_second() as f64;
+
+    let stack = stack_pointer(&dir).and_then(|sp| {
+        mappings
+            .iter()
+            .find(|m| m.start <= sp && sp < m.end)
+            .map(|m| (m.end - m.start, m.rss))
+    });
+    Some(Thread {
+        tid,
+        name,
+        state: fields.first()?.chars().next()?,
+        cpu: Duration::from_secs_f64((number(11) + number(12)) as f64 / ticks),
+        minor_faults: number(7),
+        major_faults: number(9),
+        stack,
+    })
+}
+
+/// Stack pointer of a thread, the second to last field of `syscall`
+/// unless the thread is running
+fn stack_pointer(dir: &str) -> Option<u64> {
+    let syscall = fs::read_to_string(format!("{}/syscall", dir)).ok()?;
+    let fields: Vec<&str> = syscall.split_whitespace().collect();
+    if fields.len() < 3 {
+        return None;
+    }
+    let sp = fields[fields.len() - 2];
+    u64::from_str_radix(sp.trim_start_matches("0x"), 16).ok()
+}
+
+fn read_mappings(pid: i32) -> Vec<Mapping> {
+    let Ok(smaps) = fs::read_to_string(format!("/proc/{}/smaps", pid)) else {
+        return Vec::new();
+    };
+    let mut mappings: Vec<Mapping> = Vec::new();
+    for line in smaps.lines() {
+        if let Some(rss) = line.strip_prefix("Rss:") {
+            if let (Some(mapping), Ok(kb)) = (
+                mappings.last_mut(),
+                rss.trim().trim_end_matches("kB").trim().parse::<u64>(),
+            ) {
+                mapping.rss = kb * 1024;
+            }
+            continue;
+        }
+        let Some((range, _)) = line.split_once(' ') else {
+            continue;
+        };
+        let Some((start, end)) = range.split_once('-') else {
+            continue;
+        };
+        if let (Ok(start), Ok(end)) = (u64::from_str_radix(start, 16), u64::from_str_radix(end, 16)) {
+            mappings.push(Mapping { start, end, rss: 0 });
+        }
+    }
+    mappings
+}
+
+/// `worker-12` and `worker-3` are both `worker-#`
+fn pool_name(name: &str) -> String {
+    let stem = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    if stem.len() == name.len() {
+        name.to_string()
+    } else {
+        format!("{}#", stem)
+    }
+}
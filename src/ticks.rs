@@ -0,0 +1,73 @@
+//! Sampling schedule on a fixed grid
+//!
+//! Samples are due at `start + n * interval` rather than an interval after
+//! the previous one finished, so time spent sampling and rendering does
+//! not add up into drift and recorded timestamps stay evenly spaced.
+//! Changing the interval starts a new grid at that moment.
+
+use std::time::{
+    Duration,
+    Instant,
+};
+
+pub struct Ticks {
+    start: Instant,
+    /// Where the grid begins, since the start
+    origin: Duration,
+    interval: Duration,
+    /// Index of the current tick
+    tick: u32,
+    missed: u64,
+}
+
+impl Ticks {
+    pub fn new(start: Instant, interval: Duration) -> Self {
+        Self {
+            start,
+            origin: Duration::ZERO,
+            interval,
+            tick: 0,
+            missed: 0,
+        }
+    }
+
+    /// Time of the current tick on the grid, since the start
+    pub fn elapsed(&self) -> Duration {
+        self.origin + self.interval * self.tick
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Sample every `interval` from now on; the next tick is due an
+    /// `interval` from now
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.origin = self.start.elapsed();
+        self.interval = interval;
+        self.tick = 0;
+    }
+
+    /// Move on to the next tick and return when it is due
+    ///
+    /// Ticks whose time has already passed are skipped rather than sampled
+    /// in a burst to catch up; they are counted in [`Ticks::missed`].
+    pub fn advance(&mut self) -> Instant {
+        let mut next = self.tick + 1;
+        if !self.interval.is_zero() {
+            let since = self.start.elapsed().saturating_sub(self.origin);
+            let behind = (since.as_nanos() / self.interval.as_nanos()) as u32;
+            if behind >= next {
+                self.missed += u64::from(behind + 1 - next);
+                next = behind + 1;
+            }
+        }
+        self.tick = next;
+        self.start + self.elapsed()
+    }
+
+    /// Ticks skipped because sampling fell behind
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
@@ -0,0 +1,59 @@
+//! RSS of the tree over time, for reports and exports
+
+use std::time::Duration;
+
+/// One sample of the whole tree
+#[derive(Clone, Copy)]
+pub struct Point {
+    /// Time since the command started
+    pub at: Duration,
+    pub rss: u64,
+}
+
+#[derive(Default)]
+pub struct Timeline {
+    pub points: Vec<Point>,
+}
+
+impl Timeline {
+    pub fn record(&mut self, at: Duration, rss: u64) {
+        self.points.push(Point { at, rss });
+    }
+
+    /// Largest RSS in each of `buckets` equal slices of time; slices
+    /// without a sample repeat the previous one
+    pub fn rss_buckets(&self, buckets: usize) -> Vec<u64> {
+        let Some(last) = self.points.last() else {
+            return Vec::new();
+        };
+        self.rss_buckets_over(last.at, buckets)
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect()
+    }
+
+    /// [`Self::rss_buckets`] slicing `span`, which may outlast this
+    /// timeline so that runs of different lengths line up; slices after
+    /// the last sample are `None`
+    pub fn rss_buckets_over(&self, span: Duration, buckets: usize) -> Vec<Option<u64>> {
+        let Some(last) = self.points.last() else {
+            return vec![None; buckets];
+        };
+        let slice = |at: Duration| {
+            let i = (at.as_secs_f64() / span.as_secs_f64().max(f64::EPSILON) * buckets as f64) as usize;
+            i.min(buckets - 1)
+        };
+        let mut out: Vec<Option<u64>> = vec![None; buckets];
+        for point in &self.points {
+            let slot = &mut out[slice(point.at)];
+            *slot = Some(slot.unwrap_or(0).max(point.rss));
+        }
+        let end = slice(last.at);
+        let mut previous = 0;
+        for rss in &mut out[..=end] {
+            previous = rss.unwrap_or(previous);
+            *rss = Some(previous);
+        }
+        out
+    }
+}
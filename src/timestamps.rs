@@ -0,0 +1,65 @@
+//! `--timestamps`: which clocks each output format gives sample times in
+
+use crate::record::Timestamps;
+
+/// Output formats with a choice of timestamps
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    /// Samples in `--record`, `--exporter-cmd` and the JSON summary
+    Json,
+    /// Rows of `--extract-csv`
+    Csv,
+}
+
+/// One `FORMAT=KIND` setting
+#[derive(Clone, Copy, Debug)]
+pub struct Setting {
+    format: Format,
+    kind: Timestamps,
+}
+
+pub fn parse_setting(s: &str) -> Result<Setting, String> {
+    let (format, kind) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected FORMAT=KIND, got '{}'", s))?;
+    let format = match format {
+        "json" => Format::Json,
+        "csv" => Format::Csv,
+        _ => return Err(format!("unknown format '{}', expected json or csv", format)),
+    };
+    let kind = match kind {
+        "elapsed" => Timestamps::Elapsed,
+        "wall" => Timestamps::Wall,
+        "both" => Timestamps::Both,
+        _ => return Err(format!("unknown timestamps '{}', expected elapsed, wall or both", kind)),
+    };
+    if let (Format::Json, Timestamps::Wall) = (format, kind) {
+        return Err(String::from(
+            "JSON records always carry elapsed_secs; use json=both to add wall-clock time",
+        ));
+    }
+    Ok(Setting { format, kind })
+}
+
+/// Timestamps of every format, after the `--timestamps` settings
+#[derive(Clone, Copy, Debug)]
+pub struct OutputTimestamps {
+    pub json: Timestamps,
+    pub csv: Timestamps,
+}
+
+impl OutputTimestamps {
+    pub fn new(settings: &[Setting]) -> Self {
+        let mut timestamps = Self {
+            json: Timestamps::Both,
+            csv: Timestamps::Elapsed,
+        };
+        for setting in settings {
+            match setting.format {
+                Format::Json => timestamps.json = setting.kind,
+                Format::Csv => timestamps.csv = setting.kind,
+            }
+        }
+        timestamps
+    }
+}
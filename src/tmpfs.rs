@@ -0,0 +1,152 @@
+//! `--tmpfs`: files created on tmpfs during the run
+//!
+//! A file on tmpfs or `/dev/shm` lives in memory but in no process's RSS
+//! until it is mapped and touched, and it stays when its writer exits.
+//! The given directories are scanned at the start and about once a second
+//! after; files that were not there at the start count towards the tree's
+//! off-RSS footprint, by the memory they take (allocated blocks, so sparse
+//! files count what is written). Other programs creating files there in
+//! the meantime count too.
+
+use std::{
+    collections::HashSet,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    json::Value,
+    size::format_bytes,
+};
+
+/// Least time between two scans
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How deep below each directory files are looked for
+const MAX_DEPTH: usize = 8;
+
+/// Reported in the summary
+pub struct TmpfsStats {
+    pub peak: u64,
+    /// Files that made up the peak
+    pub peak_files: usize,
+    /// Still there after the command exited
+    pub left_behind: u64,
+}
+
+impl TmpfsStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("peak".into(), self.peak.into()),
+            ("peak_files".into(), (self.peak_files as u64).into()),
+            ("left_behind".into(), self.left_behind.into()),
+        ])
+    }
+
+    /// `tmpfs: peak 400.00 MB in 3 new files, 400.00 MB left behind`
+    pub fn format(&self) -> String {
+        format!(
+            "tmpfs: peak {} in {} new file{}, {} left behind",
+            format_bytes(self.peak),
+            self.peak_files,
+            if self.peak_files == 1 { "" } else { "s" },
+            format_bytes(self.left_behind)
+        )
+    }
+}
+
+pub struct TmpfsTracker {
+    dirs: Vec<PathBuf>,
+    /// Device and inode of the files there at the start
+    existing: HashSet<(u64, u64)>,
+    last_scan: Instant,
+    footprint: u64,
+    stats: TmpfsStats,
+}
+
+impl TmpfsTracker {
+    /// Take note of the files in `dirs` now, before the command starts
+    pub fn start(dirs: Vec<PathBuf>) -> Self {
+        let mut existing = HashSet::new();
+        for dir in &dirs {
+            scan(dir, MAX_DEPTH, &mut |key, _| {
+                existing.insert(key);
+            });
+        }
+        Self {
+            dirs,
+            existing,
+            last_scan: Instant::now(),
+            footprint: 0,
+            stats: TmpfsStats {
+                peak: 0,
+                peak_files: 0,
+                left_behind: 0,
+            },
+        }
+    }
+
+    /// Scan again if one is due
+    pub fn update(&mut self) {
+        if self.last_scan.elapsed() >= SCAN_INTERVAL {
+            self.scan();
+        }
+    }
+
+    fn scan(&mut self) {
+        self.last_scan = Instant::now();
+        let mut new = HashSet::new();
+        let mut footprint = 0;
+        for dir in &self.dirs {
+            scan(dir, MAX_DEPTH, &mut |key, bytes| {
+                // Hard links are counted once
+                if !self.existing.contains(&key) && new.insert(key) {
+                    footprint += bytes;
+                }
+            });
+        }
+        self.footprint = footprint;
+        if footprint > self.stats.peak {
+            self.stats.peak = footprint;
+            self.stats.peak_files = new.len();
+        }
+    }
+
+    /// Off-RSS footprint for the status line
+    pub fn status(&self) -> Vec<String> {
+        vec![format!("tmpfs: {}", format_bytes(self.footprint))]
+    }
+
+    /// Scan a last time, once the command is done
+    pub fn finish(mut self) -> TmpfsStats {
+        self.scan();
+        self.stats.left_behind = self.footprint;
+        self.stats
+    }
+}
+
+/// Call `found` with the device and inode and the allocated size of every
+/// regular file under `dir`; unreadable entries are skipped
+fn scan(dir: &Path, depth: usize, found: &mut impl FnMut((u64, u64), u64)) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_file() {
+            found((metadata.dev(), metadata.ino()), metadata.blocks() * 512);
+        } else if metadata.is_dir() && depth > 0 {
+            scan(&entry.path(), depth - 1, found);
+        }
+    }
+}
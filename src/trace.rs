@@ -0,0 +1,163 @@
+//! Chrome Trace Event export of the sampled timeline
+//!
+//! The JSON trace format opens in Perfetto UI, `chrome://tracing` and
+//! speedscope.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{
+        self,
+        BufWriter,
+        Write,
+    },
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::{
+    json::Value,
+    observer::Observer,
+    record::{
+        self,
+        RunSummary,
+        Sample,
+        SCHEMA_VERSION,
+    },
+    tree::ProcessEvent,
+};
+
+/// Track of the whole tree's totals
+const TREE_PID: i32 = 0;
+
+/// Collects per-process RSS counters and process lifetimes, written to
+/// `path` when the run ends
+pub struct TraceRecorder {
+    path: PathBuf,
+    events: Vec<Value>,
+    /// Last RSS written per process, to emit counters only on change
+    last_rss: HashMap<i32, u64>,
+    /// Processes alive: first seen at, name
+    open: HashMap<i32, (Duration, String)>,
+}
+
+impl Observer for TraceRecorder {
+    fn on_sample(&mut self, sample: &Sample) {
+        self.counter(sample.elapsed, TREE_PID, sample.rss);
+        for process in &sample.processes {
+            self.counter(sample.elapsed, process.pid, process.rss);
+        }
+    }
+
+    fn on_process(&mut self, at: Duration, event: &ProcessEvent) {
+        match event {
+            ProcessEvent::Started { pid, name } => {
+                self.open.insert(*pid, (at, name.clone()));
+                self.events.push(metadata(*pid, name));
+            }
+            ProcessEvent::Exited {
+                pid,
+                name,
+                lifetime,
+                ..
+            } => {
+                let started = self
+                    .open
+                    .remove(pid)
+                    .map(|(started, _)| started)
+                    .unwrap_or_else(|| at.saturating_sub(*lifetime));
+                self.slice(*pid, name, started, at);
+                self.last_rss.remove(pid);
+            }
+        }
+    }
+
+    fn on_exit(&mut self, summary: &RunSummary) {
+        if let Err(e) = self.write(summary) {
+            eprintln!("memwatch: failed to write trace to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl TraceRecorder {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            events: Vec::new(),
+            last_rss: HashMap::new(),
+            open: HashMap::new(),
+        }
+    }
+
+    /// Close the slices of processes still alive at the end of the run and
+    /// write the trace
+    fn write(&mut self, summary: &RunSummary) -> io::Result<()> {
+        let end = summary.elapsed;
+        for (pid, (started, name)) in std::mem::take(&mut self.open) {
+            self.slice(pid, &name, started, end);
+        }
+        self.events.push(metadata(TREE_PID, "process tree"));
+
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        // `otherData` is free-form metadata the viewers show alongside the
+        // trace; `started_at` maps its relative timestamps to wall-clock time
+        let other_data = Value::Object(vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("started_at".into(), summary.started_at.map(record::format_time).into()),
+        ]);
+        write!(
+            file,
+            "{{\"displayTimeUnit\":\"ms\",\"otherData\":{},\"traceEvents\":[",
+            other_data
+        )?;
+        for (i, event) in self.events.iter().enumerate() {
+            if i > 0 {
+                write!(file, ",")?;
+            }
+            write!(file, "\n{}", event)?;
+        }
+        writeln!(file, "\n]}}")?;
+        file.flush()
+    }
+
+    fn counter(&mut self, at: Duration, pid: i32, rss: u64) {
+        if self.last_rss.insert(pid, rss) == Some(rss) {
+            return;
+        }
+        self.events.push(Value::Object(vec![
+            ("name".into(), "RSS".into()),
+            ("ph".into(), "C".into()),
+            ("ts".into(), micros(at).into()),
+            ("pid".into(), pid.into()),
+            ("args".into(), Value::Object(vec![("bytes".into(), rss.into())])),
+        ]));
+    }
+
+    fn slice(&mut self, pid: i32, name: &str, started: Duration, ended: Duration) {
+        self.events.push(Value::Object(vec![
+            ("name".into(), name.into()),
+            ("ph".into(), "X".into()),
+            ("ts".into(), micros(started).into()),
+            ("dur".into(), micros(ended.saturating_sub(started)).into()),
+            ("pid".into(), pid.into()),
+            ("tid".into(), pid.into()),
+        ]));
+    }
+}
+
+/// Names the track of `pid` in trace viewers
+fn metadata(pid: i32, name: &str) -> Value {
+    Value::Object(vec![
+        ("name".into(), "process_name".into()),
+        ("ph".into(), "M".into()),
+        ("pid".into(), pid.into()),
+        (
+            "args".into(),
+            Value::Object(vec![("name".into(), format!("{} ({})", name, pid).into())]),
+        ),
+    ])
+}
+
+fn micros(d: Duration) -> u64 {
+    d.as_micros() as u64
+}
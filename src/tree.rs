@@ -0,0 +1,804 @@
+//! Process tree sampling and membership tracking
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fs,
+    io,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use procfs::{
+    process::{
+        all_processes,
+        Process,
+    },
+    ProcError,
+};
+
+use crate::taskstats::ExitRecord;
+
+/// Number of processes kept in the per-process peak ranking
+const TOP_PROCESSES: usize = 10;
+
+/// How long an exited PID still counts as a tree member for late exit records
+const EXITED_GRACE: Duration = Duration::from_secs(10);
+
+/// How often `--cheap` sampling rescans `/proc` for tree membership
+const MEMBERSHIP_REFRESH: Duration = Duration::from_secs(1);
+
+/// Kill passes made to catch processes forked while the tree is torn down
+const KILL_ROUNDS: usize = 20;
+
+/// Memory of a single process in the tree
+#[derive(Clone, Debug)]
+pub struct ProcessSample {
+    pub pid: i32,
+    /// Process start time in clock ticks since boot, distinguishes reused PIDs
+    pub starttime: u64,
+    pub name: String,
+    pub rss: u64,
+    /// Kernel-tracked RSS high-water mark (`VmHWM`)
+    pub hwm: u64,
+    /// Kernel-tracked virtual memory high-water mark (`VmPeak`)
+    pub vm_peak: u64,
+    /// CPU time used so far in clock ticks, user plus system
+    pub cpu_ticks: u64,
+    pub minor_faults: u64,
+    pub major_faults: u64,
+    /// Bytes swapped out (`VmSwap`), compressed or not
+    pub swap: u64,
+}
+
+/// One sample of the whole process tree
+#[derive(Clone, Default)]
+pub struct TreeSample {
+    pub rss: u64,
+    pub vsz: u64,
+    pub processes: Vec<ProcessSample>,
+    /// Files of tree processes that could not be read
+    pub denied: Denied,
+    /// Reads that failed while taking the sample
+    pub errors: ReadErrors,
+    /// The scan itself failed; the figures are those of the sample before
+    pub stale: bool,
+    /// Entries of `/proc` read by a full scan; none for cheap samples
+    pub scanned: usize,
+}
+
+impl TreeSample {
+    /// Figures may leave out memory memwatch could not read
+    pub fn is_incomplete(&self) -> bool {
+        self.stale || self.errors.denied > 0 || self.errors.transient > 0
+    }
+
+    /// This sample repeated in place of one whose scan failed with `error`
+    pub fn stale(&self, error: ReadError) -> Self {
+        let mut errors = ReadErrors::default();
+        errors.count(error);
+        Self {
+            errors,
+            stale: true,
+            scanned: 0,
+            ..self.clone()
+        }
+    }
+}
+
+/// Why reading `/proc` for a tree process failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadError {
+    /// The process exited between being listed and being read
+    Gone,
+    /// Not allowed to, see [`Denied`]
+    Denied,
+    /// Anything else, such as a truncated read; the next sample may succeed
+    Transient,
+}
+
+impl ReadError {
+    pub fn of(error: &ProcError) -> Self {
+        match error {
+            ProcError::NotFound(_) => Self::Gone,
+            ProcError::PermissionDenied(_) => Self::Denied,
+            ProcError::Io(error, _) => Self::of_io(error),
+            _ => Self::Transient,
+        }
+    }
+
+    fn of_io(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => Self::Gone,
+            io::ErrorKind::PermissionDenied => Self::Denied,
+            // Reading a file of a process that has just been reaped
+            _ if error.raw_os_error() == Some(libc::ESRCH) => Self::Gone,
+            _ => Self::Transient,
+        }
+    }
+}
+
+/// Failed reads by kind, of one sample or summed over a run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadErrors {
+    pub gone: u64,
+    pub denied: u64,
+    pub transient: u64,
+}
+
+impl ReadErrors {
+    pub fn count(&mut self, error: ReadError) {
+        match error {
+            ReadError::Gone => self.gone += 1,
+            ReadError::Denied => self.denied += 1,
+            ReadError::Transient => self.transient += 1,
+        }
+    }
+
+    pub fn add(&mut self, other: Self) {
+        self.gone += other.gone;
+        self.denied += other.denied;
+        self.transient += other.transient;
+    }
+
+    pub fn total(self) -> u64 {
+        self.gone + self.denied + self.transient
+    }
+}
+
+/// `/proc` files of tree processes memwatch is not allowed to read
+///
+/// Under a `hidepid` mount or a restrictive security module, processes of
+/// other users are only partly readable. Whatever can be read is still
+/// sampled; the fields the denied files carry are left at zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Denied {
+    /// `stat`: name, CPU time, page faults and child processes
+    pub stat: bool,
+    /// `statm`: RSS and VSZ
+    pub statm: bool,
+    /// `status`: the kernel's high-water marks
+    pub status: bool,
+}
+
+impl Denied {
+    pub fn any(self) -> bool {
+        self.stat || self.statm || self.status
+    }
+
+    /// Files denied in either
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            stat: self.stat || other.stat,
+            statm: self.statm || other.statm,
+            status: self.status || other.status,
+        }
+    }
+
+    /// Files denied here but not in `seen`
+    pub fn since(self, seen: Self) -> Self {
+        Self {
+            stat: self.stat && !seen.stat,
+            statm: self.statm && !seen.statm,
+            status: self.status && !seen.status,
+        }
+    }
+
+    /// Names of the denied files, e.g. `["statm", "status"]`
+    pub fn files(self) -> Vec<&'static str> {
+        [
+            (self.stat, "stat"),
+            (self.statm, "statm"),
+            (self.status, "status"),
+        ]
+        .into_iter()
+        .filter_map(|(denied, file)| denied.then_some(file))
+        .collect()
+    }
+
+    /// What the denied files would have told, for warnings
+    pub fn missing(self) -> Vec<&'static str> {
+        [
+            (self.stat, "process names, CPU time, page faults and child processes"),
+            (self.statm, "RSS and VSZ"),
+            (self.status, "kernel-reported peaks"),
+        ]
+        .into_iter()
+        .filter_map(|(denied, what)| denied.then_some(what))
+        .collect()
+    }
+}
+
+/// Processes a sample is taken of
+#[derive(Clone, Copy)]
+enum Scope<'a> {
+    /// A process and all of its descendants
+    Tree(i32),
+    /// Every process `select` takes, whatever its parent
+    Selected(&'a dyn Fn(&Process) -> bool),
+}
+
+/// Sample memory of `root_pid` and all of its descendants
+pub fn sample(root_pid: i32) -> procfs::ProcResult<TreeSample> {
+    scan(Scope::Tree(root_pid))
+}
+
+/// Sample memory of every process whose `/proc/<pid>` is owned by `uid`,
+/// which is its effective user
+pub fn sample_user(uid: u32) -> procfs::ProcResult<TreeSample> {
+    sample_selected(&|proc| proc.uid().is_ok_and(|owner| owner == uid))
+}
+
+/// Sample memory of every process `select` takes, without descendants of
+/// their own; memwatch itself is never taken
+pub fn sample_selected(select: &dyn Fn(&Process) -> bool) -> procfs::ProcResult<TreeSample> {
+    scan(Scope::Selected(select))
+}
+
+fn scan(scope: Scope) -> procfs::ProcResult<TreeSample> {
+    let page_size = procfs::page_size();
+
+    let mut children_map: HashMap<i32, Vec<i32>> = HashMap::new();
+    let mut stats = HashMap::new();
+    let mut stat_failed = HashMap::new();
+    let own_pid = std::process::id() as i32;
+    let mut owned = Vec::new();
+    let mut scanned = 0;
+    for proc in all_processes()?.flatten() {
+        scanned += 1;
+        if let Scope::Selected(select) = scope {
+            if proc.pid() != own_pid && select(&proc) {
+                owned.push(proc.pid());
+            }
+        }
+        match proc.stat() {
+            Ok(stat) => {
+                children_map.entry(stat.ppid).or_default().push(stat.pid);
+                let counters = Counters {
+                    cpu_ticks: stat.utime + stat.stime,
+                    minor_faults: stat.minflt,
+                    major_faults: stat.majflt,
+                };
+                stats.insert(stat.pid, (stat.starttime, stat.comm, counters));
+            }
+            // Without the parent PID, descendants of this process cannot be
+            // told apart; only a failed root is noticed below
+            Err(e) => {
+                stat_failed.insert(proc.pid(), ReadError::of(&e));
+            }
+        }
+    }
+
+    let mut total_rss = 0u64;
+    let mut total_vsz = 0u64;
+    let mut processes = Vec::new();
+    let mut denied = Denied::default();
+    let mut errors = ReadErrors::default();
+
+    let mut stack = match scope {
+        Scope::Tree(root_pid) => vec![root_pid],
+        Scope::Selected(_) => owned,
+    };
+
+    while let Some(pid) = stack.pop() {
+        if let Ok(proc) = Process::new(pid) {
+            let memory = match proc.statm() {
+                Ok(statm) => Some((statm.resident * page_size, statm.size * page_size)),
+                Err(e) => {
+                    let error = ReadError::of(&e);
+                    errors.count(error);
+                    denied.statm |= error == ReadError::Denied;
+                    // Unless it is gone, still a member whose memory is unknown
+                    (error != ReadError::Gone).then_some((0, 0))
+                }
+            };
+            if let Some((rss, vsz)) = memory {
+                total_vsz = total_vsz.saturating_add(vsz);
+                total_rss = total_rss.saturating_add(rss);
+
+                if let Some(&error) = stat_failed.get(&pid) {
+                    errors.count(error);
+                    denied.stat |= error == ReadError::Denied;
+                }
+                let (starttime, name, counters) = stats.remove(&pid).unwrap_or_default();
+                let (hwm, vm_peak, swap) = match proc.status() {
+                    Ok(status) => (status.vmhwm, status.vmpeak, status.vmswap),
+                    Err(e) => {
+                        let error = ReadError::of(&e);
+                        errors.count(error);
+                        denied.status |= error == ReadError::Denied;
+                        (None, None, None)
+                    }
+                };
+                processes.push(ProcessSample {
+                    pid,
+                    starttime,
+                    name,
+                    rss,
+                    hwm: hwm.unwrap_or(0) * 1024,
+                    vm_peak: vm_peak.unwrap_or(0) * 1024,
+                    cpu_ticks: counters.cpu_ticks,
+                    minor_faults: counters.minor_faults,
+                    major_faults: counters.major_faults,
+                    swap: swap.unwrap_or(0) * 1024,
+                });
+            }
+        }
+
+        if let (Scope::Tree(_), Some(children)) = (scope, children_map.get(&pid)) {
+            stack.extend(children);
+        }
+    }
+
+    Ok(TreeSample {
+        rss: total_rss,
+        vsz: total_vsz,
+        processes,
+        denied,
+        errors,
+        stale: false,
+        scanned,
+    })
+}
+
+/// Cumulative per-process counters from `/proc/<pid>/stat`
+#[derive(Default)]
+struct Counters {
+    cpu_ticks: u64,
+    minor_faults: u64,
+    major_faults: u64,
+}
+
+/// Samples the tree, either fully each time or cheaply between rescans
+pub struct Sampler {
+    cheap: bool,
+    /// Processes of the last full scan, reused by cheap samples
+    members: Vec<ProcessSample>,
+    /// Files denied in the last full scan
+    denied: Denied,
+    last_scan: Option<Instant>,
+    /// Repeated, marked stale, when a scan fails
+    last: TreeSample,
+}
+
+impl Sampler {
+    /// With `cheap`, only `statm` of the processes found by the last full
+    /// scan is read between rescans; new processes show up late and
+    /// high-water marks, CPU time and faults are as of the last rescan,
+    /// which is why full scans remain the default
+    pub fn new(cheap: bool) -> Self {
+        Self {
+            cheap,
+            members: Vec::new(),
+            denied: Denied::default(),
+            last_scan: None,
+            last: TreeSample::default(),
+        }
+    }
+
+    pub fn sample(&mut self, root_pid: i32) -> TreeSample {
+        let fresh = self
+            .last_scan
+            .is_some_and(|last| last.elapsed() < MEMBERSHIP_REFRESH);
+        if !self.cheap || !fresh {
+            let sample = match sample(root_pid) {
+                Ok(sample) => sample,
+                Err(e) => return self.last.stale(ReadError::of(&e)),
+            };
+            if self.cheap {
+                self.last_scan = Some(Instant::now());
+                self.members = sample.processes.clone();
+                self.denied = sample.denied;
+            }
+            self.last = sample.clone();
+            return sample;
+        }
+
+        let page_size = procfs::page_size();
+        let mut total = TreeSample {
+            denied: self.denied,
+            ..TreeSample::default()
+        };
+        self.members.retain_mut(|member| {
+            // Checked after statm, so that a PID reused before either read
+            // is not counted as the member
+            let read = read_statm(member.pid).and_then(|pages| {
+                match read_starttime(member.pid)? == member.starttime {
+                    true => Ok(pages),
+                    false => Err(io::Error::from(io::ErrorKind::NotFound)),
+                }
+            });
+            let (size, resident) = match read {
+                Ok(pages) => pages,
+                Err(e) => {
+                    let error = ReadError::of_io(&e);
+                    total.errors.count(error);
+                    total.denied.statm |= error == ReadError::Denied;
+                    if error == ReadError::Gone {
+                        return false;
+                    }
+                    (0, 0)
+                }
+            };
+            member.rss = resident * page_size;
+            total.rss = total.rss.saturating_add(member.rss);
+            total.vsz = total.vsz.saturating_add(size * page_size);
+            true
+        });
+        total.processes = self.members.clone();
+        self.last = total.clone();
+        total
+    }
+}
+
+/// Total and resident pages from `/proc/<pid>/statm`
+fn read_statm(pid: i32) -> io::Result<(u64, u64)> {
+    let statm = fs::read_to_string(format!("/proc/{}/statm", pid))?;
+    let mut fields = statm
+        .split_ascii_whitespace()
+        .map(|field| field.parse::<u64>().ok());
+    match (fields.next().flatten(), fields.next().flatten()) {
+        (Some(size), Some(resident)) => Ok((size, resident)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed statm")),
+    }
+}
+
+/// Start time in clock ticks since boot from `/proc/<pid>/stat`, field 22
+fn read_starttime(pid: i32) -> io::Result<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // The name before the fields may hold spaces and parentheses itself
+    stat.rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_ascii_whitespace().nth(19))
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed stat"))
+}
+
+/// SIGKILL `root_pid`, the `known` processes and all their descendants
+///
+/// `known` are `(pid, starttime)` pairs from earlier samples: processes that
+/// were orphaned after their parent exited have left the tree by ancestry,
+/// but are still ours to clean up. Pass no root once it has been reaped, as
+/// its PID may already belong to someone else.
+pub fn kill_tree(root_pid: Option<i32>, known: &[(i32, u64)]) {
+    for _ in 0..KILL_ROUNDS {
+        if signal_tree(root_pid, known, libc::SIGKILL) == 0 {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Send `signal` once to every live process of the tree, see [`kill_tree`]
+///
+/// Returns the number of processes signalled.
+pub fn signal_tree(root_pid: Option<i32>, known: &[(i32, u64)], signal: i32) -> usize {
+    let targets = tree_members(root_pid, known);
+    for &pid in &targets {
+        // SAFETY: sending a signal has no memory-safety implications
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+    targets.len()
+}
+
+/// Live (non-zombie) processes descending from `root_pid` or `known`
+pub fn tree_members(root_pid: Option<i32>, known: &[(i32, u64)]) -> Vec<i32> {
+    let own_pid = std::process::id() as i32;
+    let mut children_map: HashMap<i32, Vec<i32>> = HashMap::new();
+    let mut alive = HashMap::new();
+    if let Ok(processes) = all_processes() {
+        for stat in processes.flatten().filter_map(|p| p.stat().ok()) {
+            if stat.state != 'Z' && stat.pid != own_pid {
+                children_map.entry(stat.ppid).or_default().push(stat.pid);
+                alive.insert(stat.pid, stat.starttime);
+            }
+        }
+    }
+
+    let mut stack: Vec<i32> = known
+        .iter()
+        .filter(|(pid, starttime)| alive.get(pid) == Some(starttime))
+        .map(|(pid, _)| *pid)
+        .collect();
+    stack.extend(root_pid);
+
+    let mut members = HashSet::new();
+    while let Some(pid) = stack.pop() {
+        if !members.insert(pid) {
+            continue;
+        }
+        if let Some(children) = children_map.get(&pid) {
+            stack.extend(children);
+        }
+    }
+    members.retain(|pid| alive.contains_key(pid));
+    members.into_iter().collect()
+}
+
+/// A process joining or leaving the tree between two samples
+pub enum ProcessEvent {
+    Started {
+        pid: i32,
+        name: String,
+    },
+    Exited {
+        pid: i32,
+        name: String,
+        lifetime: Duration,
+        peak_rss: u64,
+    },
+}
+
+/// Highest RSS one process reached over its lifetime
+#[derive(Clone, Debug)]
+pub struct ProcessPeak {
+    pub pid: i32,
+    pub name: String,
+    pub peak_rss: u64,
+    pub lifetime: Duration,
+    /// Peak comes from kernel exit accounting rather than sampling
+    pub exact: bool,
+}
+
+struct TrackedProcess {
+    starttime: u64,
+    name: String,
+    first_seen: Instant,
+    peak_rss: u64,
+    /// High-water mark reported by taskstats once the process exited
+    exact_peak: Option<u64>,
+}
+
+/// Diffs consecutive samples to report process churn and per-process peaks
+#[derive(Default)]
+pub struct ProcessTracker {
+    live: HashMap<i32, TrackedProcess>,
+    exited: HashMap<i32, Instant>,
+    top: Vec<ProcessPeak>,
+    /// Number of distinct processes observed during the run
+    pub seen: u64,
+    /// Processes whose name changed between two samples, as it does when
+    /// they exec
+    pub execs: u64,
+    /// Largest high-water mark the kernel reported for a process of the
+    /// tree as it exited
+    pub exit_peak: u64,
+}
+
+impl ProcessTracker {
+    /// Fold a new sample into the tracked set and return what changed
+    pub fn update(&mut self, sample: &TreeSample) -> Vec<ProcessEvent> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+        let mut current: HashMap<i32, TrackedProcess> = HashMap::new();
+
+        for p in &sample.processes {
+            let tracked = match self.live.remove(&p.pid) {
+                Some(t) if t.starttime == p.starttime => t,
+                previous => {
+                    // PID reuse shows up as an exit of the old process
+                    if let Some(old) = previous {
+                        events.push(self.exited(p.pid, old, now));
+                    }
+                    self.seen += 1;
+                    events.push(ProcessEvent::Started {
+                        pid: p.pid,
+                        name: p.name.clone(),
+                    });
+                    TrackedProcess {
+                        starttime: p.starttime,
+                        name: p.name.clone(),
+                        first_seen: now,
+                        peak_rss: 0,
+                        exact_peak: None,
+                    }
+                }
+            };
+            if tracked.name != p.name {
+                self.execs += 1;
+            }
+            current.insert(
+                p.pid,
+                TrackedProcess {
+                    // The name changes when the process execs
+                    name: p.name.clone(),
+                    peak_rss: tracked.peak_rss.max(p.rss).max(p.hwm),
+                    ..tracked
+                },
+            );
+        }
+
+        for (pid, old) in std::mem::take(&mut self.live) {
+            events.push(self.exited(pid, old, now));
+        }
+        self.live = current;
+        self.exited.retain(|_, at| now.duration_since(*at) < EXITED_GRACE);
+
+        events
+    }
+
+    /// Fold a kernel exit record into the tracked set
+    ///
+    /// Returns events for tree processes that started and exited between two
+    /// samples, which the sampler never saw.
+    pub fn record_exit(&mut self, root_pid: i32, record: ExitRecord) -> Vec<ProcessEvent> {
+        let member = self.live.contains_key(&record.pid)
+            || self.exited.contains_key(&record.pid)
+            || self.is_member(root_pid, record.ppid);
+        if member {
+            self.exit_peak = self.exit_peak.max(record.peak_rss);
+        }
+
+        if let Some(tracked) = self.live.get_mut(&record.pid) {
+            // Sampled before the process last exec'd, perhaps
+            tracked.name = record.name;
+            tracked.exact_peak = Some(record.peak_rss);
+            return Vec::new();
+        }
+
+        if self.exited.contains_key(&record.pid) {
+            // The sampler already reported this exit; only correct its peak
+            if let Some(i) = self.top.iter().position(|p| p.pid == record.pid) {
+                let mut peak = self.top.remove(i);
+                peak.peak_rss = peak.peak_rss.max(record.peak_rss);
+                peak.exact = true;
+                self.push_top(peak);
+            }
+            return Vec::new();
+        }
+
+        if !member {
+            return Vec::new();
+        }
+
+        self.seen += 1;
+        self.exited.insert(record.pid, Instant::now());
+        self.push_top(ProcessPeak {
+            pid: record.pid,
+            name: record.name.clone(),
+            peak_rss: record.peak_rss,
+            lifetime: record.lifetime,
+            exact: true,
+        });
+
+        vec![
+            ProcessEvent::Started {
+                pid: record.pid,
+                name: record.name.clone(),
+            },
+            ProcessEvent::Exited {
+                pid: record.pid,
+                name: record.name,
+                lifetime: record.lifetime,
+                peak_rss: record.peak_rss,
+            },
+        ]
+    }
+
+    /// `(pid, starttime)` of every process alive at the last sample
+    pub fn live(&self) -> Vec<(i32, u64)> {
+        self.live
+            .iter()
+            .map(|(&pid, p)| (pid, p.starttime))
+            .collect()
+    }
+
+    /// Processes with the highest peak RSS, still running ones included
+    pub fn top(&self) -> Vec<ProcessPeak> {
+        let now = Instant::now();
+        let mut top = self.top.clone();
+        top.extend(self.live.iter().map(|(&pid, p)| ProcessPeak {
+            pid,
+            name: p.name.clone(),
+            peak_rss: p.peak_rss.max(p.exact_peak.unwrap_or(0)),
+            lifetime: now.duration_since(p.first_seen),
+            exact: p.exact_peak.is_some(),
+        }));
+        top.sort_by_key(|p| std::cmp::Reverse(p.peak_rss));
+        top.truncate(TOP_PROCESSES);
+        top
+    }
+
+    fn exited(&mut self, pid: i32, process: TrackedProcess, now: Instant) -> ProcessEvent {
+        let peak = ProcessPeak {
+            pid,
+            name: process.name,
+            peak_rss: process.peak_rss.max(process.exact_peak.unwrap_or(0)),
+            lifetime: now.duration_since(process.first_seen),
+            exact: process.exact_peak.is_some(),
+        };
+        self.exited.insert(pid, now);
+        self.push_top(peak.clone());
+
+        ProcessEvent::Exited {
+            pid,
+            name: peak.name,
+            lifetime: peak.lifetime,
+            peak_rss: peak.peak_rss,
+        }
+    }
+
+    fn push_top(&mut self, peak: ProcessPeak) {
+        let at = self
+            .top
+            .iter()
+            .position(|p| p.peak_rss < peak.peak_rss)
+            .unwrap_or(self.top.len());
+        if at < TOP_PROCESSES {
+            self.top.insert(at, peak);
+            self.top.truncate(TOP_PROCESSES);
+        }
+    }
+
+    /// Whether `pid` is the root or descends from a known tree member
+    fn is_member(&self, root_pid: i32, mut pid: i32) -> bool {
+        // Bounded walk up the parent chain of processes still alive
+        for _ in 0..64 {
+            if pid == root_pid || self.live.contains_key(&pid) || self.exited.contains_key(&pid) {
+                return true;
+            }
+            if pid <= 1 {
+                return false;
+            }
+            match Process::new(pid).and_then(|p| p.stat()) {
+                Ok(stat) => pid = stat.ppid,
+                Err(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starttime_matches_procfs() {
+        let pid = std::process::id() as i32;
+        let stat = procfs::process::Process::myself().unwrap().stat().unwrap();
+        assert_eq!(read_starttime(pid).unwrap(), stat.starttime);
+        assert_eq!(read_starttime(i32::MAX).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn cheap_samples_keep_live_members() {
+        let pid = std::process::id() as i32;
+        let mut sampler = Sampler::new(true);
+        let full = sampler.sample(pid);
+        let cheap = sampler.sample(pid);
+        assert_eq!(full.processes.len(), cheap.processes.len());
+        assert!(cheap.rss > 0);
+        assert_eq!(cheap.scanned, 0);
+    }
+
+    #[test]
+    fn exit_records_feed_the_peak_and_the_name() {
+        let pid = std::process::id() as i32;
+        let mut tracker = ProcessTracker::default();
+        tracker.update(&Sampler::new(false).sample(pid));
+        let record = |pid, ppid| ExitRecord {
+            pid,
+            ppid,
+            name: "python3".into(),
+            peak_rss: 300 << 20,
+            lifetime: Duration::from_secs(1),
+        };
+
+        tracker.record_exit(pid, record(i32::MAX, 1));
+        assert_eq!(tracker.exit_peak, 0);
+
+        tracker.record_exit(pid, record(pid, 1));
+        assert_eq!(tracker.exit_peak, 300 << 20);
+        let top = tracker.top();
+        let largest = top.iter().find(|p| p.pid == pid).unwrap();
+        assert_eq!((largest.name.as_str(), largest.peak_rss), ("python3", 300 << 20));
+        assert!(largest.exact);
+    }
+}
@@ -0,0 +1,521 @@
+//! Full-screen terminal UI: a live plot of two metrics over the child's
+//! output, which is kept for scrolling back and searching
+
+use std::{
+    collections::VecDeque,
+    io::{
+        self,
+        Stdout,
+        Write,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc::SyncSender,
+        Arc,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use termion::{
+    clear,
+    color,
+    cursor,
+    event::Key,
+    input::TermRead,
+    raw::{
+        IntoRawMode,
+        RawTerminal,
+    },
+    screen::{
+        ToAlternateScreen,
+        ToMainScreen,
+    },
+    style,
+};
+
+use crate::{
+    render::{
+        fit_width,
+        OutputMsg,
+    },
+    size::format_bytes,
+    record::Sample,
+};
+
+/// Samples kept for the plot
+const HISTORY: usize = 4096;
+
+/// Output lines kept for the output pane
+const SCROLLBACK: usize = 10_000;
+
+/// Redraws caused by output are limited to one per this period
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Columns taken by each axis label
+const AXIS_WIDTH: usize = 11;
+
+/// Columns taken by the RSS shown next to each output line
+const GUTTER_WIDTH: usize = 11;
+
+/// A metric that can be plotted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Series {
+    Rss,
+    Vsz,
+    Cpu,
+    MinorFaults,
+    MajorFaults,
+    Processes,
+}
+
+impl Series {
+    const ALL: [Series; 6] = [
+        Series::Rss,
+        Series::Vsz,
+        Series::Cpu,
+        Series::MinorFaults,
+        Series::MajorFaults,
+        Series::Processes,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Rss => "rss",
+            Self::Vsz => "vsz",
+            Self::Cpu => "cpu",
+            Self::MinorFaults => "minflt",
+            Self::MajorFaults => "majflt",
+            Self::Processes => "procs",
+        }
+    }
+
+    fn format(self, value: f64) -> String {
+        match self {
+            Self::Rss | Self::Vsz => format_bytes(value as u64),
+            Self::Cpu => format!("{:.0}%", value),
+            Self::MinorFaults | Self::MajorFaults => format!("{:.0}/s", value),
+            Self::Processes => format!("{:.0}", value),
+        }
+    }
+
+    fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&s| s == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+/// Parse `--series`: two comma-separated metric names
+pub fn parse_series(s: &str) -> Result<[Series; 2], String> {
+    let find = |name: &str| {
+        Series::ALL
+            .into_iter()
+            .find(|series| series.name() == name.trim())
+            .ok_or_else(|| {
+                let names: Vec<_> = Series::ALL.iter().map(|s| s.name()).collect();
+                format!("unknown series '{}', expected one of {}", name, names.join(", "))
+            })
+    };
+    match s.split_once(',') {
+        Some((left, right)) => Ok([find(left)?, find(right)?]),
+        None => Err(format!("expected two series like 'rss,cpu', got '{}'", s)),
+    }
+}
+
+/// Send key presses to the main loop, which hands them to the renderer
+pub fn forward_keys(tx: SyncSender<OutputMsg>) {
+    thread::spawn(move || {
+        for key in io::stdin().keys().map_while(Result::ok) {
+            if tx.send(OutputMsg::Key(key)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Values of every series at one sample
+#[derive(Clone, Copy, Default)]
+struct Point {
+    rss: f64,
+    vsz: f64,
+    cpu: f64,
+    minor_faults: f64,
+    major_faults: f64,
+    processes: f64,
+}
+
+impl Point {
+    fn get(&self, series: Series) -> f64 {
+        match series {
+            Series::Rss => self.rss,
+            Series::Vsz => self.vsz,
+            Series::Cpu => self.cpu,
+            Series::MinorFaults => self.minor_faults,
+            Series::MajorFaults => self.major_faults,
+            Series::Processes => self.processes,
+        }
+    }
+}
+
+/// Cumulative counters of the previous sample, for rates
+struct Totals {
+    at: Duration,
+    cpu_ticks: u64,
+    minor_faults: u64,
+    major_faults: u64,
+}
+
+/// A line of the output pane
+struct Line {
+    text: String,
+    /// Printed by memwatch rather than the child
+    notice: bool,
+    /// RSS of the tree when the line was printed
+    rss: u64,
+}
+
+/// Rows of the plot and of the output pane for a terminal this high
+fn layout(rows: usize) -> (usize, usize) {
+    let plot_rows = (rows.saturating_sub(3) / 2).max(3);
+    (plot_rows, rows.saturating_sub(3 + plot_rows))
+}
+
+pub struct Screen {
+    terminal: RawTerminal<Stdout>,
+    series: [Series; 2],
+    history: VecDeque<Point>,
+    previous: Option<Totals>,
+    ticks_per_second: f64,
+    status: String,
+    lines: VecDeque<Line>,
+    /// Lines between the bottom of the output pane and the latest line
+    scroll: usize,
+    /// Pattern being typed after `/`
+    typing: Option<String>,
+    /// Last pattern searched for, highlighted in the output pane
+    pattern: Option<String>,
+    /// One-off message shown in the help row
+    message: Option<&'static str>,
+    rss: u64,
+    terminated: Arc<AtomicBool>,
+    last_draw: Option<Instant>,
+}
+
+impl Screen {
+    /// Switch to the alternate screen in raw mode; `q` and Ctrl+C set
+    /// `terminated`
+    pub fn new(series: [Series; 2], terminated: Arc<AtomicBool>) -> io::Result<Self> {
+        let mut terminal = io::stdout().into_raw_mode()?;
+        write!(terminal, "{}{}", ToAlternateScreen, cursor::Hide)?;
+        Ok(Self {
+            terminal,
+            series,
+            history: VecDeque::new(),
+            previous: None,
+            ticks_per_second: procfs::ticks_per_second() as f64,
+            status: String::new(),
+            lines: VecDeque::new(),
+            scroll: 0,
+            typing: None,
+            pattern: None,
+            message: None,
+            rss: 0,
+            terminated,
+            last_draw: None,
+        })
+    }
+
+    pub fn sample(&mut self, sample: &Sample) {
+        let at = sample.elapsed;
+        let sum = |f: fn(&crate::tree::ProcessSample) -> u64| -> u64 {
+            sample.processes.iter().map(f).sum()
+        };
+        let totals = Totals {
+            at,
+            cpu_ticks: sum(|p| p.cpu_ticks),
+            minor_faults: sum(|p| p.minor_faults),
+            major_faults: sum(|p| p.major_faults),
+        };
+
+        let mut point = Point {
+            rss: sample.rss as f64,
+            vsz: sample.vsz as f64,
+            processes: sample.processes.len() as f64,
+            ..Point::default()
+        };
+        if let Some(previous) = &self.previous {
+            let dt = (at - previous.at).as_secs_f64().max(f64::EPSILON);
+            // Counters shrink when processes exit; that is not negative usage
+            let rate = |now: u64, before: u64| now.saturating_sub(before) as f64 / dt;
+            point.cpu = rate(totals.cpu_ticks, previous.cpu_ticks) / self.ticks_per_second * 100.0;
+            point.minor_faults = rate(totals.minor_faults, previous.minor_faults);
+            point.major_faults = rate(totals.major_faults, previous.major_faults);
+        }
+        self.previous = Some(totals);
+        self.rss = sample.rss;
+
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(point);
+    }
+
+    pub fn line(&mut self, text: String, notice: bool) {
+        if self.lines.len() == SCROLLBACK {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(Line {
+            text,
+            notice,
+            rss: self.rss,
+        });
+        // Keep a scrolled-back view on the same lines
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.lines.len() - 1);
+        }
+    }
+
+    pub fn key(&mut self, key: Key) {
+        self.message = None;
+        if let Some(typing) = &mut self.typing {
+            match key {
+                Key::Char('\n') => {
+                    self.pattern = self.typing.take().filter(|p| !p.is_empty());
+                    self.search(self.lines.len(), true);
+                }
+                Key::Char(c) => typing.push(c),
+                Key::Backspace => {
+                    typing.pop();
+                }
+                Key::Esc | Key::Ctrl('c') => self.typing = None,
+                _ => return,
+            }
+            self.draw();
+            return;
+        }
+
+        let (_, rows) = termion::terminal_size().unwrap_or((80, 24));
+        let page = layout(rows as usize).1.max(1);
+        let last = self.lines.len().saturating_sub(1);
+        match key {
+            Key::Char('q') | Key::Ctrl('c') => self.terminated.store(true, Ordering::SeqCst),
+            Key::Char('1') => self.series[0] = self.series[0].next(),
+            Key::Char('2') => self.series[1] = self.series[1].next(),
+            Key::PageUp => self.scroll = (self.scroll + page).min(last),
+            Key::PageDown => self.scroll = self.scroll.saturating_sub(page),
+            Key::Up => self.scroll = (self.scroll + 1).min(last),
+            Key::Down => self.scroll = self.scroll.saturating_sub(1),
+            Key::Home => self.scroll = last,
+            Key::End => self.scroll = 0,
+            Key::Char('/') => self.typing = Some(String::new()),
+            Key::Char('n') => self.search(self.bottom().saturating_sub(1), true),
+            Key::Char('N') => self.search(self.bottom(), false),
+            _ => return,
+        }
+        self.draw();
+    }
+
+    /// Index after the last line shown in the output pane
+    fn bottom(&self) -> usize {
+        self.lines.len() - self.scroll
+    }
+
+    /// Scroll to the nearest line matching the pattern before `from`, or
+    /// after it; the match becomes the bottom line of the pane
+    fn search(&mut self, from: usize, older: bool) {
+        let Some(pattern) = &self.pattern else {
+            return;
+        };
+        let matches = |i: &usize| self.lines[*i].text.contains(pattern.as_str());
+        let found = if older {
+            (0..from.min(self.lines.len())).rev().find(matches)
+        } else {
+            (from..self.lines.len()).find(matches)
+        };
+        match found {
+            Some(i) => self.scroll = self.lines.len() - 1 - i,
+            None => self.message = Some("pattern not found"),
+        }
+    }
+
+    pub fn status(&mut self, status: &str) {
+        self.status = status.to_string();
+        if self
+            .last_draw
+            .is_some_and(|last| last.elapsed() < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.draw();
+    }
+
+    fn draw(&mut self) {
+        self.last_draw = Some(Instant::now());
+        let (cols, rows) = termion::terminal_size().unwrap_or((80, 24));
+        let (cols, rows) = (cols as usize, rows as usize);
+        if rows < 6 || cols < AXIS_WIDTH * 2 + 10 {
+            return;
+        }
+
+        let (plot_rows, output_rows) = layout(rows);
+        let mut screen = String::new();
+
+        screen.push_str(&format!(
+            "{}{}{}{}",
+            cursor::Goto(1, 1),
+            style::Invert,
+            fit_width(&format!("{:<width$}", self.status, width = cols), cols),
+            style::Reset
+        ));
+
+        for (i, line) in self.plot(plot_rows, cols).into_iter().enumerate() {
+            screen.push_str(&format!(
+                "{}{}{}",
+                cursor::Goto(1, (i + 2) as u16),
+                clear::CurrentLine,
+                line
+            ));
+        }
+
+        let legend = format!(
+            "{}{}{} {}  {}{}{} {}",
+            color::Fg(color::Cyan),
+            "█",
+            color::Fg(color::Reset),
+            self.series[0].name(),
+            color::Fg(color::Yellow),
+            "•",
+            color::Fg(color::Reset),
+            self.series[1].name()
+        );
+        screen.push_str(&format!(
+            "{}{}{}",
+            cursor::Goto(1, (plot_rows + 2) as u16),
+            clear::CurrentLine,
+            legend
+        ));
+
+        let bottom = self.bottom();
+        let first = bottom.saturating_sub(output_rows);
+        for row in 0..output_rows {
+            screen.push_str(&format!(
+                "{}{}",
+                cursor::Goto(1, (plot_rows + 3 + row) as u16),
+                clear::CurrentLine
+            ));
+            if let Some(line) = self.lines.range(first..bottom).nth(row) {
+                screen.push_str(&self.output_line(line, cols));
+            }
+        }
+
+        let help = match (&self.typing, self.message) {
+            (Some(typing), _) => format!("/{}", typing),
+            (None, Some(message)) => message.to_string(),
+            (None, None) => {
+                let mut help = String::from(
+                    "q quit | 1/2 change series | -/+ interval | t threads | PgUp/PgDn scroll | / search | n/N older/newer match",
+                );
+                if self.scroll > 0 {
+                    help = format!("[{} lines below] {}", self.scroll, help);
+                }
+                format!("{}{}{}", style::Faint, help, style::Reset)
+            }
+        };
+        screen.push_str(&format!(
+            "{}{}{}",
+            cursor::Goto(1, rows as u16),
+            clear::CurrentLine,
+            fit_width(&help, cols - 1)
+        ));
+
+        self.terminal.write_all(screen.as_bytes()).ok();
+        self.terminal.flush().ok();
+    }
+
+    /// A line of the output pane with the RSS gutter and search matches
+    /// highlighted
+    fn output_line(&self, line: &Line, cols: usize) -> String {
+        let gutter = format!(
+            "{}{:>width$} │{}",
+            style::Faint,
+            format_bytes(line.rss),
+            style::Reset,
+            width = GUTTER_WIDTH - 2
+        );
+        let mut text = fit_width(&line.text, cols.saturating_sub(GUTTER_WIDTH + 1));
+        // Lines with their own colors are left alone rather than risk
+        // splitting an escape sequence
+        if let Some(pattern) = self.pattern.as_deref() {
+            if !text.contains('\x1b') {
+                let marked = format!("{}{}{}", style::Invert, pattern, style::NoInvert);
+                text = text.replace(pattern, &marked);
+            }
+        }
+        if line.notice {
+            text = format!("{}{}{}", style::Bold, text, style::Reset);
+        }
+        format!("{} {}", gutter, text)
+    }
+
+    /// Rows of the dual-axis plot: the left series as bars scaled to the
+    /// left axis, the right one as dots scaled to the right axis
+    fn plot(&self, rows: usize, cols: usize) -> Vec<String> {
+        let width = cols - AXIS_WIDTH * 2 - 2;
+        let points: Vec<&Point> = self.history.iter().rev().take(width).rev().collect();
+        let [left, right] = self.series;
+        let max = |series: Series| {
+            points
+                .iter()
+                .map(|p| p.get(series))
+                .fold(0.0, f64::max)
+        };
+        let (left_max, right_max) = (max(left), max(right));
+        // Height in rows of each value, 0 for nothing to show
+        let height = |value: f64, max: f64| match max {
+            m if m <= 0.0 => 0,
+            m => (value / m * rows as f64).round() as usize,
+        };
+
+        (0..rows)
+            .map(|row| {
+                // Rows are counted from the bottom, starting at 1
+                let level = rows - row;
+                let (left_label, right_label) = match level {
+                    l if l == rows => (left.format(left_max), right.format(right_max)),
+                    1 => (left.format(0.0), right.format(0.0)),
+                    _ => (String::new(), String::new()),
+                };
+                let mut line = format!("{:>width$}┤", left_label, width = AXIS_WIDTH);
+                // Right-align the history so the latest sample is at the edge
+                line.push_str(&" ".repeat(width - points.len()));
+                for point in &points {
+                    let bar = height(point.get(left), left_max) >= level;
+                    let dot = height(point.get(right), right_max).max(1) == level
+                        && right_max > 0.0;
+                    line.push_str(&match (bar, dot) {
+                        (_, true) => format!("{}•{}", color::Fg(color::Yellow), color::Fg(color::Reset)),
+                        (true, false) => format!("{}█{}", color::Fg(color::Cyan), color::Fg(color::Reset)),
+                        (false, false) => String::from(" "),
+                    });
+                }
+                line.push_str(&format!("├{}", right_label));
+                line
+            })
+            .collect()
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        // Flushed here: stdout is line buffered and the final report that
+        // follows goes to stderr
+        let _ = write!(self.terminal, "{}{}", cursor::Show, ToMainScreen);
+        let _ = self.terminal.flush();
+    }
+}
@@ -0,0 +1,181 @@
+//! `--watch-dir`: disk space taken by scratch directories over the run
+//!
+//! Each directory is measured like `du` does, by allocated blocks with
+//! hard links counted once, at most once a second. Scans are incremental:
+//! the listing of a directory is read again only when its modification
+//! time changed, so a large tree that mostly grows files costs one `stat`
+//! per entry.
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+
+use crate::{
+    json::Value,
+    size::format_bytes,
+};
+
+/// Least time between two scans
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size of one directory over the run
+pub struct DirSeries {
+    pub path: PathBuf,
+    /// Time since the start and bytes taken
+    pub points: Vec<(Duration, u64)>,
+}
+
+impl DirSeries {
+    pub fn json(&self) -> Value {
+        let sizes = || self.points.iter().map(|&(_, bytes)| bytes);
+        Value::Object(vec![
+            ("path".into(), self.path.display().to_string().into()),
+            ("start".into(), sizes().next().into()),
+            ("last".into(), sizes().next_back().into()),
+            ("peak".into(), sizes().max().into()),
+            (
+                "points".into(),
+                Value::Array(
+                    self.points
+                        .iter()
+                        .map(|&(at, bytes)| {
+                            Value::Object(vec![
+                                ("elapsed_secs".into(), at.as_secs_f64().into()),
+                                ("bytes".into(), bytes.into()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+
+    /// `/scratch: 120.00 MB → 4.20 GB (peak 5.00 GB)`
+    pub fn format(&self) -> Option<String> {
+        let (first, last) = (self.points.first()?.1, self.points.last()?.1);
+        let peak = self.points.iter().map(|&(_, bytes)| bytes).max()?;
+        Some(format!(
+            "{}: {} → {} (peak {})",
+            self.path.display(),
+            format_bytes(first),
+            format_bytes(last),
+            format_bytes(peak)
+        ))
+    }
+}
+
+/// Listing of a directory as of its modification time
+struct Listing {
+    modified: SystemTime,
+    entries: Vec<PathBuf>,
+}
+
+struct Watched {
+    series: DirSeries,
+    /// Shown on the status line: the last component of the path
+    label: String,
+}
+
+pub struct DirWatcher {
+    dirs: Vec<Watched>,
+    listings: HashMap<PathBuf, Listing>,
+    last_scan: Option<Instant>,
+}
+
+impl DirWatcher {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let dirs = paths
+            .into_iter()
+            .map(|path| Watched {
+                label: path.file_name().map_or_else(
+                    || path.display().to_string(),
+                    |name| name.to_string_lossy().into_owned(),
+                ),
+                series: DirSeries {
+                    path,
+                    points: Vec::new(),
+                },
+            })
+            .collect();
+        Self {
+            dirs,
+            listings: HashMap::new(),
+            last_scan: None,
+        }
+    }
+
+    /// Measure the directories if a scan is due
+    pub fn update(&mut self, at: Duration) {
+        if self.last_scan.is_some_and(|last| last.elapsed() < SCAN_INTERVAL) {
+            return;
+        }
+        self.last_scan = Some(Instant::now());
+        for dir in &mut self.dirs {
+            let bytes = measure(&dir.series.path, &mut self.listings, &mut HashSet::new());
+            dir.series.points.push((at, bytes));
+        }
+    }
+
+    /// Latest size of every directory, for the status line
+    pub fn status(&self) -> Vec<String> {
+        self.dirs
+            .iter()
+            .filter_map(|dir| {
+                let &(_, bytes) = dir.series.points.last()?;
+                Some(format!("{}: {}", dir.label, format_bytes(bytes)))
+            })
+            .collect()
+    }
+
+    pub fn finish(self) -> Vec<DirSeries> {
+        self.dirs.into_iter().map(|dir| dir.series).collect()
+    }
+}
+
+/// Bytes allocated under `path`, not following symlinks; files in `seen`
+/// by device and inode are not counted again
+fn measure(
+    path: &Path,
+    listings: &mut HashMap<PathBuf, Listing>,
+    seen: &mut HashSet<(u64, u64)>,
+) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        listings.remove(path);
+        return 0;
+    };
+    // Only files with other links can be met again
+    if metadata.nlink() > 1 && !seen.insert((metadata.dev(), metadata.ino())) {
+        return 0;
+    }
+    let mut bytes = metadata.blocks() * 512;
+    if !metadata.is_dir() {
+        return bytes;
+    }
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let current = listings.get(path).is_some_and(|listing| listing.modified == modified);
+    if !current {
+        let entries = fs::read_dir(path)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default();
+        listings.insert(path.to_path_buf(), Listing { modified, entries });
+    }
+    let entries = listings[path].entries.clone();
+    for entry in &entries {
+        bytes += measure(entry, listings, seen);
+    }
+    bytes
+}
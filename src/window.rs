@@ -0,0 +1,243 @@
+//! `--between`: watching only in windows of the day, such as a nightly
+//! batch window
+//!
+//! Outside every window the tree is still sampled for the status line,
+//! but the samples are left out of the summary, the timeline and the
+//! exporters, and no threshold, anomaly or step is looked for in them.
+//! Windows are in local time; one whose end is before its start, such as
+//! `22:00-06:00`, runs over midnight.
+
+use std::{
+    fmt,
+    time::SystemTime,
+};
+
+use crate::{
+    clock,
+    json::Value,
+};
+
+const DAY: u32 = 86400;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Window {
+    /// Seconds since local midnight
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    fn contains(&self, of_day: u32) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&of_day)
+        } else {
+            of_day >= self.start || of_day < self.end
+        }
+    }
+
+    /// Seconds from `of_day` until the window opens next
+    fn opens_in(&self, of_day: u32) -> u32 {
+        (self.start + DAY - of_day) % DAY
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", format_hm(self.start), format_hm(self.end))
+    }
+}
+
+fn format_hm(of_day: u32) -> String {
+    format!("{:02}:{:02}", of_day / 3600, of_day / 60 % 60)
+}
+
+/// `HH:MM-HH:MM`, e.g. `22:00-06:00`
+pub fn parse_window(s: &str) -> Result<Window, String> {
+    let invalid = || format!("invalid window `{}`, expected HH:MM-HH:MM such as 22:00-06:00", s);
+    let (start, end) = s.trim().split_once('-').ok_or_else(invalid)?;
+    let start = parse_hm(start).filter(|&start| start < DAY).ok_or_else(invalid)?;
+    let end = parse_hm(end).ok_or_else(invalid)?;
+    if start == end {
+        return Err(format!("window `{}` is empty; leave out --between to watch all day", s));
+    }
+    Ok(Window { start, end })
+}
+
+fn parse_hm(s: &str) -> Option<u32> {
+    let (hours, minutes) = s.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    // 24:00 is the end of the day, for the end of a window
+    match (hours, minutes) {
+        (24, 0) => Some(DAY),
+        (0..=23, 0..=59) => Some(hours * 3600 + minutes * 60),
+        _ => None,
+    }
+}
+
+/// A window opening or the last one closing
+pub enum WindowEvent {
+    Opened(Window),
+    /// Every window is closed now; this is the one to open next
+    Closed(Window),
+}
+
+/// Reported in the summary
+pub struct WindowStats {
+    pub windows: Vec<Window>,
+    /// Samples left out, taken outside every window
+    pub samples_outside: u64,
+    /// Times a window opened while watching, counting one open at the start
+    pub openings: u64,
+}
+
+impl WindowStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            (
+                "windows".into(),
+                Value::Array(self.windows.iter().map(|w| w.to_string().into()).collect()),
+            ),
+            ("samples_outside".into(), self.samples_outside.into()),
+            ("openings".into(), self.openings.into()),
+        ])
+    }
+
+    /// `Windows: 22:00-06:00 | 2 openings, 43200 samples outside left out`
+    pub fn format(&self) -> String {
+        let windows: Vec<String> = self.windows.iter().map(Window::to_string).collect();
+        format!(
+            "Windows: {} | {} opening{}, {} sample{} outside left out",
+            windows.join(", "),
+            self.openings,
+            if self.openings == 1 { "" } else { "s" },
+            self.samples_outside,
+            if self.samples_outside == 1 { "" } else { "s" }
+        )
+    }
+}
+
+pub struct Schedule {
+    /// Inside a window at the last update, `None` before the first
+    inside: Option<bool>,
+    /// The window to open next, while outside
+    next: Option<Window>,
+    stats: WindowStats,
+}
+
+impl Schedule {
+    pub fn new(windows: Vec<Window>) -> Self {
+        Self {
+            inside: None,
+            next: None,
+            stats: WindowStats {
+                windows,
+                samples_outside: 0,
+                openings: 0,
+            },
+        }
+    }
+
+    /// Whether the sample taken at `time` counts, and whether a window
+    /// opened or closed since the previous one
+    pub fn update(&mut self, time: SystemTime) -> (bool, Option<WindowEvent>) {
+        let of_day = clock::seconds_of_day(time);
+        let open = self.stats.windows.iter().find(|window| window.contains(of_day)).copied();
+        let was_inside = self.inside.replace(open.is_some());
+        let event = match open {
+            Some(window) if was_inside != Some(true) => {
+                self.stats.openings += 1;
+                self.next = None;
+                Some(WindowEvent::Opened(window))
+            }
+            Some(_) => None,
+            None => {
+                self.stats.samples_outside += 1;
+                let next = self
+                    .stats
+                    .windows
+                    .iter()
+                    .min_by_key(|window| window.opens_in(of_day))
+                    .copied();
+                self.next = next;
+                match was_inside {
+                    Some(false) => None,
+                    _ => next.map(WindowEvent::Closed),
+                }
+            }
+        };
+        (open.is_some(), event)
+    }
+
+    /// `Outside window until 22:00` while outside every window, for the status line
+    pub fn status(&self) -> Vec<String> {
+        match self.next {
+            Some(next) => vec![format!("Outside window until {}", format_hm(next.start))],
+            None => Vec::new(),
+        }
+    }
+
+    pub fn finish(self) -> WindowStats {
+        self.stats
+    }
+}
+
+/// Notice for a window opening or closing
+pub fn format_event(event: &WindowEvent) -> String {
+    match event {
+        WindowEvent::Opened(window) => format!("Window {} open", window),
+        WindowEvent::Closed(next) => format!("Outside the --between windows until {}", format_hm(next.start)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hm(hours: u32, minutes: u32) -> u32 {
+        hours * 3600 + minutes * 60
+    }
+
+    #[test]
+    fn parses_windows() {
+        assert_eq!(parse_window("09:00-17:30"), Ok(Window { start: hm(9, 0), end: hm(17, 30) }));
+        assert_eq!(parse_window(" 22:00 - 06:00 "), Ok(Window { start: hm(22, 0), end: hm(6, 0) }));
+        assert_eq!(parse_window("18:00-24:00"), Ok(Window { start: hm(18, 0), end: DAY }));
+        assert_eq!(parse_window("22:00-06:00").unwrap().to_string(), "22:00-06:00");
+        assert_eq!(parse_window("18:00-24:00").unwrap().to_string(), "18:00-24:00");
+    }
+
+    #[test]
+    fn refuses_bad_windows() {
+        for text in [
+            "", "22:00", "22-06", "25:00-06:00", "22:60-06:00", "24:30-06:00", "24:00-06:00", "a:b-c:d",
+        ] {
+            assert!(parse_window(text).unwrap_err().contains("expected HH:MM-HH:MM"), "{}", text);
+        }
+        assert!(parse_window("08:00-08:00").unwrap_err().contains("is empty"));
+    }
+
+    #[test]
+    fn windows_over_midnight() {
+        let night = parse_window("22:00-06:00").unwrap();
+        assert!(night.contains(hm(23, 0)));
+        assert!(night.contains(hm(0, 0)));
+        assert!(night.contains(hm(5, 59)));
+        assert!(!night.contains(hm(6, 0)));
+        assert!(!night.contains(hm(12, 0)));
+        assert_eq!(night.opens_in(hm(21, 0)), 3600);
+        assert_eq!(night.opens_in(hm(23, 0)), DAY - 3600);
+
+        let day = parse_window("09:00-17:00").unwrap();
+        assert!(day.contains(hm(9, 0)));
+        assert!(!day.contains(hm(17, 0)));
+        assert!(!day.contains(hm(8, 59)));
+
+        let evening = parse_window("18:00-24:00").unwrap();
+        assert!(evening.contains(hm(23, 59)));
+        assert!(!evening.contains(hm(0, 0)));
+
+        let all = parse_window("00:00-24:00").unwrap();
+        assert!(all.contains(hm(0, 0)));
+        assert!(all.contains(DAY - 1));
+    }
+}
@@ -0,0 +1,264 @@
+//! `--working-set`: how much of RSS the tree actually uses
+//!
+//! RSS counts every resident page, including ones not touched in hours. At
+//! each sample memwatch marks the tree's pages idle in the kernel's idle
+//! page bitmap, and the kernel clears the mark of a page when it is
+//! accessed. At the next sample, pages still marked are cold and the others
+//! were touched in between: they are hot. Pages are found through
+//! `/proc/<pid>/pagemap` and counted once however many processes map them.
+//!
+//! This needs root and a kernel built with `CONFIG_IDLE_PAGE_TRACKING`, and
+//! it looks up every resident page on each sample, which takes a while for
+//! trees of many gigabytes.
+
+use std::{
+    fs::{
+        self,
+        File,
+        OpenOptions,
+    },
+    io,
+    os::unix::fs::FileExt,
+};
+
+use crate::{
+    json::Value,
+    size::format_bytes,
+    tree::TreeSample,
+};
+
+const BITMAP: &str = "/sys/kernel/mm/page_idle/bitmap";
+
+/// Bits of a `pagemap` entry
+const PRESENT: u64 = 1 << 63;
+const PFN_MASK: u64 = (1 << 55) - 1;
+
+/// Entries of `pagemap` read at once
+const PAGEMAP_CHUNK: u64 = 4096;
+
+/// Bitmap words between two pages for them to be read in one go
+const MAX_GAP: u64 = 64;
+
+/// Hot and cold memory between two samples
+#[derive(Clone, Copy)]
+struct Window {
+    hot: u64,
+    cold: u64,
+}
+
+impl Window {
+    fn hot_share(&self) -> f64 {
+        self.hot as f64 / (self.hot + self.cold).max(1) as f64
+    }
+}
+
+/// Reported in the summary
+pub struct WorkingSetStats {
+    pub peak_hot: u64,
+    /// Share of resident memory that was hot, averaged over the windows
+    pub average_hot_share: f64,
+    pub windows: u64,
+}
+
+impl WorkingSetStats {
+    pub fn json(&self) -> Value {
+        Value::Object(vec![
+            ("peak_hot".into(), self.peak_hot.into()),
+            ("average_hot_share".into(), self.average_hot_share.into()),
+            ("windows".into(), self.windows.into()),
+        ])
+    }
+}
+
+pub struct WorkingSet {
+    bitmap: File,
+    page_size: u64,
+    /// The tree's pages were marked idle at the previous sample
+    marked: bool,
+    latest: Option<Window>,
+    peak_hot: u64,
+    hot_shares: f64,
+    windows: u64,
+    /// Set once the bitmap failed, which ends the estimate
+    failed: bool,
+}
+
+impl WorkingSet {
+    pub fn open() -> io::Result<Self> {
+        let bitmap = OpenOptions::new().read(true).write(true).open(BITMAP).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "--working-set needs root and a kernel with idle page tracking: {}: {}",
+                    BITMAP, e
+                ),
+            )
+        })?;
+        let working_set = Self {
+            bitmap,
+            page_size: procfs::page_size(),
+            marked: false,
+            latest: None,
+            peak_hot: 0,
+            hot_shares: 0.0,
+            windows: 0,
+            failed: false,
+        };
+        // Unprivileged readers of pagemap get zeros instead of frames
+        let probe = [1u8; 1];
+        let address = probe.as_ptr() as u64;
+        let mut pfns = Vec::new();
+        working_set.read_pagemap(std::process::id() as i32, address, address + 1, &mut pfns)?;
+        if pfns.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "--working-set needs CAP_SYS_ADMIN to read page frames from /proc/<pid>/pagemap",
+            ));
+        }
+        Ok(working_set)
+    }
+
+    /// Measure the window since the previous sample and mark the tree's
+    /// pages idle for the next; returns a notice should that fail
+    pub fn update(&mut self, sample: &TreeSample) -> Option<String> {
+        if self.failed {
+            return None;
+        }
+        let mut pfns = Vec::new();
+        for process in &sample.processes {
+            self.resident_pages(process.pid, &mut pfns);
+        }
+        pfns.sort_unstable();
+        pfns.dedup();
+        let window = match self.check_and_mark(&pfns) {
+            Ok(window) => window,
+            Err(e) => {
+                self.failed = true;
+                self.latest = None;
+                return Some(format!("memwatch: --working-set stopped: {}: {}", BITMAP, e));
+            }
+        };
+        // Nothing was marked before the first sample, so every page would
+        // look hot
+        if std::mem::replace(&mut self.marked, true) {
+            self.peak_hot = self.peak_hot.max(window.hot);
+            self.hot_shares += window.hot_share();
+            self.windows += 1;
+            self.latest = Some(window);
+        }
+        None
+    }
+
+    /// Resident memory touched in the latest window, for the sample
+    pub fn hot(&self) -> Option<u64> {
+        self.latest.map(|window| window.hot)
+    }
+
+    /// Hot memory for the status line
+    pub fn status(&self) -> Vec<String> {
+        let Some(window) = self.latest else {
+            return Vec::new();
+        };
+        vec![format!(
+            "Hot: {} ({:.0}%)",
+            format_bytes(window.hot),
+            window.hot_share() * 100.0
+        )]
+    }
+
+    /// Statistics, if a window was ever measured
+    pub fn finish(self) -> Option<WorkingSetStats> {
+        (self.windows > 0).then(|| WorkingSetStats {
+            peak_hot: self.peak_hot,
+            average_hot_share: self.hot_shares / self.windows as f64,
+            windows: self.windows,
+        })
+    }
+
+    /// Page frames of the resident pages of `pid`; processes that are gone
+    /// or unreadable have none
+    fn resident_pages(&self, pid: i32, pfns: &mut Vec<u64>) {
+        // smaps tells which mappings have resident pages at all, so that
+        // the pagemap of large reservations is not read for nothing
+        let Ok(smaps) = fs::read_to_string(format!("/proc/{}/smaps", pid)) else {
+            return;
+        };
+        let mut range = None;
+        for line in smaps.lines() {
+            if let Some(rss) = line.strip_prefix("Rss:") {
+                let resident = rss.trim().trim_end_matches("kB").trim() != "0";
+                if let (true, Some((start, end))) = (resident, range.take()) {
+                    let _ = self.read_pagemap(pid, start, end, pfns);
+                }
+            } else if let Some((start, end)) = mapping_range(line) {
+                range = Some((start, end));
+            }
+        }
+    }
+
+    fn read_pagemap(&self, pid: i32, start: u64, end: u64, pfns: &mut Vec<u64>) -> io::Result<()> {
+        let pagemap = File::open(format!("/proc/{}/pagemap", pid))?;
+        let (first, last) = (start / self.page_size, end.div_ceil(self.page_size));
+        let mut buf = vec![0u8; (PAGEMAP_CHUNK * 8) as usize];
+        let mut page = first;
+        while page < last {
+            let count = (last - page).min(PAGEMAP_CHUNK);
+            let buf = &mut buf[..(count * 8) as usize];
+            pagemap.read_exact_at(buf, page * 8)?;
+            for entry in buf.chunks_exact(8) {
+                let entry = u64::from_ne_bytes(entry.try_into().unwrap());
+                let pfn = entry & PFN_MASK;
+                if entry & PRESENT != 0 && pfn != 0 {
+                    pfns.push(pfn);
+                }
+            }
+            page += count;
+        }
+        Ok(())
+    }
+
+    /// Count the pages of `pfns`, sorted, still marked idle and mark them
+    /// all again; runs of nearby words are read and written at once
+    fn check_and_mark(&self, pfns: &[u64]) -> io::Result<Window> {
+        let mut window = Window { hot: 0, cold: 0 };
+        let mut rest = pfns;
+        while let Some(&first) = rest.first() {
+            let first_word = first / 64;
+            let mut len = 1;
+            while len < rest.len() && rest[len] / 64 - rest[len - 1] / 64 <= MAX_GAP {
+                len += 1;
+            }
+            let (run, next) = rest.split_at(len);
+            rest = next;
+
+            let words = (run[run.len() - 1] / 64 - first_word + 1) as usize;
+            let mut buf = vec![0u8; words * 8];
+            self.bitmap.read_exact_at(&mut buf, first_word * 8)?;
+            let mut marks = vec![0u64; words];
+            for &pfn in run {
+                let word = (pfn / 64 - first_word) as usize;
+                let bit = 1 << (pfn % 64);
+                let idle = u64::from_ne_bytes(buf[word * 8..][..8].try_into().unwrap());
+                if idle & bit != 0 {
+                    window.cold += self.page_size;
+                } else {
+                    window.hot += self.page_size;
+                }
+                marks[word] |= bit;
+            }
+            let marks: Vec<u8> = marks.iter().flat_map(|word| word.to_ne_bytes()).collect();
+            self.bitmap.write_all_at(&marks, first_word * 8)?;
+        }
+        Ok(window)
+    }
+}
+
+/// Addresses of an smaps mapping line, e.g. `7f3c1a000000-7f3c1a021000 rw-p ...`
+fn mapping_range(line: &str) -> Option<(u64, u64)> {
+    let (range, _) = line.split_once(' ')?;
+    let (start, end) = range.split_once('-')?;
+    Some((
+        u64::from_str_radix(start, 16).ok()?,
+        u64::from_str_radix(end, 16).ok()?,
+    ))
+}